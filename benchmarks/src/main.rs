@@ -1,6 +1,6 @@
 use std::{collections::BTreeMap, path::PathBuf, time::Duration};
 
-use cellulite::{Cellulite, roaring::RoaringBitmapCodec};
+use cellulite::{Cellulite, CelluliteOptions, reader::MatchSource, roaring::RoaringBitmapCodec};
 use clap::{Parser, ValueEnum};
 use france_query_zones::{gard, le_vigan, nimes, occitanie};
 use geojson::GeoJson;
@@ -23,6 +23,8 @@ mod france_regions;
 mod france_shops;
 mod france_zones;
 mod paris_voies;
+mod synthetic;
+mod workload;
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -64,6 +66,32 @@ struct Args {
     #[arg(long, default_value_t = false, conflicts_with = "skip_indexing")]
     index_metadata: bool,
 
+    /// Don't store the items covering a whole cell in its belly cell, to measure how much the
+    /// belly cells speed up the queries on large zones. Only valid if no_indexing is false.
+    #[arg(long, default_value_t = false, conflicts_with = "no_indexing")]
+    no_belly_cells: bool,
+
+    /// Run the polygons of this geojson file instead of the built-in queries, and report the
+    /// latency percentiles and the throughput.
+    #[arg(long, conflicts_with = "no_queries")]
+    workload: Option<PathBuf>,
+
+    /// Number of times each shape of the workload is queried.
+    #[arg(long, default_value_t = 10, requires = "workload")]
+    repeat: usize,
+
+    /// Number of threads running the workload at the same time.
+    #[arg(long, default_value_t = 1, requires = "workload")]
+    concurrency: usize,
+
+    /// Write the report of the workload as JSON in this file, to track the regressions across releases.
+    #[arg(long, requires = "workload")]
+    report: Option<PathBuf>,
+
+    /// The seed of the synthetic dataset, the same seed always generates the same items.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
     /// Set the number of items to index, will be capped at the number of items in the dataset
     #[arg(long)]
     limit: Option<usize>,
@@ -91,6 +119,9 @@ enum Dataset {
     Region,
     /// Mix of all the canton, arrondissement, commune, departement and region
     Zone,
+    /// Reproducible generated items, the selector chooses the kind of items: uniform-points,
+    /// clustered-points,<clusters>, polygons,<vertices> or edges for the antimeridian and the poles
+    Synthetic,
 }
 
 fn main() {
@@ -123,6 +154,8 @@ fn main() {
             &mut france_regions::parse() as &mut dyn Iterator<Item = (String, GeoJson)>
         }
         Dataset::Zone => &mut france_zones::parse() as &mut dyn Iterator<Item = (String, GeoJson)>,
+        Dataset::Synthetic => &mut synthetic::parse(&args.selector, args.limit, args.seed)
+            as &mut dyn Iterator<Item = (String, GeoJson)>,
     };
     let input = input.take(args.limit.unwrap_or(usize::MAX));
 
@@ -148,7 +181,14 @@ fn main() {
     }
     .unwrap();
     let mut wtxn = env.write_txn().unwrap();
-    let cellulite = Cellulite::create_from_env(&env, &mut wtxn, "cellulite").unwrap();
+    let mut cellulite = Cellulite::create_from_env(&env, &mut wtxn, "cellulite").unwrap();
+    if args.no_belly_cells {
+        let options = CelluliteOptions {
+            belly_cells: false,
+            ..*cellulite.options()
+        };
+        cellulite.set_options(&mut wtxn, options).unwrap();
+    }
     let metadata: heed::Database<Str, Bytes> =
         env.create_database(&mut wtxn, Some("metadata")).unwrap();
 
@@ -220,7 +260,22 @@ fn main() {
         }
     }
 
-    if !args.no_queries {
+    if let Some(path) = &args.workload {
+        let shapes = workload::parse_shapes(path);
+        println!(
+            "Running {} shapes {} times on {} threads",
+            shapes.len(),
+            args.repeat,
+            args.concurrency
+        );
+        let report = workload::run(&env, &cellulite, &shapes, args.repeat, args.concurrency);
+        println!("{report:#?}");
+        if let Some(path) = &args.report {
+            let file = std::fs::File::create(path).unwrap();
+            serde_json::to_writer_pretty(file, &report).unwrap();
+            println!("Report written to {}", path.display());
+        }
+    } else if !args.no_queries {
         let repeat = 1000;
 
         let rtxn = env.read_txn().unwrap();
@@ -276,5 +331,20 @@ fn main() {
             result.len(),
             time.elapsed() / repeat
         );
+
+        // On large zones most of the items should be returned without reading their shape
+        let provenance = cellulite
+            .in_shape_with_provenance(&rtxn, &occitanie)
+            .unwrap();
+        let mut sources: BTreeMap<&str, usize> = BTreeMap::new();
+        for provenance in provenance.values() {
+            let source = match provenance.source {
+                MatchSource::ContainedCell => "contained cells",
+                MatchSource::BellyCell => "belly cells",
+                MatchSource::DoubleCheck => "double-checks",
+            };
+            *sources.entry(source).or_default() += 1;
+        }
+        println!("The items in Occitanie were returned from {sources:?}");
     }
 }
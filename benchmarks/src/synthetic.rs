@@ -0,0 +1,36 @@
+use cellulite::test_utils::{SyntheticDataset, synthetic_dataset};
+use geojson::GeoJson;
+
+/// The number of items generated when no limit is given.
+const DEFAULT_COUNT: usize = 100_000;
+
+/// The selector chooses the kind of items: `uniform-points` by default, `clustered-points`,
+/// `polygons` or `edges`. The second value is the number of clusters or vertices.
+pub fn parse(
+    selector: &[String],
+    limit: Option<usize>,
+    seed: u64,
+) -> impl Iterator<Item = (String, GeoJson)> {
+    let kind = selector.first().map_or("uniform-points", String::as_str);
+    let parameter = selector.get(1).map(|p| p.parse::<usize>().unwrap());
+    let dataset = match kind {
+        "uniform-points" => SyntheticDataset::UniformPoints,
+        "clustered-points" => SyntheticDataset::ClusteredPoints {
+            clusters: parameter.unwrap_or(100),
+        },
+        "polygons" => SyntheticDataset::Polygons {
+            vertices: parameter.unwrap_or(8),
+        },
+        "edges" => SyntheticDataset::Edges,
+        other => panic!(
+            "Unknown synthetic dataset `{other}`, use uniform-points, clustered-points, polygons or edges"
+        ),
+    };
+    let kind = kind.to_string();
+    synthetic_dataset(dataset, limit.unwrap_or(DEFAULT_COUNT), seed)
+        .enumerate()
+        .map(move |(i, geometry)| {
+            let geojson = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&geometry)));
+            (format!("{kind}-{i}"), geojson)
+        })
+}
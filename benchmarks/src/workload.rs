@@ -0,0 +1,103 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use cellulite::Cellulite;
+use geo::{Geometry, Polygon};
+use geojson::GeoJson;
+use heed::Env;
+use serde::Serialize;
+
+/// The latencies of a query workload, written as JSON with `--report` to compare the releases.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub shapes: usize,
+    pub repeat: usize,
+    pub concurrency: usize,
+    pub queries: usize,
+    pub matches: u64,
+    pub total_ms: f64,
+    pub throughput_qps: f64,
+    pub p50_us: f64,
+    pub p95_us: f64,
+    pub p99_us: f64,
+    pub max_us: f64,
+}
+
+/// Read the polygons of a geojson file, the multi-polygons are split in one query per polygon.
+pub fn parse_shapes(path: &Path) -> Vec<Polygon> {
+    let file = std::fs::read_to_string(path).unwrap();
+    let geojson: GeoJson = file.parse().unwrap();
+    let collection: geo::GeometryCollection = geojson::quick_collection(&geojson).unwrap();
+    let mut shapes = Vec::new();
+    for geometry in collection {
+        match geometry {
+            Geometry::Polygon(polygon) => shapes.push(polygon),
+            Geometry::MultiPolygon(multi_polygon) => shapes.extend(multi_polygon),
+            other => panic!("Only polygons can be queried, got {other:?}"),
+        }
+    }
+    shapes
+}
+
+/// Run every shape `repeat` times, spread on `concurrency` threads that each query the shapes in
+/// their own read transaction.
+pub fn run(
+    env: &Env,
+    cellulite: &Cellulite,
+    shapes: &[Polygon],
+    repeat: usize,
+    concurrency: usize,
+) -> Report {
+    let concurrency = concurrency.max(1);
+    let time = Instant::now();
+    let results: Vec<(Vec<Duration>, u64)> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..concurrency)
+            .map(|thread| {
+                s.spawn(move || {
+                    let rtxn = env.read_txn().unwrap();
+                    let mut latencies = Vec::new();
+                    let mut matches = 0;
+                    // Each thread runs its share of the repetitions on all the shapes
+                    for _ in (thread..repeat).step_by(concurrency) {
+                        for shape in shapes {
+                            let time = Instant::now();
+                            let ret = cellulite.in_shape(&rtxn, shape).unwrap();
+                            latencies.push(time.elapsed());
+                            matches += ret.len();
+                        }
+                    }
+                    (latencies, matches)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let total = time.elapsed();
+
+    let mut latencies: Vec<Duration> = results.iter().flat_map(|(l, _)| l).copied().collect();
+    latencies.sort_unstable();
+    let matches = results.iter().map(|(_, matches)| matches).sum();
+    let percentile = |p: f64| {
+        if latencies.is_empty() {
+            return 0.0;
+        }
+        let rank = ((latencies.len() as f64 * p).ceil() as usize).clamp(1, latencies.len());
+        latencies[rank - 1].as_secs_f64() * 1_000_000.0
+    };
+
+    Report {
+        shapes: shapes.len(),
+        repeat,
+        concurrency,
+        queries: latencies.len(),
+        matches,
+        total_ms: total.as_secs_f64() * 1000.0,
+        throughput_qps: latencies.len() as f64 / total.as_secs_f64(),
+        p50_us: percentile(0.50),
+        p95_us: percentile(0.95),
+        p99_us: percentile(0.99),
+        max_us: percentile(1.0),
+    }
+}
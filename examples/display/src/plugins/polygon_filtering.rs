@@ -254,6 +254,7 @@ impl Plugin for PolygonFiltering {
                             FilteringStep::NotPresentInDB => Color32::BLACK,
                             FilteringStep::OutsideOfShape => Color32::RED,
                             FilteringStep::Returned => Color32::GREEN,
+                            FilteringStep::ReturnedFromBelly => Color32::LIGHT_GREEN,
                             FilteringStep::RequireDoubleCheck => Color32::YELLOW,
                             FilteringStep::DeepDive => Color32::BLUE,
                         };
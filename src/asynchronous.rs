@@ -0,0 +1,158 @@
+use std::{
+    panic::{AssertUnwindSafe, catch_unwind, resume_unwind},
+    sync::mpsc,
+};
+
+use geo::{Geometry, Point, Polygon};
+#[cfg(feature = "geojson")]
+use geojson::GeoJson;
+use heed::{Env, RoTxn, RwTxn};
+use roaring::RoaringBitmap;
+use steppe::Progress;
+use tokio::sync::oneshot;
+
+use crate::{Cellulite, ItemId, Result, reader::QueryOptions};
+
+type WriteJob = Box<dyn FnOnce(&Env, &Cellulite) + Send>;
+
+/// Wraps a database to use it from an async runtime, available with the `tokio` feature.
+///
+/// The writes are sent to a dedicated thread and applied one after the other, each in its own
+/// write transaction. The queries run on the blocking pool of tokio with a fresh read transaction,
+/// they see every write that was awaited before them.
+///
+/// The handle can be cloned, the clones share the same writer thread. The thread stops once every
+/// clone has been dropped.
+#[derive(Clone)]
+pub struct AsyncCellulite {
+    env: Env,
+    cellulite: Cellulite,
+    writes: mpsc::Sender<WriteJob>,
+}
+
+impl AsyncCellulite {
+    pub fn new(env: Env, cellulite: Cellulite) -> Self {
+        let (writes, queue) = mpsc::channel::<WriteJob>();
+        let (writer_env, writer_cellulite) = (env.clone(), cellulite.clone());
+        std::thread::spawn(move || {
+            for job in queue {
+                job(&writer_env, &writer_cellulite);
+            }
+        });
+        Self {
+            env,
+            cellulite,
+            writes,
+        }
+    }
+
+    /// Return the wrapped database.
+    pub fn cellulite(&self) -> &Cellulite {
+        &self.cellulite
+    }
+
+    /// Run `f` in a new write transaction on the writer thread and commit it if `f` succeeds.
+    ///
+    /// The write is queued as soon as this method is called, it's applied even if the returned
+    /// future is dropped before completion. A panic in `f` is resumed in the caller.
+    pub async fn write<O: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Cellulite, &mut RwTxn) -> Result<O> + Send + 'static,
+    ) -> Result<O> {
+        let (sender, receiver) = oneshot::channel();
+        let job: WriteJob = Box::new(move |env, cellulite| {
+            let ret = catch_unwind(AssertUnwindSafe(|| -> Result<O> {
+                let mut wtxn = env.write_txn()?;
+                let output = f(cellulite, &mut wtxn)?;
+                wtxn.commit()?;
+                Ok(output)
+            }));
+            // The caller may not be waiting anymore
+            let _ = sender.send(ret);
+        });
+        // The writer thread catches the panics and lives as long as we hold a sender
+        self.writes.send(job).expect("the writer thread is alive");
+        match receiver.await.expect("the writer thread answers every job") {
+            Ok(ret) => ret,
+            Err(panic) => resume_unwind(panic),
+        }
+    }
+
+    /// Run `f` in a new read transaction on the blocking pool of tokio. A panic in `f` is resumed
+    /// in the caller.
+    pub async fn read<O: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Cellulite, &RoTxn) -> Result<O> + Send + 'static,
+    ) -> Result<O> {
+        let (env, cellulite) = (self.env.clone(), self.cellulite.clone());
+        let ret = tokio::task::spawn_blocking(move || {
+            let rtxn = env.read_txn()?;
+            f(&cellulite, &rtxn)
+        })
+        .await;
+        match ret {
+            Ok(ret) => ret,
+            Err(error) => resume_unwind(error.into_panic()),
+        }
+    }
+
+    /// See [`Cellulite::add`].
+    #[cfg(feature = "geojson")]
+    pub async fn add(&self, item: ItemId, geo: GeoJson) -> Result<()> {
+        self.write(move |cellulite, wtxn| cellulite.add(wtxn, item, &geo))
+            .await
+    }
+
+    /// See [`Cellulite::add_geometry`].
+    pub async fn add_geometry(&self, item: ItemId, geom: Geometry) -> Result<()> {
+        self.write(move |cellulite, wtxn| cellulite.add_geometry(wtxn, item, geom))
+            .await
+    }
+
+    /// See [`Cellulite::delete`].
+    pub async fn delete(&self, item: ItemId) -> Result<()> {
+        self.write(move |cellulite, wtxn| cellulite.delete(wtxn, item))
+            .await
+    }
+
+    /// See [`Cellulite::build`]. The build runs on the writer thread, the writes queued after it
+    /// wait for its completion.
+    pub async fn build(
+        &self,
+        cancel: impl Fn() -> bool + Send + Sync + 'static,
+        progress: impl Progress + Send + 'static,
+    ) -> Result<()> {
+        self.write(move |cellulite, wtxn| cellulite.build(wtxn, &cancel, &progress))
+            .await
+    }
+
+    /// Return the shape of an item, see [`Cellulite::item`].
+    pub async fn item(&self, item: ItemId) -> Result<Option<Geometry>> {
+        self.read(move |cellulite, rtxn| {
+            Ok(cellulite.item(rtxn, item)?.map(|shape| shape.to_geo()))
+        })
+        .await
+    }
+
+    /// See [`Cellulite::in_shape`].
+    pub async fn in_shape(&self, polygon: Polygon) -> Result<RoaringBitmap> {
+        self.read(move |cellulite, rtxn| cellulite.in_shape(rtxn, &polygon))
+            .await
+    }
+
+    /// See [`Cellulite::in_shape_with_options`].
+    pub async fn in_shape_with_options(
+        &self,
+        polygon: Polygon,
+        options: QueryOptions,
+    ) -> Result<RoaringBitmap> {
+        self.read(move |cellulite, rtxn| cellulite.in_shape_with_options(rtxn, &polygon, options))
+            .await
+    }
+
+    /// See [`Cellulite::items_containing_point`].
+    pub async fn items_containing_point(&self, point: Point) -> Result<RoaringBitmap> {
+        self.read(move |cellulite, rtxn| cellulite.items_containing_point(rtxn, point))
+            .await
+    }
+}
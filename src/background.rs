@@ -0,0 +1,77 @@
+use std::{
+    panic::resume_unwind,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+};
+
+use heed::Env;
+use steppe::Progress;
+
+use crate::{Cellulite, Result};
+
+/// A build running on its own thread, returned by [`Cellulite::spawn_build`].
+///
+/// Dropping the handle doesn't stop the build, it keeps running in the background and its result
+/// is lost. Call [`Self::cancel`] first to stop it.
+pub struct BuildHandle<P> {
+    canceled: Arc<AtomicBool>,
+    progress: P,
+    thread: JoinHandle<Result<()>>,
+}
+
+impl Cellulite {
+    /// Build the database on a dedicated thread, in its own write transaction that is committed
+    /// once the build succeeds. The transaction is aborted if the build fails or is canceled.
+    ///
+    /// The `progress` is cloned: the build updates it from its thread while the returned handle
+    /// lets you follow it with [`BuildHandle::progress`].
+    /// The other write transactions of the environment wait for the end of the build.
+    pub fn spawn_build<P>(self, env: Env, progress: P) -> BuildHandle<P>
+    where
+        P: Progress + Clone + Send + 'static,
+    {
+        let canceled = Arc::new(AtomicBool::new(false));
+        let (thread_canceled, thread_progress) = (canceled.clone(), progress.clone());
+        let thread = std::thread::spawn(move || {
+            let mut wtxn = env.write_txn()?;
+            let cancel = || thread_canceled.load(Ordering::Relaxed);
+            self.build(&mut wtxn, &cancel, &thread_progress)?;
+            wtxn.commit()?;
+            Ok(())
+        });
+        BuildHandle {
+            canceled,
+            progress,
+            thread,
+        }
+    }
+}
+
+impl<P> BuildHandle<P> {
+    /// Ask the build to stop, it returns [`crate::Error::BuildCanceled`] as soon as it notices.
+    pub fn cancel(&self) {
+        self.canceled.store(true, Ordering::Relaxed);
+    }
+
+    /// Return the progress updated by the build.
+    pub fn progress(&self) -> &P {
+        &self.progress
+    }
+
+    /// Return `true` once the build is over, [`Self::join`] won't block then.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Wait for the end of the build and return its result. A panic of the build is resumed in
+    /// the caller.
+    pub fn join(self) -> Result<()> {
+        match self.thread.join() {
+            Ok(ret) => ret,
+            Err(panic) => resume_unwind(panic),
+        }
+    }
+}
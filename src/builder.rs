@@ -1,15 +1,29 @@
-use std::{cell::RefCell, collections::HashMap, sync::atomic::Ordering};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    panic::AssertUnwindSafe,
+    sync::{Arc, atomic::Ordering},
+};
 
 use crate::{
-    AtomicCellStep, AtomicItemStep, BuildSteps, ItemId, Result, keys::UpdateType,
-    metadata::Version, pos,
+    AtomicCellStep, AtomicItemStep, BuildSteps, CanceledBuild, CellDb, CelluliteOptions, ItemId,
+    OversizedLeafPolicy, Result,
+    checksum::{self, Checksummed},
+    error::panic_message,
+    keys::{KeyVariant, UpdateType, cell_to_locality_key, retrieve_cell_and_belly},
+    metadata::Version,
+    pos,
+    zerometry::ZerometryCodec,
 };
-use geo::MultiPolygon;
+use geo::{Intersects, MultiPolygon, Polygon};
 use h3o::{
     CellIndex, LatLng, Resolution,
     geom::{ContainmentMode, PlotterBuilder, TilerBuilder},
 };
-use heed::{RoTxn, RwTxn};
+use heed::{
+    BytesDecode, Env, RoTxn, RwTxn,
+    types::{Bytes, DecodeIgnore},
+};
 use intmap::IntMap;
 use rayon::iter::{ParallelBridge, ParallelIterator};
 use roaring::RoaringBitmap;
@@ -19,53 +33,155 @@ use zerometry::{InputRelation, RelationBetweenShapes, Zerometry};
 
 use crate::{Cellulite, Error, keys::Key};
 
+/// How many cells or relations we compute in the hot loops before checking if the build was canceled.
+const CANCEL_CHECK_INTERVAL: usize = 64;
+
 impl Cellulite {
-    fn retrieve_frozen_items<'a>(
+    /// Retrieve the items that are not frozen yet among `items`.
+    /// Only the items the build is going to read are retrieved, when a small part of the
+    /// database changed most of the item database is never touched.
+    fn freeze_items(
         &self,
-        rtxn: &'a RoTxn,
+        rtxn: &RoTxn,
         cancel: impl Fn() -> bool + Send + Sync,
-    ) -> Result<FrozenItems<'a>> {
-        let mut items = IntMap::with_capacity(self.item.len(rtxn)? as usize);
-        for ret in self.item.iter(rtxn)? {
+        items: &RoaringBitmap,
+        frozen_items: &mut FrozenItems<'static>,
+    ) -> Result<()> {
+        let items: RoaringBitmap = items
+            .iter()
+            .filter(|item| frozen_items.get(*item).is_none())
+            .collect();
+        if items.is_empty() {
+            return Ok(());
+        }
+
+        let db = self.item.remap_data_type::<Bytes>();
+        // When we need most of the items, iterating over the database is faster than seeking them
+        // one by one
+        let entries: Box<dyn Iterator<Item = heed::Result<(ItemId, &[u8])>> + '_> =
+            if items.len() * 2 >= db.len(rtxn)? {
+                Box::new(db.iter(rtxn)?.filter(|ret| match ret {
+                    Ok((item, _)) => items.contains(*item),
+                    Err(_) => true,
+                }))
+            } else {
+                Box::new(items.iter().filter_map(|item| {
+                    db.get(rtxn, &item)
+                        .transpose()
+                        .map(|ret| ret.map(|v| (item, v)))
+                }))
+            };
+        for ret in entries {
             if cancel() {
-                return Err(Error::BuildCanceled);
+                return Err(Error::BuildCanceled(None));
             }
             let (k, v) = ret?;
-            items.insert(k, v);
+            // We decode the value ourselves to know which item is corrupted
+            let v = Checksummed::<ZerometryCodec>::bytes_decode(v)
+                .map_err(heed::Error::Decoding)
+                .map_err(checksum::corruption(format_args!("item {k}")))?;
+            // currently heed doesn't know that writing in a database doesn't invalidate the
+            // pointers in another
+            let v: Zerometry<'static> = unsafe { std::mem::transmute(v) };
+            frozen_items.items.insert(k, v);
+        }
+        Ok(())
+    }
+
+    /// Return the item from the frozen items, or read it from the database if it's not frozen.
+    fn frozen_item<'a>(
+        &self,
+        rtxn: &'a RoTxn,
+        frozen_items: &FrozenItems<'static>,
+        item: ItemId,
+    ) -> Result<Zerometry<'a>> {
+        match frozen_items.get(item) {
+            Some(shape) => Ok(shape),
+            None => self
+                .item(rtxn, item)?
+                .ok_or_else(|| Error::InternalDocIdMissing(item, pos!())),
         }
-        Ok(FrozenItems { items })
     }
 
+    /// Retrieve and remove at most `limit` updates from the update database.
+    /// Return the inserted, deleted and updated items and wether some updates are still pending.
     fn retrieve_and_clear_updated_items(
         &self,
         wtxn: &mut RwTxn,
         cancel: impl Fn() -> bool + Send + Sync,
         progress: &impl Progress,
-    ) -> Result<(RoaringBitmap, RoaringBitmap)> {
+        limit: Option<u64>,
+        region: Option<&Polygon>,
+    ) -> Result<(RoaringBitmap, RoaringBitmap, RoaringBitmap, bool)> {
         progress.update(BuildSteps::RetrieveUpdatedItems);
-        let (atomic, step) = AtomicItemStep::new(self.update.len(wtxn)?);
+        let total = self.update.len(wtxn)?;
+        let limit = limit.unwrap_or(total).min(total);
+        let (atomic, step) = AtomicItemStep::new(limit);
         progress.update(step);
 
         let mut inserted = RoaringBitmap::new();
         let mut deleted = RoaringBitmap::new();
+        let mut updated = RoaringBitmap::new();
+        let mut skipped = false;
+        // The extent index still holds the bounding boxes of the last build, `None` if it's missing
+        let previously_in_region = match region {
+            Some(region) => self.extent_candidates(wtxn, region)?,
+            None => None,
+        };
 
-        for ret in self.update.iter(wtxn)? {
+        for ret in self.update.iter(wtxn)?.take(limit as usize) {
             if cancel() {
-                return Err(Error::BuildCanceled);
-            }
-            match ret? {
-                (item, UpdateType::Insert) => inserted.try_push(item).unwrap(),
-                (item, UpdateType::Delete) => deleted.try_push(item).unwrap(),
+                return Err(Error::BuildCanceled(None));
             }
+            let (item, update) = ret?;
             atomic.fetch_add(1, Ordering::Relaxed);
+            if let Some(region) = region {
+                // The previous shape of an updated item is lost, it may have left stale entries in
+                // the cells of the region
+                let was_in_region = update == UpdateType::Update
+                    && previously_in_region
+                        .as_ref()
+                        .is_none_or(|items| items.contains(item));
+                if !was_in_region && !self.item_in_region(wtxn, item, region)? {
+                    skipped = true;
+                    continue;
+                }
+            }
+            match update {
+                UpdateType::Insert => inserted.try_push(item).unwrap(),
+                UpdateType::Delete => deleted.try_push(item).unwrap(),
+                UpdateType::Update => updated.try_push(item).unwrap(),
+            }
         }
         progress.update(BuildSteps::ClearUpdatedItems);
-        self.update.clear(wtxn)?;
+        if limit == total && !skipped {
+            self.update.clear(wtxn)?;
+        } else {
+            for item in inserted.iter().chain(deleted.iter()).chain(updated.iter()) {
+                self.update.delete(wtxn, &item)?;
+            }
+        }
 
-        Ok((inserted, deleted))
+        Ok((inserted, deleted, updated, limit < total || skipped))
+    }
+
+    /// The items that don't exist are in every region, there is nothing to do to delete them.
+    fn item_in_region(&self, rtxn: &RoTxn, item: ItemId, region: &Polygon) -> Result<bool> {
+        match self.item(rtxn, item)? {
+            Some(shape) => Ok(shape.to_geo().intersects(region)),
+            None => Ok(true),
+        }
     }
 
     /// Build all the internal structure required to query the database.
+    /// The parallel steps only compute unions of bitmaps, building the same updates always produces
+    /// the same cells whatever the number of threads of the rayon thread pool.
+    /// If the environment is too small, an [`Error::MapFull`] is returned instead of the
+    /// `MDB_MAP_FULL` error of LMDB and the transaction must be aborted.
+    /// If the build is canceled, the [`Error::BuildCanceled`] tells where it stopped.
+    /// If a build in multiple transactions was interrupted, an [`Error::InterruptedBuild`] is
+    /// returned, see [`Self::recover`]. If another handle changed the options since this one
+    /// loaded them, an [`Error::StaleOptions`] is returned, see [`Self::reload_options`].
     // Indexing is in 4 steps:
     // 1. We retrieve all the items that have been updated since the last indexing
     // 2. We remove the deleted items from the database and remove the empty cells at the same time
@@ -79,67 +195,540 @@ impl Cellulite {
         cancel: &(impl Fn() -> bool + Send + Sync),
         progress: &impl Progress,
     ) -> Result<()> {
+        if self.needs_recovery(wtxn)? {
+            let processed = self.build_checkpoint(wtxn)?.unwrap_or_default();
+            return Err(Error::InterruptedBuild(processed));
+        }
+        self.build_pending_updates(wtxn, cancel, progress)
+    }
+
+    /// Finish a build in multiple transactions that was interrupted by a crash or a cancellation,
+    /// see [`Self::needs_recovery`]. The updates it left pending are built in `wtxn` along with
+    /// the ones made since, then the database is marked as recovered.
+    /// Use [`Self::build_in_multiple_transactions`] instead to resume it with small transactions.
+    pub fn recover(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        self.build_pending_updates(wtxn, cancel, progress)
+    }
+
+    fn build_pending_updates(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        let usage = self.map_usage(wtxn)?;
+        self.build_updates(wtxn, cancel, progress, None, None)
+            .map_err(|error| usage.explain(error))?;
+        // If a build in multiple transactions was interrupted, we just finished it
+        self.delete_build_checkpoint(wtxn)?;
+        Ok(())
+    }
+
+    /// Build only the pending updates of the items intersecting the `region`, the other updates
+    /// stay pending until the next build. The deleted items are checked with the shape they had
+    /// before their deletion, and the updated items with their new shape and the bounding box of
+    /// their previous shape, so the items moving out of the region are removed from it. Without
+    /// the extent index of [`Self::items_in_extent`], all the updated items are built.
+    /// Useful to make the most queried regions up to date first.
+    pub fn build_region(
+        &self,
+        wtxn: &mut RwTxn,
+        region: &Polygon,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        if self.needs_recovery(wtxn)? {
+            let processed = self.build_checkpoint(wtxn)?.unwrap_or_default();
+            return Err(Error::InterruptedBuild(processed));
+        }
+        let usage = self.map_usage(wtxn)?;
+        self.build_updates(wtxn, cancel, progress, None, Some(region))
+            .map_err(|error| usage.explain(error))?;
+        Ok(())
+    }
+
+    /// Build the database without ever subdividing the cells deeper than `max_resolution`, even if they contain
+    /// more items than the threshold. The index is coarser, and the queries must double-check more items,
+    /// but it's much faster to build. Useful to preview a dataset before doing a full-precision build.
+    ///
+    /// The resolution is stored in the database and used by all the following builds and queries.
+    /// It can be lowered later on, but to increase it the database must be cleared and built again.
+    pub fn build_up_to_resolution(
+        &self,
+        wtxn: &mut RwTxn,
+        max_resolution: Resolution,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        let current = self.max_resolution(wtxn)?;
+        if max_resolution > current && !self.cell_db().is_empty(wtxn)? {
+            return Err(Error::CannotIncreaseMaxResolution(current, max_resolution));
+        }
+        self.set_max_resolution(wtxn, max_resolution)?;
+        self.build(wtxn, cancel, progress)
+    }
+
+    /// Throw away the cells and build them again from the items stored in the database, without
+    /// requiring their geojson. The pending updates are applied in the same build.
+    /// Useful to recover from a corrupted cell database.
+    pub fn reindex(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        self.clear_cells(wtxn)?;
+        self.build(wtxn, cancel, progress)
+    }
+
+    /// Like [`Self::reindex`], but the options are replaced before the build. Unlike
+    /// [`Self::set_options`], the maximum resolution can be increased and the threshold decreased
+    /// since the cells are built from scratch.
+    pub fn reindex_with_options(
+        &mut self,
+        wtxn: &mut RwTxn,
+        options: CelluliteOptions,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        self.clear_cells(wtxn)?;
+        self.set_options(wtxn, options)?;
+        self.build(wtxn, cancel, progress)
+    }
+
+    /// Remove all the cells and mark every item that is not waiting to be deleted as inserted.
+    fn clear_cells(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.cell_db().clear(wtxn)?;
+        // Every item is inserted again, the extent index is complete after the build
+        self.extent.clear(wtxn)?;
+        self.mark_extent_indexed(wtxn)?;
+        self.delete_build_checkpoint(wtxn)?;
+        let mut items = RoaringBitmap::new();
+        for ret in self
+            .item_db()
+            .remap_data_type::<DecodeIgnore>()
+            .iter(wtxn)?
+        {
+            let (item, ()) = ret?;
+            items.insert(item);
+        }
+        for item in items {
+            if self.update.get(wtxn, &item)? != Some(UpdateType::Delete) {
+                self.update.put(wtxn, &item, &UpdateType::Insert)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the database over multiple write transactions, committing every `updates_per_transaction` updates.
+    /// It bounds the size of the transactions on very large imports, at the cost of atomicity: if the build fails
+    /// or is canceled, the updates processed in the previous transactions stay applied and the others are still pending.
+    /// Calling this method again resumes the build where it stopped, and [`Self::build_checkpoint`] tells you if a
+    /// build was interrupted.
+    /// When the build is canceled, the [`Error::BuildCanceled`] tells how many updates were committed.
+    pub fn build_in_multiple_transactions<Tls>(
+        &self,
+        env: &Env<Tls>,
+        updates_per_transaction: u64,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        loop {
+            let mut wtxn = env.write_txn()?;
+            let processed = self.build_checkpoint(&wtxn)?.unwrap_or_default();
+            let usage = self.map_usage(&wtxn)?;
+            let (built, remaining) = self
+                .build_updates(
+                    &mut wtxn,
+                    cancel,
+                    progress,
+                    Some(updates_per_transaction),
+                    None,
+                )
+                .map_err(|error| match usage.explain(error) {
+                    Error::BuildCanceled(Some(canceled)) => {
+                        Error::BuildCanceled(Some(CanceledBuild {
+                            committed_updates: processed,
+                            ..canceled
+                        }))
+                    }
+                    error => error,
+                })?;
+            if remaining {
+                self.set_build_checkpoint(&mut wtxn, processed + built)?;
+            } else {
+                self.delete_build_checkpoint(&mut wtxn)?;
+            }
+            wtxn.commit()?;
+            if !remaining {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Build the pending updates until about `max_cells` cells were created or updated, then
+    /// return [`BuildStatus::Pending`] so the caller can commit and build the rest in the next
+    /// write transaction. It spreads the maintenance of the index over short transactions instead
+    /// of blocking the other writers during a long build.
+    ///
+    /// The updates are built in small batches and the budget is checked between them, the last
+    /// batch may exceed it. Every batch is complete: the updates that were not built stay pending
+    /// and the database can be queried between two calls. [`Self::last_build_changes`] returns
+    /// the cells changed by all the batches of the call.
+    pub fn build_with_cell_budget(
+        &self,
+        wtxn: &mut RwTxn,
+        max_cells: usize,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<BuildStatus> {
+        // The first batch is small, the next ones are sized from the cells changed per update
+        const FIRST_BATCH: u64 = 64;
+
+        if self.needs_recovery(wtxn)? {
+            let processed = self.build_checkpoint(wtxn)?.unwrap_or_default();
+            return Err(Error::InterruptedBuild(processed));
+        }
+        let usage = self.map_usage(wtxn)?;
+        let mut changes = BTreeSet::new();
+        let (mut built, mut batch) = (0, FIRST_BATCH);
+        loop {
+            let (processed, remaining) = self
+                .build_updates(wtxn, cancel, progress, Some(batch), None)
+                .map_err(|error| usage.explain(error))?;
+            changes.extend(self.last_build_changes(wtxn)?);
+            built += processed;
+            if !remaining {
+                self.set_last_build_changes(wtxn, &changes)?;
+                return Ok(BuildStatus::Finished);
+            }
+            if changes.len() >= max_cells {
+                self.set_last_build_changes(wtxn, &changes)?;
+                return Ok(BuildStatus::Pending {
+                    pending_updates: self.update.len(wtxn)?,
+                });
+            }
+            let cells_per_update = (changes.len() as u64).div_ceil(built.max(1)).max(1);
+            batch = ((max_cells - changes.len()) as u64 / cells_per_update).max(1);
+        }
+    }
+
+    /// Physically remove the items deleted with [`Self::soft_delete`] from the index without building the other updates.
+    pub fn purge(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        let tombstones = self.tombstones(wtxn)?;
+        if tombstones.is_empty() {
+            return Ok(());
+        }
+        for item in tombstones.iter() {
+            // The deletion has been applied, there is no need to do it again at the next build
+            if self.update.get(wtxn, &item)? == Some(UpdateType::Delete) {
+                self.update.delete(wtxn, &item)?;
+            }
+        }
+        self.set_tombstones(wtxn, &RoaringBitmap::new())?;
+        let mut changes = BTreeSet::new();
+        self.remove_deleted_items(wtxn, cancel, progress, tombstones, &mut changes)?;
+        self.set_last_build_changes(wtxn, &changes)?;
+        Ok(())
+    }
+
+    /// Build at most `limit` updates.
+    /// Return the number of updates processed and wether some updates are still pending.
+    fn build_updates(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+        limit: Option<u64>,
+        region: Option<&Polygon>,
+    ) -> Result<(u64, bool)> {
+        let mut canceled = CanceledBuild {
+            step: BuildSteps::RetrieveUpdatedItems,
+            items: 0,
+            committed_updates: 0,
+        };
+        self.build_updates_and_track(wtxn, cancel, progress, limit, region, &mut canceled)
+            .map_err(|error| match error {
+                Error::BuildCanceled(None) => Error::BuildCanceled(Some(canceled)),
+                error => error,
+            })
+    }
+
+    /// Same as [`Self::build_updates`], `canceled` follows the steps of the build to explain where
+    /// it was if it gets canceled.
+    fn build_updates_and_track(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+        limit: Option<u64>,
+        region: Option<&Polygon>,
+        canceled: &mut CanceledBuild,
+    ) -> Result<(u64, bool)> {
         let db_version = self.get_version(wtxn)?;
         if db_version != Version::default() {
             return Err(Error::VersionMismatchOnBuild(db_version));
         }
+        self.check_options_are_current(wtxn)?;
 
         // 1.
-        let (inserted_items, removed_items) =
-            self.retrieve_and_clear_updated_items(wtxn, cancel, progress)?;
-        if inserted_items.is_empty() && removed_items.is_empty() {
+        let (mut inserted_items, removed_items, updated_items, remaining) =
+            self.retrieve_and_clear_updated_items(wtxn, cancel, progress, limit, region)?;
+        let processed = inserted_items.len() + removed_items.len() + updated_items.len();
+        canceled.items = processed;
+        // The cells whose bitmap changed during this build
+        let mut changes = BTreeSet::new();
+        if processed == 0 {
+            self.set_last_build_changes(wtxn, &changes)?;
             self.set_version(wtxn, &Version::default())?;
-            return Ok(());
+            return Ok((processed, remaining));
         }
+        self.increment_build_generation(wtxn)?;
+
+        // The updated items may have a new bounding box, even if they stay in their cells. The
+        // inserted items are not looked for since most of them are new: the previous bounding
+        // box of an item added again stays indexed, it only adds a candidate to the queries
+        let extent_removed = &removed_items | &updated_items;
 
         // 2.
-        self.remove_deleted_items(wtxn, cancel, progress, removed_items)?;
+        canceled.step = BuildSteps::RemoveDeletedItemsFromDatabase;
+        self.remove_tombstones(wtxn, &removed_items)?;
+        self.remove_deleted_items(wtxn, cancel, progress, removed_items, &mut changes)?;
+
+        // 2.1 The updated items that moved out of their cells must be inserted again
+        canceled.step = BuildSteps::UpdateItemsInPlace;
+        progress.update(BuildSteps::UpdateItemsInPlace);
+        inserted_items |= self.update_items_in_place(wtxn, cancel, &updated_items, &mut changes)?;
+
+        // 2.2 The extent index is updated with the new bounding boxes
+        let extent_inserted = &inserted_items | &updated_items;
+        self.update_extents(wtxn, cancel, &extent_removed, &extent_inserted)?;
         if inserted_items.is_empty() {
+            self.set_last_build_changes(wtxn, &changes)?;
             self.set_version(wtxn, &Version::default())?;
-            return Ok(());
+            return Ok((processed, remaining));
         }
 
         // 3.0
-        let frozen_items = self.retrieve_frozen_items(wtxn, cancel)?;
-        // currently heed doesn't know that writing in a database doesn't invalidate the pointers in another
-        let frozen_items: FrozenItems<'static> = unsafe { std::mem::transmute(frozen_items) };
+        canceled.step = BuildSteps::InsertItemsAtLevelZero;
+        let max_resolution = self.max_resolution(wtxn)?;
+        let mut frozen_items = FrozenItems::default();
+        self.freeze_items(wtxn, cancel, &inserted_items, &mut frozen_items)?;
 
         // 3.1
-        self.insert_items_at_level_zero(wtxn, cancel, progress, &inserted_items, &frozen_items)?;
+        self.insert_items_at_level_zero(
+            wtxn,
+            cancel,
+            progress,
+            &inserted_items,
+            &frozen_items,
+            &mut changes,
+        )?;
 
-        // 4. We have to iterate over all the level-zero cells and insert the new items that are in them in the database at the next level if we need to
+        // 4. We have to iterate over all the level-zero cells and insert the new items that are in them in the database at the next level if we need to.
+        //    Each level-zero cell is a sub-tree that only reads the database and produces its own batch of changes.
+        //    The batches are merged and written in key order at the end.
         //    TODO: Could be parallelized
         progress.update(BuildSteps::InsertItemsRecursively); // we cannot detail more here
+        canceled.step = BuildSteps::InsertItemsRecursively;
+        let mut merged = WriteBatch::default();
         for cell in CellIndex::base_cells() {
             if cancel() {
-                return Err(Error::BuildCanceled);
+                return Err(Error::BuildCanceled(None));
             }
             let bitmap = self
                 .cell_db()
                 .get(wtxn, &Key::Cell(cell))?
                 .unwrap_or_default();
             // Awesome, we don't care about what's in the cell, wether it have multiple levels or not
-            if bitmap.len() < self.threshold || bitmap.intersection_len(&inserted_items) == 0 {
+            if bitmap.len() < self.options.threshold
+                || bitmap.intersection_len(&inserted_items) == 0
+                || max_resolution == Resolution::Zero
+            {
                 continue;
             }
-            self.insert_chunk_of_items_recursively(
-                wtxn,
-                cancel,
-                inserted_items.clone(),
-                bitmap,
-                cell,
-                &frozen_items,
-            )?;
+            let original = &bitmap - &inserted_items;
+            let batch = if original.len() >= self.options.threshold {
+                // The cell was already split, only the new items must follow their path down
+                // the tree
+                let items_to_insert = &bitmap & &inserted_items;
+                self.build_sub_tree(
+                    wtxn,
+                    cancel,
+                    original,
+                    items_to_insert,
+                    cell,
+                    max_resolution,
+                    &frozen_items,
+                )?
+            } else {
+                // The cell just became too large, all its items must be sent to its children
+                self.build_sub_tree(
+                    wtxn,
+                    cancel,
+                    inserted_items.clone(),
+                    bitmap,
+                    cell,
+                    max_resolution,
+                    &frozen_items,
+                )?
+            };
+            merged.merge(batch);
+        }
+        self.apply_write_batch(
+            wtxn,
+            cancel,
+            merged,
+            max_resolution,
+            &frozen_items,
+            &mut changes,
+        )?;
+
+        if self.options.oversized_leaves == OversizedLeafPolicy::Reject {
+            self.check_oversized_leaves(wtxn, &changes, max_resolution)?;
         }
 
         progress.update(BuildSteps::UpdateTheMetadata);
+        self.set_last_build_changes(wtxn, &changes)?;
         self.set_version(wtxn, &Version::default())?;
 
+        Ok((processed, remaining))
+    }
+
+    /// Find the updated items whose new shape still fits in the cells they're stored in, there is
+    /// nothing to build for them. The other items are removed from their cells and returned to be
+    /// inserted again.
+    fn update_items_in_place(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: impl Fn() -> bool + Send + Sync,
+        items: &RoaringBitmap,
+        changes: &mut BTreeSet<CellIndex>,
+    ) -> Result<RoaringBitmap> {
+        let max_resolution = self.max_resolution(wtxn)?;
+        let mut moved = Vec::new();
+        for item in items.iter() {
+            if cancel() {
+                return Err(Error::BuildCanceled(None));
+            }
+            let (fits, stored) = self.stored_cells_of_item(wtxn, item, max_resolution)?;
+            if !fits {
+                moved.push((item, stored));
+            }
+        }
+
+        let mut to_insert = RoaringBitmap::new();
+        for (item, stored) in moved {
+            for key in stored {
+                let mut bitmap = self
+                    .cell_db()
+                    .get(wtxn, &key)
+                    .map_err(checksum::corruption(format_args!("{key:?}")))?
+                    .unwrap_or_default();
+                bitmap.remove(item);
+                if bitmap.is_empty() {
+                    self.cell_db().delete(wtxn, &key)?;
+                } else {
+                    self.cell_db().put(wtxn, &key, &bitmap)?;
+                }
+                changes.insert(key.parts().0);
+            }
+            to_insert.insert(item);
+        }
+        Ok(to_insert)
+    }
+
+    /// Walk down the cells storing the item like the queries do, and return the keys of these
+    /// cells along with wether its current shape fits in them: it must cover its belly cells,
+    /// intersect its other cells, and not intersect any cell of the tree it's missing from.
+    fn stored_cells_of_item(
+        &self,
+        rtxn: &RoTxn,
+        item: ItemId,
+        max_resolution: Resolution,
+    ) -> Result<(bool, Vec<Key>)> {
+        let shape = self
+            .item(rtxn, item)?
+            .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+        let mut fits = true;
+        let mut stored = Vec::new();
+        let mut to_explore: VecDeque<_> = CellIndex::base_cells().collect();
+        let mut already_explored = HashSet::new();
+
+        while let Some(cell) = to_explore.pop_front() {
+            if !already_explored.insert(cell) {
+                continue;
+            }
+            let (cell_items, belly_items) = retrieve_cell_and_belly(rtxn, &self.cell, cell)?;
+            let cell_items = cell_items.unwrap_or_default();
+            let relation = shape.relation(
+                &get_cell_shape(cell),
+                InputRelation {
+                    strict_contained: false,
+                    ..InputRelation::all()
+                },
+            );
+            if belly_items.is_some_and(|belly_items| belly_items.contains(item)) {
+                stored.push(Key::Belly(cell));
+                // The cell may have been split after the item was stored in it
+                if cell_items.contains(item) {
+                    stored.push(Key::Cell(cell));
+                }
+                fits &= relation.strict_contains.unwrap_or_default();
+            } else if cell_items.contains(item) {
+                stored.push(Key::Cell(cell));
+                fits &= relation.any_relation();
+                if cell_items.len() >= self.options.threshold && cell.resolution() < max_resolution
+                {
+                    to_explore.extend(get_children_cells(cell).unwrap_or_default());
+                }
+            } else {
+                fits &= !relation.any_relation();
+            }
+        }
+        Ok((fits, stored))
+    }
+
+    /// Return an [`Error::OversizedLeaf`] if one of the cells modified by the build cannot be
+    /// split anymore but contains more items than the threshold.
+    fn check_oversized_leaves(
+        &self,
+        rtxn: &RoTxn,
+        changes: &BTreeSet<CellIndex>,
+        max_resolution: Resolution,
+    ) -> Result<()> {
+        for &cell in changes
+            .iter()
+            .filter(|cell| cell.resolution() >= max_resolution)
+        {
+            let key = Key::Cell(cell);
+            let Some(bitmap) = self
+                .cell_db()
+                .get(rtxn, &key)
+                .map_err(checksum::corruption(format_args!("{key:?}")))?
+            else {
+                continue;
+            };
+            if bitmap.len() >= self.options.threshold {
+                return Err(Error::OversizedLeaf(cell, bitmap.len()));
+            }
+        }
         Ok(())
     }
 
     /// 1. We remove all the items by id of the items database
     /// 2. We do a scan of the whole cell database and remove the items from the bitmaps
+    /// 3. We remove the items from the namespaces they belong to
     ///
     /// TODO: We could optimize 2 and 3 by diving into the cells and stopping early when one is empty
     fn remove_deleted_items(
@@ -148,12 +737,14 @@ impl Cellulite {
         cancel: impl Fn() -> bool + Send + Sync,
         progress: &impl Progress,
         items: RoaringBitmap,
+        changes: &mut BTreeSet<CellIndex>,
     ) -> Result<()> {
         progress.update(BuildSteps::RemoveDeletedItemsFromDatabase);
         steppe::make_enum_progress! {
             pub enum RemoveDeletedItemsSteps {
                 RemoveDeletedItemsFromItemsDatabase,
                 RemoveDeletedItemsFromCellsDatabase,
+                RemoveDeletedItemsFromNamespacesDatabase,
             }
         }
 
@@ -162,7 +753,7 @@ impl Cellulite {
         progress.update(step.clone());
         for item in items.iter() {
             if cancel() {
-                return Err(Error::BuildCanceled);
+                return Err(Error::BuildCanceled(None));
             }
             self.item_db().delete(wtxn, &item)?;
             atomic.fetch_add(1, Ordering::Relaxed);
@@ -174,13 +765,17 @@ impl Cellulite {
         let mut iter = self.cell_db().iter_mut(wtxn)?;
         while let Some(ret) = iter.next() {
             if cancel() {
-                return Err(Error::BuildCanceled);
+                return Err(Error::BuildCanceled(None));
             }
             let (key, mut bitmap) = ret?;
             let len = bitmap.len();
             bitmap -= &items;
             let removed = len - bitmap.len();
             atomic.fetch_add(removed, Ordering::Relaxed);
+            if removed == 0 {
+                continue;
+            }
+            changes.insert(key.parts().0);
 
             // safe because everything is owned
             unsafe {
@@ -192,6 +787,100 @@ impl Cellulite {
             }
         }
         drop(iter);
+
+        progress.update(RemoveDeletedItemsSteps::RemoveDeletedItemsFromNamespacesDatabase);
+        self.remove_from_namespaces(wtxn, &items)?;
+        Ok(())
+    }
+
+    /// Compute all the changes required to insert the items in the sub-tree of a cell without writing anything.
+    #[allow(clippy::too_many_arguments)]
+    fn build_sub_tree(
+        &self,
+        rtxn: &RoTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        items_in_current_cell: RoaringBitmap,
+        items_to_insert: RoaringBitmap,
+        cell: CellIndex,
+        max_resolution: Resolution,
+        frozen_items: &FrozenItems<'static>,
+    ) -> Result<WriteBatch> {
+        let mut batch = WriteBatch::default();
+        self.insert_chunk_of_items_recursively(
+            rtxn,
+            &mut batch,
+            cancel,
+            items_in_current_cell,
+            items_to_insert,
+            cell,
+            max_resolution,
+            frozen_items,
+        )?;
+        Ok(batch)
+    }
+
+    /// Write a batch in key order.
+    ///
+    /// Since the sub-trees overlap, a cell shared by multiple sub-trees can become too large once their batches
+    /// are merged while none of them decided to split it. These cells are split afterward, one by one.
+    fn apply_write_batch(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        batch: WriteBatch,
+        max_resolution: Resolution,
+        frozen_items: &FrozenItems<'static>,
+        changes: &mut BTreeSet<CellIndex>,
+    ) -> Result<()> {
+        let mut to_split = Vec::new();
+        for ((cell, variant), bitmap) in batch.entries.iter() {
+            if *variant != KeyVariant::Cell
+                || bitmap.len() < self.options.threshold
+                || cell.resolution() >= max_resolution
+            {
+                continue;
+            }
+            let mut dispatched = batch.dispatched.get(cell).cloned().unwrap_or_default();
+            if let Some(original) = self
+                .cell_db()
+                .get(wtxn, &Key::Cell(*cell))
+                .map_err(checksum::corruption(format_args!("{:?}", Key::Cell(*cell))))?
+                && original.len() >= self.options.threshold
+            {
+                dispatched |= original;
+            }
+            let missing = bitmap - dispatched;
+            if !missing.is_empty() {
+                to_split.push((*cell, bitmap.clone(), missing));
+            }
+        }
+
+        // The entries are sorted by cell but the database is sorted by locality
+        let mut entries: Vec<_> = batch.entries.into_iter().collect();
+        entries
+            .sort_unstable_by_key(|((cell, variant), _)| (cell_to_locality_key(*cell), *variant));
+        for ((cell, variant), bitmap) in entries {
+            if cancel() {
+                return Err(Error::BuildCanceled(None));
+            }
+            self.cell_db()
+                .put(wtxn, &Key::from_parts(cell, variant), &bitmap)?;
+            changes.insert(cell);
+        }
+
+        for (cell, items_in_cell, missing) in to_split {
+            let batch = self.build_sub_tree(
+                wtxn,
+                cancel,
+                items_in_cell,
+                missing,
+                cell,
+                max_resolution,
+                frozen_items,
+            )?;
+            self.apply_write_batch(wtxn, cancel, batch, max_resolution, frozen_items, changes)?;
+        }
+
         Ok(())
     }
 
@@ -202,6 +891,7 @@ impl Cellulite {
         progress: &impl Progress,
         items: &RoaringBitmap,
         frozen_items: &FrozenItems<'static>,
+        changes: &mut BTreeSet<CellIndex>,
     ) -> Result<()> {
         progress.update(BuildSteps::InsertItemsAtLevelZero);
         steppe::make_enum_progress! {
@@ -223,29 +913,36 @@ impl Cellulite {
             .par_bridge()
             .try_for_each(|item| -> Result<_> {
                 if cancel() {
-                    return Err(Error::BuildCanceled);
-                }
-                let (cells_map, belly_map) = &mut *tls_maps.get_or_default().borrow_mut();
-                let (cells_vec, belly_vec) = &mut *tls_vecs.get_or_default().borrow_mut();
-                cells_vec.clear();
-                belly_vec.clear();
-
-                let shape = frozen_items
-                    .get(item)
-                    .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
-                Self::explode_level_zero_geo(item, shape, cells_vec, belly_vec)?;
-                for cell in cells_vec {
-                    cells_map
-                        .entry(*cell)
-                        .or_insert_with(RoaringBitmap::new)
-                        .insert(item);
-                }
-                for cell in belly_vec {
-                    belly_map
-                        .entry(*cell)
-                        .or_insert_with(RoaringBitmap::new)
-                        .insert(item);
+                    return Err(Error::BuildCanceled(None));
                 }
+                // A panic must not unwind through rayon and leave the write transaction poisoned
+                catch_panic(item, || {
+                    let (cells_map, belly_map) = &mut *tls_maps.get_or_default().borrow_mut();
+                    let (cells_vec, belly_vec) = &mut *tls_vecs.get_or_default().borrow_mut();
+                    cells_vec.clear();
+                    belly_vec.clear();
+
+                    let shape = frozen_items
+                        .get(item)
+                        .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+                    Self::explode_level_zero_geo(&cancel, item, shape, cells_vec, belly_vec)?;
+                    if !self.options.belly_cells {
+                        cells_vec.append(belly_vec);
+                    }
+                    for cell in cells_vec {
+                        cells_map
+                            .entry(*cell)
+                            .or_insert_with(RoaringBitmap::new)
+                            .insert(item);
+                    }
+                    for cell in belly_vec {
+                        belly_map
+                            .entry(*cell)
+                            .or_insert_with(RoaringBitmap::new)
+                            .insert(item);
+                    }
+                    Ok(())
+                })?;
                 atomic.fetch_add(1, Ordering::Relaxed);
                 Ok(())
             })?;
@@ -269,9 +966,12 @@ impl Cellulite {
         progress.update(InsertItemsAtLevelZeroSteps::WriteCellsToDatabase);
         let (atomic, step) = AtomicCellStep::new(to_insert.len() as u64 + belly.len() as u64);
         progress.update(step);
+        // We write the cells in key order
+        let to_insert: BTreeMap<_, _> = to_insert.into_iter().collect();
+        let belly: BTreeMap<_, _> = belly.into_iter().collect();
         for (cell, items) in to_insert {
             if cancel() {
-                return Err(Error::BuildCanceled);
+                return Err(Error::BuildCanceled(None));
             }
             let mut bitmap = self
                 .cell_db()
@@ -279,11 +979,12 @@ impl Cellulite {
                 .unwrap_or_default();
             bitmap |= items;
             self.cell_db().put(wtxn, &Key::Cell(cell), &bitmap)?;
+            changes.insert(cell);
             atomic.fetch_add(1, Ordering::Relaxed);
         }
         for (cell, items) in belly {
             if cancel() {
-                return Err(Error::BuildCanceled);
+                return Err(Error::BuildCanceled(None));
             }
             let mut bitmap = self
                 .cell_db()
@@ -291,6 +992,7 @@ impl Cellulite {
                 .unwrap_or_default();
             bitmap |= items;
             self.cell_db().put(wtxn, &Key::Belly(cell), &bitmap)?;
+            changes.insert(cell);
             atomic.fetch_add(1, Ordering::Relaxed);
         }
 
@@ -298,6 +1000,7 @@ impl Cellulite {
     }
 
     fn explode_level_zero_geo(
+        cancel: &(impl Fn() -> bool + Send + Sync),
         // only used for error handling
         item: ItemId,
         shape: Zerometry,
@@ -307,14 +1010,14 @@ impl Cellulite {
         match shape {
             Zerometry::Point(point) => {
                 let cell = LatLng::new(point.lat(), point.lng())
-                    .unwrap()
+                    .map_err(|err| Error::InvalidItemCoordinate(item, err))?
                     .to_cell(Resolution::Zero);
                 cells.push(cell);
             }
             Zerometry::MultiPoints(multi_point) => {
                 for point in multi_point.points() {
                     let cell = LatLng::new(point.lat(), point.lng())
-                        .unwrap()
+                        .map_err(|err| Error::InvalidItemCoordinate(item, err))?
                         .to_cell(Resolution::Zero);
                     cells.push(cell);
                 }
@@ -325,7 +1028,10 @@ impl Cellulite {
                     .build();
                 tiler.add(polygon.to_geo())?;
 
-                for cell in tiler.into_coverage() {
+                for (i, cell) in tiler.into_coverage().enumerate() {
+                    if i % CANCEL_CHECK_INTERVAL == 0 && cancel() {
+                        return Err(Error::BuildCanceled(None));
+                    }
                     // If the cell is entirely contained in the polygon, insert directly to belly_cell_db
                     let cell_polygon = MultiPolygon::from(cell);
                     if polygon.contains(&cell_polygon) {
@@ -338,14 +1044,22 @@ impl Cellulite {
             }
             Zerometry::MultiPolygon(multi_polygon) => {
                 for polygon in multi_polygon.polygons() {
-                    Self::explode_level_zero_geo(item, polygon.into(), cells, belly)?;
+                    if cancel() {
+                        return Err(Error::BuildCanceled(None));
+                    }
+                    Self::explode_level_zero_geo(cancel, item, polygon.into(), cells, belly)?;
                 }
             }
             Zerometry::Line(line) => {
                 let mut plotter = PlotterBuilder::new(Resolution::Zero).build();
-                plotter.add_batch(line.to_geo().lines()).unwrap();
+                plotter.add_batch(line.to_geo().lines()).map_err(|err| {
+                    Error::CannotConvertLineToCell(item, err, format!("{line:?}"))
+                })?;
 
-                for cell in plotter.plot() {
+                for (i, cell) in plotter.plot().enumerate() {
+                    if i % CANCEL_CHECK_INTERVAL == 0 && cancel() {
+                        return Err(Error::BuildCanceled(None));
+                    }
                     let ret_cells = cell.map_err(|err| {
                         Error::CannotConvertLineToCell(item, err, format!("{line:?}"))
                     })?;
@@ -356,10 +1070,15 @@ impl Cellulite {
             Zerometry::MultiLines(multi_lines) => {
                 let mut plotter = PlotterBuilder::new(Resolution::Zero).build();
                 for line in multi_lines.lines() {
-                    plotter.add_batch(line.to_geo().lines()).unwrap();
+                    plotter.add_batch(line.to_geo().lines()).map_err(|err| {
+                        Error::CannotConvertLineToCell(item, err, format!("{multi_lines:?}"))
+                    })?;
                 }
 
-                for cell in plotter.plot() {
+                for (i, cell) in plotter.plot().enumerate() {
+                    if i % CANCEL_CHECK_INTERVAL == 0 && cancel() {
+                        return Err(Error::BuildCanceled(None));
+                    }
                     let ret_cells = cell.map_err(|err| {
                         Error::CannotConvertLineToCell(item, err, format!("{multi_lines:?}"))
                     })?;
@@ -369,18 +1088,21 @@ impl Cellulite {
             }
             Zerometry::Collection(collection) => {
                 Self::explode_level_zero_geo(
+                    cancel,
                     item,
                     Zerometry::MultiPoints(collection.points()),
                     cells,
                     belly,
                 )?;
                 Self::explode_level_zero_geo(
+                    cancel,
                     item,
                     Zerometry::MultiLines(collection.lines()),
                     cells,
                     belly,
                 )?;
                 Self::explode_level_zero_geo(
+                    cancel,
                     item,
                     Zerometry::MultiPolygon(collection.polygons()),
                     cells,
@@ -405,32 +1127,44 @@ impl Cellulite {
     ///  - If it was already too large, repeat the process with the next resolution
     ///  - If it **just became** too large. Retrieve all the items it contains and add them to the list of items to handle
     ///    Call ourselves recursively on the next resolution
+    ///
+    /// Nothing is written to the database, the changes are accumulated in the batch.
+    #[allow(clippy::too_many_arguments)]
     fn insert_chunk_of_items_recursively(
         &self,
-        wtxn: &mut RwTxn,
+        rtxn: &RoTxn,
+        batch: &mut WriteBatch,
         cancel: &(impl Fn() -> bool + Send + Sync),
         items_in_current_cell: RoaringBitmap,
         items_to_insert: RoaringBitmap,
         parent_cell: CellIndex,
+        max_resolution: Resolution,
         frozen_items: &FrozenItems<'static>,
     ) -> Result<()> {
         // 1. If we cannot increase the resolution, we are done
-        let Some(children_cells) = get_children_cells(parent_cell)? else {
+        if parent_cell.resolution() >= max_resolution {
+            return Ok(());
+        }
+        let Some(children_cells) = batch.children_of(parent_cell) else {
             return Ok(());
         };
+        batch.dispatch(parent_cell, &items_to_insert);
         // 2.
-        let mut to_insert = HashMap::with_capacity(children_cells.len());
-        let mut to_insert_in_belly = HashMap::new();
+        // The children are visited in order since they overlap with the children of their siblings,
+        // whether a shared cell is split can depend on which sibling fills it first
+        let mut to_insert = BTreeMap::new();
+        let mut to_insert_in_belly = BTreeMap::new();
 
         for &child_cell in children_cells.iter() {
             if cancel() {
-                return Err(Error::BuildCanceled);
+                return Err(Error::BuildCanceled(None));
             }
             let cell_shape = get_cell_shape(child_cell);
-            for item in items_to_insert.iter() {
-                let shape = frozen_items
-                    .get(item)
-                    .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+            for (i, item) in items_to_insert.iter().enumerate() {
+                if i % CANCEL_CHECK_INTERVAL == 0 && cancel() {
+                    return Err(Error::BuildCanceled(None));
+                }
+                let shape = self.frozen_item(rtxn, frozen_items, item)?;
                 let relation = shape.relation(
                     &cell_shape,
                     InputRelation {
@@ -439,7 +1173,7 @@ impl Cellulite {
                         ..InputRelation::all()
                     },
                 );
-                if relation.strict_contains.unwrap_or_default() {
+                if self.options.belly_cells && relation.strict_contains.unwrap_or_default() {
                     let entry = to_insert_in_belly
                         .entry(child_cell)
                         .or_insert_with(RoaringBitmap::new);
@@ -456,47 +1190,49 @@ impl Cellulite {
         // 3.
         for (cell, items) in to_insert_in_belly {
             if cancel() {
-                return Err(Error::BuildCanceled);
+                return Err(Error::BuildCanceled(None));
             }
-            let mut bitmap = self
-                .cell_db()
-                .get(wtxn, &Key::Belly(cell))?
+            let mut bitmap = batch
+                .get(rtxn, self.cell_db(), Key::Belly(cell))?
                 .unwrap_or_default();
             bitmap |= items;
-            self.cell_db().put(wtxn, &Key::Belly(cell), &bitmap)?;
+            batch.put(Key::Belly(cell), bitmap);
         }
 
         for (cell, mut items_to_insert) in to_insert {
             if cancel() {
-                return Err(Error::BuildCanceled);
+                return Err(Error::BuildCanceled(None));
             }
-            let original_bitmap = self.cell_db().get(wtxn, &Key::Cell(cell))?;
+            let original_bitmap = batch.get(rtxn, self.cell_db(), Key::Cell(cell))?;
             let new_bitmap =
                 original_bitmap.as_ref().unwrap_or(&Default::default()) | &items_to_insert;
-            self.cell_db().put(wtxn, &Key::Cell(cell), &new_bitmap)?;
+            batch.put(Key::Cell(cell), new_bitmap.clone());
             if let Some(ref original_bitmap) = original_bitmap
-                && original_bitmap.len() >= self.threshold
+                && original_bitmap.len() >= self.options.threshold
             {
                 // if we were already too large we can immediately jump to the next resolution
                 self.insert_chunk_of_items_recursively(
-                    wtxn,
+                    rtxn,
+                    batch,
                     cancel,
                     original_bitmap.clone(),
                     items_to_insert,
                     cell,
+                    max_resolution,
                     frozen_items,
                 )?;
-            } else if new_bitmap.len() >= self.threshold {
+            } else if new_bitmap.len() >= self.options.threshold {
                 let cell_shape = get_cell_shape(cell);
                 let mut belly_items = RoaringBitmap::new();
                 let original_bitmap =
                     original_bitmap.unwrap_or_else(|| items_in_current_cell.clone());
 
                 // If we just became too large, we have to retrieve the items that were already in the database insert them at the next resolution
-                for item_id in original_bitmap.iter() {
-                    let shape = frozen_items
-                        .get(item_id)
-                        .ok_or_else(|| Error::InternalDocIdMissing(item_id, pos!()))?;
+                for (i, item_id) in original_bitmap.iter().enumerate() {
+                    if i % CANCEL_CHECK_INTERVAL == 0 && cancel() {
+                        return Err(Error::BuildCanceled(None));
+                    }
+                    let shape = self.frozen_item(rtxn, frozen_items, item_id)?;
 
                     let relation = shape.relation(
                         &cell_shape,
@@ -506,26 +1242,28 @@ impl Cellulite {
                             ..InputRelation::all()
                         },
                     );
-                    if relation.strict_contains.unwrap_or_default() {
+                    if self.options.belly_cells && relation.strict_contains.unwrap_or_default() {
                         belly_items.insert(item_id);
                     } else if relation.any_relation() {
                         items_to_insert.insert(item_id);
                     }
                 }
 
-                let mut belly_cells = self
-                    .cell_db()
-                    .get(wtxn, &Key::Belly(cell))?
+                let mut belly_cells = batch
+                    .get(rtxn, self.cell_db(), Key::Belly(cell))?
                     .unwrap_or_default();
-                belly_cells |= belly_items;
-                self.cell_db().put(wtxn, &Key::Belly(cell), &belly_cells)?;
+                belly_cells |= &belly_items;
+                batch.put(Key::Belly(cell), belly_cells);
+                batch.dispatch(cell, &belly_items);
 
                 self.insert_chunk_of_items_recursively(
-                    wtxn,
+                    rtxn,
+                    batch,
                     cancel,
                     RoaringBitmap::new(),
                     items_to_insert,
                     cell,
+                    max_resolution,
                     frozen_items,
                 )?;
             }
@@ -542,15 +1280,73 @@ fn get_cell_shape(cell: CellIndex) -> MultiPolygon {
 /// Return None if we cannot increase the resolution
 /// Otherwise, return the children cells in a very non-efficient way
 /// Note: We cannot use the `get_children_cells` function because it doesn't return the full coverage of our cells and leaves holes
-fn get_children_cells(cell: CellIndex) -> Result<Option<Vec<CellIndex>>, Error> {
-    let Some(next_res) = cell.resolution().succ() else {
-        return Ok(None);
-    };
+fn get_children_cells(cell: CellIndex) -> Option<Vec<CellIndex>> {
+    let next_res = cell.resolution().succ()?;
     // safe to unwrap because we just increased the resolution
     let center_child = cell.center_child(next_res).unwrap();
-    Ok(Some(center_child.grid_disk(2)))
+    Some(center_child.grid_disk(2))
+}
+
+/// Run `f` on an item and turn its panics into an [`Error::BuildPanicked`] with the id of the item.
+fn catch_panic<T>(item: ItemId, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    std::panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(Error::BuildPanicked(item, panic_message(payload))))
+}
+
+/// The changes made to the cell database by a sub-tree.
+#[derive(Default)]
+struct WriteBatch {
+    /// The new value of every cell modified, it already contains the items of the database.
+    entries: BTreeMap<(CellIndex, KeyVariant), RoaringBitmap>,
+    /// The items that were sent to the children or the belly of a cell.
+    dispatched: HashMap<CellIndex, RoaringBitmap>,
+    /// The children of the cells split by the sub-tree. The chunks of items often go through the
+    /// same cells, computing their children with h3o every time was a noticeable part of the build.
+    children: HashMap<CellIndex, Arc<[CellIndex]>>,
 }
 
+impl WriteBatch {
+    /// Return the value of the batch if the cell was modified, or the value in the database otherwise.
+    fn get(&self, rtxn: &RoTxn, db: CellDb, key: Key) -> Result<Option<RoaringBitmap>> {
+        match self.entries.get(&key.parts()) {
+            Some(bitmap) => Ok(Some(bitmap.clone())),
+            None => Ok(db.get(rtxn, &key)?),
+        }
+    }
+
+    fn put(&mut self, key: Key, bitmap: RoaringBitmap) {
+        self.entries.insert(key.parts(), bitmap);
+    }
+
+    fn dispatch(&mut self, cell: CellIndex, items: &RoaringBitmap) {
+        *self.dispatched.entry(cell).or_default() |= items;
+    }
+
+    /// Return the children of the cell computed by [`get_children_cells`], only once per batch.
+    fn children_of(&mut self, cell: CellIndex) -> Option<Arc<[CellIndex]>> {
+        if let Some(children) = self.children.get(&cell) {
+            return Some(children.clone());
+        }
+        let children: Arc<[CellIndex]> = get_children_cells(cell)?.into();
+        self.children.insert(cell, children.clone());
+        Some(children)
+    }
+
+    /// Since every entry contains the items of the database we can simply union them.
+    fn merge(&mut self, other: WriteBatch) {
+        for (key, bitmap) in other.entries {
+            *self.entries.entry(key).or_default() |= bitmap;
+        }
+        for (cell, items) in other.dispatched {
+            *self.dispatched.entry(cell).or_default() |= items;
+        }
+        self.children.extend(other.children);
+    }
+}
+
+/// The inserted items, retrieved once and shared between the threads inserting them at level zero.
+/// The other items are read from the database when a cell containing them is split.
+#[derive(Default)]
 struct FrozenItems<'a> {
     items: IntMap<ItemId, Zerometry<'a>>,
 }
@@ -560,3 +1356,13 @@ impl<'a> FrozenItems<'a> {
         self.items.get(item).copied()
     }
 }
+
+/// Returned by [`Cellulite::build_with_cell_budget`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildStatus {
+    /// All the pending updates were built.
+    Finished,
+    /// The budget of cells was reached before the end of the build, call
+    /// [`Cellulite::build_with_cell_budget`] again to build the remaining updates.
+    Pending { pending_updates: u64 },
+}
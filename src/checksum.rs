@@ -0,0 +1,194 @@
+use std::{borrow::Cow, fmt, marker::PhantomData};
+
+use heed::{
+    BoxedError, Database, RoTxn, RwTxn,
+    types::{Bytes, DecodeIgnore, Unit},
+};
+
+use crate::{
+    Cellulite, Error, Result, keys::MetadataKey, metadata::Version,
+    zerometry::InvalidZerometryBytes,
+};
+
+/// The size of the checksum appended to the values.
+/// The checksum is a crc32 but it's stored on 8 bytes to keep the values aligned on 64 bits.
+const CHECKSUM_SIZE: usize = size_of::<u64>();
+
+/// Codec appending a checksum to the values encoded by `C` when the `checksum` feature is enabled.
+/// The checksum is verified before decoding the value, if it doesn't match a [`ChecksumMismatch`] is returned.
+/// Without the feature, the values are encoded and decoded by `C` as-is.
+pub struct Checksummed<C>(PhantomData<C>);
+
+impl<'a, C: heed::BytesEncode<'a>> heed::BytesEncode<'a> for Checksummed<C> {
+    type EItem = C::EItem;
+
+    fn bytes_encode(item: &'a Self::EItem) -> Result<Cow<'a, [u8]>, BoxedError> {
+        if !cfg!(feature = "checksum") {
+            return C::bytes_encode(item);
+        }
+        let mut bytes = C::bytes_encode(item)?.into_owned();
+        let checksum = crc32fast::hash(&bytes) as u64;
+        bytes.extend_from_slice(&checksum.to_be_bytes());
+        Ok(Cow::Owned(bytes))
+    }
+}
+
+impl<'a, C: heed::BytesDecode<'a>> heed::BytesDecode<'a> for Checksummed<C> {
+    type DItem = C::DItem;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Result<Self::DItem, BoxedError> {
+        if !cfg!(feature = "checksum") {
+            return C::bytes_decode(bytes);
+        }
+        let Some((value, checksum)) = bytes.split_last_chunk::<CHECKSUM_SIZE>() else {
+            let computed = crc32fast::hash(bytes);
+            return Err(ChecksumMismatch {
+                expected: None,
+                computed,
+            }
+            .into());
+        };
+        let expected = u64::from_be_bytes(*checksum);
+        let computed = crc32fast::hash(value);
+        if expected != computed as u64 {
+            return Err(ChecksumMismatch {
+                expected: Some(expected),
+                computed,
+            }
+            .into());
+        }
+        C::bytes_decode(value)
+    }
+}
+
+/// Return the value without its checksum and without verifying it, to peek at a small part of a
+/// large value. The value is returned as-is if it's too short to contain a checksum or if the
+/// `checksum` feature is disabled.
+pub(crate) fn without_checksum(bytes: &[u8]) -> &[u8] {
+    if !cfg!(feature = "checksum") {
+        return bytes;
+    }
+    bytes
+        .split_last_chunk::<CHECKSUM_SIZE>()
+        .map_or(bytes, |(value, _)| value)
+}
+
+/// Returned when the checksum of a value doesn't match its content.
+#[derive(Debug, thiserror::Error)]
+pub struct ChecksumMismatch {
+    /// The checksum stored with the value, `None` if the value was too short to contain one.
+    pub expected: Option<u64>,
+    /// The checksum of the content of the value.
+    pub computed: u32,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expected {
+            Some(expected) => write!(
+                f,
+                "Checksum mismatch, expected {expected:#x} but the value has a checksum of {:#x}",
+                self.computed
+            ),
+            None => write!(f, "The value is too short to contain a checksum"),
+        }
+    }
+}
+
+/// Convert the decoding errors caused by a checksum mismatch or an unreadable shape into an
+/// [`Error::Corruption`] of `key`.
+pub(crate) fn corruption(key: impl fmt::Display) -> impl FnOnce(heed::Error) -> Error {
+    move |error| match error {
+        heed::Error::Decoding(e)
+            if e.is::<ChecksumMismatch>() || e.is::<InvalidZerometryBytes>() =>
+        {
+            Error::Corruption(key.to_string(), e.to_string())
+        }
+        error => Error::from(error),
+    }
+}
+
+impl Cellulite {
+    /// Tell if the values of the database are stored with a checksum.
+    pub(crate) fn values_checksummed(&self, rtxn: &RoTxn) -> heed::Result<bool> {
+        Ok(self
+            .metadata
+            .remap_data_type::<DecodeIgnore>()
+            .get(rtxn, &MetadataKey::Checksum)?
+            .is_some())
+    }
+
+    /// Store the values of an empty database the way this build of cellulite reads them.
+    pub(crate) fn init_checksums(&self, wtxn: &mut RwTxn) -> heed::Result<()> {
+        let empty =
+            self.item.is_empty(wtxn)? && self.cell.is_empty(wtxn)? && self.update.is_empty(wtxn)?;
+        if !empty {
+            return Ok(());
+        }
+        self.set_values_checksummed(wtxn, cfg!(feature = "checksum"))
+    }
+
+    /// Mark the values of the database as stored with or without a checksum. The key is required
+    /// to read the database: the versions of cellulite that don't know it must not read the values.
+    pub(crate) fn set_values_checksummed(
+        &self,
+        wtxn: &mut RwTxn,
+        checksummed: bool,
+    ) -> heed::Result<()> {
+        if checksummed {
+            self.metadata
+                .remap_data_type::<Unit>()
+                .put(wtxn, &MetadataKey::Checksum, &())
+        } else {
+            self.metadata.delete(wtxn, &MetadataKey::Checksum).map(drop)
+        }
+    }
+
+    /// Make sure this build of cellulite reads the values the way they're stored. The databases
+    /// created by an older version are checked once upgraded.
+    pub(crate) fn check_checksums(&self, rtxn: &RoTxn) -> Result<()> {
+        if self.get_version(rtxn)? != Version::default() {
+            return Ok(());
+        }
+        match (self.values_checksummed(rtxn)?, cfg!(feature = "checksum")) {
+            (true, false) => Err(Error::MissingChecksumFeature),
+            (false, true) => Err(Error::UnexpectedChecksumFeature),
+            _ => Ok(()),
+        }
+    }
+
+    /// Return a crc32 of the content of all the databases of cellulite, to check that a replica or
+    /// a backup is identical to the original database without comparing their dumps.
+    /// The entries are hashed one by one in key order, the digest only depends on their content
+    /// and not on the layout of the LMDB pages. It's not a cryptographic hash.
+    pub fn checksum(&self, rtxn: &RoTxn) -> Result<u32> {
+        let mut hasher = crc32fast::Hasher::new();
+        hash_database(&mut hasher, rtxn, self.item)?;
+        hash_database(&mut hasher, rtxn, self.cell)?;
+        hash_database(&mut hasher, rtxn, self.update)?;
+        hash_database(&mut hasher, rtxn, self.metadata)?;
+        hash_database(&mut hasher, rtxn, self.expiration)?;
+        hash_database(&mut hasher, rtxn, self.weight)?;
+        hash_database(&mut hasher, rtxn, self.namespace)?;
+        hash_database(&mut hasher, rtxn, self.extent)?;
+        Ok(hasher.finalize())
+    }
+}
+
+fn hash_database<KC, DC>(
+    hasher: &mut crc32fast::Hasher,
+    rtxn: &RoTxn,
+    database: Database<KC, DC>,
+) -> heed::Result<()> {
+    let database = database.remap_types::<Bytes, Bytes>();
+    hasher.update(&database.len(rtxn)?.to_be_bytes());
+    for ret in database.iter(rtxn)? {
+        let (key, value) = ret?;
+        // The lengths are hashed so the boundaries between the keys and values are not ambiguous
+        hasher.update(&(key.len() as u64).to_be_bytes());
+        hasher.update(key);
+        hasher.update(&(value.len() as u64).to_be_bytes());
+        hasher.update(value);
+    }
+    Ok(())
+}
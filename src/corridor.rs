@@ -0,0 +1,102 @@
+use geo::{
+    Bearing, BooleanOps, Centroid, Closest, ClosestPoint, Destination, Distance, LineString,
+    MultiPolygon, Point, Polygon,
+};
+use heed::RoTxn;
+use roaring::RoaringBitmap;
+
+use crate::{Cellulite, Error, ItemId, Result, pos};
+
+impl Cellulite {
+    /// Return all the items within `width` meters of the route, according to the
+    /// [`Self::distance_model`]. The corridor is made of a rectangle on each segment of the route,
+    /// and a circle approximated by a polygon on each of its vertices.
+    pub fn in_corridor(
+        &self,
+        rtxn: &RoTxn,
+        route: &LineString,
+        width: f64,
+    ) -> Result<RoaringBitmap> {
+        self.in_geometry(rtxn, self.corridor(route, width))
+    }
+
+    /// Return the items within `width` meters of the route like [`Self::in_corridor`], along with
+    /// their position on the route in meters, ordered from the start to the end of the route.
+    /// The position of an item is the distance from the start of the route to the projection of
+    /// its centroid on the route.
+    pub fn in_corridor_along_route(
+        &self,
+        rtxn: &RoTxn,
+        route: &LineString,
+        width: f64,
+    ) -> Result<Vec<(ItemId, f64)>> {
+        let items = self.in_corridor(rtxn, route, width)?;
+        let mut ret = Vec::with_capacity(items.len() as usize);
+        for item in items {
+            let shape = self
+                .item(rtxn, item)?
+                .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+            // Only happens on empty shapes, they're at the start of the route
+            let position = match shape.to_geo().centroid() {
+                Some(centroid) => self.position_along_route(route, centroid),
+                None => 0.0,
+            };
+            ret.push((item, position));
+        }
+        ret.sort_by(|(_, left), (_, right)| left.total_cmp(right));
+        Ok(ret)
+    }
+
+    fn corridor(&self, route: &LineString, width: f64) -> MultiPolygon {
+        const NB_POINTS: usize = 16;
+
+        let model = self.distance_model;
+        let mut corridor = MultiPolygon::new(Vec::new());
+        for line in route.lines() {
+            let (start, end) = (Point::from(line.start), Point::from(line.end));
+            let bearing = model.bearing(start, end);
+            let rectangle = Polygon::new(
+                vec![
+                    model.destination(start, bearing - 90.0, width),
+                    model.destination(end, bearing - 90.0, width),
+                    model.destination(end, bearing + 90.0, width),
+                    model.destination(start, bearing + 90.0, width),
+                ]
+                .into(),
+                Vec::new(),
+            );
+            corridor = corridor.union(&rectangle);
+        }
+        // Round the turns of the route
+        for point in route.points() {
+            let circle = (0..NB_POINTS)
+                .map(|i| model.destination(point, 360.0 * i as f64 / NB_POINTS as f64, width))
+                .collect::<Vec<_>>();
+            corridor = corridor.union(&Polygon::new(circle.into(), Vec::new()));
+        }
+        corridor
+    }
+
+    /// Return the distance in meters from the start of the route to the closest point of the
+    /// route to `point`.
+    fn position_along_route(&self, route: &LineString, point: Point) -> f64 {
+        let model = self.distance_model;
+        let mut travelled = 0.0;
+        let mut closest = f64::INFINITY;
+        let mut ret = 0.0;
+        for line in route.lines() {
+            let start = Point::from(line.start);
+            let projection = match line.closest_point(&point) {
+                Closest::Intersection(projection) | Closest::SinglePoint(projection) => projection,
+                Closest::Indeterminate => start,
+            };
+            let distance = model.distance(point, projection);
+            if distance < closest {
+                closest = distance;
+                ret = travelled + model.distance(start, projection);
+            }
+            travelled += model.distance(start, Point::from(line.end));
+        }
+        ret
+    }
+}
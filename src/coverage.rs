@@ -0,0 +1,123 @@
+use std::collections::BTreeSet;
+
+use geo::{Orient, Polygon, orient::Direction};
+use h3o::{
+    CellIndex, Resolution,
+    geom::{ContainmentMode, TilerBuilder},
+};
+use heed::RoTxn;
+
+use crate::{Cellulite, Result};
+
+impl Cellulite {
+    /// Return the H3 cells covering the `polygon` at `resolution`, compacted with
+    /// [`CellIndex::compact`]: every group of sibling cells is replaced by their parent. The
+    /// polygon is oriented and densified like the queries do, but no item is read. Useful to push
+    /// the covering of a query in a store that speaks H3.
+    ///
+    /// It's the standard H3 covering of the polygon, not the cells visited by the reader: the
+    /// queries follow the tree of the database, whose sub-cells overlap with their neighbours and
+    /// depend on the items stored. See [`Self::compact_cells`] to compact cells following the tree.
+    /// The resolution is capped to the maximum resolution of the database since the queries never
+    /// go deeper.
+    pub fn coverage_of_shape(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+        resolution: Resolution,
+    ) -> Result<Vec<CellIndex>> {
+        let resolution = resolution.min(self.max_resolution(rtxn)?);
+        let polygon = self.distance_model.densify(
+            &polygon.orient(Direction::Default),
+            self.options.densify_distance,
+        );
+        let mut tiler = TilerBuilder::new(resolution)
+            .containment_mode(ContainmentMode::Covers)
+            .build();
+        tiler.add(polygon)?;
+        // safe to unwrap because the tiler returns every cell once, at the same resolution
+        let mut cells: Vec<_> = CellIndex::compact(tiler.into_coverage()).unwrap().collect();
+        cells.sort_unstable();
+        Ok(cells)
+    }
+
+    /// Compact a set of cells following the tree of the database: a cell stored in the database
+    /// replaces its sub-cells once all of them are in the set, from the deepest resolution up to
+    /// the resolution zero. The cells are returned in order.
+    ///
+    /// Unlike the generic H3 compaction, only the sub-cells actually stored count. The sub-cells
+    /// of a cell overlap with its neighbours, it compacts the tree, not the area covered by the
+    /// cells: a parent contains every item of its sub-cells but not their whole area.
+    pub fn compact_cells(
+        &self,
+        rtxn: &RoTxn,
+        cells: impl IntoIterator<Item = CellIndex>,
+    ) -> Result<Vec<CellIndex>> {
+        let mut cells: BTreeSet<CellIndex> = cells.into_iter().collect();
+        let deepest = cells.iter().map(|cell| cell.resolution()).max();
+        let Some(deepest) = deepest else {
+            return Ok(Vec::new());
+        };
+        for resolution in Resolution::range(Resolution::One, deepest).rev() {
+            // safe to unwrap because the resolution is never zero
+            let parent_resolution = resolution.pred().unwrap();
+            // The sub-cells are around the center child of their parent, so their parent is the
+            // H3 parent or one of its neighbours
+            let candidates: BTreeSet<CellIndex> = cells
+                .iter()
+                .filter(|cell| cell.resolution() == resolution)
+                // safe to unwrap because the parent is shallower than the cell
+                .flat_map(|cell| {
+                    cell.parent(parent_resolution)
+                        .unwrap()
+                        .grid_disk::<Vec<_>>(1)
+                })
+                .collect();
+            // The merges are decided before updating the set, the sub-cells may be shared
+            let mut merged = Vec::new();
+            for candidate in candidates {
+                let children = self.cell_cursor(rtxn, candidate)?.children()?;
+                let complete = children.iter().all(|child| cells.contains(&child.cell()));
+                if !children.is_empty() && complete {
+                    merged.push((candidate, children));
+                }
+            }
+            for (parent, children) in merged {
+                for child in children {
+                    cells.remove(&child.cell());
+                }
+                cells.insert(parent);
+            }
+        }
+        Ok(cells.into_iter().collect())
+    }
+
+    /// Replace every cell by its descendants stored in the database at `resolution`. The leaves of
+    /// the tree shallower than `resolution` are kept as-is since there is nothing below them, like
+    /// the cells already at or below `resolution`. The cells are returned in order.
+    pub fn uncompact_cells(
+        &self,
+        rtxn: &RoTxn,
+        cells: impl IntoIterator<Item = CellIndex>,
+        resolution: Resolution,
+    ) -> Result<Vec<CellIndex>> {
+        let mut ret = BTreeSet::new();
+        let mut stack = Vec::new();
+        for cell in cells {
+            stack.push(self.cell_cursor(rtxn, cell)?);
+            while let Some(cursor) = stack.pop() {
+                let children = if cursor.cell().resolution() < resolution {
+                    cursor.children()?
+                } else {
+                    Vec::new()
+                };
+                if children.is_empty() {
+                    ret.insert(cursor.cell());
+                } else {
+                    stack.extend(children);
+                }
+            }
+        }
+        Ok(ret.into_iter().collect())
+    }
+}
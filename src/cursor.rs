@@ -0,0 +1,122 @@
+use h3o::{CellIndex, Resolution};
+use heed::{BytesDecode, RoTxn};
+use roaring::RoaringBitmap;
+
+use crate::{
+    CellDb, Cellulite, Result, keys::retrieve_raw_cell_and_belly, roaring::RoaringBitmapCodec,
+};
+
+/// A cell of the database, to implement custom traversals of the tree of cells.
+/// Returned by [`Cellulite::cell_cursor`] and [`Cellulite::root_cursors`].
+///
+/// The bitmaps are borrowed from the transaction, they're only decoded on demand.
+pub struct CellCursor<'t> {
+    rtxn: &'t RoTxn<'t>,
+    db: CellDb,
+    threshold: u64,
+    max_resolution: Resolution,
+    cell: CellIndex,
+    items: Option<&'t [u8]>,
+    belly_items: Option<&'t [u8]>,
+}
+
+impl Cellulite {
+    /// Open a cursor on a cell, whether it's stored in the database or not.
+    pub fn cell_cursor<'t>(&self, rtxn: &'t RoTxn, cell: CellIndex) -> Result<CellCursor<'t>> {
+        let (items, belly_items) = retrieve_raw_cell_and_belly(rtxn, &self.cell, cell)?;
+        Ok(CellCursor {
+            rtxn,
+            db: self.cell,
+            threshold: self.options.threshold,
+            max_resolution: self.max_resolution(rtxn)?,
+            cell,
+            items,
+            belly_items,
+        })
+    }
+
+    /// Open a cursor on every base cell stored in the database, the roots of the tree.
+    pub fn root_cursors<'t>(&self, rtxn: &'t RoTxn) -> Result<Vec<CellCursor<'t>>> {
+        let mut ret = Vec::new();
+        for cell in CellIndex::base_cells() {
+            let cursor = self.cell_cursor(rtxn, cell)?;
+            if cursor.exists() {
+                ret.push(cursor);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl<'t> CellCursor<'t> {
+    pub fn cell(&self) -> CellIndex {
+        self.cell
+    }
+
+    /// Return `true` if the cell or its belly cell is stored in the database.
+    pub fn exists(&self) -> bool {
+        self.items.is_some() || self.belly_items.is_some()
+    }
+
+    /// Return the bitmap of the items intersecting the cell serialized with the portable format
+    /// of roaring, followed by a few bytes of padding. It's directly borrowed from the database.
+    pub fn raw_items(&self) -> Option<&'t [u8]> {
+        self.items
+    }
+
+    /// Return the bitmap of the items covering the whole cell, serialized like
+    /// [`Self::raw_items`].
+    pub fn raw_belly_items(&self) -> Option<&'t [u8]> {
+        self.belly_items
+    }
+
+    /// Return the items intersecting the cell, they're not filtered by the tombstones.
+    pub fn items(&self) -> Result<RoaringBitmap> {
+        decode(self.items)
+    }
+
+    /// Return the items covering the whole cell, they're stored in the belly cell instead of the
+    /// sub-cells.
+    pub fn belly_items(&self) -> Result<RoaringBitmap> {
+        decode(self.belly_items)
+    }
+
+    /// Return `true` if the cell is not split, its items must be double-checked by the queries.
+    pub fn is_leaf(&self) -> Result<bool> {
+        Ok(self.items()?.len() < self.threshold || self.cell.resolution() >= self.max_resolution)
+    }
+
+    /// Open a cursor on the sub-cells of the cell stored in the database, they're empty if the
+    /// cell is a leaf. Like during the build, the sub-cells are the cells of the next resolution
+    /// around the center child of the cell, they overlap with the sub-cells of the neighbours.
+    pub fn children(&self) -> Result<Vec<CellCursor<'t>>> {
+        let Some(next_res) = self.cell.resolution().succ() else {
+            return Ok(Vec::new());
+        };
+        if self.is_leaf()? {
+            return Ok(Vec::new());
+        }
+        // safe to unwrap because we just increased the resolution
+        let center_child = self.cell.center_child(next_res).unwrap();
+        let mut ret = Vec::new();
+        for cell in center_child.grid_disk::<Vec<_>>(2) {
+            let (items, belly_items) = retrieve_raw_cell_and_belly(self.rtxn, &self.db, cell)?;
+            if items.is_some() || belly_items.is_some() {
+                ret.push(CellCursor {
+                    cell,
+                    items,
+                    belly_items,
+                    ..*self
+                });
+            }
+        }
+        Ok(ret)
+    }
+}
+
+fn decode(bytes: Option<&[u8]>) -> Result<RoaringBitmap> {
+    match bytes {
+        Some(bytes) => Ok(RoaringBitmapCodec::bytes_decode(bytes).map_err(heed::Error::Decoding)?),
+        None => Ok(RoaringBitmap::new()),
+    }
+}
@@ -0,0 +1,121 @@
+use std::{cmp::Ordering, iter::Peekable};
+
+use h3o::CellIndex;
+use heed::{RoTxn, types::Bytes};
+use roaring::RoaringBitmap;
+
+use crate::{
+    Cellulite, Result,
+    keys::{CellKeyCodec, Key, KeyVariant, cell_to_locality_key},
+};
+
+/// The differences between two cellulite databases, returned by [`Cellulite::diff`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Diff {
+    /// The items only present in the other database.
+    pub added: RoaringBitmap,
+    /// The items only present in the current database.
+    pub removed: RoaringBitmap,
+    /// The items present in both databases but with a different shape.
+    pub modified: RoaringBitmap,
+    /// The cells whose items changed, sorted and deduplicated.
+    /// Both the normal and belly cells are included.
+    pub changed_cells: Vec<CellIndex>,
+}
+
+impl Cellulite {
+    /// Compare the current database, seen by `rtxn`, with `other`, seen by `other_rtxn`.
+    /// The other database is considered as the most recent one: the items it contains and we don't are `added`.
+    ///
+    /// Both databases are read in key order at the same time, the shapes are compared byte per byte.
+    /// The pending updates are ignored, you should build both databases before diffing them.
+    pub fn diff(&self, rtxn: &RoTxn, other: &Cellulite, other_rtxn: &RoTxn) -> Result<Diff> {
+        let mut diff = Diff::default();
+
+        let left = self.item.remap_data_type::<Bytes>().iter(rtxn)?;
+        let right = other.item.remap_data_type::<Bytes>().iter(other_rtxn)?;
+        merge_join(left, right, |joined| match joined {
+            Joined::Left(item, _) => {
+                diff.removed.insert(item);
+            }
+            Joined::Right(item, _) => {
+                diff.added.insert(item);
+            }
+            Joined::Both(item, left, right) if left != right => {
+                diff.modified.insert(item);
+            }
+            Joined::Both(..) => (),
+        })?;
+
+        let left = self.cell.remap_key_type::<CellKeyCodec>().iter(rtxn)?;
+        let right = other
+            .cell
+            .remap_key_type::<CellKeyCodec>()
+            .iter(other_rtxn)?;
+        let left = left.map(|ret| ret.map(|(key, bitmap)| (db_order(key), bitmap)));
+        let right = right.map(|ret| ret.map(|(key, bitmap)| (db_order(key), bitmap)));
+        merge_join(left, right, |joined| match joined {
+            Joined::Left((_, _, cell), _) | Joined::Right((_, _, cell), _) => {
+                diff.changed_cells.push(cell)
+            }
+            Joined::Both((_, _, cell), left, right) if left != right => {
+                diff.changed_cells.push(cell)
+            }
+            Joined::Both(..) => (),
+        })?;
+        // The cells are ordered by locality in the database
+        diff.changed_cells.sort_unstable();
+        diff.changed_cells.dedup();
+
+        Ok(diff)
+    }
+}
+
+/// Return the key in the same order as the database, with its cell.
+fn db_order(key: Key) -> (u64, KeyVariant, CellIndex) {
+    let (cell, variant) = key.parts();
+    (cell_to_locality_key(cell), variant, cell)
+}
+
+enum Joined<K, V> {
+    Left(K, V),
+    Right(K, V),
+    Both(K, V, V),
+}
+
+/// Walk two iterators sorted by key at the same time and call `f` on each key with the values it's associated with.
+fn merge_join<K: Ord, V>(
+    left: impl Iterator<Item = heed::Result<(K, V)>>,
+    right: impl Iterator<Item = heed::Result<(K, V)>>,
+    mut f: impl FnMut(Joined<K, V>),
+) -> Result<()> {
+    let mut left: Peekable<_> = left.peekable();
+    let mut right: Peekable<_> = right.peekable();
+
+    loop {
+        let ordering = match (left.peek(), right.peek()) {
+            (None, None) => return Ok(()),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(Ok((l, _))), Some(Ok((r, _)))) => l.cmp(r),
+            // Let the error be returned below
+            (Some(Err(_)), _) => Ordering::Less,
+            (_, Some(Err(_))) => Ordering::Greater,
+        };
+        match ordering {
+            Ordering::Less => {
+                let (key, value) = left.next().unwrap()?;
+                f(Joined::Left(key, value));
+            }
+            Ordering::Greater => {
+                let (key, value) = right.next().unwrap()?;
+                f(Joined::Right(key, value));
+            }
+            Ordering::Equal => {
+                let (key, l) = left.next().unwrap()?;
+                let (_, r) = right.next().unwrap()?;
+                f(Joined::Both(key, l, r));
+            }
+        }
+    }
+}
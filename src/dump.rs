@@ -0,0 +1,53 @@
+use std::fmt::Write;
+
+use h3o::LatLng;
+use heed::RoTxn;
+
+use crate::{Cellulite, Result, keys::Key};
+
+impl Cellulite {
+    /// Render the version, the items, the cells and the belly cells of the database as text.
+    /// The output is deterministic, which makes it suitable for snapshot testing.
+    ///
+    /// The cells are sorted by index and displayed with their resolution and center.
+    pub fn debug_dump(&self, rtxn: &RoTxn) -> Result<String> {
+        let mut s = String::new();
+
+        writeln!(s, "# Version: {}", self.get_version(rtxn)?).unwrap();
+        s.push_str("# Items\n");
+        for ret in self.item.iter(rtxn)? {
+            let (key, value) = ret?;
+            writeln!(s, "{key}: {value:?}").unwrap();
+        }
+
+        let mut cells = Vec::new();
+        let mut belly = Vec::new();
+        for ret in self.cell.iter(rtxn)? {
+            let (key, value) = ret?;
+            match key {
+                Key::Cell(cell_index) => cells.push((cell_index, value)),
+                Key::Belly(cell_index) => belly.push((cell_index, value)),
+            }
+        }
+        // The cells are stored by locality, we sort them by index to keep the output readable
+        cells.sort_unstable_by_key(|(cell, _)| *cell);
+        belly.sort_unstable_by_key(|(cell, _)| *cell);
+
+        for (title, cells) in [("# Cells", cells), ("# Belly Cells", belly)] {
+            s.push_str(title);
+            s.push('\n');
+            for (cell, bitmap) in cells {
+                let lat_lng = LatLng::from(cell);
+                let (lat, lng) = (lat_lng.lat(), lat_lng.lng());
+                let res = cell.resolution();
+                writeln!(
+                    s,
+                    "Cell {{ res: {res}, center: ({lat:.4}, {lng:.4}) }}: {bitmap:?}"
+                )
+                .unwrap();
+            }
+        }
+
+        Ok(s)
+    }
+}
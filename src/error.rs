@@ -1,35 +1,200 @@
-use h3o::error::{InvalidGeometry, PlotterError};
+use h3o::{
+    CellIndex, Resolution,
+    error::{InvalidCellIndex, InvalidGeometry, InvalidLatLng, InvalidResolution, PlotterError},
+};
 
-use crate::{ItemId, metadata::Version};
+use crate::{BuildSteps, ItemId, metadata::Version};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     // User errors
-    #[error("The build was canceled")]
-    BuildCanceled,
+    #[error(
+        "The build was canceled{}",
+        .0.as_ref().map_or(String::new(), |canceled| format!(" {canceled}"))
+    )]
+    BuildCanceled(Option<CanceledBuild>),
     #[error(
         "Version mismatch while building, was expecting v{} but instead got v{}. Upgrade the version before building.",
         Version::default(), .0
     )]
     VersionMismatchOnBuild(Version),
+    #[error(
+        "A build in multiple transactions was interrupted after building {0} updates. Call `Cellulite::recover` to finish it before building again."
+    )]
+    InterruptedBuild(u64),
     #[error(
         "Tried to open a cellulite database, but it's inner database don't exists yet. Call `create_from_env` first."
     )]
     DatabaseDoesntExists,
+    #[error("The item `{0}` doesn't exists in the database.")]
+    ItemDoesntExists(ItemId),
+    #[error("The item `{0}` has an empty geometry, it must contain at least one coordinate.")]
+    EmptyGeometry(ItemId),
+    #[error("The item `{0}` contains an invalid polygon: {1}.")]
+    InvalidPolygon(ItemId, String),
+    #[error("The item `{0}` contains an invalid coordinate: {1}.")]
+    InvalidItemCoordinate(ItemId, InvalidLatLng),
+    #[error("The item `{0}` is not a valid zerometry: {1}.")]
+    InvalidZerometry(ItemId, String),
+    #[error("The fragment size must be a positive number of degrees, got {0}.")]
+    InvalidFragmentSize(f64),
+    #[error(
+        "Cannot increase the maximum resolution of the database from {0} to {1}. Clear the database and build it again instead."
+    )]
+    CannotIncreaseMaxResolution(Resolution, Resolution),
+    #[error(
+        "Cannot decrease the threshold of the database from {0} to {1}. Clear the database and build it again instead."
+    )]
+    CannotDecreaseThreshold(u64, u64),
+    #[error(
+        "Cannot upgrade the database from v{0} to v{}. Only the databases created by an older version can be upgraded.",
+        Version::default()
+    )]
+    CannotUpgradeFromVersion(Version),
+    #[error("The upgrade was canceled, the database must be upgraded before being used.")]
+    UpgradeCanceled,
+    #[error(
+        "The database was written by a newer version of cellulite, the metadata `{0}` is required to read it. Upgrade cellulite to open it."
+    )]
+    UnknownRequiredMetadata(u8),
+    #[error(
+        "The values of the database are stored with a checksum, enable the `checksum` feature of cellulite to read them."
+    )]
+    MissingChecksumFeature,
+    #[error(
+        "The values of the database are stored without a checksum, disable the `checksum` feature of cellulite to read them."
+    )]
+    UnexpectedChecksumFeature,
+    #[error("The database is corrupted, the value of {0} is invalid: {1}")]
+    Corruption(String, String),
+    #[error("The copy of the database cannot be used as a replica because {0}.")]
+    InvalidReplica(String),
+    #[error("The update log cannot be applied because {0}.")]
+    InvalidUpdateLog(String),
+    #[error("A sharded database needs at least one environment.")]
+    NoShards,
+    #[error("The shard `{0}` doesn't exists, there are only {1} shards.")]
+    ShardDoesntExists(usize, usize),
+    #[error("The item `{0}` shifted by the id offset {1} of its shard doesn't fit in an item id.")]
+    ItemIdOverflow(ItemId, ItemId),
+    #[error(
+        "The LMDB environment is full, the cellulite databases were using {0} bytes and the build needed about {1} more. Increase the `map_size` of the environment, `Cellulite::estimated_size_for` can help you choose it."
+    )]
+    MapFull(u64, u64),
+    #[error(
+        "The cell {0} cannot be split because it's at the maximum resolution but contains {1} items. They're probably at the same place, remove the duplicates or allow the oversized leaves in the options."
+    )]
+    OversizedLeaf(CellIndex, u64),
+    #[error(
+        "The extent index is missing, the database was created by an older version of cellulite. Reindex the database to build it."
+    )]
+    MissingExtentIndex,
+    #[error(
+        "Another writer already opened the cellulite database `{0}`, in this process or another one. Only one writer can open it at a time."
+    )]
+    WriterAlreadyOpened(String),
+    #[error(
+        "The writer thread of the queue stopped after a panic of the batch callback, the operations cannot be queued anymore."
+    )]
+    WriterQueueStopped,
+    #[error(
+        "The options of this handle are outdated, another handle changed the ones stored in the database. Call `Cellulite::reload_options` before building or clearing it."
+    )]
+    StaleOptions,
+    #[error("The environment cannot be written by a process while others read it because {0}.")]
+    InvalidEnvFlags(&'static str),
+    #[error(
+        "The query exceeded its working set limit {0}. Narrow the shape or raise the limit in the query options."
+    )]
+    QueryTooLarge(WorkingSet),
+    #[cfg(feature = "proj")]
+    #[error("Cannot reproject the item `{0}` from `{1}` to WGS84: {2}.")]
+    CannotReproject(ItemId, String, String),
 
     // External errors, sometimes it's a user error and sometimes it's not
     #[error(transparent)]
     Heed(#[from] heed::Error),
     #[error(transparent)]
     InvalidGeometry(#[from] InvalidGeometry),
+    #[cfg(feature = "geojson")]
     #[error(transparent)]
     InvalidGeoJson(#[from] Box<geojson::Error>),
+    #[error(transparent)]
+    InvalidResolution(#[from] InvalidResolution),
+    #[error(transparent)]
+    InvalidLatLng(#[from] InvalidLatLng),
+    #[error(transparent)]
+    InvalidCellIndex(#[from] InvalidCellIndex),
 
     // Internal errors
     #[error("unexpected document id `{0}` missing at `{1}`")]
     InternalDocIdMissing(ItemId, String),
     #[error("Error with document `{0}`, could not convert it's line(s) to cells because: {1}\n{2}")]
     CannotConvertLineToCell(ItemId, PlotterError, String),
+    #[error("The build panicked while processing the item `{0}`: {1}")]
+    BuildPanicked(ItemId, String),
+    #[error("The writer queue panicked while applying a batch, the batch was rolled back: {0}")]
+    WriterQueuePanicked(String),
+}
+
+/// Return the message of a panic caught with [`std::panic::catch_unwind`].
+pub(crate) fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic payload".to_string(),
+        },
+    }
+}
+
+/// Where a build was when it got canceled, to decide whether to resume, retry or roll it back.
+/// The write transaction being built is left half-written and must be aborted, its updates are
+/// still pending afterward. Only the transactions already committed by
+/// [`crate::Cellulite::build_in_multiple_transactions`] are kept, calling it again resumes the
+/// build.
+#[derive(Debug, Clone, Copy)]
+pub struct CanceledBuild {
+    /// The step the build was running when it got canceled.
+    pub step: BuildSteps,
+    /// The number of inserted and deleted items the canceled transaction was building, or zero
+    /// if the updates were still being retrieved.
+    pub items: u64,
+    /// The number of updates built by the transactions committed before the cancellation.
+    pub committed_updates: u64,
+}
+
+impl std::fmt::Display for CanceledBuild {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "at the step {:?} while building {} items, {} updates were committed before",
+            self.step, self.items, self.committed_updates
+        )
+    }
+}
+
+/// How far a query went before exceeding its [`crate::reader::WorkingSetLimit`].
+#[derive(Debug, Clone, Copy)]
+pub struct WorkingSet {
+    /// The number of cells explored so far.
+    pub explored_cells: usize,
+    /// The number of cells waiting to be explored.
+    pub queued_cells: usize,
+    /// The number of items waiting to be double-checked.
+    pub double_check: u64,
+    /// The number of items confirmed so far, they're not filtered by the tombstones.
+    pub confirmed: u64,
+}
+
+impl std::fmt::Display for WorkingSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "after exploring {} cells with {} cells queued, {} items to double-check and {} items confirmed",
+            self.explored_cells, self.queued_cells, self.double_check, self.confirmed
+        )
+    }
 }
 
 #[macro_export]
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use geo::{BoundingRect, Polygon, Rect, coord};
+use h3o::{
+    CellIndex, Resolution,
+    error::InvalidGeometry,
+    geom::{ContainmentMode, TilerBuilder},
+};
+use heed::{
+    RoTxn, RwTxn,
+    types::{DecodeIgnore, Unit},
+};
+use roaring::RoaringBitmap;
+
+use crate::{Cellulite, Error, Result, keys::MetadataKey, pos};
+
+/// The resolution of the cells of the extent index. The cells are about 150km wide, coarse enough
+/// to keep the index small and fine enough to skip most of the items far from a query.
+const EXTENT_RESOLUTION: Resolution = Resolution::Two;
+/// The margin in degrees added around the bounding boxes, so the points and the straight lines
+/// have an area and cover at least one cell.
+const MARGIN: f64 = 1e-6;
+/// The tiler considers the edges longer than 180° to cross the antimeridian, the bounding boxes
+/// are cut in strips narrower than that.
+const MAX_STRIP_WIDTH: f64 = 90.0;
+
+impl Cellulite {
+    /// Return the items whose bounding box may intersect the `rect`, without reading their shape.
+    ///
+    /// The bounding boxes are indexed in cells of a fixed, low resolution: the result contains
+    /// every item whose bounding box intersects the `rect`, and some items close to it or that
+    /// were there before being added again. It's a fast pre-filter, use [`Self::in_shape`] to get
+    /// the exact matches.
+    /// Like the other queries, only the built items are returned.
+    ///
+    /// Returns [`Error::MissingExtentIndex`] if the database was created by a version of cellulite
+    /// that didn't maintain the index, call [`Self::reindex`] once to build it.
+    pub fn items_in_extent(&self, rtxn: &RoTxn, rect: Rect) -> Result<RoaringBitmap> {
+        if !self.extent_indexed(rtxn)? {
+            return Err(Error::MissingExtentIndex);
+        }
+        self.items_of_extent_cells(rtxn, rect_coverage(rect)?)
+    }
+
+    /// Return the items whose bounding box may intersect the `polygon`, or `None` if the extent
+    /// index is missing and all the items may match.
+    pub(crate) fn extent_candidates(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+    ) -> Result<Option<RoaringBitmap>> {
+        if !self.extent_indexed(rtxn)? {
+            return Ok(None);
+        }
+        let mut tiler = TilerBuilder::new(EXTENT_RESOLUTION)
+            .containment_mode(ContainmentMode::Covers)
+            .build();
+        tiler.add(polygon.clone())?;
+        self.items_of_extent_cells(rtxn, tiler.into_coverage())
+            .map(Some)
+    }
+
+    fn items_of_extent_cells(
+        &self,
+        rtxn: &RoTxn,
+        cells: impl IntoIterator<Item = CellIndex>,
+    ) -> Result<RoaringBitmap> {
+        let mut items = RoaringBitmap::new();
+        for cell in cells {
+            if let Some(bitmap) = self.extent.get(rtxn, &u64::from(cell))? {
+                items |= bitmap;
+            }
+        }
+        Ok(items)
+    }
+
+    /// Tell if the extent index contains every item of the database. It's only the case if the
+    /// database was empty or reindexed when this version of cellulite opened it.
+    pub(crate) fn extent_indexed(&self, rtxn: &RoTxn) -> heed::Result<bool> {
+        Ok(self
+            .metadata
+            .remap_data_type::<DecodeIgnore>()
+            .get(rtxn, &MetadataKey::ExtentIndex)?
+            .is_some())
+    }
+
+    /// Mark the extent index as complete. The key is required to read the database: the versions
+    /// of cellulite that don't maintain the index must not write in it.
+    pub(crate) fn mark_extent_indexed(&self, wtxn: &mut RwTxn) -> heed::Result<()> {
+        self.metadata
+            .remap_data_type::<Unit>()
+            .put(wtxn, &MetadataKey::ExtentIndex, &())
+    }
+
+    /// Remove the `removed` items from the extent index, then insert the `inserted` items with
+    /// the bounding box of their current shape.
+    pub(crate) fn update_extents(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: impl Fn() -> bool,
+        removed: &RoaringBitmap,
+        inserted: &RoaringBitmap,
+    ) -> Result<()> {
+        if !removed.is_empty() {
+            let mut iter = self.extent.iter_mut(wtxn)?;
+            while let Some(ret) = iter.next() {
+                if cancel() {
+                    return Err(Error::BuildCanceled(None));
+                }
+                let (cell, mut bitmap) = ret?;
+                let len = bitmap.len();
+                bitmap -= removed;
+                if bitmap.len() == len {
+                    continue;
+                }
+                // safe because everything is owned
+                unsafe {
+                    if bitmap.is_empty() {
+                        iter.del_current()?;
+                    } else {
+                        iter.put_current(&cell, &bitmap)?;
+                    }
+                }
+            }
+        }
+
+        let mut cells: BTreeMap<CellIndex, RoaringBitmap> = BTreeMap::new();
+        for item in inserted {
+            if cancel() {
+                return Err(Error::BuildCanceled(None));
+            }
+            let shape = self
+                .item(wtxn, item)?
+                .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+            let Some(rect) = shape.to_geo().bounding_rect() else {
+                continue;
+            };
+            for cell in rect_coverage(rect)? {
+                cells.entry(cell).or_default().insert(item);
+            }
+        }
+        for (cell, items) in cells {
+            let key = u64::from(cell);
+            let mut bitmap = self.extent.get(wtxn, &key)?.unwrap_or_default();
+            bitmap |= items;
+            self.extent.put(wtxn, &key, &bitmap)?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the cells of the extent index covering the `rect`.
+fn rect_coverage(rect: Rect) -> Result<BTreeSet<CellIndex>, InvalidGeometry> {
+    let (min, max) = (rect.min(), rect.max());
+    let (min_y, max_y) = ((min.y - MARGIN).max(-90.0), (max.y + MARGIN).min(90.0));
+    let max_x = (max.x + MARGIN).min(180.0);
+    let mut coverage = BTreeSet::new();
+    let mut x = (min.x - MARGIN).max(-180.0);
+    while x < max_x {
+        let next_x = (x + MAX_STRIP_WIDTH).min(max_x);
+        let strip = Rect::new(coord! { x: x, y: min_y }, coord! { x: next_x, y: max_y });
+        let mut tiler = TilerBuilder::new(EXTENT_RESOLUTION)
+            .containment_mode(ContainmentMode::Covers)
+            .build();
+        tiler.add(strip.to_polygon())?;
+        coverage.extend(tiler.into_coverage());
+        x = next_x;
+    }
+    Ok(coverage)
+}
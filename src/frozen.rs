@@ -0,0 +1,87 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use geo::{Geometry, Point, Polygon};
+use heed::{Env, RoTxn, WithoutTls};
+use roaring::RoaringBitmap;
+
+use crate::{Cellulite, ItemId, Result, reader::QueryOptions};
+
+/// A read-only handle on a committed snapshot of the database, returned by [`Cellulite::freeze`].
+///
+/// It owns its read transaction, it can be shared between threads and moved into a
+/// `spawn_blocking` closure without managing the lifetime of a [`RoTxn`]. The writes committed
+/// after its creation are not visible, freeze the database again to see them.
+///
+/// The read transaction is used by a single query at a time, the queries on the same handle are
+/// serialized. Freeze the database multiple times to run them in parallel.
+pub struct FrozenCellulite {
+    cellulite: Cellulite,
+    rtxn: Mutex<RoTxn<'static, WithoutTls>>,
+}
+
+impl Cellulite {
+    /// Pin a read transaction on the last committed state of the database. The environment must
+    /// be opened with [`heed::EnvOpenOptions::read_txn_without_tls`] so the transaction can be sent
+    /// between threads.
+    ///
+    /// The transaction is kept open as long as the handle lives, the pages it reads cannot be
+    /// reused by the writers in the meantime. Don't keep it for too long if the database is
+    /// updated often.
+    pub fn freeze(&self, env: Env<WithoutTls>) -> Result<FrozenCellulite> {
+        Ok(FrozenCellulite {
+            cellulite: self.clone(),
+            rtxn: Mutex::new(env.static_read_txn()?),
+        })
+    }
+}
+
+impl FrozenCellulite {
+    /// Return the database the snapshot was taken from.
+    pub fn cellulite(&self) -> &Cellulite {
+        &self.cellulite
+    }
+
+    /// Return the shape of an item, see [`Cellulite::item`].
+    pub fn item(&self, item: ItemId) -> Result<Option<Geometry>> {
+        let rtxn = self.rtxn();
+        Ok(self
+            .cellulite
+            .item(&rtxn, item)?
+            .map(|shape| shape.to_geo()))
+    }
+
+    /// See [`Cellulite::in_shape`].
+    pub fn in_shape(&self, polygon: &Polygon) -> Result<RoaringBitmap> {
+        self.cellulite.in_shape(&self.rtxn(), polygon)
+    }
+
+    /// See [`Cellulite::in_shape_with_options`].
+    pub fn in_shape_with_options(
+        &self,
+        polygon: &Polygon,
+        options: QueryOptions,
+    ) -> Result<RoaringBitmap> {
+        self.cellulite
+            .in_shape_with_options(&self.rtxn(), polygon, options)
+    }
+
+    /// See [`Cellulite::items_containing_point`].
+    pub fn items_containing_point(&self, point: Point) -> Result<RoaringBitmap> {
+        self.cellulite.items_containing_point(&self.rtxn(), point)
+    }
+
+    /// See [`Cellulite::classify_points`].
+    pub fn classify_points(&self, points: &[Point]) -> Result<Vec<RoaringBitmap>> {
+        self.cellulite.classify_points(&self.rtxn(), points)
+    }
+
+    /// Run custom queries on the snapshot, the transaction stays locked while `f` runs.
+    pub fn with_rtxn<O>(&self, f: impl FnOnce(&Cellulite, &RoTxn) -> O) -> O {
+        f(&self.cellulite, &self.rtxn())
+    }
+
+    fn rtxn(&self) -> MutexGuard<'_, RoTxn<'static, WithoutTls>> {
+        // A panic during a query cannot leave the read transaction in an invalid state
+        self.rtxn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, HashMap};
+
+use h3o::{CellIndex, Resolution};
+use heed::{DatabaseStat, RoTxn, RwTxn};
+
+use crate::{Cellulite, Result, keys::Key};
+
+/// A summary of the state of the database returned by [`Cellulite::health_report`].
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    /// The LMDB statistics of the item database.
+    pub item_db: DatabaseStat,
+    /// The LMDB statistics of the cell database.
+    pub cell_db: DatabaseStat,
+    /// The number of updates waiting for the next build.
+    pub pending_updates: u64,
+    /// The number of normal and belly cells that don't contain any item.
+    pub empty_bitmaps: u64,
+    /// The number of belly cells none of whose parents in the tree is split anymore. They're never
+    /// read by the queries.
+    pub orphaned_belly_cells: u64,
+    /// The shallowest resolution of a leaf cell, `None` if the database is empty.
+    pub shallowest_leaf: Option<Resolution>,
+    /// The deepest resolution of a leaf cell, `None` if the database is empty.
+    pub deepest_leaf: Option<Resolution>,
+    /// Human-readable explanations of the problems found in the database.
+    pub warnings: Vec<String>,
+}
+
+impl HealthReport {
+    /// The difference of resolution between the deepest and the shallowest leaf cells.
+    pub fn depth_skew(&self) -> u8 {
+        match (self.shallowest_leaf, self.deepest_leaf) {
+            (Some(shallowest), Some(deepest)) => u8::from(deepest) - u8::from(shallowest),
+            _ => 0,
+        }
+    }
+}
+
+/// How the items are spread in the cells of a resolution, returned by
+/// [`Cellulite::resolution_report`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolutionReport {
+    /// The number of cells of the resolution.
+    pub cells: u64,
+    /// The number of items of the emptiest cell.
+    pub min_items: u64,
+    /// The number of items of the cell in the middle once they're sorted, the largest of the two
+    /// middle cells if there is an even number of cells.
+    pub median_items: u64,
+    /// The number of items of the fullest cell.
+    pub max_items: u64,
+    /// The number of cells containing exactly as many items as the threshold. They're the smallest
+    /// cells to be split and wouldn't be with a threshold one higher.
+    pub at_threshold: u64,
+}
+
+impl Cellulite {
+    /// Inspect the whole database to tell if it should be compacted or rebuilt.
+    /// It reads every cell and can take a while on large databases.
+    pub fn health_report(&self, rtxn: &RoTxn) -> Result<HealthReport> {
+        // Above this skew, the queries are slowed down by the deepest parts of the tree
+        const MAX_DEPTH_SKEW: u8 = 8;
+
+        let max_resolution = self.max_resolution(rtxn)?;
+        let mut cells = HashMap::new();
+        let mut bellies = Vec::new();
+        let mut empty_bitmaps = 0;
+        for ret in self.cell.iter(rtxn)? {
+            let (key, bitmap) = ret?;
+            if bitmap.is_empty() {
+                empty_bitmaps += 1;
+            }
+            match key {
+                Key::Cell(cell) => {
+                    cells.insert(cell, bitmap.len());
+                }
+                Key::Belly(cell) => bellies.push(cell),
+            }
+        }
+
+        let orphaned_belly_cells = bellies
+            .iter()
+            .filter(|belly| self.is_orphaned_belly(&cells, max_resolution, **belly))
+            .count() as u64;
+        let leaves = cells
+            .keys()
+            .filter(|cell| !self.is_split(&cells, max_resolution, **cell))
+            .map(|cell| cell.resolution());
+        let shallowest_leaf = leaves.clone().min();
+        let deepest_leaf = leaves.max();
+
+        let mut report = HealthReport {
+            item_db: self.item_db_stats(rtxn)?,
+            cell_db: self.cell_db_stats(rtxn)?,
+            pending_updates: self.update.len(rtxn)?,
+            empty_bitmaps,
+            orphaned_belly_cells,
+            shallowest_leaf,
+            deepest_leaf,
+            warnings: Vec::new(),
+        };
+
+        if self.needs_recovery(rtxn)? {
+            report.warnings.push(
+                "A build in multiple transactions was interrupted. Call `Cellulite::recover` to finish it.".to_string(),
+            );
+        }
+        if report.pending_updates > 0 {
+            report.warnings.push(format!(
+                "{} updates are waiting for a build, they're not visible to the queries.",
+                report.pending_updates
+            ));
+        }
+        if report.cell_db.overflow_pages > report.cell_db.leaf_pages {
+            report.warnings.push(format!(
+                "The cell database uses more overflow pages ({}) than leaf pages ({}). Its bitmaps are too large, consider lowering the threshold or increasing the maximum resolution.",
+                report.cell_db.overflow_pages, report.cell_db.leaf_pages
+            ));
+        }
+        if report.empty_bitmaps > 0 {
+            report.warnings.push(format!(
+                "{} cells don't contain any item. Rebuild the database to reclaim their space.",
+                report.empty_bitmaps
+            ));
+        }
+        if report.orphaned_belly_cells > 0 {
+            report.warnings.push(format!(
+                "{} belly cells are never read because their parent is not split anymore. Call `Cellulite::vacuum` to reclaim their space.",
+                report.orphaned_belly_cells
+            ));
+        }
+        if report.depth_skew() > MAX_DEPTH_SKEW {
+            report.warnings.push(format!(
+                "The leaf cells go from the resolution {} to {}, the tree is unbalanced. Consider increasing the threshold.",
+                // safe to unwrap because there is a skew
+                report.shallowest_leaf.unwrap(),
+                report.deepest_leaf.unwrap()
+            ));
+        }
+
+        Ok(report)
+    }
+
+    /// Return how the items are spread in the cells of every resolution, to choose the threshold
+    /// from the shape of the data instead of rebuilding the database with different values.
+    /// Only the normal cells are inspected, the belly cells are never split.
+    pub fn resolution_report(
+        &self,
+        rtxn: &RoTxn,
+    ) -> Result<BTreeMap<Resolution, ResolutionReport>> {
+        let mut lens: BTreeMap<Resolution, Vec<u64>> = BTreeMap::new();
+        for ret in self.inner_db_cells(rtxn)? {
+            let (cell, bitmap) = ret?;
+            lens.entry(cell.resolution())
+                .or_default()
+                .push(bitmap.len());
+        }
+
+        Ok(lens
+            .into_iter()
+            .map(|(resolution, mut lens)| {
+                lens.sort_unstable();
+                let threshold = self.options.threshold;
+                let at_threshold = lens.iter().filter(|len| **len == threshold).count() as u64;
+                // safe to index because every resolution contains at least one cell
+                let report = ResolutionReport {
+                    cells: lens.len() as u64,
+                    min_items: lens[0],
+                    median_items: lens[lens.len() / 2],
+                    max_items: lens[lens.len() - 1],
+                    at_threshold,
+                };
+                (resolution, report)
+            })
+            .collect())
+    }
+
+    /// Remove the belly cells that are never read by the queries: the empty ones, and the ones
+    /// none of whose parents in the tree is split anymore since their items were deleted.
+    /// Returns the number of belly cells removed.
+    pub fn vacuum(&self, wtxn: &mut RwTxn) -> Result<u64> {
+        let max_resolution = self.max_resolution(wtxn)?;
+        let mut cells = HashMap::new();
+        let mut bellies = Vec::new();
+        let mut to_remove = Vec::new();
+        for ret in self.cell.iter(wtxn)? {
+            let (key, bitmap) = ret?;
+            match key {
+                Key::Cell(cell) => {
+                    cells.insert(cell, bitmap.len());
+                }
+                Key::Belly(cell) if bitmap.is_empty() => to_remove.push(cell),
+                Key::Belly(cell) => bellies.push(cell),
+            }
+        }
+        to_remove.extend(
+            bellies
+                .into_iter()
+                .filter(|belly| self.is_orphaned_belly(&cells, max_resolution, *belly)),
+        );
+
+        for belly in to_remove.iter() {
+            self.cell.delete(wtxn, &Key::Belly(*belly))?;
+        }
+        Ok(to_remove.len() as u64)
+    }
+
+    fn is_split(
+        &self,
+        cells: &HashMap<CellIndex, u64>,
+        max_resolution: Resolution,
+        cell: CellIndex,
+    ) -> bool {
+        cells
+            .get(&cell)
+            .is_some_and(|len| *len >= self.options.threshold && cell.resolution() < max_resolution)
+    }
+
+    /// The belly cells are only read when one of their parents in the tree is split, the ones at
+    /// the resolution zero are always read. The sub-cells of a cell are around its center child,
+    /// so the parents of a cell are its H3 parent or one of its neighbours.
+    fn is_orphaned_belly(
+        &self,
+        cells: &HashMap<CellIndex, u64>,
+        max_resolution: Resolution,
+        belly: CellIndex,
+    ) -> bool {
+        let Some(parent_resolution) = belly.resolution().pred() else {
+            return false;
+        };
+        // safe to unwrap because the parent is shallower than the cell
+        let h3_parent = belly.parent(parent_resolution).unwrap();
+        !h3_parent.grid_disk::<Vec<_>>(1).into_iter().any(|parent| {
+            self.is_split(cells, max_resolution, parent)
+                // safe to unwrap because the cell is deeper than its parent
+                && parent
+                    .center_child(belly.resolution())
+                    .unwrap()
+                    .grid_disk::<Vec<_>>(2)
+                    .contains(&belly)
+        })
+    }
+}
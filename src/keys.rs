@@ -1,27 +1,47 @@
 use std::borrow::Cow;
 
-use h3o::CellIndex;
+use h3o::{CellIndex, error::InvalidCellIndex};
 use heed::{
-    RoTxn,
+    BytesDecode, RoTxn,
     byteorder::{BE, BigEndian, ByteOrder},
-    types::U64,
+    types::{Bytes, U64},
 };
 use roaring::RoaringBitmap;
 
-use crate::CellDb;
+use crate::{CellDb, checksum::Checksummed, roaring::RoaringBitmapCodec};
 
 /// Codec used to encode and decode the item id in the item database.
 ///
+/// The item id is written as a big-endian u64, it's part of the on-disk format and won't change
+/// without a new version of the database.
+///
 /// The reason why we're using this codec instead of a `U32<BE>` is because the
-/// keys must be aligned of 64 bits.
+/// keys must be aligned of 64 bits. For the same reason we're not using the LMDB integer keys:
+/// a 4-bytes key would misalign the values, and an 8-bytes native integer key wouldn't be smaller.
+/// The big-endian encoding also keeps the items sorted by id when iterating over the database.
 pub struct ItemKeyCodec;
 
+impl ItemKeyCodec {
+    /// The size of an encoded key.
+    pub const SIZE: usize = size_of::<u64>();
+
+    /// Encode the item id without allocating.
+    pub fn encode(item: u32) -> [u8; Self::SIZE] {
+        (item as u64).to_be_bytes()
+    }
+
+    /// Decode an item id, return `None` if the bytes are not a valid key.
+    pub fn decode(bytes: &[u8]) -> Option<u32> {
+        let bytes: [u8; Self::SIZE] = bytes.try_into().ok()?;
+        u32::try_from(u64::from_be_bytes(bytes)).ok()
+    }
+}
+
 impl heed::BytesEncode<'_> for ItemKeyCodec {
     type EItem = u32;
 
     fn bytes_encode(item: &'_ Self::EItem) -> Result<std::borrow::Cow<'_, [u8]>, heed::BoxedError> {
-        let aligned_item = *item as u64;
-        Ok(Cow::from(aligned_item.to_be_bytes().to_vec()))
+        Ok(Cow::from(Self::encode(*item).to_vec()))
     }
 }
 
@@ -29,13 +49,13 @@ impl heed::BytesDecode<'_> for ItemKeyCodec {
     type DItem = u32;
 
     fn bytes_decode(bytes: &'_ [u8]) -> Result<Self::DItem, heed::BoxedError> {
-        U64::<BE>::bytes_decode(bytes).map(|n| n as u32)
+        Self::decode(bytes).ok_or_else(|| format!("Invalid item key: {bytes:?}").into())
     }
 }
 
 /// Codec used to encode and decode the cell id.
 ///
-/// - The cell is encoded as a u64
+/// - The cell is encoded as a u64 with [`cell_to_locality_key`]
 /// - The next byte is used to indicate if it's a belly cell or a normal cell.
 /// - And finally there is some padding to align the roaring bitmap on 64 bits
 pub struct CellKeyCodec;
@@ -51,7 +71,7 @@ impl<'a> heed::BytesEncode<'a> for CellKeyCodec {
                 let capacity = size_of::<KeyVariant>() + size_of_val(cell);
                 let missing_to_align = ALIGNMENT - (capacity % ALIGNMENT);
                 ret = Vec::with_capacity(capacity + missing_to_align);
-                let output: u64 = (*cell).into();
+                let output = cell_to_locality_key(*cell);
                 ret.extend_from_slice(&output.to_be_bytes());
                 ret.push(KeyVariant::Cell as u8);
                 ret.extend(std::iter::repeat_n(0, missing_to_align));
@@ -60,7 +80,7 @@ impl<'a> heed::BytesEncode<'a> for CellKeyCodec {
                 let capacity = size_of::<KeyVariant>() + size_of_val(cell);
                 let missing_to_align = ALIGNMENT - (capacity % ALIGNMENT);
                 ret = Vec::with_capacity(capacity + missing_to_align);
-                let output: u64 = (*cell).into();
+                let output = cell_to_locality_key(*cell);
                 ret.extend_from_slice(&output.to_be_bytes());
                 ret.push(KeyVariant::Belly as u8);
                 ret.extend(std::iter::repeat_n(0, missing_to_align));
@@ -78,8 +98,8 @@ impl heed::BytesDecode<'_> for CellKeyCodec {
         let bytes = &bytes[std::mem::size_of_val(&cell)..];
         let variant = bytes[0];
         let key = match variant {
-            v if v == KeyVariant::Cell as u8 => Key::Cell(cell.try_into()?),
-            v if v == KeyVariant::Belly as u8 => Key::Belly(cell.try_into()?),
+            v if v == KeyVariant::Cell as u8 => Key::Cell(locality_key_to_cell(cell)?),
+            v if v == KeyVariant::Belly as u8 => Key::Belly(locality_key_to_cell(cell)?),
             _ => unreachable!(),
         };
         // In any case we can skip the padding
@@ -88,13 +108,93 @@ impl heed::BytesDecode<'_> for CellKeyCodec {
     }
 }
 
+/// The offset of the resolution in the bits of a cell index.
+const RESOLUTION_OFFSET: u64 = 52;
+/// The mask of the resolution in the bits of a cell index.
+const RESOLUTION_MASK: u64 = 0b1111 << RESOLUTION_OFFSET;
+/// The number of bits used by each digit of a cell index.
+const DIGIT_BITS: u64 = 3;
+/// The value of the digits unused by the resolution of a cell index.
+const UNUSED_DIGIT: u64 = 0b111;
+
+/// Convert a cell to the u64 used in the keys of the cell database.
+///
+/// A cell index stores its resolution in its most significant bits, followed by its base cell and the digits
+/// of its path in the tree. The unused digits are all set to `7`. By removing the resolution we get:
+/// - The cells of the same resolution that share an ancestor follow each other.
+/// - The descendants of a cell follow each other and are followed by the cell itself.
+///
+/// It means the cells explored by a query, or built by the same sub-tree, are close to each other in LMDB.
+/// Since the unused digits are still set to `7`, no two cells share the same key.
+pub fn cell_to_locality_key(cell: CellIndex) -> u64 {
+    u64::from(cell) & !RESOLUTION_MASK
+}
+
+/// Convert back a key created by [`cell_to_locality_key`] to its cell.
+pub fn locality_key_to_cell(key: u64) -> Result<CellIndex, InvalidCellIndex> {
+    // The resolution is the number of used digits, starting from the most significant one
+    let mut resolution = 0;
+    while resolution < 15 {
+        let offset = (14 - resolution) * DIGIT_BITS;
+        if (key >> offset) & UNUSED_DIGIT == UNUSED_DIGIT {
+            break;
+        }
+        resolution += 1;
+    }
+    CellIndex::try_from(key | (resolution << RESOLUTION_OFFSET))
+}
+
+/// Codec used to encode and decode a list of cells, each cell is encoded as a big-endian u64.
+pub struct CellsCodec;
+
+impl heed::BytesEncode<'_> for CellsCodec {
+    type EItem = [CellIndex];
+
+    fn bytes_encode(cells: &Self::EItem) -> Result<Cow<'_, [u8]>, heed::BoxedError> {
+        let mut ret = Vec::with_capacity(cells.len() * size_of::<u64>());
+        for cell in cells {
+            ret.extend_from_slice(&u64::from(*cell).to_be_bytes());
+        }
+        Ok(ret.into())
+    }
+}
+
+impl heed::BytesDecode<'_> for CellsCodec {
+    type DItem = Vec<CellIndex>;
+
+    fn bytes_decode(bytes: &'_ [u8]) -> Result<Self::DItem, heed::BoxedError> {
+        bytes
+            .chunks_exact(size_of::<u64>())
+            .map(|chunk| Ok(CellIndex::try_from(BigEndian::read_u64(chunk))?))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
 pub enum Key {
     Cell(CellIndex),
     Belly(CellIndex),
 }
 
+impl Key {
+    pub fn from_parts(cell: CellIndex, variant: KeyVariant) -> Self {
+        match variant {
+            KeyVariant::Cell => Key::Cell(cell),
+            KeyVariant::Belly => Key::Belly(cell),
+        }
+    }
+
+    /// Return the cell and the variant of the key.
+    pub fn parts(&self) -> (CellIndex, KeyVariant) {
+        match self {
+            Key::Cell(cell) => (*cell, KeyVariant::Cell),
+            Key::Belly(cell) => (*cell, KeyVariant::Belly),
+        }
+    }
+}
+
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum KeyVariant {
     Cell = 1,
     Belly = 2,
@@ -104,15 +204,36 @@ pub(crate) fn retrieve_cell_and_belly(
     rtxn: &RoTxn,
     db: &CellDb,
     cell_index: CellIndex,
-) -> Result<(Option<RoaringBitmap>, Option<RoaringBitmap>), heed::Error> {
+) -> crate::Result<(Option<RoaringBitmap>, Option<RoaringBitmap>)> {
+    let (cell, belly) = retrieve_raw_cell_and_belly(rtxn, db, cell_index)?;
+    let decode = |bytes: Option<&[u8]>| {
+        bytes
+            .map(RoaringBitmapCodec::bytes_decode)
+            .transpose()
+            .map_err(heed::Error::Decoding)
+    };
+    Ok((decode(cell)?, decode(belly)?))
+}
+
+/// Like [`retrieve_cell_and_belly`] but return the serialized bitmaps without their checksum,
+/// once it has been verified.
+pub(crate) fn retrieve_raw_cell_and_belly<'t>(
+    rtxn: &'t RoTxn,
+    db: &CellDb,
+    cell_index: CellIndex,
+) -> crate::Result<(Option<&'t [u8]>, Option<&'t [u8]>)> {
     let mut cell = None;
     let mut belly = None;
     let iter = db
-        .remap_key_type::<U64<BE>>()
-        .prefix_iter(rtxn, &(cell_index.into()))?
+        .remap_types::<U64<BE>, Bytes>()
+        .prefix_iter(rtxn, &cell_to_locality_key(cell_index))?
         .remap_key_type::<CellKeyCodec>();
     for ret in iter {
         let (key, value) = ret?;
+        // We decode the value ourselves to know which key is corrupted
+        let value = Checksummed::<Bytes>::bytes_decode(value)
+            .map_err(heed::Error::Decoding)
+            .map_err(crate::checksum::corruption(format_args!("{key:?}")))?;
         match key {
             Key::Cell(_) => cell = Some(value),
             Key::Belly(_) => belly = Some(value),
@@ -126,6 +247,9 @@ pub(crate) fn retrieve_cell_and_belly(
 pub enum UpdateType {
     Insert = 0,
     Delete = 1,
+    /// The item was already built, the build can keep it in its cells if its new shape still
+    /// fits in them.
+    Update = 2,
 }
 
 impl<'a> heed::BytesEncode<'a> for UpdateType {
@@ -143,14 +267,51 @@ impl<'a> heed::BytesDecode<'a> for UpdateType {
         match bytes {
             [b] if *b == UpdateType::Insert as u8 => Ok(UpdateType::Insert),
             [b] if *b == UpdateType::Delete as u8 => Ok(UpdateType::Delete),
+            [b] if *b == UpdateType::Update as u8 => Ok(UpdateType::Update),
             _ => panic!("Invalid update type {bytes:?}"),
         }
     }
 }
 
+/// The keys of the metadata database, each key is a single byte.
+///
+/// The keys below [`MetadataKey::FIRST_REQUIRED_KEY`] are optional: a version of cellulite that
+/// doesn't know one of them ignores it. The keys from [`MetadataKey::FIRST_REQUIRED_KEY`] change
+/// how the database must be read, a version that doesn't know one of them refuses to open it.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MetadataKey {
     Version = 0,
+    BuildCheckpoint = 1,
+    MaxResolution = 2,
+    BuildGeneration = 3,
+    Tombstones = 4,
+    LastBuildChanges = 5,
+    Options = 6,
+    /// Set when the extent index contains every item, see [`crate::Cellulite::items_in_extent`].
+    ExtentIndex = 128,
+    /// Set when the values are stored with a checksum, see [`crate::checksum::Checksummed`].
+    Checksum = 129,
+}
+
+impl MetadataKey {
+    /// The first key of the range reserved for the metadata required to read the database.
+    pub const FIRST_REQUIRED_KEY: u8 = 128;
+
+    /// Return `None` if the key was added by a newer version of cellulite.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b if b == MetadataKey::Version as u8 => Some(MetadataKey::Version),
+            b if b == MetadataKey::BuildCheckpoint as u8 => Some(MetadataKey::BuildCheckpoint),
+            b if b == MetadataKey::MaxResolution as u8 => Some(MetadataKey::MaxResolution),
+            b if b == MetadataKey::BuildGeneration as u8 => Some(MetadataKey::BuildGeneration),
+            b if b == MetadataKey::Tombstones as u8 => Some(MetadataKey::Tombstones),
+            b if b == MetadataKey::LastBuildChanges as u8 => Some(MetadataKey::LastBuildChanges),
+            b if b == MetadataKey::Options as u8 => Some(MetadataKey::Options),
+            b if b == MetadataKey::ExtentIndex as u8 => Some(MetadataKey::ExtentIndex),
+            b if b == MetadataKey::Checksum as u8 => Some(MetadataKey::Checksum),
+            _ => None,
+        }
+    }
 }
 
 impl<'a> heed::BytesEncode<'a> for MetadataKey {
@@ -166,8 +327,10 @@ impl<'a> heed::BytesDecode<'a> for MetadataKey {
 
     fn bytes_decode(bytes: &'a [u8]) -> Result<Self::DItem, heed::BoxedError> {
         match bytes {
-            [b] if *b == MetadataKey::Version as u8 => Ok(MetadataKey::Version),
-            _ => panic!("Invalid metadata key {bytes:?}"),
+            [b] => MetadataKey::from_byte(*b).ok_or_else(|| {
+                format!("Unknown metadata key {b}, it was added by a newer version").into()
+            }),
+            _ => Err(format!("Invalid metadata key {bytes:?}").into()),
         }
     }
 }
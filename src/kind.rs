@@ -0,0 +1,112 @@
+use heed::{BoxedError, BytesDecode, RoTxn, types::Bytes};
+use zerometry::Zerometry;
+
+use crate::{
+    Cellulite, ItemId, Result,
+    checksum::{self, Checksummed},
+    zerometry::ZerometryCodec,
+};
+
+/// The type of geometry of an item, as stored in the tag of its [`Zerometry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GeometryKind {
+    Point,
+    MultiPoints,
+    Line,
+    MultiLines,
+    Polygon,
+    MultiPolygon,
+    Collection,
+}
+
+impl GeometryKind {
+    /// Return the kind of the shape. Only its tag is inspected, the coordinates are not read.
+    pub fn of(shape: &Zerometry) -> Self {
+        match shape {
+            Zerometry::Point(_) => GeometryKind::Point,
+            Zerometry::MultiPoints(_) => GeometryKind::MultiPoints,
+            Zerometry::Line(_) => GeometryKind::Line,
+            Zerometry::MultiLines(_) => GeometryKind::MultiLines,
+            Zerometry::Polygon(_) => GeometryKind::Polygon,
+            Zerometry::MultiPolygon(_) => GeometryKind::MultiPolygon,
+            Zerometry::Collection(_) => GeometryKind::Collection,
+        }
+    }
+}
+
+/// A set of [`GeometryKind`], to restrict the items returned by the queries, see
+/// [`crate::reader::QueryOptions::kinds`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GeometryKinds(u8);
+
+impl GeometryKinds {
+    pub fn insert(&mut self, kind: GeometryKind) {
+        self.0 |= 1 << kind as u8;
+    }
+
+    pub fn contains(&self, kind: GeometryKind) -> bool {
+        self.0 & (1 << kind as u8) != 0
+    }
+}
+
+impl From<GeometryKind> for GeometryKinds {
+    fn from(kind: GeometryKind) -> Self {
+        Self::from_iter([kind])
+    }
+}
+
+impl FromIterator<GeometryKind> for GeometryKinds {
+    fn from_iter<T: IntoIterator<Item = GeometryKind>>(iter: T) -> Self {
+        let mut kinds = Self::default();
+        iter.into_iter().for_each(|kind| kinds.insert(kind));
+        kinds
+    }
+}
+
+impl Cellulite {
+    /// Iterate over the items of the database of a single kind, to process the points, lines and
+    /// polygons of a mixed dataset differently.
+    /// Like [`Self::items`], the items are read from the item database and don't need to be built.
+    /// Only the tag of the items of the other kinds is read.
+    pub fn items_of_kind<'a>(
+        &self,
+        rtxn: &'a RoTxn,
+        kind: GeometryKind,
+    ) -> Result<impl Iterator<Item = Result<(ItemId, Zerometry<'a>), heed::Error>> + 'a> {
+        let items = self.item.remap_data_type::<Bytes>();
+        Ok(items.iter(rtxn)?.filter_map(move |ret| {
+            // The errors are forwarded to the caller
+            let (item, bytes) = match ret {
+                Ok(entry) => entry,
+                Err(error) => return Some(Err(error)),
+            };
+            match peek_kind(bytes) {
+                Ok(other) if other != kind => None,
+                // The shape is decoded again to verify its checksum
+                _ => Some(
+                    Checksummed::<ZerometryCodec>::bytes_decode(bytes)
+                        .map(|shape| (item, shape))
+                        .map_err(heed::Error::Decoding),
+                ),
+            }
+        }))
+    }
+
+    /// Return the kind of an item by peeking the tag of its shape, `None` if it doesn't exist.
+    /// The checksum of the shape isn't verified.
+    pub(crate) fn kind_of(&self, rtxn: &RoTxn, item: ItemId) -> Result<Option<GeometryKind>> {
+        let Some(bytes) = self.item.remap_data_type::<Bytes>().get(rtxn, &item)? else {
+            return Ok(None);
+        };
+        let kind = peek_kind(bytes)
+            .map_err(heed::Error::Decoding)
+            .map_err(checksum::corruption(format_args!("item {item}")))?;
+        Ok(Some(kind))
+    }
+}
+
+/// Read the tag of a shape stored with its checksum, without reading its coordinates.
+fn peek_kind(bytes: &[u8]) -> Result<GeometryKind, BoxedError> {
+    let shape = ZerometryCodec::bytes_decode(checksum::without_checksum(bytes))?;
+    Ok(GeometryKind::of(&shape))
+}
@@ -1,39 +1,101 @@
 #![doc = include_str!("../README.md")]
 
 use core::f64;
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
 
 use ::roaring::RoaringBitmap;
 use ::zerometry::Zerometry;
-use geo::{Densify, Geometry, Haversine};
+use geo::{Centroid, Geometry, HasDimensions, Point};
+#[cfg(feature = "geojson")]
 use geojson::GeoJson;
 use h3o::{CellIndex, Resolution};
 use heed::{
     DatabaseStat, Env, RoTxn, RwTxn, Unspecified,
     byteorder::BE,
-    types::{Bytes, U32},
+    types::{Bytes, DecodeIgnore, F64, Str, U8, U32, U64},
 };
-use keys::{CellKeyCodec, ItemKeyCodec, Key, MetadataKey, UpdateType};
+use keys::{CellKeyCodec, CellsCodec, ItemKeyCodec, Key, MetadataKey, UpdateType};
 use metadata::{Version, VersionCodec};
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod background;
 mod builder;
+pub mod checksum;
+mod corridor;
+mod coverage;
+mod cursor;
+mod diff;
+mod dump;
 mod error;
+mod extent;
+mod frozen;
+mod health;
 pub(crate) mod keys;
+mod kind;
+mod map_size;
 mod metadata;
+mod multi_index;
+mod multi_process;
+mod namespace;
+mod nearest;
+mod options;
+mod query_stats;
+mod raw_cells;
 pub mod reader;
+mod readonly;
+mod replica;
 pub mod roaring;
+mod sharded;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+mod tree;
+mod update_log;
+mod upgrade;
+mod validation;
+mod writer_queue;
 pub mod zerometry;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "geojson"))]
 mod test;
 
-pub use crate::error::Error;
-use crate::{roaring::RoaringBitmapCodec, zerometry::ZerometryCodec};
+#[cfg(feature = "tokio")]
+pub use crate::asynchronous::AsyncCellulite;
+pub use crate::{
+    background::BuildHandle,
+    builder::BuildStatus,
+    cursor::CellCursor,
+    diff::Diff,
+    error::{CanceledBuild, Error, WorkingSet},
+    frozen::FrozenCellulite,
+    health::{HealthReport, ResolutionReport},
+    keys::ItemKeyCodec,
+    kind::{GeometryKind, GeometryKinds},
+    multi_index::MultiIndexReader,
+    multi_process::CelluliteWriter,
+    options::{CelluliteOptions, OversizedLeafPolicy},
+    query_stats::QueryStats,
+    readonly::CelluliteReader,
+    sharded::{ShardedCellulite, ShardingStrategy},
+    tree::CellTree,
+    writer_queue::{BatchReport, CelluliteWriterQueue},
+};
+use crate::{
+    checksum::Checksummed, query_stats::QueryCounters, reader::DistanceModel,
+    roaring::RoaringBitmapCodec, zerometry::ZerometryCodec,
+};
 
-pub type ItemDb = heed::Database<ItemKeyCodec, ZerometryCodec>;
-pub type CellDb = heed::Database<CellKeyCodec, RoaringBitmapCodec>;
+pub type ItemDb = heed::Database<ItemKeyCodec, Checksummed<ZerometryCodec>>;
+pub type CellDb = heed::Database<CellKeyCodec, Checksummed<RoaringBitmapCodec>>;
 pub type UpdateDb = heed::Database<U32<BE>, UpdateType>;
 pub type MetadataDb = heed::Database<MetadataKey, Unspecified>;
+pub type ExpirationDb = heed::Database<U32<BE>, U64<BE>>;
+pub type WeightDb = heed::Database<U32<BE>, F64<BE>>;
+pub type NamespaceDb = heed::Database<Str, RoaringBitmapCodec>;
+pub type ExtentDb = heed::Database<U64<BE>, Checksummed<RoaringBitmapCodec>>;
 pub type ItemId = u32;
 
 steppe::make_enum_progress! {
@@ -42,6 +104,7 @@ steppe::make_enum_progress! {
         ClearUpdatedItems,
         RetrieveAndClearDeletedItems,
         RemoveDeletedItemsFromDatabase,
+        UpdateItemsInPlace,
         InsertItemsAtLevelZero,
         InsertItemsRecursively,
         UpdateTheMetadata,
@@ -64,15 +127,31 @@ pub struct Cellulite {
     pub(crate) update: UpdateDb,
     /// Contains all the metadata related to the database.
     pub(crate) metadata: MetadataDb,
-
-    /// After how many elements should we break a cell into sub-cells
-    /// This is only available for the test and visualizing tools to use it.
-    pub threshold: u64,
+    /// Links the item IDs with the timestamp after which they expire.
+    pub(crate) expiration: ExpirationDb,
+    /// Links the item IDs with their weight.
+    pub(crate) weight: WeightDb,
+    /// Links the namespaces with the items they contain.
+    pub(crate) namespace: NamespaceDb,
+    /// Links the cells of a low resolution with the items whose bounding box intersects them,
+    /// see [`Self::items_in_extent`].
+    pub(crate) extent: ExtentDb,
+
+    /// The options stored in the metadata, see [`Self::set_options`].
+    pub(crate) options: CelluliteOptions,
+    /// How the distances are measured when densifying the query shapes and building the circles.
+    pub distance_model: DistanceModel,
+    /// Repair the self-intersecting polygons on insertion instead of returning an [`Error::InvalidPolygon`].
+    pub repair_polygons: bool,
+    /// Remove the repeated consecutive vertices and the collinear vertices of the polygons on insertion.
+    pub clean_vertices: bool,
+    /// The counters of the double-checked items, see [`Self::enable_query_stats`].
+    pub(crate) query_stats: Option<Arc<QueryCounters>>,
 }
 
 impl Cellulite {
     pub const fn nb_dbs() -> u32 {
-        4
+        8
     }
 
     pub fn item_db_stats(&self, rtxn: &RoTxn) -> heed::Result<DatabaseStat> {
@@ -102,13 +181,34 @@ impl Cellulite {
         let cell = env.create_database(wtxn, Some(&format!("{prefix}-cell")))?;
         let update = env.create_database(wtxn, Some(&format!("{prefix}-update")))?;
         let metadata = env.create_database(wtxn, Some(&format!("{prefix}-metadata")))?;
-        Ok(Self {
+        let expiration = env.create_database(wtxn, Some(&format!("{prefix}-expiration")))?;
+        let weight = env.create_database(wtxn, Some(&format!("{prefix}-weight")))?;
+        let namespace = env.create_database(wtxn, Some(&format!("{prefix}-namespace")))?;
+        let extent = env.create_database(wtxn, Some(&format!("{prefix}-extent")))?;
+        let mut cellulite = Self {
             item,
             cell,
             update,
             metadata,
-            threshold: Self::default_threshold(),
-        })
+            expiration,
+            weight,
+            namespace,
+            extent,
+            options: CelluliteOptions::default(),
+            distance_model: DistanceModel::default(),
+            repair_polygons: false,
+            clean_vertices: false,
+            query_stats: None,
+        };
+        cellulite.init_checksums(wtxn)?;
+        cellulite.check_metadata_keys(wtxn)?;
+        cellulite.check_checksums(wtxn)?;
+        cellulite.reload_options(wtxn)?;
+        // The databases created by older versions must be reindexed to fill the extent index
+        if cellulite.item.is_empty(wtxn)? && !cellulite.extent_indexed(wtxn)? {
+            cellulite.mark_extent_indexed(wtxn)?;
+        }
+        Ok(cellulite)
     }
 
     /// Open all the databases required for cellulite to work, return an error if any of the required database doesn't exists.
@@ -126,32 +226,142 @@ impl Cellulite {
         let metadata = env
             .open_database(rtxn, Some(&format!("{prefix}-metadata")))?
             .ok_or(Error::DatabaseDoesntExists)?;
-        Ok(Self {
+        let expiration = env
+            .open_database(rtxn, Some(&format!("{prefix}-expiration")))?
+            .ok_or(Error::DatabaseDoesntExists)?;
+        let weight = env
+            .open_database(rtxn, Some(&format!("{prefix}-weight")))?
+            .ok_or(Error::DatabaseDoesntExists)?;
+        let namespace = env
+            .open_database(rtxn, Some(&format!("{prefix}-namespace")))?
+            .ok_or(Error::DatabaseDoesntExists)?;
+        let extent = env
+            .open_database(rtxn, Some(&format!("{prefix}-extent")))?
+            .ok_or(Error::DatabaseDoesntExists)?;
+        let mut cellulite = Self {
             item,
             cell,
             update,
             metadata,
-            threshold: Self::default_threshold(),
-        })
+            expiration,
+            weight,
+            namespace,
+            extent,
+            options: CelluliteOptions::default(),
+            distance_model: DistanceModel::default(),
+            repair_polygons: false,
+            clean_vertices: false,
+            query_stats: None,
+        };
+        cellulite.check_metadata_keys(rtxn)?;
+        cellulite.check_checksums(rtxn)?;
+        cellulite.reload_options(rtxn)?;
+        Ok(cellulite)
+    }
+
+    /// Make sure this version of cellulite can read the database. The unknown optional metadata
+    /// are ignored, but the unknown required ones mean the database was written by a newer version.
+    fn check_metadata_keys(&self, rtxn: &RoTxn) -> Result<()> {
+        for ret in self
+            .metadata
+            .remap_types::<Bytes, DecodeIgnore>()
+            .iter(rtxn)?
+        {
+            let (key, ()) = ret?;
+            if let [byte] = key
+                && *byte >= MetadataKey::FIRST_REQUIRED_KEY
+                && MetadataKey::from_byte(*byte).is_none()
+            {
+                return Err(Error::UnknownRequiredMetadata(*byte));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the prefixes of all the cellulite databases stored in the environment, in order.
+    /// Only the prefixes with all the databases required by [`Self::open_from_env`] are returned.
+    pub fn list_prefixes<Tls>(env: &Env<Tls>, rtxn: &RoTxn) -> Result<Vec<String>> {
+        const SUFFIXES: [&str; 8] = [
+            "-item",
+            "-cell",
+            "-update",
+            "-metadata",
+            "-expiration",
+            "-weight",
+            "-namespace",
+            "-extent",
+        ];
+
+        // The names of the databases are the keys of the unnamed database
+        let Some(main) = env.open_database::<Bytes, DecodeIgnore>(rtxn, None)? else {
+            return Ok(Vec::new());
+        };
+        let mut prefixes = BTreeMap::<String, usize>::new();
+        for ret in main.iter(rtxn)? {
+            let (name, ()) = ret?;
+            // The unnamed database can also contain the keys of the user, not only names
+            let Ok(name) = std::str::from_utf8(name) else {
+                continue;
+            };
+            for suffix in SUFFIXES {
+                if let Some(prefix) = name.strip_suffix(suffix) {
+                    *prefixes.entry(prefix.to_string()).or_default() += 1;
+                }
+            }
+        }
+        Ok(prefixes
+            .into_iter()
+            .filter(|(_, count)| *count == SUFFIXES.len())
+            .map(|(prefix, _)| prefix)
+            .collect())
     }
 
     /// Create the cellulite struct from already opened databases.
-    pub fn from_dbs(item: ItemDb, cell: CellDb, update: UpdateDb, metadata: MetadataDb) -> Self {
+    /// The options are the default ones until [`Self::set_options`] is called.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_dbs(
+        item: ItemDb,
+        cell: CellDb,
+        update: UpdateDb,
+        metadata: MetadataDb,
+        expiration: ExpirationDb,
+        weight: WeightDb,
+        namespace: NamespaceDb,
+        extent: ExtentDb,
+    ) -> Self {
         Self {
             item,
             cell,
             update,
             metadata,
-            threshold: Self::default_threshold(),
+            expiration,
+            weight,
+            namespace,
+            extent,
+            options: CelluliteOptions::default(),
+            distance_model: DistanceModel::default(),
+            repair_polygons: false,
+            clean_vertices: false,
+            query_stats: None,
         }
     }
 
-    /// Clear all the databases.
+    /// Clear all the databases, the options are kept.
+    /// Returns [`Error::StaleOptions`] if another handle changed the options since this one
+    /// loaded them, see [`Self::reload_options`].
     pub fn clear(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.check_options_are_current(wtxn)?;
         self.item.clear(wtxn)?;
         self.cell.clear(wtxn)?;
         self.update.clear(wtxn)?;
         self.metadata.clear(wtxn)?;
+        self.expiration.clear(wtxn)?;
+        self.weight.clear(wtxn)?;
+        self.namespace.clear(wtxn)?;
+        self.extent.clear(wtxn)?;
+        self.write_options(wtxn, &self.options)?;
+        self.mark_extent_indexed(wtxn)?;
+        self.set_values_checksummed(wtxn, cfg!(feature = "checksum"))?;
         Ok(())
     }
 
@@ -181,6 +391,134 @@ impl Cellulite {
             .put(wtxn, &MetadataKey::Version, version)
     }
 
+    /// Return the number of updates already processed by a build in multiple transactions that didn't finish.
+    /// Return `None` if no such build is in progress.
+    pub fn build_checkpoint(&self, rtxn: &RoTxn) -> heed::Result<Option<u64>> {
+        self.metadata
+            .remap_data_type::<U64<BE>>()
+            .get(rtxn, &MetadataKey::BuildCheckpoint)
+    }
+
+    fn set_build_checkpoint(&self, wtxn: &mut RwTxn, processed: u64) -> heed::Result<()> {
+        self.metadata.remap_data_type::<U64<BE>>().put(
+            wtxn,
+            &MetadataKey::BuildCheckpoint,
+            &processed,
+        )
+    }
+
+    fn delete_build_checkpoint(&self, wtxn: &mut RwTxn) -> heed::Result<bool> {
+        self.metadata.delete(wtxn, &MetadataKey::BuildCheckpoint)
+    }
+
+    /// Return `true` if a build in multiple transactions started but didn't finish yet.
+    /// A build made in a single transaction is never visible from the other transactions.
+    pub fn is_building(&self, rtxn: &RoTxn) -> heed::Result<bool> {
+        self.build_checkpoint(rtxn)
+            .map(|checkpoint| checkpoint.is_some())
+    }
+
+    /// Return `true` if a build in multiple transactions was interrupted, by a crash or a
+    /// cancellation, and left some updates pending. Check it after opening the database:
+    /// [`Self::build`] refuses to run until [`Self::recover`] or
+    /// [`Self::build_in_multiple_transactions`] finishes the interrupted build.
+    pub fn needs_recovery(&self, rtxn: &RoTxn) -> heed::Result<bool> {
+        Ok(self.is_building(rtxn)? && !self.update.is_empty(rtxn)?)
+    }
+
+    /// Return the number of builds that changed the database.
+    /// Two read transactions seeing the same generation see the same data.
+    pub fn build_generation(&self, rtxn: &RoTxn) -> heed::Result<u64> {
+        self.metadata
+            .remap_data_type::<U64<BE>>()
+            .get(rtxn, &MetadataKey::BuildGeneration)
+            .map(|opt| opt.unwrap_or_default())
+    }
+
+    /// Return the items deleted with [`Self::soft_delete`] that are still in the index.
+    pub fn tombstones(&self, rtxn: &RoTxn) -> heed::Result<RoaringBitmap> {
+        self.metadata
+            .remap_data_type::<RoaringBitmapCodec>()
+            .get(rtxn, &MetadataKey::Tombstones)
+            .map(|opt| opt.unwrap_or_default())
+    }
+
+    fn set_tombstones(&self, wtxn: &mut RwTxn, tombstones: &RoaringBitmap) -> heed::Result<()> {
+        if tombstones.is_empty() {
+            self.metadata.delete(wtxn, &MetadataKey::Tombstones)?;
+            Ok(())
+        } else {
+            self.metadata.remap_data_type::<RoaringBitmapCodec>().put(
+                wtxn,
+                &MetadataKey::Tombstones,
+                tombstones,
+            )
+        }
+    }
+
+    /// Remove the items from the tombstones, if there were any.
+    fn remove_tombstones(&self, wtxn: &mut RwTxn, items: &RoaringBitmap) -> heed::Result<()> {
+        let mut tombstones = self.tombstones(wtxn)?;
+        if tombstones.is_disjoint(items) {
+            return Ok(());
+        }
+        tombstones -= items;
+        self.set_tombstones(wtxn, &tombstones)
+    }
+
+    /// Return the cells whose items changed during the last build, sorted and deduplicated.
+    /// Both the normal and belly cells are included, which lets you invalidate the caches built on top of these regions.
+    /// For a build in multiple transactions, only the changes of the last transaction are returned.
+    pub fn last_build_changes(&self, rtxn: &RoTxn) -> heed::Result<Vec<CellIndex>> {
+        self.metadata
+            .remap_data_type::<CellsCodec>()
+            .get(rtxn, &MetadataKey::LastBuildChanges)
+            .map(|opt| opt.unwrap_or_default())
+    }
+
+    fn set_last_build_changes(
+        &self,
+        wtxn: &mut RwTxn,
+        changes: &BTreeSet<CellIndex>,
+    ) -> heed::Result<()> {
+        let changes: Vec<_> = changes.iter().copied().collect();
+        self.metadata.remap_data_type::<CellsCodec>().put(
+            wtxn,
+            &MetadataKey::LastBuildChanges,
+            &changes,
+        )
+    }
+
+    fn increment_build_generation(&self, wtxn: &mut RwTxn) -> heed::Result<()> {
+        let generation = self.build_generation(wtxn)?;
+        self.metadata.remap_data_type::<U64<BE>>().put(
+            wtxn,
+            &MetadataKey::BuildGeneration,
+            &(generation + 1),
+        )
+    }
+
+    /// Return the deepest resolution the database can be subdivided to.
+    /// It's [`Resolution::Fifteen`] unless the database was built with [`Self::build_up_to_resolution`].
+    pub fn max_resolution(&self, rtxn: &RoTxn) -> Result<Resolution> {
+        let resolution = self
+            .metadata
+            .remap_data_type::<U8>()
+            .get(rtxn, &MetadataKey::MaxResolution)?;
+        match resolution {
+            Some(resolution) => Ok(Resolution::try_from(resolution)?),
+            None => Ok(Resolution::Fifteen),
+        }
+    }
+
+    fn set_max_resolution(&self, wtxn: &mut RwTxn, resolution: Resolution) -> heed::Result<()> {
+        self.metadata.remap_data_type::<U8>().put(
+            wtxn,
+            &MetadataKey::MaxResolution,
+            &u8::from(resolution),
+        )
+    }
+
     /// Return all the cells used internally in the database
     pub fn inner_db_cells<'a>(
         &self,
@@ -225,9 +563,40 @@ impl Cellulite {
             }))
     }
 
+    /// Return the items of `cell` and of its belly cell in a single lookup, `None` when they're not
+    /// stored. The two entries share the same prefix in the database, which makes it cheaper
+    /// than reading them one after the other. A missing cell means it's below a leaf, or that
+    /// none of the items intersects it.
+    pub fn cell_entry(
+        &self,
+        rtxn: &RoTxn,
+        cell: CellIndex,
+    ) -> Result<(Option<RoaringBitmap>, Option<RoaringBitmap>)> {
+        keys::retrieve_cell_and_belly(rtxn, &self.cell, cell)
+    }
+
+    /// Return the items stored in the belly cell of `cell`, the items covering the whole cell.
+    /// The bitmap is empty if the cell has no belly cell.
+    pub fn belly_items_of_cell(&self, rtxn: &RoTxn, cell: CellIndex) -> Result<RoaringBitmap> {
+        let key = Key::Belly(cell);
+        Ok(self
+            .cell
+            .get(rtxn, &key)
+            .map_err(checksum::corruption(format_args!("{key:?}")))?
+            .unwrap_or_default())
+    }
+
+    /// Return `true` if the item is stored in the belly cell of `cell`, in which case it covers
+    /// the whole cell and is not stored in its sub-cells.
+    pub fn is_item_belly_of(&self, rtxn: &RoTxn, cell: CellIndex, item: ItemId) -> Result<bool> {
+        Ok(self.belly_items_of_cell(rtxn, cell)?.contains(item))
+    }
+
     /// Return the coordinates of the items rounded down to 50cm if this id exists in the DB. Returns `None` otherwise.
     pub fn item<'a>(&self, rtxn: &'a RoTxn, item: ItemId) -> Result<Option<Zerometry<'a>>> {
-        self.item_db().get(rtxn, &item).map_err(Error::from)
+        self.item_db()
+            .get(rtxn, &item)
+            .map_err(checksum::corruption(format_args!("item {item}")))
     }
 
     /// Iterate over all the items in the database
@@ -238,22 +607,239 @@ impl Cellulite {
         Ok(self.item.iter(rtxn)?)
     }
 
+    /// Return the centroid of the items that exist in the database, to place a marker on the
+    /// results without sending their whole shape. The items without a centroid, like the empty
+    /// collections, are skipped.
+    pub fn centroids<'a>(
+        &'a self,
+        rtxn: &'a RoTxn,
+        items: &'a RoaringBitmap,
+    ) -> impl Iterator<Item = Result<(ItemId, Point)>> + 'a {
+        items
+            .iter()
+            .filter_map(move |item| match self.item(rtxn, item) {
+                Ok(Some(shape)) => shape
+                    .to_geo()
+                    .centroid()
+                    .map(|centroid| Ok((item, centroid))),
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            })
+    }
+
     /// Insert a geojson to the database. The geojson won't be stored as-is and cannot be returned later.
     /// For the item to be searchable you must [`Self::build`] the database afterward.
+    /// See [`Self::add_geometry`] for the validation of the shape, and returns
+    /// [`Error::InvalidGeoJson`] if the geojson cannot be converted to a geometry.
+    #[cfg(feature = "geojson")]
     pub fn add(&self, wtxn: &mut RwTxn, item: ItemId, geo: &GeoJson) -> Result<()> {
-        let geom = geo_types::Geometry::<f64>::try_from(geo.clone()).unwrap();
+        let geom = geo_types::Geometry::<f64>::try_from(geo.clone()).map_err(Box::new)?;
+        self.add_geometry(wtxn, item, geom)
+    }
+
+    /// Insert a geometry to the database, it's the same as [`Self::add`] without the `geojson` feature.
+    /// For the item to be searchable you must [`Self::build`] the database afterward.
+    /// The polygons crossing the antimeridian are split in two parts, one on each side.
+    /// Returns [`Error::EmptyGeometry`] if the geometry doesn't contain any coordinate, and
+    /// [`Error::InvalidPolygon`] if one of its polygons intersects itself, unless [`Self::repair_polygons`] is set.
+    pub fn add_geometry(&self, wtxn: &mut RwTxn, item: ItemId, geom: Geometry) -> Result<()> {
+        let geom = self.prepare_geometry(item, geom)?;
         self.item_db().put(wtxn, &item, &geom)?;
         self.update.put(wtxn, &item, &UpdateType::Insert)?;
+        self.expiration.delete(wtxn, &item)?;
+        self.weight.delete(wtxn, &item)?;
+        self.remove_tombstones(wtxn, &RoaringBitmap::from_iter([item]))?;
+        Ok(())
+    }
+
+    /// Replace the geojson of an item already in the database, like a position reported by a GPS.
+    /// Unlike [`Self::add`], the weight and expiration of the item are kept. For the new shape to
+    /// be searchable you must [`Self::build`] the database afterward.
+    /// See [`Self::update_geometry`] for the fast path of the build. Returns
+    /// [`Error::InvalidGeoJson`] if the geojson cannot be converted to a geometry.
+    #[cfg(feature = "geojson")]
+    pub fn update(&self, wtxn: &mut RwTxn, item: ItemId, geo: &GeoJson) -> Result<()> {
+        let geom = geo_types::Geometry::<f64>::try_from(geo.clone()).map_err(Box::new)?;
+        self.update_geometry(wtxn, item, geom)
+    }
+
+    /// Replace the geometry of an item, it's the same as [`Self::update`] without the `geojson`
+    /// feature. The geometry is validated like in [`Self::add_geometry`].
+    ///
+    /// When the new shape stays in the cells the item was built in, which is common for small
+    /// moves, the build only keeps the new shape in the item database without touching the cells.
+    /// Otherwise, the item is removed from its cells and inserted again.
+    pub fn update_geometry(&self, wtxn: &mut RwTxn, item: ItemId, geom: Geometry) -> Result<()> {
+        let geom = self.prepare_geometry(item, geom)?;
+        let update = self.update_type_of(wtxn, item)?;
+        self.item_db().put(wtxn, &item, &geom)?;
+        self.update.put(wtxn, &item, &update)?;
+        self.remove_tombstones(wtxn, &RoaringBitmap::from_iter([item]))?;
         Ok(())
     }
 
-    /// The `geo` must be a valid `Zerometry` otherwise the database will be corrupted.
+    /// Return how the new shape of an item must be built: the items that were never built must be
+    /// inserted, the others can be updated.
+    fn update_type_of(&self, rtxn: &RoTxn, item: ItemId) -> Result<UpdateType> {
+        let exists = self
+            .item_db()
+            .remap_data_type::<DecodeIgnore>()
+            .get(rtxn, &item)?
+            .is_some();
+        match self.update.get(rtxn, &item)? {
+            Some(UpdateType::Insert) => Ok(UpdateType::Insert),
+            _ if exists => Ok(UpdateType::Update),
+            _ => Ok(UpdateType::Insert),
+        }
+    }
+
+    /// Validate and fix the geometry of an item before storing it, see [`Self::add_geometry`].
+    fn prepare_geometry(&self, item: ItemId, mut geom: Geometry) -> Result<Geometry> {
+        if geom.is_empty() {
+            return Err(Error::EmptyGeometry(item));
+        }
+        if self.clean_vertices {
+            validation::clean_vertices(&mut geom);
+        }
+        validation::split_antimeridian(&mut geom);
+        if let Some(max_segment_length) = self.options.densify_items {
+            self.distance_model
+                .densify_geometry(&mut geom, max_segment_length);
+        }
+        validation::check_polygons(item, &mut geom, self.repair_polygons)?;
+        if let Some(fragment_size) = self.options.fragment_size {
+            validation::check_fragment_size(fragment_size)?;
+            validation::fragment_polygons(&mut geom, fragment_size);
+        }
+        validation::orient_polygons(&mut geom);
+        Ok(geom)
+    }
+
+    /// Insert a geometry whose coordinates are in the `crs` coordinate reference system, like
+    /// `"EPSG:3857"`. It's reprojected to WGS84 with the longitude first, then inserted with
+    /// [`Self::add_geometry`].
+    /// Returns [`Error::CannotReproject`] if the CRS is unknown or a coordinate cannot be converted.
+    #[cfg(feature = "proj")]
+    pub fn add_with_crs(
+        &self,
+        wtxn: &mut RwTxn,
+        item: ItemId,
+        mut geom: Geometry,
+        crs: &str,
+    ) -> Result<()> {
+        use geo::{Transform, algorithm::proj::Proj};
+
+        let error = |e: String| Error::CannotReproject(item, crs.to_string(), e);
+        // The axis order of the output is normalized to the longitude first by PROJ
+        let proj = Proj::new_known_crs(crs, "EPSG:4326", None).map_err(|e| error(e.to_string()))?;
+        geom.transform(&proj).map_err(|e| error(e.to_string()))?;
+        self.add_geometry(wtxn, item, geom)
+    }
+
+    /// Insert a geojson to the database like [`Self::add`], but the item will be deleted by the first
+    /// call to [`Self::expire`] made after `expires_at`.
+    /// The unit of the timestamp is up to you as long as it's the same one used in [`Self::expire`].
+    #[cfg(feature = "geojson")]
+    pub fn add_with_expiration(
+        &self,
+        wtxn: &mut RwTxn,
+        item: ItemId,
+        geo: &GeoJson,
+        expires_at: u64,
+    ) -> Result<()> {
+        self.add(wtxn, item, geo)?;
+        self.expiration.put(wtxn, &item, &expires_at)?;
+        Ok(())
+    }
+
+    /// Return the timestamp after which the item expires if it has one.
+    pub fn expiration(&self, rtxn: &RoTxn, item: ItemId) -> heed::Result<Option<u64>> {
+        self.expiration.get(rtxn, &item)
+    }
+
+    /// Attach a weight to an item, like its population or its revenue, to aggregate the results
+    /// with [`Self::weight_by_cell`]. The weight is removed when the item is added again or
+    /// deleted. Returns [`Error::ItemDoesntExists`] if the item was never added.
+    pub fn set_weight(&self, wtxn: &mut RwTxn, item: ItemId, weight: f64) -> Result<()> {
+        if self.item(wtxn, item)?.is_none() {
+            return Err(Error::ItemDoesntExists(item));
+        }
+        self.weight.put(wtxn, &item, &weight)?;
+        Ok(())
+    }
+
+    /// Return the weight of the item if it has one.
+    pub fn weight(&self, rtxn: &RoTxn, item: ItemId) -> heed::Result<Option<f64>> {
+        self.weight.get(rtxn, &item)
+    }
+
+    /// Delete all the items that expired strictly before `now` and return their ids.
+    /// Like [`Self::delete`], the items are only removed from the index by the next [`Self::build`].
+    pub fn expire(&self, wtxn: &mut RwTxn, now: u64) -> Result<RoaringBitmap> {
+        let mut expired = RoaringBitmap::new();
+        for ret in self.expiration.iter(wtxn)? {
+            let (item, expires_at) = ret?;
+            if expires_at < now {
+                expired.insert(item);
+            }
+        }
+        for item in expired.iter() {
+            self.delete(wtxn, item)?;
+        }
+        Ok(expired)
+    }
+
+    /// The `geo` must be a valid `Zerometry` otherwise the database will be corrupted, encode it
+    /// with [`Self::encode_geometry`] or [`crate::zerometry::encode`].
     /// For the item to be searchable you must [`Self::build`] the database afterward.
     pub fn add_raw_zerometry(&self, wtxn: &mut RwTxn, item: ItemId, geo: &[u8]) -> Result<()> {
         self.item_db()
-            .remap_data_type::<Bytes>()
+            .remap_data_type::<Checksummed<Bytes>>()
             .put(wtxn, &item, geo)?;
         self.update.put(wtxn, &item, &UpdateType::Insert)?;
+        self.expiration.delete(wtxn, &item)?;
+        self.weight.delete(wtxn, &item)?;
+        self.remove_tombstones(wtxn, &RoaringBitmap::from_iter([item]))?;
+        Ok(())
+    }
+
+    /// Validate and encode a geometry exactly like [`Self::add_geometry`] does, without storing it.
+    /// The bytes can be precomputed off the write path and inserted with
+    /// [`Self::add_raw_zerometry`] by a database using the same options.
+    /// Returns the same errors as [`Self::add_geometry`].
+    pub fn encode_geometry(&self, item: ItemId, geom: Geometry) -> Result<Vec<u8>> {
+        let geom = self.prepare_geometry(item, geom)?;
+        crate::zerometry::encode(&geom)
+    }
+
+    /// Insert a shape already encoded as a `Zerometry` like [`Self::add_raw_zerometry`], but
+    /// check the bytes first so a malformed buffer cannot corrupt the database. The buffer must be
+    /// readable as a `Zerometry`, its coordinates must be finite and on the globe, and its
+    /// bounding boxes must be the ones of its coordinates.
+    /// Returns [`Error::InvalidZerometry`] or [`Error::InvalidItemCoordinate`] otherwise, and
+    /// [`Error::EmptyGeometry`] if the shape doesn't contain any coordinate.
+    ///
+    /// Unlike [`Self::add_geometry`], the shape is stored as-is: the polygons are not repaired nor
+    /// split on the antimeridian.
+    pub fn add_raw_zerometry_checked(
+        &self,
+        wtxn: &mut RwTxn,
+        item: ItemId,
+        geo: &[u8],
+    ) -> Result<()> {
+        validation::check_zerometry(item, geo)?;
+        self.add_raw_zerometry(wtxn, item, geo)
+    }
+
+    /// Replace the shape of an item like [`Self::update_geometry`], the `geo` must be a valid
+    /// `Zerometry` otherwise the database will be corrupted.
+    pub fn update_raw_zerometry(&self, wtxn: &mut RwTxn, item: ItemId, geo: &[u8]) -> Result<()> {
+        let update = self.update_type_of(wtxn, item)?;
+        self.item_db()
+            .remap_data_type::<Checksummed<Bytes>>()
+            .put(wtxn, &item, geo)?;
+        self.update.put(wtxn, &item, &update)?;
+        self.remove_tombstones(wtxn, &RoaringBitmap::from_iter([item]))?;
         Ok(())
     }
 
@@ -261,19 +847,44 @@ impl Cellulite {
     /// For the item to be removed you must [`Self::build`] the database afterward.
     pub fn delete(&self, wtxn: &mut RwTxn, item: ItemId) -> Result<()> {
         self.update.put(wtxn, &item, &UpdateType::Delete)?;
+        self.expiration.delete(wtxn, &item)?;
+        self.weight.delete(wtxn, &item)?;
+        Ok(())
+    }
+
+    /// Delete an item by its id and immediately exclude it from the queries.
+    /// It stays in the index until the next [`Self::build`] or [`Self::purge`], which makes the
+    /// queries slightly slower in the meantime.
+    pub fn soft_delete(&self, wtxn: &mut RwTxn, item: ItemId) -> Result<()> {
+        self.delete(wtxn, item)?;
+        let mut tombstones = self.tombstones(wtxn)?;
+        if tombstones.insert(item) {
+            self.set_tombstones(wtxn, &tombstones)?;
+        }
         Ok(())
     }
 
     /// Return stats of all the entries in the database.
     pub fn stats(&self, rtxn: &RoTxn) -> Result<Stats> {
-        let total_items = self.items(rtxn)?.count();
+        let mut total_items = 0;
+        let mut items_by_kind = BTreeMap::new();
+        for entry in self.items(rtxn)? {
+            let (_, shape) = entry?;
+            total_items += 1;
+            *items_by_kind.entry(GeometryKind::of(&shape)).or_default() += 1;
+        }
+        let max_resolution = self.max_resolution(rtxn)?;
         let mut total_cells = 0;
         let mut cells_by_resolution = BTreeMap::new();
+        let mut oversized_leaves = 0;
 
         for entry in self.inner_db_cells(rtxn)? {
-            let (cell, _) = entry?;
+            let (cell, bitmap) = entry?;
             total_cells += 1;
             *cells_by_resolution.entry(cell.resolution()).or_default() += 1;
+            if cell.resolution() >= max_resolution && bitmap.len() >= self.options.threshold {
+                oversized_leaves += 1;
+            }
         }
 
         let mut total_belly_cells = 0;
@@ -290,9 +901,11 @@ impl Cellulite {
         Ok(Stats {
             total_cells,
             total_items,
+            items_by_kind,
             cells_by_resolution,
             total_belly_cells,
             belly_cells_by_resolution,
+            oversized_leaves,
         })
     }
 }
@@ -302,40 +915,17 @@ pub struct Stats {
     pub total_cells: usize,
     pub total_belly_cells: usize,
     pub total_items: usize,
+    /// The number of items of each kind of geometry, see [`Cellulite::items_of_kind`].
+    pub items_by_kind: BTreeMap<GeometryKind, usize>,
     pub cells_by_resolution: BTreeMap<Resolution, usize>,
     pub belly_cells_by_resolution: BTreeMap<Resolution, usize>,
+    /// The cells of the maximum resolution containing more items than the threshold, see
+    /// [`OversizedLeafPolicy`].
+    pub oversized_leaves: usize,
 }
 
+/// Densify the geometry on the sphere so its edges are never longer than 10km.
+/// See [`DistanceModel::densify_geometry`] to choose the distance model and the length of the edges.
 pub fn densify_geom(geom: &mut Geometry) {
-    match geom {
-        Geometry::Line(line) => {
-            *geom = Geometry::LineString(
-                Haversine.densify(&geo_types::LineString(vec![line.start, line.end]), 10_000.0),
-            );
-        }
-        Geometry::LineString(line_string) => {
-            *line_string = Haversine.densify(line_string, 10_000.0);
-        }
-        Geometry::Polygon(polygon) => {
-            *polygon = Haversine.densify(polygon, 10_000.0);
-        }
-        Geometry::MultiLineString(multi_line_string) => {
-            *multi_line_string = Haversine.densify(multi_line_string, 10_000.0);
-        }
-        Geometry::MultiPolygon(multi_polygon) => {
-            *multi_polygon = Haversine.densify(multi_polygon, 10_000.0);
-        }
-        Geometry::GeometryCollection(geometry_collection) => {
-            for geom in geometry_collection.0.iter_mut() {
-                densify_geom(geom);
-            }
-        }
-        Geometry::Rect(rect) => {
-            *geom = Geometry::Polygon(Haversine.densify(rect, 10_000.0));
-        }
-        Geometry::Triangle(triangle) => {
-            *geom = Geometry::Polygon(Haversine.densify(triangle, 10_000.0));
-        }
-        _ => (),
-    };
+    DistanceModel::Haversine.densify_geometry(geom, 10_000.0);
 }
@@ -0,0 +1,77 @@
+use heed::{DatabaseStat, MdbError, RoTxn};
+
+use crate::{Cellulite, Error, Result};
+
+/// The size of the zerometry header of an item, and of a coordinate.
+const ITEM_HEADER_SIZE: u64 = 64;
+const COORD_SIZE: u64 = 16;
+/// An item is stored in about this many cells and belly cells across all the resolutions, and
+/// costs a few bytes in each of their bitmaps.
+const CELLS_PER_ITEM: u64 = 16;
+const BYTES_PER_CELL_ENTRY: u64 = 8;
+/// LMDB pages are half-full on average, and the pages written by a transaction are copied
+/// instead of being updated in place until it's committed.
+const LMDB_OVERHEAD: u64 = 4;
+
+impl Cellulite {
+    /// Return a rough estimation of the space in bytes required to store and build `items` items
+    /// made of `avg_vertices` coordinates on average, to size the `map_size` of the environment
+    /// before the first build. It's voluntarily pessimistic, the actual size also depends on how
+    /// the items are spread on the earth.
+    pub fn estimated_size_for(items: u64, avg_vertices: u64) -> u64 {
+        let item_size = ITEM_HEADER_SIZE + avg_vertices * COORD_SIZE;
+        estimated_size(items, item_size)
+    }
+
+    /// Measure the space used by the databases before a build, to explain the `MDB_MAP_FULL`
+    /// errors it may return.
+    pub(crate) fn map_usage(&self, rtxn: &RoTxn) -> Result<MapUsage> {
+        let item_db = self.item_db_stats(rtxn)?;
+        let used = [
+            &item_db,
+            &self.cell_db_stats(rtxn)?,
+            &self.update_db_stats(rtxn)?,
+            &self.metadata_db_stats(rtxn)?,
+            &self.expiration.stat(rtxn)?,
+            &self.weight.stat(rtxn)?,
+            &self.namespace.stat(rtxn)?,
+            &self.extent.stat(rtxn)?,
+        ]
+        .into_iter()
+        .map(stat_size)
+        .sum();
+        let item_size = stat_size(&item_db)
+            .checked_div(item_db.entries as u64)
+            .unwrap_or(ITEM_HEADER_SIZE);
+        let needed = estimated_size(self.update.len(rtxn)?, item_size);
+        Ok(MapUsage { used, needed })
+    }
+}
+
+fn estimated_size(items: u64, item_size: u64) -> u64 {
+    items * (item_size + CELLS_PER_ITEM * BYTES_PER_CELL_ENTRY) * LMDB_OVERHEAD
+}
+
+fn stat_size(stat: &DatabaseStat) -> u64 {
+    let pages = stat.branch_pages + stat.leaf_pages + stat.overflow_pages;
+    pages as u64 * stat.page_size as u64
+}
+
+/// The space used by the databases before a build and the space the build should need.
+pub(crate) struct MapUsage {
+    used: u64,
+    needed: u64,
+}
+
+impl MapUsage {
+    /// Replace the `MDB_MAP_FULL` errors by an [`Error::MapFull`]. The usage must be measured
+    /// before the error happens since the transaction cannot be read afterward.
+    pub(crate) fn explain(&self, error: Error) -> Error {
+        match error {
+            Error::Heed(heed::Error::Mdb(MdbError::MapFull)) => {
+                Error::MapFull(self.used, self.needed)
+            }
+            error => error,
+        }
+    }
+}
@@ -5,6 +5,8 @@ use std::mem::size_of;
 use heed::BoxedError;
 use heed::byteorder::{BigEndian, ByteOrder};
 
+use crate::{CelluliteOptions, options::OversizedLeafPolicy, reader::QueryMode};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Version {
     pub major: u32,
@@ -75,6 +77,108 @@ impl heed::BytesDecode<'_> for VersionCodec {
     }
 }
 
+/// Encodes the [`CelluliteOptions`] stored in the metadata, except for the maximum resolution
+/// that has its own key.
+/// The options are appended at the end, the options missing from an older database take their
+/// default value.
+pub enum OptionsCodec {}
+
+impl<'a> heed::BytesEncode<'a> for OptionsCodec {
+    type EItem = CelluliteOptions;
+
+    fn bytes_encode(item: &'a Self::EItem) -> Result<Cow<'a, [u8]>, BoxedError> {
+        let mut output = Vec::with_capacity(size_of::<u64>() + size_of::<f64>() * 3 + 5);
+        output.extend_from_slice(&item.threshold.to_be_bytes());
+        output.extend_from_slice(&item.densify_distance.to_be_bytes());
+        output.push(match item.query_mode {
+            QueryMode::Intersects => 0,
+            QueryMode::StrictlyWithin => 1,
+        });
+        output.push(item.belly_cells as u8);
+        output.push(match item.oversized_leaves {
+            OversizedLeafPolicy::Allow => 0,
+            OversizedLeafPolicy::Reject => 1,
+        });
+        encode_optional_f64(&mut output, item.densify_items);
+        encode_optional_f64(&mut output, item.fragment_size);
+        Ok(Cow::Owned(output))
+    }
+}
+
+impl heed::BytesDecode<'_> for OptionsCodec {
+    type DItem = CelluliteOptions;
+
+    fn bytes_decode(bytes: &'_ [u8]) -> Result<Self::DItem, BoxedError> {
+        let mut options = CelluliteOptions::default();
+        let Some((threshold, bytes)) = bytes.split_first_chunk() else {
+            return Ok(options);
+        };
+        options.threshold = u64::from_be_bytes(*threshold);
+        let Some((densify_distance, bytes)) = bytes.split_first_chunk() else {
+            return Ok(options);
+        };
+        options.densify_distance = f64::from_be_bytes(*densify_distance);
+        let Some((query_mode, bytes)) = bytes.split_first() else {
+            return Ok(options);
+        };
+        options.query_mode = match query_mode {
+            0 => QueryMode::Intersects,
+            1 => QueryMode::StrictlyWithin,
+            other => return Err(format!("Unknown query mode {other}").into()),
+        };
+        let Some((belly_cells, bytes)) = bytes.split_first() else {
+            return Ok(options);
+        };
+        options.belly_cells = *belly_cells != 0;
+        let Some((oversized_leaves, bytes)) = bytes.split_first() else {
+            return Ok(options);
+        };
+        options.oversized_leaves = match oversized_leaves {
+            0 => OversizedLeafPolicy::Allow,
+            1 => OversizedLeafPolicy::Reject,
+            other => return Err(format!("Unknown oversized leaf policy {other}").into()),
+        };
+        let Some((densify_items, bytes)) = decode_optional_f64(bytes)? else {
+            return Ok(options);
+        };
+        options.densify_items = densify_items;
+        let Some((fragment_size, _)) = decode_optional_f64(bytes)? else {
+            return Ok(options);
+        };
+        options.fragment_size = fragment_size;
+        Ok(options)
+    }
+}
+
+/// Push a presence byte, followed by the value if there is one.
+fn encode_optional_f64(output: &mut Vec<u8>, value: Option<f64>) {
+    match value {
+        Some(value) => {
+            output.push(1);
+            output.extend_from_slice(&value.to_be_bytes());
+        }
+        None => output.push(0),
+    }
+}
+
+/// Read a value written by [`encode_optional_f64`] and the remaining bytes, `None` if the bytes
+/// are empty.
+fn decode_optional_f64(bytes: &[u8]) -> Result<Option<(Option<f64>, &[u8])>, BoxedError> {
+    let Some((present, bytes)) = bytes.split_first() else {
+        return Ok(None);
+    };
+    match present {
+        0 => Ok(Some((None, bytes))),
+        1 => {
+            let (value, bytes) = bytes
+                .split_first_chunk()
+                .ok_or("The optional value is truncated")?;
+            Ok(Some((Some(f64::from_be_bytes(*value)), bytes)))
+        }
+        other => Err(format!("Unknown optional value tag {other}").into()),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use heed::{BytesDecode, BytesEncode};
@@ -96,4 +200,32 @@ mod test {
         assert_eq!(version.minor, decoded.minor);
         assert_eq!(version.patch, decoded.patch);
     }
+
+    #[test]
+    fn options_codec() {
+        let options = CelluliteOptions {
+            threshold: 12,
+            densify_distance: 250.0,
+            query_mode: QueryMode::StrictlyWithin,
+            belly_cells: false,
+            oversized_leaves: OversizedLeafPolicy::Reject,
+            densify_items: Some(500.0),
+            fragment_size: Some(2.5),
+            ..CelluliteOptions::default()
+        };
+
+        let encoded = OptionsCodec::bytes_encode(&options).unwrap();
+        let decoded = OptionsCodec::bytes_decode(&encoded).unwrap();
+        assert_eq!(options, decoded);
+
+        // The options missing from an older database take their default value
+        let decoded = OptionsCodec::bytes_decode(&encoded[..size_of::<u64>()]).unwrap();
+        assert_eq!(
+            decoded,
+            CelluliteOptions {
+                threshold: 12,
+                ..CelluliteOptions::default()
+            }
+        );
+    }
 }
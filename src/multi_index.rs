@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use geo::Polygon;
+use heed::{Env, RoTxn};
+use roaring::RoaringBitmap;
+
+use crate::{
+    Cellulite, Result,
+    reader::{PreparedShape, QueryOptions},
+};
+
+/// Multiple cellulite databases of the same environment queried together, like one database per
+/// layer of data: the shops, the parcels and the roads. Returned by [`Self::open`].
+///
+/// The databases are never created, like with [`Cellulite::open_readonly`], and they're all
+/// queried in the same read transaction. Each of them is identified by its prefix.
+#[derive(Clone)]
+pub struct MultiIndexReader {
+    indexes: BTreeMap<String, Cellulite>,
+}
+
+impl MultiIndexReader {
+    /// Open the databases of every prefix without creating them.
+    /// Returns [`crate::Error::DatabaseDoesntExists`] if one of them doesn't exist.
+    pub fn open<Tls>(env: &Env<Tls>, rtxn: &RoTxn, prefixes: &[&str]) -> Result<Self> {
+        let indexes = prefixes
+            .iter()
+            .map(|prefix| {
+                Ok((
+                    prefix.to_string(),
+                    Cellulite::open_from_env(env, rtxn, prefix)?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { indexes })
+    }
+
+    /// Open all the cellulite databases of the environment, see [`Cellulite::list_prefixes`].
+    pub fn open_all<Tls>(env: &Env<Tls>, rtxn: &RoTxn) -> Result<Self> {
+        let prefixes = Cellulite::list_prefixes(env, rtxn)?;
+        let prefixes: Vec<_> = prefixes.iter().map(String::as_str).collect();
+        Self::open(env, rtxn, &prefixes)
+    }
+
+    /// Return the prefixes of the databases, sorted.
+    pub fn prefixes(&self) -> impl Iterator<Item = &str> {
+        self.indexes.keys().map(String::as_str)
+    }
+
+    /// Return the database of a prefix to run the other queries on it.
+    pub fn index(&self, prefix: &str) -> Option<&Cellulite> {
+        self.indexes.get(prefix)
+    }
+
+    /// Return the items of every database that intersect or are contained in the polygon, like
+    /// [`Cellulite::in_shape`], by prefix.
+    /// The polygon is densified and tiled once for all the databases sharing the same options.
+    pub fn in_shape(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+    ) -> Result<BTreeMap<String, RoaringBitmap>> {
+        self.query(polygon, |cellulite, shape| {
+            cellulite.in_shape_prepared(rtxn, shape)
+        })
+    }
+
+    /// Same as [`Self::in_shape`] with the options of [`Cellulite::in_shape_with_options`].
+    pub fn in_shape_with_options(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+        options: QueryOptions,
+    ) -> Result<BTreeMap<String, RoaringBitmap>> {
+        self.query(polygon, |cellulite, shape| {
+            cellulite.in_shape_prepared_with_options(rtxn, shape, options)
+        })
+    }
+
+    fn query(
+        &self,
+        polygon: &Polygon,
+        mut f: impl FnMut(&Cellulite, &PreparedShape) -> Result<RoaringBitmap>,
+    ) -> Result<BTreeMap<String, RoaringBitmap>> {
+        let Some(first) = self.indexes.values().next() else {
+            return Ok(BTreeMap::new());
+        };
+        // The databases with other options prepare the shape again for themselves
+        let shape = first.prepare_shape(polygon)?;
+        self.indexes
+            .iter()
+            .map(|(prefix, cellulite)| Ok((prefix.clone(), f(cellulite, &shape)?)))
+            .collect()
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+};
+
+use heed::{Env, EnvFlags, RwTxn};
+
+use crate::{Cellulite, Error, Result};
+
+/// A database opened by the single writer of an environment shared between processes, returned
+/// by [`Cellulite::open_writer`]. It derefs to the [`Cellulite`] to write in the database.
+///
+/// The lock is held until the writer is dropped. It's released by the operating system if the
+/// process dies, a crashed writer never prevents the next one from opening the database.
+pub struct CelluliteWriter {
+    cellulite: Cellulite,
+    _lock: File,
+}
+
+impl Cellulite {
+    /// Open the databases, creating them if needed, as the only writer of the `prefix`. Any
+    /// number of processes can read the database at the same time with
+    /// [`Self::open_readonly`].
+    ///
+    /// Returns [`Error::WriterAlreadyOpened`] if another writer, in this process or another one,
+    /// holds the database. The lock is a file next to the environment, named after the prefix.
+    /// Returns [`Error::InvalidEnvFlags`] if the environment is read-only or doesn't use the
+    /// LMDB lock file, the other processes couldn't read it safely.
+    pub fn open_writer<Tls>(
+        env: &Env<Tls>,
+        wtxn: &mut RwTxn,
+        prefix: &str,
+    ) -> Result<CelluliteWriter> {
+        let flags = EnvFlags::from_bits_retain(env.get_flags()?);
+        if flags.contains(EnvFlags::READ_ONLY) {
+            return Err(Error::InvalidEnvFlags("it was opened read-only"));
+        }
+        if flags.contains(EnvFlags::NO_LOCK) {
+            return Err(Error::InvalidEnvFlags(
+                "it doesn't use the LMDB lock file, the readers of other processes could see a half-written transaction",
+            ));
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(writer_lock_path(env, prefix))
+            .map_err(heed::Error::Io)?;
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                return Err(Error::WriterAlreadyOpened(prefix.to_string()));
+            }
+            Err(TryLockError::Error(error)) => return Err(heed::Error::Io(error).into()),
+        }
+        let cellulite = Self::create_from_env(env, wtxn, prefix)?;
+        Ok(CelluliteWriter {
+            cellulite,
+            _lock: file,
+        })
+    }
+}
+
+impl Deref for CelluliteWriter {
+    type Target = Cellulite;
+
+    fn deref(&self) -> &Self::Target {
+        &self.cellulite
+    }
+}
+
+impl DerefMut for CelluliteWriter {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.cellulite
+    }
+}
+
+/// The environment is either a directory or a file with the `NO_SUB_DIR` flag, the lock is
+/// always created beside the LMDB files.
+fn writer_lock_path<Tls>(env: &Env<Tls>, prefix: &str) -> PathBuf {
+    let path = env.path();
+    let name = format!("{prefix}-writer.lock");
+    if path.is_dir() {
+        path.join(name)
+    } else {
+        path.with_file_name(name)
+    }
+}
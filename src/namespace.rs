@@ -0,0 +1,107 @@
+use geo::Polygon;
+use heed::{RoTxn, RwTxn};
+use roaring::RoaringBitmap;
+
+use crate::{Cellulite, Error, ItemId, Result, reader::QueryOptions};
+
+impl Cellulite {
+    /// Add an item to a namespace, like the tenant it belongs to, to scope the queries with
+    /// [`Self::in_shape_for_namespace`]. An item can belong to multiple namespaces, it's removed
+    /// from all of them when its deletion is built.
+    /// Returns [`Error::ItemDoesntExists`] if the item was never added.
+    pub fn add_to_namespace(&self, wtxn: &mut RwTxn, namespace: &str, item: ItemId) -> Result<()> {
+        if self.item(wtxn, item)?.is_none() {
+            return Err(Error::ItemDoesntExists(item));
+        }
+        let mut items = self.namespace.get(wtxn, namespace)?.unwrap_or_default();
+        if items.insert(item) {
+            self.namespace.put(wtxn, namespace, &items)?;
+        }
+        Ok(())
+    }
+
+    /// Remove an item from a namespace. The namespace is deleted when it becomes empty.
+    pub fn remove_from_namespace(
+        &self,
+        wtxn: &mut RwTxn,
+        namespace: &str,
+        item: ItemId,
+    ) -> heed::Result<()> {
+        let Some(mut items) = self.namespace.get(wtxn, namespace)? else {
+            return Ok(());
+        };
+        if !items.remove(item) {
+            return Ok(());
+        }
+        if items.is_empty() {
+            self.namespace.delete(wtxn, namespace)?;
+        } else {
+            self.namespace.put(wtxn, namespace, &items)?;
+        }
+        Ok(())
+    }
+
+    /// Return the items of a namespace.
+    pub fn namespace_items(&self, rtxn: &RoTxn, namespace: &str) -> heed::Result<RoaringBitmap> {
+        Ok(self.namespace.get(rtxn, namespace)?.unwrap_or_default())
+    }
+
+    /// Return all the namespaces containing at least one item, in order.
+    pub fn namespaces(&self, rtxn: &RoTxn) -> heed::Result<Vec<String>> {
+        self.namespace
+            .remap_data_type::<heed::types::DecodeIgnore>()
+            .iter(rtxn)?
+            .map(|ret| ret.map(|(namespace, ())| namespace.to_string()))
+            .collect()
+    }
+
+    /// Return the items of the namespace matching the polygon, see [`Self::in_shape`].
+    /// The namespaces share the cells of the index, but only the shapes of the items of the
+    /// namespace are read to be double-checked.
+    pub fn in_shape_for_namespace(
+        &self,
+        rtxn: &RoTxn,
+        namespace: &str,
+        polygon: &Polygon,
+    ) -> Result<RoaringBitmap> {
+        let items = self.namespace_items(rtxn, namespace)?;
+        if items.is_empty() {
+            return Ok(items);
+        }
+        let options = QueryOptions {
+            mode: self.options.query_mode,
+            ..QueryOptions::default()
+        };
+        self.search_in_shape(rtxn, polygon, options, None, |_| (), None, Some(&items))
+    }
+
+    /// Remove the items from all the namespaces and delete the namespaces that become empty.
+    pub(crate) fn remove_from_namespaces(
+        &self,
+        wtxn: &mut RwTxn,
+        items: &RoaringBitmap,
+    ) -> heed::Result<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        let mut iter = self.namespace.iter_mut(wtxn)?;
+        while let Some(ret) = iter.next() {
+            let (namespace, mut bitmap) = ret?;
+            let len = bitmap.len();
+            bitmap -= items;
+            if bitmap.len() == len {
+                continue;
+            }
+            let namespace = namespace.to_string();
+            // safe because everything is owned
+            unsafe {
+                if bitmap.is_empty() {
+                    iter.del_current()?;
+                } else {
+                    iter.put_current(&namespace, &bitmap)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
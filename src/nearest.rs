@@ -0,0 +1,206 @@
+use std::collections::{HashMap, HashSet, VecDeque, hash_map::Entry};
+
+use geo::{Closest, ClosestPoint, CoordsIter, Distance, Geometry, Intersects, MultiPolygon, Point};
+use h3o::{CellIndex, Resolution};
+use heed::RoTxn;
+use roaring::RoaringBitmap;
+
+use crate::{Cellulite, Error, ItemId, Result, keys::retrieve_cell_and_belly, pos};
+
+impl Cellulite {
+    /// Return the `k` items closest to an already indexed item along with their distance in meters
+    /// to it, according to the [`Self::distance_model`], ordered from the closest to the furthest.
+    /// The distance between two items is the distance between their closest points, the items
+    /// intersecting the item are at a distance of zero. If `exclude_self` is set, the item itself
+    /// is not returned.
+    ///
+    /// The search starts from the cells the item is stored in and explores the cells around them,
+    /// at a coarser resolution each time, until the `k` items found are closer than the cells
+    /// left unexplored. The item must have been built, otherwise nothing will be returned.
+    pub fn nearest_to_item(
+        &self,
+        rtxn: &RoTxn,
+        item: ItemId,
+        k: usize,
+        exclude_self: bool,
+    ) -> Result<Vec<(ItemId, f64)>> {
+        let shape = self
+            .item(rtxn, item)?
+            .ok_or(Error::ItemDoesntExists(item))?
+            .to_geo();
+        let origins = self.cells_of_item(rtxn, item)?;
+        // The search starts at the resolution of the largest cell storing the item, the cells
+        // around it should contain about as many items
+        let Some(mut resolution) = origins.iter().map(|cell| cell.resolution()).min() else {
+            return Ok(Vec::new());
+        };
+        let mut origins: Option<HashSet<_>> = Some(
+            origins
+                .into_iter()
+                .filter_map(|cell| cell.parent(resolution))
+                .collect(),
+        );
+
+        let mut indexed = RoaringBitmap::new();
+        for cell in CellIndex::base_cells() {
+            let (cell_items, belly_items) = retrieve_cell_and_belly(rtxn, &self.cell, cell)?;
+            indexed |= cell_items.unwrap_or_default() | belly_items.unwrap_or_default();
+        }
+        indexed -= self.tombstones(rtxn)?;
+        if exclude_self {
+            indexed.remove(item);
+        }
+
+        let max_resolution = self.max_resolution(rtxn)?;
+        let mut distances = HashMap::new();
+        let mut explored = HashSet::new();
+        loop {
+            // Once the resolution zero is exhausted, the whole globe is explored
+            let disk: HashSet<CellIndex> = match &origins {
+                Some(origins) => origins
+                    .iter()
+                    .flat_map(|origin| origin.grid_disk::<Vec<_>>(1))
+                    .collect(),
+                None => CellIndex::base_cells().collect(),
+            };
+            for &cell in disk.iter() {
+                if !explored.insert(cell) {
+                    continue;
+                }
+                for candidate in self.items_around(rtxn, max_resolution, cell)? & &indexed {
+                    if let Entry::Vacant(entry) = distances.entry(candidate) {
+                        let other = self
+                            .item(rtxn, candidate)?
+                            .ok_or_else(|| Error::InternalDocIdMissing(candidate, pos!()))?;
+                        entry.insert(self.distance_between(&shape, &other.to_geo()));
+                    }
+                }
+            }
+
+            let mut ret: Vec<_> = distances.iter().map(|(item, d)| (*item, *d)).collect();
+            ret.sort_by(|(li, ld), (ri, rd)| ld.total_cmp(rd).then(li.cmp(ri)));
+            ret.truncate(k);
+            let complete = ret.len() == k
+                && ret
+                    .last()
+                    .is_none_or(|(_, d)| *d <= self.distance_outside(&shape, &disk));
+            if complete || origins.is_none() || distances.len() as u64 == indexed.len() {
+                return Ok(ret);
+            }
+            // Growing the disk at a deep resolution would take millions of cells to reach a far
+            // neighbour, its parents cover the same area with a handful of cells
+            origins = resolution.pred().map(|parent_resolution| {
+                resolution = parent_resolution;
+                origins
+                    .iter()
+                    .flatten()
+                    .filter_map(|cell| cell.parent(parent_resolution))
+                    .collect()
+            });
+        }
+    }
+
+    /// The minimum distance in meters between the shape and the cells bordering the `disk`. An
+    /// item intersecting none of the cells of the disk is at least this far from the shape.
+    fn distance_outside(&self, shape: &Geometry, disk: &HashSet<CellIndex>) -> f64 {
+        disk.iter()
+            .flat_map(|cell| cell.grid_disk::<Vec<_>>(1))
+            .filter(|cell| !disk.contains(cell))
+            .map(|cell| self.distance_between(shape, &Geometry::from(MultiPolygon::from(cell))))
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Return the deepest cells and belly cells the item is stored in.
+    fn cells_of_item(&self, rtxn: &RoTxn, item: ItemId) -> Result<Vec<CellIndex>> {
+        let max_resolution = self.max_resolution(rtxn)?;
+        let mut ret = Vec::new();
+        let mut to_explore: VecDeque<_> = CellIndex::base_cells().collect();
+        let mut already_explored: HashSet<CellIndex> = HashSet::with_capacity(to_explore.len());
+
+        while let Some(cell) = to_explore.pop_front() {
+            if !already_explored.insert(cell) {
+                continue;
+            }
+            let (cell_items, belly_items) = retrieve_cell_and_belly(rtxn, &self.cell, cell)?;
+            let cell_items = cell_items.unwrap_or_default();
+            if belly_items.is_some_and(|belly_items| belly_items.contains(item)) {
+                ret.push(cell);
+            } else if cell_items.contains(item) {
+                let resolution = cell.resolution();
+                if cell_items.len() < self.options.threshold || resolution >= max_resolution {
+                    ret.push(cell);
+                } else {
+                    let next_res = resolution.succ().unwrap();
+                    // Same children as the one used while building the database
+                    let center_child = cell.center_child(next_res).unwrap();
+                    to_explore.extend(center_child.grid_disk::<Vec<_>>(2));
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Return the items that may intersect the cell: the items of the cells of the tree
+    /// intersecting it down to its resolution, and the belly items on the way. The sub-cells of a
+    /// split cell are around its center child, they can be under a neighbour of the H3 ancestors
+    /// of the cell.
+    fn items_around(
+        &self,
+        rtxn: &RoTxn,
+        max_resolution: Resolution,
+        cell: CellIndex,
+    ) -> Result<RoaringBitmap> {
+        let shape = MultiPolygon::from(cell);
+        let mut ret = RoaringBitmap::new();
+        // safe to unwrap because every cell has an ancestor at the resolution zero
+        let base_cell = cell.parent(Resolution::Zero).unwrap();
+        let mut to_explore: Vec<CellIndex> = base_cell.grid_disk(1);
+        while !to_explore.is_empty() {
+            let mut next = HashSet::new();
+            for tree_cell in to_explore {
+                if !MultiPolygon::from(tree_cell).intersects(&shape) {
+                    continue;
+                }
+                let (cell_items, belly_items) =
+                    retrieve_cell_and_belly(rtxn, &self.cell, tree_cell)?;
+                ret |= belly_items.unwrap_or_default();
+                let Some(cell_items) = cell_items else {
+                    continue;
+                };
+                let resolution = tree_cell.resolution();
+                if resolution >= cell.resolution()
+                    || cell_items.len() < self.options.threshold
+                    || resolution >= max_resolution
+                {
+                    ret |= cell_items;
+                } else {
+                    // safe to unwrap because the cell is shallower than the max resolution
+                    let next_res = resolution.succ().unwrap();
+                    let center_child = tree_cell.center_child(next_res).unwrap();
+                    next.extend(center_child.grid_disk::<Vec<_>>(2));
+                }
+            }
+            to_explore = next.into_iter().collect();
+        }
+        Ok(ret)
+    }
+
+    /// The distance in meters between the closest points of the two geometries.
+    fn distance_between(&self, left: &Geometry, right: &Geometry) -> f64 {
+        if left.intersects(right) {
+            return 0.0;
+        }
+        // When the geometries don't intersect, the closest points are on a vertex of one of them
+        let closest = |from: &Geometry, to: &Geometry| {
+            from.coords_iter()
+                .filter_map(|coord| match to.closest_point(&Point::from(coord)) {
+                    Closest::Intersection(point) | Closest::SinglePoint(point) => {
+                        Some(self.distance_model.distance(Point::from(coord), point))
+                    }
+                    Closest::Indeterminate => None,
+                })
+                .fold(f64::INFINITY, f64::min)
+        };
+        closest(left, right).min(closest(right, left))
+    }
+}
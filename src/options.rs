@@ -0,0 +1,182 @@
+use h3o::Resolution;
+use heed::{Env, RoTxn, RwTxn};
+
+use crate::{
+    Cellulite, Error, Result, keys::MetadataKey, metadata::OptionsCodec, reader::QueryMode,
+    validation,
+};
+
+/// The configuration of a cellulite database. It's stored in the database and loaded back when
+/// the database is opened, the same options are used by every process reading or writing it.
+///
+/// ```
+/// use cellulite::CelluliteOptions;
+/// use h3o::Resolution;
+///
+/// let options = CelluliteOptions {
+///     threshold: 500,
+///     max_resolution: Resolution::Ten,
+///     ..CelluliteOptions::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CelluliteOptions {
+    /// After how many items a cell is split into sub-cells.
+    pub threshold: u64,
+    /// The deepest resolution the cells can be split to, see [`Cellulite::build_up_to_resolution`].
+    pub max_resolution: Resolution,
+    /// The maximum distance in meters between two vertices of the query shapes. Longer edges are
+    /// densified to follow the distance model.
+    pub densify_distance: f64,
+    /// The relation the items must have with the shape to be returned by [`Cellulite::in_shape`].
+    pub query_mode: QueryMode,
+    /// Store the items covering a whole cell in its belly cell instead of its sub-cells.
+    /// Disabling them makes the builds faster and the database larger when the items are large
+    /// polygons.
+    pub belly_cells: bool,
+    /// What to do when a cell of the maximum resolution contains more items than the threshold,
+    /// usually because the items are at the same place.
+    pub oversized_leaves: OversizedLeafPolicy,
+    /// Densify the items on insertion so their edges are never longer than this many meters,
+    /// following the [`Cellulite::distance_model`]. `None` by default, the items are stored as-is.
+    pub densify_items: Option<f64>,
+    /// Cut the polygons spanning more than this many degrees in fragments of at most this size on
+    /// insertion. The fragments are stored as a single multi-polygon for the same item, and the
+    /// relations with the cells and the queries are computed on smaller polygons.
+    /// `None` by default, the polygons are stored whole. It must be a positive number of degrees.
+    pub fragment_size: Option<f64>,
+}
+
+/// What a build does when a cell cannot be split because it's at the maximum resolution but
+/// contains more items than the threshold, see [`CelluliteOptions::oversized_leaves`].
+/// The number of these cells is reported by [`Cellulite::stats`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OversizedLeafPolicy {
+    /// Keep the items in the cell, the queries must double-check all of them when they reach it.
+    #[default]
+    Allow,
+    /// Fail the build with an [`Error::OversizedLeaf`], the transaction must be aborted and the
+    /// duplicated items removed.
+    Reject,
+}
+
+impl Default for CelluliteOptions {
+    fn default() -> Self {
+        Self {
+            threshold: Cellulite::default_threshold(),
+            max_resolution: Resolution::Fifteen,
+            densify_distance: 1_000.0,
+            query_mode: QueryMode::default(),
+            belly_cells: true,
+            oversized_leaves: OversizedLeafPolicy::default(),
+            densify_items: None,
+            fragment_size: None,
+        }
+    }
+}
+
+impl Cellulite {
+    /// Create all the databases required for cellulite to work and store the options in them.
+    /// See [`Self::create_from_env`] and [`Self::set_options`].
+    pub fn create_with_options<Tls>(
+        env: &Env<Tls>,
+        wtxn: &mut RwTxn,
+        prefix: &str,
+        options: CelluliteOptions,
+    ) -> Result<Self> {
+        let mut cellulite = Self::create_from_env(env, wtxn, prefix)?;
+        cellulite.set_options(wtxn, options)?;
+        Ok(cellulite)
+    }
+
+    /// Return the options used by this handle on the database. They're loaded when the database
+    /// is opened, see [`Self::stored_options`] to read the current ones.
+    pub fn options(&self) -> &CelluliteOptions {
+        &self.options
+    }
+
+    /// Read the options stored in the database. They differ from [`Self::options`] if another
+    /// process or another handle changed them since this handle was opened, in which case
+    /// [`Self::reload_options`] must be called before building the database.
+    pub fn stored_options(&self, rtxn: &RoTxn) -> Result<CelluliteOptions> {
+        let options = self
+            .metadata
+            .remap_data_type::<OptionsCodec>()
+            .get(rtxn, &MetadataKey::Options)?;
+        let max_resolution = self.max_resolution(rtxn)?;
+        Ok(CelluliteOptions {
+            max_resolution,
+            ..options.unwrap_or_default()
+        })
+    }
+
+    /// Replace the options of this handle with the ones stored in the database.
+    pub fn reload_options(&mut self, rtxn: &RoTxn) -> Result<()> {
+        self.options = self.stored_options(rtxn)?;
+        Ok(())
+    }
+
+    /// Update the options and store them in the database.
+    /// Once the database is built, the maximum resolution cannot be increased and the threshold
+    /// cannot be decreased since the cells that should be split are not, use
+    /// [`Self::reindex_with_options`] instead. The changes are checked against the options stored
+    /// in the database, not the ones of this handle. The other options are taken into account by
+    /// the next build.
+    /// Returns [`Error::InvalidFragmentSize`] if the fragment size is not a positive number.
+    pub fn set_options(&mut self, wtxn: &mut RwTxn, options: CelluliteOptions) -> Result<()> {
+        if let Some(size) = options.fragment_size {
+            validation::check_fragment_size(size)?;
+        }
+        if !self.cell_db().is_empty(wtxn)? {
+            let current = self.stored_options(wtxn)?;
+            if options.max_resolution > current.max_resolution {
+                return Err(Error::CannotIncreaseMaxResolution(
+                    current.max_resolution,
+                    options.max_resolution,
+                ));
+            }
+            if options.threshold < current.threshold {
+                return Err(Error::CannotDecreaseThreshold(
+                    current.threshold,
+                    options.threshold,
+                ));
+            }
+        }
+        self.write_options(wtxn, &options)?;
+        self.options = options;
+        Ok(())
+    }
+
+    /// Return an [`Error::StaleOptions`] if another handle changed the options stored in the
+    /// database since this one loaded them. The maximum resolution is ignored, the builds always
+    /// read the stored one.
+    pub(crate) fn check_options_are_current(&self, rtxn: &RoTxn) -> Result<()> {
+        let stored = self
+            .metadata
+            .remap_data_type::<OptionsCodec>()
+            .get(rtxn, &MetadataKey::Options)?;
+        match stored {
+            Some(stored)
+                if stored
+                    != CelluliteOptions {
+                        max_resolution: stored.max_resolution,
+                        ..self.options
+                    } =>
+            {
+                Err(Error::StaleOptions)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn write_options(
+        &self,
+        wtxn: &mut RwTxn,
+        options: &CelluliteOptions,
+    ) -> heed::Result<()> {
+        self.set_max_resolution(wtxn, options.max_resolution)?;
+        self.metadata
+            .remap_data_type::<OptionsCodec>()
+            .put(wtxn, &MetadataKey::Options, options)
+    }
+}
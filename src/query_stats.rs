@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::Cellulite;
+
+/// The counters shared by all the clones of a database once [`Cellulite::enable_query_stats`] is
+/// called.
+#[derive(Debug, Default)]
+pub(crate) struct QueryCounters {
+    queries: AtomicU64,
+    double_checked: AtomicU64,
+    confirmed: AtomicU64,
+}
+
+/// A snapshot of the double-check counters of the queries, returned by
+/// [`Cellulite::query_stats`]. A low [`Self::confirmed_ratio`] means most of the items read to be
+/// double-checked were not returned: lowering the threshold or densifying the items splits the
+/// cells further and reduces the number of shapes to read.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueryStats {
+    /// The number of queries run.
+    pub queries: u64,
+    /// The number of items whose shape was read and compared with the query.
+    pub double_checked: u64,
+    /// The number of double-checked items that were returned.
+    pub confirmed: u64,
+}
+
+impl QueryStats {
+    /// The ratio of the double-checked items that were returned, `1.0` if no item was
+    /// double-checked.
+    pub fn confirmed_ratio(&self) -> f64 {
+        if self.double_checked == 0 {
+            1.0
+        } else {
+            self.confirmed as f64 / self.double_checked as f64
+        }
+    }
+}
+
+impl Cellulite {
+    /// Start counting the items double-checked by the queries in a shape, [`Self::in_item`] and
+    /// the queries on geometries without area. The counters live in memory and are shared by the
+    /// clones of the database made afterward, the queries of all the threads are counted.
+    /// Calling it again keeps the current counters.
+    pub fn enable_query_stats(&mut self) {
+        self.query_stats.get_or_insert_with(Default::default);
+    }
+
+    /// Return the counters since [`Self::enable_query_stats`] or the last
+    /// [`Self::reset_query_stats`], `None` if they're not enabled.
+    pub fn query_stats(&self) -> Option<QueryStats> {
+        self.query_stats.as_ref().map(|counters| QueryStats {
+            queries: counters.queries.load(Ordering::Relaxed),
+            double_checked: counters.double_checked.load(Ordering::Relaxed),
+            confirmed: counters.confirmed.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Reset the counters to zero, for example after changing the threshold.
+    pub fn reset_query_stats(&self) {
+        if let Some(counters) = &self.query_stats {
+            counters.queries.store(0, Ordering::Relaxed);
+            counters.double_checked.store(0, Ordering::Relaxed);
+            counters.confirmed.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a query that read the shapes of `double_checked` items and returned `confirmed` of them.
+    pub(crate) fn record_query(&self, double_checked: u64, confirmed: u64) {
+        if let Some(counters) = &self.query_stats {
+            counters.queries.fetch_add(1, Ordering::Relaxed);
+            counters
+                .double_checked
+                .fetch_add(double_checked, Ordering::Relaxed);
+            counters.confirmed.fetch_add(confirmed, Ordering::Relaxed);
+        }
+    }
+}
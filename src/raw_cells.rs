@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, BTreeSet, btree_map::Entry};
+
+use geo::{Geometry, Intersects, MultiPolygon};
+use h3o::{CellIndex, Resolution};
+use heed::{RoTxn, RwTxn, types::DecodeIgnore};
+use roaring::RoaringBitmap;
+
+use crate::{
+    Cellulite, Error, ItemId, Result,
+    keys::{Key, KeyVariant},
+};
+
+/// The new value of the cells and bellies an item is written in.
+type Entries = BTreeMap<(CellIndex, KeyVariant), RoaringBitmap>;
+
+impl Cellulite {
+    /// Insert an item covering a set of H3 cells, like the aggregates of a mobility dataset. The
+    /// cells can be of any resolution, the ones already covered by one of their ancestors are
+    /// ignored. Returns [`Error::EmptyGeometry`] if there is no cell.
+    ///
+    /// The cells are written directly in the tree and the item is searchable without a build,
+    /// their polygons are only stored to double-check the queries. If the item already exists or
+    /// one of the cells of the tree would have to be split, the item is inserted like any other
+    /// multi-polygon with [`Self::add_geometry`] and must be built.
+    pub fn add_cells(&self, wtxn: &mut RwTxn, item: ItemId, cells: &[CellIndex]) -> Result<()> {
+        let cells = normalize_cells(cells);
+        if cells.is_empty() {
+            return Err(Error::EmptyGeometry(item));
+        }
+        let shape: MultiPolygon = cells
+            .iter()
+            .flat_map(|&cell| MultiPolygon::from(cell).0)
+            .collect();
+
+        let exists = self
+            .item_db()
+            .remap_data_type::<DecodeIgnore>()
+            .get(wtxn, &item)?
+            .is_some();
+        let entries = if exists || self.update.get(wtxn, &item)?.is_some() {
+            None
+        } else {
+            self.entries_of_cells(wtxn, item, &cells)?
+        };
+        let Some(entries) = entries else {
+            return self.add_geometry(wtxn, item, Geometry::MultiPolygon(shape));
+        };
+
+        let geom = self.prepare_geometry(item, Geometry::MultiPolygon(shape))?;
+        self.item_db().put(wtxn, &item, &geom)?;
+        self.expiration.delete(wtxn, &item)?;
+        self.weight.delete(wtxn, &item)?;
+        for ((cell, variant), bitmap) in entries {
+            self.cell_db()
+                .put(wtxn, &Key::from_parts(cell, variant), &bitmap)?;
+        }
+        let item = RoaringBitmap::from_iter([item]);
+        self.update_extents(wtxn, || false, &RoaringBitmap::new(), &item)?;
+        self.increment_build_generation(wtxn)?;
+        Ok(())
+    }
+
+    /// Return the cells of the tree the build would insert the item in, from the resolution zero
+    /// down to its cells. The cells fully covered by the item go in their belly.
+    /// Returns `None` if one of them would reach the threshold and have to be split.
+    fn entries_of_cells(
+        &self,
+        rtxn: &RoTxn,
+        item: ItemId,
+        cells: &[CellIndex],
+    ) -> Result<Option<Entries>> {
+        let max_resolution = self.max_resolution(rtxn)?;
+        let threshold = self.options.threshold;
+        let mut entries = Entries::new();
+
+        for &cell in cells {
+            let shape = MultiPolygon::from(cell);
+            // safe to unwrap because every cell has an ancestor at the resolution zero
+            let base_cell = cell.parent(Resolution::Zero).unwrap();
+            // The cell can overlap with the neighbours of its base cell
+            let mut to_explore: Vec<CellIndex> = base_cell.grid_disk(1);
+
+            for resolution in
+                Resolution::range(Resolution::Zero, cell.resolution().min(max_resolution))
+            {
+                let mut next = Vec::new();
+                for candidate in to_explore {
+                    if !MultiPolygon::from(candidate).intersects(&shape) {
+                        continue;
+                    }
+                    if candidate == cell && self.options.belly_cells {
+                        self.entry(rtxn, &mut entries, Key::Belly(cell))?
+                            .insert(item);
+                        continue;
+                    }
+                    let bitmap = self.entry(rtxn, &mut entries, Key::Cell(candidate))?;
+                    let splittable = resolution < max_resolution;
+                    let split = splittable && bitmap.len() >= threshold;
+                    bitmap.insert(item);
+                    if split && resolution < cell.resolution() {
+                        // The items of a split cell are also inserted in its children
+                        let next_res = resolution.succ().unwrap();
+                        let center_child = candidate.center_child(next_res).unwrap();
+                        next.extend(center_child.grid_disk::<Vec<_>>(2));
+                    } else if splittable && bitmap.len() >= threshold {
+                        return Ok(None);
+                    }
+                }
+                next.sort_unstable();
+                next.dedup();
+                to_explore = next;
+            }
+        }
+
+        Ok(Some(entries))
+    }
+
+    /// Return the bitmap of the key in the entries, read from the database the first time.
+    fn entry<'a>(
+        &self,
+        rtxn: &RoTxn,
+        entries: &'a mut Entries,
+        key: Key,
+    ) -> Result<&'a mut RoaringBitmap> {
+        match entries.entry(key.parts()) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let bitmap = self.cell_db().get(rtxn, &key)?.unwrap_or_default();
+                Ok(entry.insert(bitmap))
+            }
+        }
+    }
+}
+
+/// Sort and deduplicate the cells, and remove the ones whose ancestor is also in the set since
+/// their polygons would overlap.
+fn normalize_cells(cells: &[CellIndex]) -> Vec<CellIndex> {
+    let set: BTreeSet<CellIndex> = cells.iter().copied().collect();
+    set.iter()
+        .copied()
+        .filter(|cell| {
+            let Some(parent_res) = cell.resolution().pred() else {
+                return true;
+            };
+            !Resolution::range(Resolution::Zero, parent_res)
+                .filter_map(|resolution| cell.parent(resolution))
+                .any(|ancestor| set.contains(&ancestor))
+        })
+        .collect()
+}
@@ -1,53 +1,683 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque, hash_map::Entry};
 
-use geo::{Densify, Destination, Haversine, MultiPolygon, Point, Polygon, Relate};
+use geo::{
+    Bearing, BoundingRect, Centroid, Closest, ClosestPoint, ConvexHull, Densify, Destination,
+    Distance, Euclidean, Geodesic, Geometry, Haversine, Intersects, MultiPolygon, Orient, Point,
+    Polygon, Relate, orient::Direction,
+};
 use h3o::{
-    CellIndex, Resolution,
-    geom::{ContainmentMode, TilerBuilder},
+    CellIndex, LatLng, Resolution,
+    geom::{ContainmentMode, PlotterBuilder, TilerBuilder},
 };
 use heed::RoTxn;
 use roaring::RoaringBitmap;
-use zerometry::RelationBetweenShapes;
+use zerometry::{InputRelation, OutputRelation, RelationBetweenShapes, Zerometry};
 
-use crate::{Cellulite, Result};
+use crate::{Cellulite, Error, GeometryKind, GeometryKinds, ItemId, Result, WorkingSet, pos};
 
 impl Cellulite {
     pub fn in_shape(&self, rtxn: &RoTxn, polygon: &Polygon) -> Result<RoaringBitmap> {
         self.in_shape_with_inspector(rtxn, polygon, &mut |_| ())
     }
 
-    /// Return all the items that intersects or are contained in the specified polygon.
+    /// Return all the items that intersects or are contained in the specified polygon, or only
+    /// the items strictly within it depending on [`crate::CelluliteOptions::query_mode`].
     /// The `inspector` lets you see how the search was made internally.
     // The strategy to retrieve the points in a shape is to:
     // 1. Retrieve all the cell@res0 that contains the shape
     // 2. Iterate over these cells
-    //  2.1.If a cell fit entirely *inside* the shape, add all its items to the result
+    //  2.1.If a cell fit entirely *inside* the shape, add all its items to the result, along with
+    //      its belly items. The belly items of its descendants are part of its items, neither are
+    //      checked against the shape.
     //  2.2 Otherwise:
+    //   - If the cell has a belly cell => its items cover the whole cell and thus intersect the
+    //     shape, add them to the result without checking them
     //   - If the cell is a leaf => iterate over all of its point and add the one that fits in the shape to the result
     //   - Otherwise, increase the precision and iterate on the range of cells => repeat step 2
     pub fn in_shape_with_inspector(
         &self,
         rtxn: &RoTxn,
         polygon: &Polygon,
+        inspector: impl FnMut((FilteringStep, CellIndex)),
+    ) -> Result<RoaringBitmap> {
+        let options = QueryOptions {
+            mode: self.options.query_mode,
+            ..QueryOptions::default()
+        };
+        self.search_in_shape(rtxn, polygon, options, None, inspector, None, None)
+    }
+
+    /// Return the items matching the polygon according to the `options`.
+    /// With a `limit` or `stop_after_candidates`, the search stops as soon as enough items were found
+    /// instead of exploring the whole shape.
+    pub fn in_shape_with_options(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+        options: QueryOptions,
+    ) -> Result<RoaringBitmap> {
+        self.search_in_shape(rtxn, polygon, options, None, |_| (), None, None)
+    }
+
+    /// Return all the items that intersects or are contained in the specified geometry.
+    /// The polygons, rectangles and triangles are searched like [`Self::in_shape`], the points
+    /// like [`Self::items_containing_point`] while the lines directly follow the cells they cross.
+    pub fn in_geometry(
+        &self,
+        rtxn: &RoTxn,
+        geometry: impl Into<Geometry>,
+    ) -> Result<RoaringBitmap> {
+        match geometry.into() {
+            Geometry::Polygon(polygon) => self.in_shape(rtxn, &polygon),
+            Geometry::Rect(rect) => self.in_shape(rtxn, &rect.to_polygon()),
+            Geometry::Triangle(triangle) => self.in_shape(rtxn, &triangle.to_polygon()),
+            Geometry::MultiPolygon(multi_polygon) => {
+                let mut ret = RoaringBitmap::new();
+                for polygon in multi_polygon {
+                    ret |= self.in_shape(rtxn, &polygon)?;
+                }
+                Ok(ret)
+            }
+            Geometry::Point(point) => self.items_containing_point(rtxn, point),
+            Geometry::MultiPoint(multi_point) => {
+                let mut ret = RoaringBitmap::new();
+                for point in multi_point {
+                    ret |= self.items_containing_point(rtxn, point)?;
+                }
+                Ok(ret)
+            }
+            Geometry::GeometryCollection(collection) => {
+                let mut ret = RoaringBitmap::new();
+                for geometry in collection {
+                    ret |= self.in_geometry(rtxn, geometry)?;
+                }
+                Ok(ret)
+            }
+            geometry => self.in_thin_geometry(rtxn, &geometry),
+        }
+    }
+
+    /// Return all the items containing or touching the point.
+    /// Only the cells containing the point are explored, which is much faster than searching in a tiny polygon.
+    pub fn items_containing_point(&self, rtxn: &RoTxn, point: Point) -> Result<RoaringBitmap> {
+        let mut ret = self.classify_points(rtxn, &[point])?;
+        // safe to unwrap because there is one bitmap per point
+        Ok(ret.pop().unwrap())
+    }
+
+    /// Return the items containing or touching each point, like [`Self::items_containing_point`].
+    /// The cells and the shapes of the items are only read once for all the points, which is much
+    /// faster when many points are in the same area, like reverse geocoding GPS positions.
+    pub fn classify_points(&self, rtxn: &RoTxn, points: &[Point]) -> Result<Vec<RoaringBitmap>> {
+        let max_resolution = self.max_resolution(rtxn)?;
+        let tombstones = self.tombstones(rtxn)?;
+        let mut cells = HashMap::new();
+        let mut shapes = HashMap::new();
+        let mut classified = Vec::with_capacity(points.len());
+
+        for point in points {
+            let lat_lng = LatLng::try_from(point.0)?;
+            let mut ret = RoaringBitmap::new();
+            let mut double_check = RoaringBitmap::new();
+
+            for resolution in Resolution::range(Resolution::Zero, max_resolution) {
+                let cell = lat_lng.to_cell(resolution);
+                let (cell_items, belly_items) = match cells.entry(cell) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let cell_and_belly =
+                            crate::keys::retrieve_cell_and_belly(rtxn, &self.cell_db(), cell)?;
+                        entry.insert(cell_and_belly)
+                    }
+                };
+                // The belly items covers the whole cell and thus the point
+                if let Some(belly_items) = belly_items {
+                    ret |= &*belly_items;
+                }
+                match cell_items {
+                    Some(cell_items)
+                        if cell_items.len() >= self.options.threshold
+                            && resolution < max_resolution => {}
+                    Some(cell_items) => {
+                        double_check |= &*cell_items;
+                        break;
+                    }
+                    None => break,
+                }
+            }
+
+            ret -= &tombstones;
+            double_check -= &ret;
+            double_check -= &tombstones;
+            for item in double_check {
+                let shape = match shapes.entry(item) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let shape = self
+                            .item(rtxn, item)?
+                            .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+                        entry.insert(shape.to_geo())
+                    }
+                };
+                if shape.intersects(point) {
+                    ret.insert(item);
+                }
+            }
+            classified.push(ret);
+        }
+
+        Ok(classified)
+    }
+
+    /// Join the points of this database with the `zones` of another one, like points of interest
+    /// with administrative boundaries: return the zones containing or touching each item of
+    /// `points`. Both databases must live in the environment of `rtxn`.
+    /// The points are classified together with [`Self::classify_points`], the cells and the
+    /// shapes of the zones are only read once. The items that are not a point are ignored.
+    pub fn annotate_with_zones(
+        &self,
+        rtxn: &RoTxn,
+        points: &RoaringBitmap,
+        zones: &Cellulite,
+    ) -> Result<BTreeMap<ItemId, RoaringBitmap>> {
+        let mut items = Vec::new();
+        let mut positions = Vec::new();
+        for item in points {
+            if let Some(Zerometry::Point(point)) = self.item(rtxn, item)? {
+                items.push(item);
+                positions.push(Point::new(point.lng(), point.lat()));
+            }
+        }
+        let classified = zones.classify_points(rtxn, &positions)?;
+        Ok(items.into_iter().zip(classified).collect())
+    }
+
+    /// Search the items intersecting a geometry without area by following the cells it crosses
+    /// from the resolution zero.
+    fn in_thin_geometry(&self, rtxn: &RoTxn, geometry: &Geometry) -> Result<RoaringBitmap> {
+        let max_resolution = self.max_resolution(rtxn)?;
+        let mut ret = RoaringBitmap::new();
+        let mut double_check = RoaringBitmap::new();
+        let mut to_explore: VecDeque<_> = CellIndex::base_cells().collect();
+        let mut already_explored: HashSet<CellIndex> = HashSet::with_capacity(to_explore.len());
+
+        while let Some(cell) = to_explore.pop_front() {
+            if !already_explored.insert(cell) {
+                continue;
+            }
+
+            let (cell_items, belly_items) =
+                crate::keys::retrieve_cell_and_belly(rtxn, &self.cell_db(), cell)?;
+            if cell_items.is_none() && belly_items.is_none() {
+                continue;
+            }
+            if !geometry.intersects(&MultiPolygon::from(cell)) {
+                continue;
+            }
+            // The belly items covers the whole cell, and thus the part of the geometry in it
+            if let Some(belly_items) = belly_items {
+                ret |= belly_items;
+            }
+            if let Some(cell_items) = cell_items {
+                let resolution = cell.resolution();
+                if cell_items.len() < self.options.threshold || resolution >= max_resolution {
+                    double_check |= cell_items;
+                } else {
+                    let next_res = resolution.succ().unwrap();
+                    // Same children as the one used while building the database
+                    let center_child = cell.center_child(next_res).unwrap();
+                    to_explore.extend(center_child.grid_disk::<Vec<_>>(2));
+                }
+            }
+        }
+
+        let tombstones = self.tombstones(rtxn)?;
+        ret -= &tombstones;
+        double_check -= &ret;
+        double_check -= &tombstones;
+        let (double_checked, before) = (double_check.len(), ret.len());
+        for item in double_check {
+            let shape = self
+                .item(rtxn, item)?
+                .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+            if shape.to_geo().intersects(geometry) {
+                ret.insert(item);
+            }
+        }
+        self.record_query(double_checked, ret.len() - before);
+
+        Ok(ret)
+    }
+
+    /// Return the cells of `resolution` covering the polygon, along with the items intersecting each cell.
+    /// The bitmaps are not filtered by the polygon itself, it's up to you to intersect them with your own
+    /// bitmaps and to double-check the items on the border of the polygon if needed.
+    ///
+    /// The cells deeper than `resolution` are merged in their ancestor, and the items of the belly and leaf
+    /// cells shallower than `resolution` are dispatched to the cells they intersect.
+    /// Beware, the number of cells grows quickly with the resolution.
+    pub fn cells_in_shape(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+        resolution: Resolution,
+    ) -> Result<Vec<(CellIndex, RoaringBitmap)>> {
+        // The results must not depend on the winding order of the query
+        let polygon = self.distance_model.densify(
+            &polygon.orient(Direction::Default),
+            self.options.densify_distance,
+        );
+        let mut tiler = TilerBuilder::new(resolution)
+            .containment_mode(ContainmentMode::Covers)
+            .build();
+        tiler.add(polygon)?;
+
+        let max_resolution = self.max_resolution(rtxn)?;
+        let tombstones = self.tombstones(rtxn)?;
+        // The same ancestors are shared by a lot of cells
+        let mut cache = HashMap::new();
+        let mut ret = Vec::new();
+
+        for cell in tiler.into_coverage() {
+            let cell_shape = MultiPolygon::from(cell);
+            let mut bitmap = RoaringBitmap::new();
+            // The sub-cells of a cell stick out of it, the cells of the tree intersecting our cell
+            // can be under the neighbours of its H3 ancestors
+            // safe to unwrap because every cell has an ancestor at the resolution zero
+            let base_cell = cell.parent(Resolution::Zero).unwrap();
+            let mut to_explore: Vec<CellIndex> = base_cell.grid_disk(1);
+            for res in Resolution::range(Resolution::Zero, resolution) {
+                let mut next = HashSet::new();
+                for tree_cell in to_explore {
+                    if !MultiPolygon::from(tree_cell).intersects(&cell_shape) {
+                        continue;
+                    }
+                    let (cell_items, belly_items) = match cache.get(&tree_cell) {
+                        Some(entry) => entry.clone(),
+                        None => {
+                            let entry = crate::keys::retrieve_cell_and_belly(
+                                rtxn,
+                                &self.cell_db(),
+                                tree_cell,
+                            )?;
+                            cache.insert(tree_cell, entry.clone());
+                            entry
+                        }
+                    };
+                    if let Some(belly_items) = belly_items {
+                        bitmap |= belly_items;
+                    }
+                    match cell_items {
+                        // Our cell exists, it contains all the items that intersect it
+                        Some(cell_items) if tree_cell == cell => bitmap |= cell_items,
+                        // A leaf, its items may not intersect our cell and must be checked
+                        Some(cell_items)
+                            if cell_items.len() < self.options.threshold
+                                || res >= max_resolution =>
+                        {
+                            for item in cell_items.iter() {
+                                let shape = self
+                                    .item(rtxn, item)?
+                                    .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+                                if shape.any_relation(&cell_shape).any_relation() {
+                                    bitmap.insert(item);
+                                }
+                            }
+                        }
+                        // The items of our cell are retrieved from our cell itself
+                        Some(_) if res == resolution => (),
+                        Some(_) => {
+                            // safe to unwrap because we're never going deeper than our cell
+                            let next_res = res.succ().unwrap();
+                            let center_child = tree_cell.center_child(next_res).unwrap();
+                            next.extend(center_child.grid_disk::<Vec<_>>(2));
+                        }
+                        None => (),
+                    }
+                }
+                if next.is_empty() {
+                    break;
+                }
+                to_explore = next.into_iter().collect();
+            }
+            bitmap -= &tombstones;
+            if !bitmap.is_empty() {
+                ret.push((cell, bitmap));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Assign each item of `items` to the cells of `resolution` it intersects.
+    /// A point belongs to a single cell while the lines and polygons can belong to many of them.
+    /// Returns an error if one of the items doesn't exist.
+    ///
+    /// Useful to aggregate the result of a query in hex-bins.
+    /// Beware, a large polygon is covered by a lot of cells when the resolution is high.
+    pub fn group_by_cell(
+        &self,
+        rtxn: &RoTxn,
+        items: &RoaringBitmap,
+        resolution: Resolution,
+    ) -> Result<BTreeMap<CellIndex, RoaringBitmap>> {
+        let mut ret: BTreeMap<CellIndex, RoaringBitmap> = BTreeMap::new();
+        let mut cells = Vec::new();
+
+        for item in items {
+            let shape = self
+                .item(rtxn, item)?
+                .ok_or(Error::ItemDoesntExists(item))?;
+            cells.clear();
+            geometry_to_cells(item, &shape.to_geo(), resolution, &mut cells)?;
+            cells.sort_unstable();
+            cells.dedup();
+            for cell in cells.iter() {
+                ret.entry(*cell).or_default().insert(item);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Sum the weights of the items of `items` in the cells of `resolution` they intersect, like
+    /// [`Self::group_by_cell`]. The items without a weight count for one.
+    /// An item intersecting multiple cells adds its whole weight to each of them.
+    pub fn weight_by_cell(
+        &self,
+        rtxn: &RoTxn,
+        items: &RoaringBitmap,
+        resolution: Resolution,
+    ) -> Result<BTreeMap<CellIndex, f64>> {
+        let mut weights = HashMap::with_capacity(items.len() as usize);
+        for item in items {
+            weights.insert(item, self.weight(rtxn, item)?.unwrap_or(1.0));
+        }
+        Ok(self
+            .group_by_cell(rtxn, items, resolution)?
+            .into_iter()
+            .map(|(cell, items)| (cell, items.iter().map(|item| weights[&item]).sum()))
+            .collect())
+    }
+
+    /// Return the groups of items whose centroid falls in the same cell of `resolution`, to find
+    /// the duplicates of a noisy dataset. Only the cells containing at least two items are
+    /// returned. The items with exactly the same geometry are always grouped together, use a high
+    /// resolution to only group the items at about the same place.
+    /// The items are read from the item database, they don't need to be built.
+    pub fn duplicate_locations(
+        &self,
+        rtxn: &RoTxn,
+        resolution: Resolution,
+    ) -> Result<BTreeMap<CellIndex, RoaringBitmap>> {
+        let tombstones = self.tombstones(rtxn)?;
+        let mut groups: BTreeMap<CellIndex, RoaringBitmap> = BTreeMap::new();
+        for ret in self.items(rtxn)? {
+            let (item, shape) = ret?;
+            if tombstones.contains(item) {
+                continue;
+            }
+            // Only happens on empty shapes, they're not anywhere
+            let Some(centroid) = shape.to_geo().centroid() else {
+                continue;
+            };
+            let cell = LatLng::try_from(centroid.0)?.to_cell(resolution);
+            groups.entry(cell).or_default().insert(item);
+        }
+        groups.retain(|_, items| items.len() > 1);
+        Ok(groups)
+    }
+
+    /// Return all the items that are entirely contained in the specified polygon.
+    /// The items touching the border of the polygon or going outside of it are excluded.
+    pub fn in_shape_strictly_within(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+    ) -> Result<RoaringBitmap> {
+        let options = QueryOptions {
+            mode: QueryMode::StrictlyWithin,
+            ..QueryOptions::default()
+        };
+        self.search_in_shape(rtxn, polygon, options, None, |_| (), None, None)
+    }
+
+    /// Return all the items that intersects or are contained in the specified polygon along with
+    /// the reason they were returned and the cell in which they were confirmed.
+    /// It's slower than [`Self::in_shape`] and should be used to debug unexpected results.
+    pub fn in_shape_with_provenance(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+    ) -> Result<BTreeMap<ItemId, Provenance>> {
+        let mut provenance = BTreeMap::new();
+        self.search_in_shape(
+            rtxn,
+            polygon,
+            QueryOptions::default(),
+            None,
+            |_| (),
+            Some(&mut provenance),
+            None,
+        )?;
+        Ok(provenance)
+    }
+
+    /// Densify the polygon and tile it once to run the same query many times, even in other
+    /// transactions, with [`Self::in_shape_prepared`]. Useful to evaluate a set of shapes
+    /// continuously, like the zones of an alerting system.
+    pub fn prepare_shape(&self, polygon: &Polygon) -> Result<PreparedShape> {
+        let densified = self.densify_query(polygon);
+        let resolution = start_resolution(&densified, self.distance_model);
+        let mut tiler = TilerBuilder::new(resolution)
+            .containment_mode(ContainmentMode::Covers)
+            .build();
+        tiler.add(densified.clone())?;
+        Ok(PreparedShape {
+            polygon: polygon.clone(),
+            densified,
+            coverage: tiler.into_coverage().collect(),
+            resolution,
+            distance_model: self.distance_model,
+            densify_distance: self.options.densify_distance,
+        })
+    }
+
+    /// Same as [`Self::in_shape`] with a shape prepared by [`Self::prepare_shape`], the shape is
+    /// neither densified nor tiled again.
+    /// If the distance model or the options of the database changed since the shape was prepared,
+    /// it's prepared again for this query, the results are always the same as [`Self::in_shape`].
+    pub fn in_shape_prepared(&self, rtxn: &RoTxn, shape: &PreparedShape) -> Result<RoaringBitmap> {
+        let options = QueryOptions {
+            mode: self.options.query_mode,
+            ..QueryOptions::default()
+        };
+        self.in_shape_prepared_with_options(rtxn, shape, options)
+    }
+
+    /// Same as [`Self::in_shape_with_options`] with a shape prepared by [`Self::prepare_shape`].
+    pub fn in_shape_prepared_with_options(
+        &self,
+        rtxn: &RoTxn,
+        shape: &PreparedShape,
+        options: QueryOptions,
+    ) -> Result<RoaringBitmap> {
+        if shape.distance_model != self.distance_model
+            || shape.densify_distance != self.options.densify_distance
+        {
+            return self.search_in_shape(rtxn, &shape.polygon, options, None, |_| (), None, None);
+        }
+        // The search must start at the maximum resolution when the shape is tiled deeper
+        let coverage =
+            (shape.resolution <= self.max_resolution(rtxn)?).then(|| shape.coverage.clone());
+        self.search_in_densified_shape(
+            rtxn,
+            &shape.densified,
+            options,
+            coverage,
+            |_| (),
+            None,
+            None,
+        )
+    }
+
+    /// The query shapes are densified so their edges follow the [`Self::distance_model`], and
+    /// oriented so the results don't depend on their winding order.
+    fn densify_query(&self, polygon: &Polygon) -> Polygon {
+        self.distance_model.densify(
+            &polygon.orient(Direction::Default),
+            self.options.densify_distance,
+        )
+    }
+
+    /// The `coverage` is the set of cells, all at the same resolution, the search starts from.
+    /// If `None`, the shape is tiled at the most appropriate resolution.
+    /// If `allowed` is set, only these items can be returned and the other ones are never
+    /// double-checked.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn search_in_shape(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+        options: QueryOptions,
+        coverage: Option<Vec<CellIndex>>,
+        inspector: impl FnMut((FilteringStep, CellIndex)),
+        provenance: Option<&mut BTreeMap<ItemId, Provenance>>,
+        allowed: Option<&RoaringBitmap>,
+    ) -> Result<RoaringBitmap> {
+        let polygon = self.densify_query(polygon);
+        self.search_in_densified_shape(
+            rtxn, &polygon, options, coverage, inspector, provenance, allowed,
+        )
+    }
+
+    /// Same as [`Self::search_in_shape`] with a polygon already densified by
+    /// [`Self::densify_query`].
+    #[allow(clippy::too_many_arguments)]
+    fn search_in_densified_shape(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+        options: QueryOptions,
+        coverage: Option<Vec<CellIndex>>,
         mut inspector: impl FnMut((FilteringStep, CellIndex)),
+        mut provenance: Option<&mut BTreeMap<ItemId, Provenance>>,
+        allowed: Option<&RoaringBitmap>,
     ) -> Result<RoaringBitmap> {
         // Roughly equivalent to the number of children we would have in three cells
         const BECOME_TOO_LARGE: usize = 60;
 
-        let polygon = Haversine.densify(polygon, 1_000.0);
-        let mut tiler = TilerBuilder::new(Resolution::Zero)
-            .containment_mode(ContainmentMode::Covers)
-            .build();
-        tiler.add(polygon.clone())?;
+        let mode = options.mode;
+        // The number of confirmed items after which we can stop searching
+        let enough = options.limit;
+        // The confirmed items of the other kinds are only dropped at the end, they must not stop
+        // the exploration of the cells
+        let stop_early = enough.filter(|_| options.kinds.is_none());
+        // The items deleted with a tombstone must not be returned even though they're still in the cells
+        let tombstones = self.tombstones(rtxn)?;
+
+        // The cells deeper than the max resolution are never subdivided, there is no need to start below it
+        let max_resolution = self.max_resolution(rtxn)?;
+        let (start_resolution, coverage) = match coverage {
+            Some(coverage) => {
+                let resolution = coverage
+                    .first()
+                    .map_or(Resolution::Zero, |cell| cell.resolution());
+                if resolution > max_resolution {
+                    let mut parents: Vec<_> = coverage
+                        .into_iter()
+                        .map(|cell| cell.parent(max_resolution).unwrap())
+                        .collect();
+                    parents.sort_unstable();
+                    parents.dedup();
+                    (max_resolution, parents)
+                } else {
+                    (resolution, coverage)
+                }
+            }
+            None => {
+                let start_resolution =
+                    start_resolution(polygon, self.distance_model).min(max_resolution);
+                let mut tiler = TilerBuilder::new(start_resolution)
+                    .containment_mode(ContainmentMode::Covers)
+                    .build();
+                tiler.add(polygon.clone())?;
+                (start_resolution, tiler.into_coverage().collect())
+            }
+        };
 
         let mut ret = RoaringBitmap::new();
         let mut double_check = RoaringBitmap::new();
-        let mut to_explore: VecDeque<_> = tiler.into_coverage().collect();
+        // Items that we know cannot be strictly within the shape
+        let mut excluded = RoaringBitmap::new();
+        let mut to_explore = VecDeque::new();
+        if start_resolution == Resolution::Zero {
+            to_explore.extend(coverage);
+        } else {
+            // We're skipping the first levels of the tree, we must walk down the tree to find where
+            // it actually stops and to retrieve the belly items on the way.
+            let mut no_provenance = None;
+            let (bellies, bellies_provenance) = match mode {
+                QueryMode::Intersects => (&mut ret, &mut provenance),
+                // The cells above the start resolution are larger than the shape, their belly items
+                // cannot be within it
+                QueryMode::StrictlyWithin => (&mut excluded, &mut no_provenance),
+            };
+            self.walk_down_the_tree(
+                rtxn,
+                polygon,
+                mode,
+                start_resolution,
+                coverage,
+                bellies,
+                &mut to_explore,
+                &mut inspector,
+                bellies_provenance,
+            )?;
+        }
         let mut already_explored: HashSet<CellIndex> = HashSet::with_capacity(to_explore.len());
         let mut too_large = false;
         let mut already_tiled = None;
+        // Only used to track the provenance of the double checked items
+        let mut double_check_cells = HashMap::new();
 
         while let Some(cell) = to_explore.pop_front() {
+            if stop_early.is_some() || options.stop_after_candidates.is_some() {
+                let (confirmed, double_check_len) = match allowed {
+                    Some(allowed) => (
+                        (&ret - &tombstones).intersection_len(allowed),
+                        double_check.intersection_len(allowed),
+                    ),
+                    None => (
+                        ret.len() - ret.intersection_len(&tombstones),
+                        double_check.len(),
+                    ),
+                };
+                let candidates = confirmed + double_check_len;
+                if stop_early.is_some_and(|enough| confirmed >= enough)
+                    || options
+                        .stop_after_candidates
+                        .is_some_and(|stop| candidates >= stop)
+                {
+                    break;
+                }
+            }
+            if let Some(limit) = options.working_set_limit
+                && (to_explore.len() > limit.max_queued_cells
+                    || double_check.len() > limit.max_double_check)
+            {
+                match limit.on_exceeded {
+                    OnLimitExceeded::Approximate => break,
+                    OnLimitExceeded::Fail => {
+                        return Err(Error::QueryTooLarge(WorkingSet {
+                            explored_cells: already_explored.len(),
+                            queued_cells: to_explore.len(),
+                            double_check: double_check.len(),
+                            confirmed: ret.len(),
+                        }));
+                    }
+                }
+            }
             if !already_explored.insert(cell) {
                 continue;
             }
@@ -73,21 +703,66 @@ impl Cellulite {
                     if let Some(next_res) = cell.resolution().succ() {
                         already_explored.extend(cell.children(next_res));
                     }
-                    ret |= cell_items;
+                    match mode {
+                        QueryMode::Intersects => {
+                            record_provenance(
+                                &mut provenance,
+                                &cell_items,
+                                MatchSource::ContainedCell,
+                                cell,
+                            );
+                            ret |= cell_items;
+                        }
+                        // The items of the cell may go outside of the shape
+                        QueryMode::StrictlyWithin => {
+                            add_to_double_check(
+                                &mut double_check,
+                                &mut double_check_cells,
+                                provenance.is_some(),
+                                &cell_items,
+                                cell,
+                            );
+                        }
+                    }
                 }
                 if let Some(belly_items) = belly_items {
-                    ret |= belly_items;
+                    match mode {
+                        QueryMode::Intersects => {
+                            (inspector)((FilteringStep::ReturnedFromBelly, cell));
+                            record_provenance(
+                                &mut provenance,
+                                &belly_items,
+                                MatchSource::BellyCell,
+                                cell,
+                            );
+                            ret |= belly_items;
+                        }
+                        QueryMode::StrictlyWithin => {
+                            add_to_double_check(
+                                &mut double_check,
+                                &mut double_check_cells,
+                                provenance.is_some(),
+                                &belly_items,
+                                cell,
+                            );
+                        }
+                    }
                 }
             } else if relate.is_intersects() {
                 if let Some(cell_items) = cell_items {
                     let resolution = cell.resolution();
-                    if cell_items.len() < self.threshold || resolution == Resolution::Fifteen {
+                    if cell_items.len() < self.options.threshold || resolution >= max_resolution {
                         (inspector)((FilteringStep::RequireDoubleCheck, cell));
-                        double_check |= cell_items;
-                    } else if already_tiled == Some(resolution) {
-                        // We already tiled the whole shape at a previous step, no need to do it again
-                        continue;
-                    } else {
+                        add_to_double_check(
+                            &mut double_check,
+                            &mut double_check_cells,
+                            provenance.is_some(),
+                            &cell_items,
+                            cell,
+                        );
+                    } else if already_tiled != Some(resolution) {
+                        // Otherwise we already tiled the whole shape at a previous step, no need to
+                        // do it again, but the belly items of the cell must still be returned
                         let next_res = resolution.succ().unwrap();
                         (inspector)((FilteringStep::DeepDive, cell));
                         let mut tiler = TilerBuilder::new(next_res)
@@ -115,7 +790,20 @@ impl Cellulite {
                     }
                 }
                 if let Some(belly_items) = belly_items {
-                    ret |= belly_items;
+                    match mode {
+                        QueryMode::Intersects => {
+                            (inspector)((FilteringStep::ReturnedFromBelly, cell));
+                            record_provenance(
+                                &mut provenance,
+                                &belly_items,
+                                MatchSource::BellyCell,
+                                cell,
+                            );
+                            ret |= belly_items;
+                        }
+                        // The items covers a cell that goes outside of the shape
+                        QueryMode::StrictlyWithin => excluded |= belly_items,
+                    }
                 }
             } else {
                 // else: we can ignore the cell, it's not part of our shape
@@ -123,20 +811,171 @@ impl Cellulite {
             }
         }
 
+        ret -= &tombstones;
+        if let Some(provenance) = provenance.as_mut() {
+            provenance.retain(|item, _| !tombstones.contains(*item));
+        }
         // Since we have overlap some items may have been definitely validated somewhere but were also included as something to double check
         double_check -= &ret;
+        double_check -= &excluded;
+        double_check -= &tombstones;
+        if let Some(allowed) = allowed {
+            ret &= allowed;
+            double_check &= allowed;
+            if let Some(provenance) = provenance.as_mut() {
+                provenance.retain(|item, _| allowed.contains(*item));
+            }
+        }
+
+        // The items whose bounding box is far from the shape don't need to be read, unless their
+        // shape changed since the last build
+        if !double_check.is_empty()
+            && let Some(candidates) = self.extent_candidates(rtxn, polygon)?
+        {
+            for item in &double_check - &candidates {
+                if self.update.get(rtxn, &item)?.is_none() {
+                    double_check.remove(item);
+                }
+            }
+        }
 
+        // Only the tag of the confirmed items is read, the kind of the items to double-check is
+        // known once their shape is read
+        if let Some(kinds) = options.kinds {
+            let mut other_kinds = RoaringBitmap::new();
+            for item in &ret {
+                let kind = self
+                    .kind_of(rtxn, item)?
+                    .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+                if !kinds.contains(kind) {
+                    other_kinds.insert(item);
+                }
+            }
+            ret -= &other_kinds;
+            if let Some(provenance) = provenance.as_mut() {
+                provenance.retain(|item, _| !other_kinds.contains(*item));
+            }
+        }
+
+        let (mut double_checked, before) = (0, ret.len());
         for item in double_check {
-            let shape = self.item_db().get(rtxn, &item)?.unwrap();
-            if shape.any_relation(&polygon).any_relation() {
+            if enough.is_some_and(|enough| ret.len() >= enough) {
+                break;
+            }
+            let shape = self.item(rtxn, item)?.unwrap();
+            if options
+                .kinds
+                .is_some_and(|kinds| !kinds.contains(GeometryKind::of(&shape)))
+            {
+                continue;
+            }
+            double_checked += 1;
+            let matches = match mode {
+                QueryMode::Intersects => shape.any_relation(polygon).any_relation(),
+                QueryMode::StrictlyWithin => shape
+                    .relation(polygon, InputRelation::all())
+                    .strict_contained
+                    .unwrap_or_default(),
+            };
+            if matches {
+                if let Some(provenance) = provenance.as_mut() {
+                    provenance.entry(item).or_insert(Provenance {
+                        source: MatchSource::DoubleCheck,
+                        cell: double_check_cells[&item],
+                    });
+                }
                 ret.insert(item);
             }
         }
+        self.record_query(double_checked, ret.len() - before);
+
+        if let Some(limit) = options.limit {
+            ret = ret.into_iter().take(limit as usize).collect();
+            if let Some(provenance) = provenance.as_mut() {
+                provenance.retain(|item, _| ret.contains(*item));
+            }
+        }
 
         Ok(ret)
     }
 
-    /// Retrieve all items intersecting a circle with a given center and radius, according to the Haversine model.
+    /// Walk down the tree from the resolution zero through the cells covering the shape, and push
+    /// in `to_explore` the leaves we encounter and the cells of the `coverage` that are sub-cells
+    /// of a split cell. The belly items of the cells intersecting the shape are added to `ret` on
+    /// the way.
+    ///
+    /// The sub-cells of a cell stick out of it: an item touching the shape can be stored under a
+    /// neighbour of the H3 ancestor of a cell of the coverage. The shape is tiled again at every
+    /// resolution instead of following the H3 parents.
+    #[allow(clippy::too_many_arguments)]
+    fn walk_down_the_tree(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+        mode: QueryMode,
+        start_resolution: Resolution,
+        coverage: Vec<CellIndex>,
+        ret: &mut RoaringBitmap,
+        to_explore: &mut VecDeque<CellIndex>,
+        inspector: &mut impl FnMut((FilteringStep, CellIndex)),
+        provenance: &mut Option<&mut BTreeMap<ItemId, Provenance>>,
+    ) -> Result<()> {
+        // The sub-cells of the split cells of the previous resolution, every cell is a root at the
+        // resolution zero
+        let mut sub_cells: Option<HashSet<CellIndex>> = None;
+        // safe to unwrap because we only walk down the tree when we start below the resolution zero
+        let last_resolution = start_resolution.pred().unwrap();
+        for resolution in Resolution::range(Resolution::Zero, last_resolution) {
+            let mut tiler = TilerBuilder::new(resolution)
+                .containment_mode(ContainmentMode::Covers)
+                .build();
+            tiler.add(polygon.clone())?;
+            let mut next_sub_cells = HashSet::new();
+            for cell in tiler.into_coverage() {
+                if sub_cells
+                    .as_ref()
+                    .is_some_and(|sub_cells| !sub_cells.contains(&cell))
+                {
+                    continue;
+                }
+                let (cell_items, belly_items) =
+                    crate::keys::retrieve_cell_and_belly(rtxn, &self.cell_db(), cell)?;
+                if let Some(belly_items) = belly_items
+                    && polygon.relate(&MultiPolygon::from(cell)).is_intersects()
+                {
+                    if mode == QueryMode::Intersects {
+                        (inspector)((FilteringStep::ReturnedFromBelly, cell));
+                    }
+                    record_provenance(provenance, &belly_items, MatchSource::BellyCell, cell);
+                    *ret |= belly_items;
+                }
+
+                match cell_items {
+                    None => (inspector)((FilteringStep::NotPresentInDB, cell)),
+                    Some(cell_items) if cell_items.len() < self.options.threshold => {
+                        to_explore.push_back(cell);
+                    }
+                    Some(_) => {
+                        (inspector)((FilteringStep::DeepDive, cell));
+                        // safe to unwrap because we never go deeper than the start resolution
+                        let next_res = resolution.succ().unwrap();
+                        let center_child = cell.center_child(next_res).unwrap();
+                        next_sub_cells.extend(center_child.grid_disk::<Vec<_>>(2));
+                    }
+                }
+            }
+            if next_sub_cells.is_empty() {
+                return Ok(());
+            }
+            sub_cells = Some(next_sub_cells);
+        }
+
+        let sub_cells = sub_cells.unwrap_or_default();
+        to_explore.extend(coverage.into_iter().filter(|cell| sub_cells.contains(cell)));
+        Ok(())
+    }
+
+    /// Retrieve all items intersecting a circle with a given center and radius, according to the [`Self::distance_model`].
     /// This is approximate. It may miss items that are in the circle, but it will never return items that are not in the circle.
     /// The resolution parameter controls the number of points used to approximate the circle.
     ///
@@ -148,7 +987,14 @@ impl Cellulite {
         radius: f64,
         resolution: usize,
     ) -> Result<RoaringBitmap> {
-        self.in_circle_with_inspector(rtxn, center, radius, resolution, &Haversine, &mut |_| ())
+        self.in_circle_with_inspector(
+            rtxn,
+            center,
+            radius,
+            resolution,
+            &self.distance_model,
+            &mut |_| (),
+        )
     }
 
     /// Retrieve all items intersecting a circle with a given center and radius.
@@ -176,6 +1022,642 @@ impl Cellulite {
 
         self.in_shape_with_inspector(rtxn, &polygon, inspector)
     }
+
+    /// Retrieve all items intersecting a circle with a given center and radius, according to the [`Self::distance_model`].
+    /// Instead of tiling a polygon approximating the circle, the search starts from a disk of cells around the cell of
+    /// the center that is large enough to cover the whole circle.
+    /// The items are then checked against the real distance to the center, the result is exact.
+    pub fn in_circle_with_grid_disk(
+        &self,
+        rtxn: &RoTxn,
+        center: Point,
+        radius: f64,
+    ) -> Result<RoaringBitmap> {
+        let coverage = self.circle_grid_disk(center, radius)?;
+        // The hull of the disk covers the circle, all the items intersecting the circle are in it
+        let hull = coverage
+            .iter()
+            .flat_map(|&cell| MultiPolygon::from(cell).0)
+            .collect::<MultiPolygon>()
+            .convex_hull();
+        let candidates = self.search_in_shape(
+            rtxn,
+            &hull,
+            QueryOptions::default(),
+            Some(coverage),
+            |_| (),
+            None,
+            None,
+        )?;
+
+        let mut ret = RoaringBitmap::new();
+        for item in candidates.iter() {
+            let shape = self
+                .item(rtxn, item)?
+                .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+            let distance = match shape.to_geo().closest_point(&center) {
+                Closest::Intersection(_) => 0.0,
+                Closest::SinglePoint(point) => self.distance_model.distance(center, point),
+                // Only happens on empty shapes
+                Closest::Indeterminate => continue,
+            };
+            if distance <= radius {
+                ret.insert(item);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Return a disk of cells around the cell of the center covering the whole circle.
+    /// The size of the cells varies, especially around the pentagons, we can't rely on their
+    /// average size: the rings are added until every cell of the outer ring is out of the circle.
+    fn circle_grid_disk(&self, center: Point, radius: f64) -> Result<Vec<CellIndex>> {
+        let resolution = grid_disk_resolution(radius);
+        let cell = LatLng::try_from(center.0)?.to_cell(resolution);
+        let mut k = 1;
+        loop {
+            let disk: Vec<(CellIndex, u32)> = cell.grid_disk_distances(k);
+            let mut outer_ring = disk
+                .iter()
+                .filter(|(_, distance)| *distance == k)
+                .peekable();
+            // When the outer ring is empty the disk already covers the whole earth
+            let covered = outer_ring.peek().is_none()
+                || outer_ring.all(|(cell, _)| {
+                    cell_distance_bounds(self.distance_model, center, *cell).0 > radius
+                });
+            if covered {
+                return Ok(disk.into_iter().map(|(cell, _)| cell).collect());
+            }
+            k += 1;
+        }
+    }
+
+    /// Retrieve all items intersecting an ellipse, according to the [`Self::distance_model`].
+    /// The `semi_major` and `semi_minor` axes are expressed in meters, and the `bearing` is the
+    /// direction of the major axis in degrees clockwise from the north.
+    /// Like the circles, the ellipse is approximated with a polygon inscribed in it.
+    pub fn in_ellipse(
+        &self,
+        rtxn: &RoTxn,
+        center: Point,
+        semi_major: f64,
+        semi_minor: f64,
+        bearing: f64,
+    ) -> Result<RoaringBitmap> {
+        const NB_POINTS: usize = 36;
+
+        let mut points = Vec::with_capacity(NB_POINTS);
+        for i in 0..NB_POINTS {
+            // The angle between the major axis and the point
+            let angle = (360.0 * i as f64 / NB_POINTS as f64).to_radians();
+            let (sin, cos) = angle.sin_cos();
+            let radius = semi_major * semi_minor
+                / ((semi_minor * cos).powi(2) + (semi_major * sin).powi(2)).sqrt();
+            points.push(self.distance_model.destination(
+                center,
+                bearing + angle.to_degrees(),
+                radius,
+            ));
+        }
+        let polygon = Polygon::new(points.into(), Vec::new());
+
+        self.in_shape(rtxn, &polygon)
+    }
+
+    /// Classify the items by their distance to the `origin`, according to the [`Self::distance_model`].
+    /// The bucket `i` contains the items whose distance is in `bucket_edges[i - 1]..bucket_edges[i]`, and an
+    /// additional bucket at the end contains the items further than the last edge. The edges must be sorted.
+    /// If `candidates` is specified, only these items are classified.
+    ///
+    /// Most items are classified with the distance bounds of the cells they're in,
+    /// only the ones whose bounds overlap multiple buckets are measured.
+    pub fn distance_buckets(
+        &self,
+        rtxn: &RoTxn,
+        origin: Point,
+        bucket_edges: &[f64],
+        candidates: Option<&RoaringBitmap>,
+    ) -> Result<Vec<RoaringBitmap>> {
+        let bucket = |distance: f64| bucket_edges.partition_point(|edge| *edge <= distance);
+        let max_resolution = self.max_resolution(rtxn)?;
+        let tombstones = self.tombstones(rtxn)?;
+
+        // The closest and furthest distance at which each item can be.
+        // Every item is entirely covered by the belly and leaf cells it's in, thus its distance
+        // cannot be lower than the lowest bound of these cells, and is lower than any of their upper bound.
+        let mut bounds: HashMap<ItemId, (f64, f64)> = HashMap::new();
+        let mut to_explore: VecDeque<_> = CellIndex::base_cells().collect();
+        let mut already_explored: HashSet<CellIndex> = HashSet::with_capacity(to_explore.len());
+
+        while let Some(cell) = to_explore.pop_front() {
+            if !already_explored.insert(cell) {
+                continue;
+            }
+            let (cell_items, belly_items) =
+                crate::keys::retrieve_cell_and_belly(rtxn, &self.cell_db(), cell)?;
+            let mut terminal = belly_items.unwrap_or_default();
+            if let Some(cell_items) = cell_items {
+                let resolution = cell.resolution();
+                if cell_items.len() < self.options.threshold || resolution >= max_resolution {
+                    terminal |= cell_items;
+                } else if candidates.is_none_or(|candidates| !candidates.is_disjoint(&cell_items)) {
+                    let next_res = resolution.succ().unwrap();
+                    // Same children as the one used while building the database
+                    let center_child = cell.center_child(next_res).unwrap();
+                    to_explore.extend(center_child.grid_disk::<Vec<_>>(2));
+                }
+            }
+            if let Some(candidates) = candidates {
+                terminal &= candidates;
+            }
+            if terminal.is_empty() {
+                continue;
+            }
+
+            let (lower, upper) = cell_distance_bounds(self.distance_model, origin, cell);
+            for item in terminal.iter() {
+                let entry = bounds.entry(item).or_insert((lower, upper));
+                entry.0 = entry.0.min(lower);
+                entry.1 = entry.1.min(upper);
+            }
+        }
+
+        let mut ret = vec![RoaringBitmap::new(); bucket_edges.len() + 1];
+        for (item, (lower, upper)) in bounds {
+            if tombstones.contains(item) {
+                continue;
+            }
+            let lower_bucket = bucket(lower);
+            if lower_bucket == bucket(upper) {
+                ret[lower_bucket].insert(item);
+                continue;
+            }
+            let shape = self
+                .item(rtxn, item)?
+                .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+            let distance = match shape.to_geo().closest_point(&origin) {
+                Closest::Intersection(_) => 0.0,
+                Closest::SinglePoint(point) => self.distance_model.distance(origin, point),
+                // Only happens on empty shapes
+                Closest::Indeterminate => continue,
+            };
+            ret[bucket(distance)].insert(item);
+        }
+
+        Ok(ret)
+    }
+
+    /// Return all the other items that satisfy the `predicate` with the shape of an already indexed item.
+    /// The cells and belly cells of the region are directly used as the starting point of the search,
+    /// which means the shape of the region is never tiled again.
+    /// The region must have been built, otherwise nothing will be returned.
+    // The strategy is to:
+    // 1. Start from all the cell@res0 and only keep the ones containing the region in their bitmaps
+    // 2. If the region is in the belly of the cell, all the items of the cell intersect the region
+    // 3. Otherwise:
+    //   - If the cell is a leaf => all of its items must be double checked
+    //   - Otherwise, explore the children of the cell => repeat step 2
+    pub fn in_item(
+        &self,
+        rtxn: &RoTxn,
+        region_item: ItemId,
+        predicate: ItemPredicate,
+    ) -> Result<RoaringBitmap> {
+        let region = self
+            .item(rtxn, region_item)?
+            .ok_or(Error::ItemDoesntExists(region_item))?;
+
+        let max_resolution = self.max_resolution(rtxn)?;
+        let mut ret = RoaringBitmap::new();
+        let mut double_check = RoaringBitmap::new();
+        let mut to_explore: VecDeque<_> = CellIndex::base_cells().collect();
+        let mut already_explored: HashSet<CellIndex> = HashSet::with_capacity(to_explore.len());
+
+        while let Some(cell) = to_explore.pop_front() {
+            if !already_explored.insert(cell) {
+                continue;
+            }
+
+            let (cell_items, belly_items) =
+                crate::keys::retrieve_cell_and_belly(rtxn, &self.cell_db(), cell)?;
+            let cell_items = cell_items.unwrap_or_default();
+            let belly_items = belly_items.unwrap_or_default();
+
+            if belly_items.contains(region_item) {
+                // The region covers the whole cell, everything in it intersects the region
+                let items = cell_items | belly_items;
+                if predicate == ItemPredicate::Intersects {
+                    ret |= items;
+                } else {
+                    double_check |= items;
+                }
+            } else if cell_items.contains(region_item) {
+                // The belly items covers the whole cell that is touched by the region
+                if predicate == ItemPredicate::Intersects {
+                    ret |= belly_items;
+                } else {
+                    double_check |= belly_items;
+                }
+
+                let resolution = cell.resolution();
+                if cell_items.len() < self.options.threshold || resolution >= max_resolution {
+                    double_check |= cell_items;
+                } else {
+                    let next_res = resolution.succ().unwrap();
+                    // Same children as the one used while building the database
+                    let center_child = cell.center_child(next_res).unwrap();
+                    to_explore.extend(center_child.grid_disk::<Vec<_>>(2));
+                }
+            }
+        }
+
+        let tombstones = self.tombstones(rtxn)?;
+        ret -= &tombstones;
+        double_check -= &ret;
+        double_check -= &tombstones;
+        double_check.remove(region_item);
+        let (double_checked, before) = (double_check.len(), ret.len());
+        for item in double_check {
+            let shape = self
+                .item(rtxn, item)?
+                .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+            let relation = shape.relation(&region, InputRelation::all());
+            if predicate.matches(&relation) {
+                ret.insert(item);
+            }
+        }
+        self.record_query(double_checked, ret.len() - before);
+        ret.remove(region_item);
+
+        Ok(ret)
+    }
+}
+
+/// The relation an item must have with the region in [`Cellulite::in_item`] to be returned.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ItemPredicate {
+    /// The item intersects, contains or is contained in the region.
+    Intersects,
+    /// The item is entirely contained in the region.
+    Within,
+    /// The item entirely contains the region.
+    Contains,
+}
+
+impl ItemPredicate {
+    fn matches(&self, relation: &OutputRelation) -> bool {
+        match self {
+            ItemPredicate::Intersects => relation.any_relation(),
+            ItemPredicate::Within => {
+                relation.contained.unwrap_or_default()
+                    || relation.strict_contained.unwrap_or_default()
+            }
+            ItemPredicate::Contains => {
+                relation.contains.unwrap_or_default()
+                    || relation.strict_contains.unwrap_or_default()
+            }
+        }
+    }
+}
+
+fn add_to_double_check(
+    double_check: &mut RoaringBitmap,
+    double_check_cells: &mut HashMap<ItemId, CellIndex>,
+    track_cells: bool,
+    items: &RoaringBitmap,
+    cell: CellIndex,
+) {
+    if track_cells {
+        for item in items.iter() {
+            double_check_cells.entry(item).or_insert(cell);
+        }
+    }
+    *double_check |= items;
+}
+
+fn record_provenance(
+    provenance: &mut Option<&mut BTreeMap<ItemId, Provenance>>,
+    items: &RoaringBitmap,
+    source: MatchSource,
+    cell: CellIndex,
+) {
+    if let Some(provenance) = provenance {
+        for item in items.iter() {
+            provenance
+                .entry(item)
+                .or_insert(Provenance { source, cell });
+        }
+    }
+}
+
+/// Return the deepest resolution at which the shape is small enough to be covered by a handful of cells.
+/// Tiny shapes can start their traversal there instead of walking down all the levels of the tree.
+fn start_resolution(polygon: &Polygon, distance_model: DistanceModel) -> Resolution {
+    let Some(bbox) = polygon.bounding_rect() else {
+        return Resolution::Zero;
+    };
+    let diagonal = distance_model.distance(Point::from(bbox.min()), Point::from(bbox.max()));
+
+    let mut ret = Resolution::Zero;
+    while let Some(next) = ret.succ() {
+        if next.edge_length_m() < diagonal {
+            break;
+        }
+        ret = next;
+    }
+    ret
+}
+
+/// Return the closest and furthest distance at which a point of the cell can be from the origin.
+fn cell_distance_bounds(
+    distance_model: DistanceModel,
+    origin: Point,
+    cell: CellIndex,
+) -> (f64, f64) {
+    let to_point = |lat_lng: LatLng| Point::new(lat_lng.lng(), lat_lng.lat());
+    let center = to_point(LatLng::from(cell));
+    let radius = cell
+        .boundary()
+        .iter()
+        .map(|vertex| distance_model.distance(center, to_point(*vertex)))
+        .fold(0.0, f64::max);
+    let distance = distance_model.distance(origin, center);
+    ((distance - radius).max(0.0), distance + radius)
+}
+
+/// Return the deepest resolution at which a circle of `radius` meters should be covered by a small grid disk
+/// around the cell of its center. The number of rings is an estimate based on the average size of the cells.
+fn grid_disk_resolution(radius: f64) -> Resolution {
+    // The maximum number of rings we aim for, a disk of 8 rings contains 217 cells
+    const MAX_RINGS: u32 = 8;
+
+    // The distance between the centers of two neighbouring cells is about `edge * sqrt(3)`.
+    // We add one ring since the center of the circle may be anywhere in its cell.
+    let rings = |resolution: Resolution| {
+        (radius / (resolution.edge_length_m() * 3.0_f64.sqrt())).ceil() as u32 + 1
+    };
+    let mut ret = Resolution::Zero;
+    while let Some(next) = ret.succ() {
+        if rings(next) > MAX_RINGS {
+            break;
+        }
+        ret = next;
+    }
+    ret
+}
+
+/// How the distances are measured on the earth.
+///
+/// The relations between the shapes are always computed on a plane, the edges of a shape are straight lines
+/// between its coordinates. With a spherical model the query shapes are densified so their edges follow the
+/// curvature of the earth, which matters at high latitudes.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum DistanceModel {
+    /// Distances on a sphere, fast and precise up to 0.5%.
+    #[default]
+    Haversine,
+    /// Distances on the WGS84 ellipsoid, precise but slower.
+    Geodesic,
+    /// The longitude and latitude are used as planar coordinates, a degree is always worth the same distance.
+    /// The query shapes are used as-is, like the relations computed on the items.
+    Planar,
+}
+
+impl DistanceModel {
+    /// The length of a degree in the planar model, computed on the mean radius of the earth.
+    const METERS_PER_DEGREE: f64 = 111_195.08;
+
+    /// Add points to the polygon so its edges are never longer than `max_segment_length` meters.
+    pub fn densify(&self, polygon: &Polygon, max_segment_length: f64) -> Polygon {
+        match self {
+            DistanceModel::Haversine => Haversine.densify(polygon, max_segment_length),
+            DistanceModel::Geodesic => Geodesic.densify(polygon, max_segment_length),
+            // The edges are already straight lines on the plane
+            DistanceModel::Planar => polygon.clone(),
+        }
+    }
+
+    /// Add points to the lines and polygons of the geometry so their edges are never longer than
+    /// `max_segment_length` meters. The points are left untouched.
+    pub fn densify_geometry(&self, geometry: &mut Geometry, max_segment_length: f64) {
+        match self {
+            DistanceModel::Haversine => densify_geometry(&Haversine, geometry, max_segment_length),
+            DistanceModel::Geodesic => densify_geometry(&Geodesic, geometry, max_segment_length),
+            // The edges are already straight lines on the plane
+            DistanceModel::Planar => (),
+        }
+    }
+}
+
+fn densify_geometry(metric: &impl Densify<f64>, geom: &mut Geometry, max_segment_length: f64) {
+    match geom {
+        Geometry::Line(line) => {
+            *geom = Geometry::LineString(metric.densify(line, max_segment_length));
+        }
+        Geometry::LineString(line_string) => {
+            *line_string = metric.densify(line_string, max_segment_length);
+        }
+        Geometry::Polygon(polygon) => {
+            *polygon = metric.densify(polygon, max_segment_length);
+        }
+        Geometry::MultiLineString(multi_line_string) => {
+            *multi_line_string = metric.densify(multi_line_string, max_segment_length);
+        }
+        Geometry::MultiPolygon(multi_polygon) => {
+            *multi_polygon = metric.densify(multi_polygon, max_segment_length);
+        }
+        Geometry::GeometryCollection(geometry_collection) => {
+            for geom in geometry_collection.0.iter_mut() {
+                densify_geometry(metric, geom, max_segment_length);
+            }
+        }
+        Geometry::Rect(rect) => {
+            *geom = Geometry::Polygon(metric.densify(rect, max_segment_length));
+        }
+        Geometry::Triangle(triangle) => {
+            *geom = Geometry::Polygon(metric.densify(triangle, max_segment_length));
+        }
+        Geometry::Point(_) | Geometry::MultiPoint(_) => (),
+    };
+}
+
+/// Push the cells of `resolution` intersecting the geometry to `cells`.
+/// The cells are not deduplicated.
+fn geometry_to_cells(
+    // only used for error handling
+    item: ItemId,
+    geometry: &Geometry,
+    resolution: Resolution,
+    cells: &mut Vec<CellIndex>,
+) -> Result<()> {
+    match geometry {
+        Geometry::Point(point) => {
+            cells.push(LatLng::new(point.y(), point.x())?.to_cell(resolution));
+        }
+        Geometry::MultiPoint(multi_point) => {
+            for point in multi_point {
+                cells.push(LatLng::new(point.y(), point.x())?.to_cell(resolution));
+            }
+        }
+        Geometry::Line(_) | Geometry::LineString(_) | Geometry::MultiLineString(_) => {
+            let error = |err| Error::CannotConvertLineToCell(item, err, format!("{geometry:?}"));
+            let mut plotter = PlotterBuilder::new(resolution).build();
+            let added = match geometry {
+                Geometry::Line(line) => plotter.add(*line),
+                Geometry::LineString(line) => plotter.add_batch(line.lines()),
+                Geometry::MultiLineString(lines) => {
+                    plotter.add_batch(lines.iter().flat_map(|line| line.lines()))
+                }
+                _ => unreachable!(),
+            };
+            added.map_err(error)?;
+            for cell in plotter.plot() {
+                cells.push(cell.map_err(error)?);
+            }
+        }
+        Geometry::Polygon(_)
+        | Geometry::MultiPolygon(_)
+        | Geometry::Rect(_)
+        | Geometry::Triangle(_) => {
+            let mut tiler = TilerBuilder::new(resolution)
+                .containment_mode(ContainmentMode::Covers)
+                .build();
+            match geometry {
+                Geometry::Polygon(polygon) => tiler.add(polygon.clone())?,
+                Geometry::MultiPolygon(polygons) => tiler.add_batch(polygons.0.iter().cloned())?,
+                Geometry::Rect(rect) => tiler.add(rect.to_polygon())?,
+                Geometry::Triangle(triangle) => tiler.add(triangle.to_polygon())?,
+                _ => unreachable!(),
+            }
+            cells.extend(tiler.into_coverage());
+        }
+        Geometry::GeometryCollection(collection) => {
+            for geometry in collection {
+                geometry_to_cells(item, geometry, resolution, cells)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Destination<f64> for DistanceModel {
+    fn destination(&self, origin: Point, bearing: f64, meters: f64) -> Point {
+        match self {
+            DistanceModel::Haversine => Haversine.destination(origin, bearing, meters),
+            DistanceModel::Geodesic => Geodesic.destination(origin, bearing, meters),
+            DistanceModel::Planar => {
+                let degrees = meters / Self::METERS_PER_DEGREE;
+                let bearing = bearing.to_radians();
+                Point::new(
+                    origin.x() + degrees * bearing.sin(),
+                    origin.y() + degrees * bearing.cos(),
+                )
+            }
+        }
+    }
+}
+
+impl Bearing<f64> for DistanceModel {
+    fn bearing(&self, origin: Point, destination: Point) -> f64 {
+        match self {
+            DistanceModel::Haversine => Haversine.bearing(origin, destination),
+            DistanceModel::Geodesic => Geodesic.bearing(origin, destination),
+            DistanceModel::Planar => {
+                let bearing = (destination.x() - origin.x())
+                    .atan2(destination.y() - origin.y())
+                    .to_degrees();
+                bearing.rem_euclid(360.0)
+            }
+        }
+    }
+}
+
+impl Distance<f64, Point, Point> for DistanceModel {
+    fn distance(&self, origin: Point, destination: Point) -> f64 {
+        match self {
+            DistanceModel::Haversine => Haversine.distance(origin, destination),
+            DistanceModel::Geodesic => Geodesic.distance(origin, destination),
+            DistanceModel::Planar => {
+                Euclidean.distance(origin, destination) * Self::METERS_PER_DEGREE
+            }
+        }
+    }
+}
+
+/// A query shape densified and tiled once by [`Cellulite::prepare_shape`], to be queried many
+/// times with [`Cellulite::in_shape_prepared`].
+#[derive(Debug, Clone)]
+pub struct PreparedShape {
+    /// The shape given by the user, prepared again if the database changed.
+    polygon: Polygon,
+    densified: Polygon,
+    /// The cells of `resolution` covering the densified shape.
+    coverage: Vec<CellIndex>,
+    resolution: Resolution,
+    distance_model: DistanceModel,
+    densify_distance: f64,
+}
+
+impl PreparedShape {
+    /// Return the polygon the shape was prepared from.
+    pub fn polygon(&self) -> &Polygon {
+        &self.polygon
+    }
+}
+
+/// Options to restrict the items returned by [`Cellulite::in_shape_with_options`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct QueryOptions {
+    /// Which relation the items must have with the shape to be returned.
+    pub mode: QueryMode,
+    /// The maximum number of items to return. The search stops once `limit` items were confirmed,
+    /// they're the first ones found and not the smallest ids. There is no offset: the next items
+    /// depend on where the search stopped, the results cannot be paginated.
+    pub limit: Option<u64>,
+    /// Stop exploring the cells once this many items were confirmed or must be double-checked.
+    /// The items to double-check are still checked, so fewer items may be returned.
+    pub stop_after_candidates: Option<u64>,
+    /// Only return the items of these kinds of geometry. The items of the other kinds are dropped
+    /// by reading the tag of their shape, they're never double-checked.
+    pub kinds: Option<GeometryKinds>,
+    /// Caps the memory used by the search, `None` by default. A shape spanning a large part of
+    /// the world can make the search queue a lot of cells and double-check a lot of items.
+    pub working_set_limit: Option<WorkingSetLimit>,
+}
+
+/// The maximum size of the working set of a query, see [`QueryOptions::working_set_limit`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WorkingSetLimit {
+    /// The maximum number of cells waiting to be explored.
+    pub max_queued_cells: usize,
+    /// The maximum number of items waiting to be double-checked. The items of the last cell
+    /// explored are added before the limit is checked, it can be exceeded by up to the threshold.
+    pub max_double_check: u64,
+    /// What to do once one of the limits is exceeded.
+    pub on_exceeded: OnLimitExceeded,
+}
+
+/// What a query does once its [`WorkingSetLimit`] is exceeded.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OnLimitExceeded {
+    /// Stop exploring the cells and return the items found so far, like with
+    /// [`QueryOptions::stop_after_candidates`]. Some items may be missing, but all the returned
+    /// items match.
+    #[default]
+    Approximate,
+    /// Return an [`Error::QueryTooLarge`] telling how far the search went.
+    Fail,
+}
+
+/// Which relation the items must have with the shape to be returned.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum QueryMode {
+    /// The items intersecting or contained in the shape are returned.
+    #[default]
+    Intersects,
+    /// Only the items entirely inside the shape are returned.
+    StrictlyWithin,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -183,6 +1665,27 @@ pub enum FilteringStep {
     NotPresentInDB,
     OutsideOfShape,
     Returned,
+    /// The cell intersects the shape and the items of its belly cell, covering the whole cell,
+    /// were returned.
+    ReturnedFromBelly,
     RequireDoubleCheck,
     DeepDive,
 }
+
+/// Why an item was returned by [`Cellulite::in_shape_with_provenance`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MatchSource {
+    /// The item was in a cell entirely contained in the shape.
+    ContainedCell,
+    /// The item entirely covers a cell intersecting the shape.
+    BellyCell,
+    /// The item was in a leaf cell intersecting the shape and its relation with the shape was checked.
+    DoubleCheck,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub source: MatchSource,
+    /// The cell in which the item was confirmed.
+    pub cell: CellIndex,
+}
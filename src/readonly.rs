@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use geo::{Point, Polygon, Rect};
+use h3o::{CellIndex, Resolution};
+use heed::{Env, RoTxn};
+use roaring::RoaringBitmap;
+use zerometry::Zerometry;
+
+use crate::{
+    Cellulite, CelluliteOptions, HealthReport, ItemId, ResolutionReport, Result, Stats,
+    reader::{PreparedShape, QueryOptions},
+};
+
+/// A database opened with [`Cellulite::open_readonly`], only the methods reading the database are
+/// available.
+///
+/// The write methods of [`Cellulite`] cannot be called on it, which prevents mistakes on the
+/// databases that must not be modified, like a production database mounted on another machine.
+/// Use [`Cellulite::open_from_env`] to write in the database.
+#[derive(Clone)]
+pub struct CelluliteReader {
+    cellulite: Cellulite,
+}
+
+impl Cellulite {
+    /// Open the databases like [`Self::open_from_env`], without ever creating them. It works with
+    /// the environments opened with [`heed::EnvFlags::READ_ONLY`].
+    /// Returns [`crate::Error::DatabaseDoesntExists`] if any of the databases doesn't exist.
+    pub fn open_readonly<Tls>(
+        env: &Env<Tls>,
+        rtxn: &RoTxn,
+        prefix: &str,
+    ) -> Result<CelluliteReader> {
+        let cellulite = Self::open_from_env(env, rtxn, prefix)?;
+        Ok(CelluliteReader { cellulite })
+    }
+}
+
+impl CelluliteReader {
+    /// Return the options stored in the database.
+    pub fn options(&self) -> &CelluliteOptions {
+        self.cellulite.options()
+    }
+
+    /// See [`Cellulite::item`].
+    pub fn item<'a>(&self, rtxn: &'a RoTxn, item: ItemId) -> Result<Option<Zerometry<'a>>> {
+        self.cellulite.item(rtxn, item)
+    }
+
+    /// See [`Cellulite::items`].
+    pub fn items<'a>(
+        &self,
+        rtxn: &'a RoTxn,
+    ) -> Result<impl Iterator<Item = Result<(ItemId, Zerometry<'a>), heed::Error>> + 'a> {
+        self.cellulite.items(rtxn)
+    }
+
+    /// See [`Cellulite::in_shape`].
+    pub fn in_shape(&self, rtxn: &RoTxn, polygon: &Polygon) -> Result<RoaringBitmap> {
+        self.cellulite.in_shape(rtxn, polygon)
+    }
+
+    /// See [`Cellulite::in_shape_with_options`].
+    pub fn in_shape_with_options(
+        &self,
+        rtxn: &RoTxn,
+        polygon: &Polygon,
+        options: QueryOptions,
+    ) -> Result<RoaringBitmap> {
+        self.cellulite.in_shape_with_options(rtxn, polygon, options)
+    }
+
+    /// See [`Cellulite::prepare_shape`].
+    pub fn prepare_shape(&self, polygon: &Polygon) -> Result<PreparedShape> {
+        self.cellulite.prepare_shape(polygon)
+    }
+
+    /// See [`Cellulite::in_shape_prepared`].
+    pub fn in_shape_prepared(&self, rtxn: &RoTxn, shape: &PreparedShape) -> Result<RoaringBitmap> {
+        self.cellulite.in_shape_prepared(rtxn, shape)
+    }
+
+    /// See [`Cellulite::items_containing_point`].
+    pub fn items_containing_point(&self, rtxn: &RoTxn, point: Point) -> Result<RoaringBitmap> {
+        self.cellulite.items_containing_point(rtxn, point)
+    }
+
+    /// See [`Cellulite::classify_points`].
+    pub fn classify_points(&self, rtxn: &RoTxn, points: &[Point]) -> Result<Vec<RoaringBitmap>> {
+        self.cellulite.classify_points(rtxn, points)
+    }
+
+    /// See [`Cellulite::annotate_with_zones`].
+    pub fn annotate_with_zones(
+        &self,
+        rtxn: &RoTxn,
+        points: &RoaringBitmap,
+        zones: &CelluliteReader,
+    ) -> Result<BTreeMap<ItemId, RoaringBitmap>> {
+        self.cellulite
+            .annotate_with_zones(rtxn, points, &zones.cellulite)
+    }
+
+    /// See [`Cellulite::cell_entry`].
+    pub fn cell_entry(
+        &self,
+        rtxn: &RoTxn,
+        cell: CellIndex,
+    ) -> Result<(Option<RoaringBitmap>, Option<RoaringBitmap>)> {
+        self.cellulite.cell_entry(rtxn, cell)
+    }
+
+    /// See [`Cellulite::items_in_extent`].
+    pub fn items_in_extent(&self, rtxn: &RoTxn, rect: Rect) -> Result<RoaringBitmap> {
+        self.cellulite.items_in_extent(rtxn, rect)
+    }
+
+    /// See [`Cellulite::namespace_items`].
+    pub fn namespace_items(&self, rtxn: &RoTxn, namespace: &str) -> heed::Result<RoaringBitmap> {
+        self.cellulite.namespace_items(rtxn, namespace)
+    }
+
+    /// See [`Cellulite::namespaces`].
+    pub fn namespaces(&self, rtxn: &RoTxn) -> heed::Result<Vec<String>> {
+        self.cellulite.namespaces(rtxn)
+    }
+
+    /// See [`Cellulite::stats`].
+    pub fn stats(&self, rtxn: &RoTxn) -> Result<Stats> {
+        self.cellulite.stats(rtxn)
+    }
+
+    /// See [`Cellulite::health_report`].
+    pub fn health_report(&self, rtxn: &RoTxn) -> Result<HealthReport> {
+        self.cellulite.health_report(rtxn)
+    }
+
+    /// See [`Cellulite::resolution_report`].
+    pub fn resolution_report(
+        &self,
+        rtxn: &RoTxn,
+    ) -> Result<BTreeMap<Resolution, ResolutionReport>> {
+        self.cellulite.resolution_report(rtxn)
+    }
+
+    /// See [`Cellulite::needs_recovery`].
+    pub fn needs_recovery(&self, rtxn: &RoTxn) -> heed::Result<bool> {
+        self.cellulite.needs_recovery(rtxn)
+    }
+
+    /// See [`Cellulite::checksum`].
+    pub fn checksum(&self, rtxn: &RoTxn) -> Result<u32> {
+        self.cellulite.checksum(rtxn)
+    }
+
+    /// See [`Cellulite::debug_dump`].
+    pub fn debug_dump(&self, rtxn: &RoTxn) -> Result<String> {
+        self.cellulite.debug_dump(rtxn)
+    }
+}
@@ -0,0 +1,124 @@
+use std::{fs, path::Path};
+
+use heed::{CompactionOption, Env, EnvFlags, EnvOpenOptions, RoTxn};
+
+use crate::{Cellulite, Error, Result, checksum::corruption, metadata::Version};
+
+impl Cellulite {
+    /// Copy the environment holding the `prefix` database into the `path` directory, with LMDB
+    /// compaction if `compact` is set. The copy is a consistent snapshot of the environment and can
+    /// be made while other transactions keep writing to it.
+    ///
+    /// The copy is then opened in read-only to make sure it can be queried: it must be built, up to
+    /// date, and every value must be readable and match its checksum with the `checksum` feature. Otherwise an [`Error::InvalidReplica`] or an
+    /// [`Error::Corruption`] is returned and the copy is removed.
+    pub fn copy_to<Tls>(
+        env: &Env<Tls>,
+        prefix: &str,
+        path: impl AsRef<Path>,
+        compact: bool,
+    ) -> Result<()> {
+        let option = if compact {
+            CompactionOption::Enabled
+        } else {
+            CompactionOption::Disabled
+        };
+        Self::copy_and_validate(env, prefix, path.as_ref(), option, Self::validate_replica)
+    }
+
+    /// Copy the environment holding the `prefix` database into the `path` directory to back it up,
+    /// while other transactions keep writing to it and the builds keep running. The copy is a compacted
+    /// and consistent snapshot of the environment.
+    ///
+    /// Unlike [`Self::copy_to`], the pending updates and the state of a build in multiple
+    /// transactions are kept, the backup can replace the original database and resume the work
+    /// where it was. The copy is then opened in read-only to make sure every value can be read and
+    /// matches its checksum with the `checksum` feature, and every update can be read. Otherwise an [`Error::Corruption`] is returned and
+    /// the copy is removed.
+    pub fn backup_to<Tls>(env: &Env<Tls>, prefix: &str, path: impl AsRef<Path>) -> Result<()> {
+        Self::copy_and_validate(
+            env,
+            prefix,
+            path.as_ref(),
+            CompactionOption::Enabled,
+            Self::validate_checksums,
+        )
+    }
+
+    fn copy_and_validate<Tls>(
+        env: &Env<Tls>,
+        prefix: &str,
+        path: &Path,
+        option: CompactionOption,
+        validate: impl FnOnce(&Self, &RoTxn) -> Result<()>,
+    ) -> Result<()> {
+        fs::create_dir_all(path).map_err(heed::Error::Io)?;
+        let file = path.join("data.mdb");
+        env.copy_to_path(&file, option)?;
+
+        // The copy cannot be bigger than the original environment
+        let map_size = env.info().map_size;
+        let ret = Self::open_copy(path, prefix, map_size, validate);
+        if ret.is_err() {
+            fs::remove_file(&file).map_err(heed::Error::Io)?;
+        }
+        ret
+    }
+
+    fn open_copy(
+        path: &Path,
+        prefix: &str,
+        map_size: usize,
+        validate: impl FnOnce(&Self, &RoTxn) -> Result<()>,
+    ) -> Result<()> {
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(map_size)
+                .max_dbs(Self::nb_dbs())
+                .flags(EnvFlags::READ_ONLY)
+                .open(path)
+        }?;
+        let rtxn = env.read_txn()?;
+        let copy = Self::open_from_env(&env, &rtxn, prefix)?;
+        validate(&copy, &rtxn)?;
+
+        drop(rtxn);
+        // Close the copy so it can be opened again right after
+        env.prepare_for_closing().wait();
+        Ok(())
+    }
+
+    fn validate_replica(replica: &Self, rtxn: &RoTxn) -> Result<()> {
+        let version = replica.get_version(rtxn)?;
+        if version != Version::default() {
+            return Err(Error::InvalidReplica(format!(
+                "it was created by cellulite v{version}, upgrade the database first"
+            )));
+        }
+        if replica.is_building(rtxn)? {
+            return Err(Error::InvalidReplica(
+                "a build in multiple transactions was in progress during the copy".to_string(),
+            ));
+        }
+        let pending_updates = replica.update.len(rtxn)?;
+        if pending_updates > 0 {
+            return Err(Error::InvalidReplica(format!(
+                "{pending_updates} updates are waiting for a build, they would never be visible"
+            )));
+        }
+        replica.validate_checksums(rtxn)
+    }
+
+    fn validate_checksums(&self, rtxn: &RoTxn) -> Result<()> {
+        for ret in self.items(rtxn)? {
+            ret.map_err(corruption("an item"))?;
+        }
+        for ret in self.cell.iter(rtxn)? {
+            ret.map_err(corruption("a cell"))?;
+        }
+        for ret in self.update.iter(rtxn)? {
+            ret?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use geo::{CoordsIter, Geometry, Polygon};
+#[cfg(feature = "geojson")]
+use geojson::GeoJson;
+use h3o::{LatLng, Resolution};
+use heed::Env;
+use roaring::RoaringBitmap;
+use steppe::Progress;
+
+use crate::{Cellulite, Error, ItemId, Result};
+
+/// How [`ShardedCellulite`] dispatches the items between its shards.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ShardingStrategy {
+    /// The item `n` is stored in the shard `n % nb_shards`. The shards are equally filled.
+    #[default]
+    ById,
+    /// The items are dispatched by the base cell (resolution zero) of their first coordinate.
+    /// The items close to each other are stored in the same shard, but the shards can be
+    /// unbalanced.
+    ByRegion,
+}
+
+/// A cellulite index split across multiple LMDB environments.
+///
+/// Each environment has its own map size and its own writer, the writes and builds of every shard
+/// are made in parallel. The ids of the items are global, an item is stored in a single shard.
+pub struct ShardedCellulite {
+    shards: Vec<(Env, Cellulite)>,
+    strategy: ShardingStrategy,
+    id_offsets: Vec<ItemId>,
+}
+
+impl ShardedCellulite {
+    /// Create, or open if they already exist, the databases of cellulite in every environment.
+    /// The environments must always be given in the same order.
+    /// Returns [`Error::NoShards`] if there is no environment.
+    pub fn create(envs: Vec<Env>, prefix: &str, strategy: ShardingStrategy) -> Result<Self> {
+        if envs.is_empty() {
+            return Err(Error::NoShards);
+        }
+        let mut shards = Vec::with_capacity(envs.len());
+        for env in envs {
+            let mut wtxn = env.write_txn()?;
+            let cellulite = Cellulite::create_from_env(&env, &mut wtxn, prefix)?;
+            wtxn.commit()?;
+            shards.push((env, cellulite));
+        }
+        let id_offsets = vec![0; shards.len()];
+        Ok(Self {
+            shards,
+            strategy,
+            id_offsets,
+        })
+    }
+
+    /// Return the environment and the database of every shard.
+    pub fn shards(&self) -> &[(Env, Cellulite)] {
+        &self.shards
+    }
+
+    /// Shift the ids of the items returned by the queries on `shard` by `offset`.
+    /// Useful when the shards were filled independently and all start their ids at zero,
+    /// the ids stored in the shard plus the offset must fit in an [`ItemId`].
+    ///
+    /// The offsets only apply to the queries, [`Self::add`] and [`Self::delete`] still expect the
+    /// ids stored in the shards. They're all zero by default.
+    /// Returns [`Error::ShardDoesntExists`] if there is no such shard.
+    pub fn set_id_offset(&mut self, shard: usize, offset: ItemId) -> Result<()> {
+        let nb_shards = self.id_offsets.len();
+        let id_offset = self
+            .id_offsets
+            .get_mut(shard)
+            .ok_or(Error::ShardDoesntExists(shard, nb_shards))?;
+        *id_offset = offset;
+        Ok(())
+    }
+
+    /// Return the index of the shard the item should be stored in.
+    pub fn shard_of(&self, item: ItemId, geometry: &Geometry) -> Result<usize> {
+        let shard = match self.strategy {
+            ShardingStrategy::ById => item as usize,
+            ShardingStrategy::ByRegion => {
+                let coord = geometry
+                    .coords_iter()
+                    .next()
+                    .ok_or(Error::EmptyGeometry(item))?;
+                let cell = LatLng::new(coord.y, coord.x)?.to_cell(Resolution::Zero);
+                cell.base_cell().into()
+            }
+        };
+        Ok(shard % self.shards.len())
+    }
+
+    /// Insert the items in their shard, each shard is written in its own transaction.
+    /// For the items to be searchable you must [`Self::build`] the shards afterward.
+    #[cfg(feature = "geojson")]
+    pub fn add(&self, items: impl IntoIterator<Item = (ItemId, GeoJson)>) -> Result<()> {
+        let items = items
+            .into_iter()
+            .map(|(item, geojson)| Ok((item, Geometry::try_from(geojson).map_err(Box::new)?)))
+            .collect::<Result<Vec<_>>>()?;
+        self.add_geometries(items)
+    }
+
+    /// Insert the geometries in their shard like [`Self::add`].
+    pub fn add_geometries(
+        &self,
+        items: impl IntoIterator<Item = (ItemId, Geometry)>,
+    ) -> Result<()> {
+        let mut dispatched = vec![Vec::new(); self.shards.len()];
+        for (item, geometry) in items {
+            let shard = self.shard_of(item, &geometry)?;
+            dispatched[shard].push((item, geometry));
+        }
+
+        // With the regions, an updated item may move to another shard, it must be deleted from the
+        // shard it was in before
+        let added: RoaringBitmap = dispatched.iter().flatten().map(|(item, _)| *item).collect();
+
+        std::thread::scope(|s| {
+            let handles: Vec<_> = self
+                .shards
+                .iter()
+                .zip(dispatched)
+                .map(|((env, cellulite), items)| {
+                    let added = &added;
+                    s.spawn(move || -> Result<()> {
+                        let mut wtxn = env.write_txn()?;
+                        if self.strategy == ShardingStrategy::ByRegion {
+                            let mut moved = added.clone();
+                            moved -= items
+                                .iter()
+                                .map(|(item, _)| *item)
+                                .collect::<RoaringBitmap>();
+                            for item in moved {
+                                if cellulite.item(&wtxn, item)?.is_some() {
+                                    cellulite.delete(&mut wtxn, item)?;
+                                }
+                            }
+                        }
+                        for (item, geometry) in items {
+                            cellulite.add_geometry(&mut wtxn, item, geometry)?;
+                        }
+                        wtxn.commit()?;
+                        Ok(())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .try_for_each(|handle| handle.join().unwrap())
+        })
+    }
+
+    /// Delete the items from their shard, each shard is written in its own transaction.
+    /// For the items to be removed you must [`Self::build`] the shards afterward.
+    pub fn delete(&self, items: &RoaringBitmap) -> Result<()> {
+        std::thread::scope(|s| {
+            let handles: Vec<_> = self
+                .shards
+                .iter()
+                .enumerate()
+                .map(|(shard, (env, cellulite))| {
+                    s.spawn(move || -> Result<()> {
+                        let mut wtxn = env.write_txn()?;
+                        for item in items {
+                            let in_shard = match self.strategy {
+                                ShardingStrategy::ById => {
+                                    item as usize % self.shards.len() == shard
+                                }
+                                ShardingStrategy::ByRegion => {
+                                    cellulite.item(&wtxn, item)?.is_some()
+                                }
+                            };
+                            if in_shard {
+                                cellulite.delete(&mut wtxn, item)?;
+                            }
+                        }
+                        wtxn.commit()?;
+                        Ok(())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .try_for_each(|handle| handle.join().unwrap())
+        })
+    }
+
+    /// Build all the shards in parallel, each in its own transaction.
+    /// If a shard fails to build, the other shards are still committed.
+    /// The progress is shared by the shards, it shows the steps of the last shard that updated it.
+    pub fn build(
+        &self,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &(impl Progress + Sync),
+    ) -> Result<()> {
+        std::thread::scope(|s| {
+            let handles: Vec<_> = self
+                .shards
+                .iter()
+                .map(|(env, cellulite)| {
+                    s.spawn(move || -> Result<()> {
+                        let mut wtxn = env.write_txn()?;
+                        cellulite.build(&mut wtxn, cancel, progress)?;
+                        wtxn.commit()?;
+                        Ok(())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .try_for_each(|handle| handle.join().unwrap())
+        })
+    }
+
+    /// Return all the items that intersects or are contained in the specified polygon, like
+    /// [`Cellulite::in_shape`].
+    /// Every shard is queried concurrently in its own read transaction, and the ids of their items
+    /// are shifted by the offset of their shard.
+    /// Returns [`Error::ItemIdOverflow`] if a shifted id doesn't fit in an [`ItemId`].
+    pub fn in_shape(&self, polygon: &Polygon) -> Result<RoaringBitmap> {
+        let results = std::thread::scope(|s| {
+            let handles: Vec<_> = self
+                .shards
+                .iter()
+                .map(|(env, cellulite)| {
+                    s.spawn(move || -> Result<RoaringBitmap> {
+                        let rtxn = env.read_txn()?;
+                        cellulite.in_shape(&rtxn, polygon)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        let mut ret = RoaringBitmap::new();
+        for (bitmap, offset) in results.into_iter().zip(&self.id_offsets) {
+            if *offset == 0 {
+                ret |= bitmap;
+            } else {
+                for item in bitmap {
+                    let shifted = item
+                        .checked_add(*offset)
+                        .ok_or(Error::ItemIdOverflow(item, *offset))?;
+                    ret.insert(shifted);
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
@@ -1,13 +1,31 @@
-use std::ops::Deref;
+use std::{
+    collections::BTreeMap,
+    ops::Deref,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
-use geo::{GeometryCollection, point, polygon};
+use geo::{Geometry, GeometryCollection, coord, point, polygon};
 use geojson::{FeatureCollection, GeoJson};
-use h3o::LatLng;
-use heed::{Env, EnvOpenOptions, RoTxn, WithTls};
+use h3o::{CellIndex, LatLng, Resolution};
+use heed::{Env, EnvOpenOptions, RoTxn, RwTxn, WithTls};
+use roaring::RoaringBitmap;
 use steppe::NoProgress;
 use tempfile::TempDir;
 
-use crate::{Cellulite, Key};
+use crate::{
+    BuildSteps, CellTree, Cellulite, CelluliteOptions, CelluliteWriterQueue, Error,
+    FrozenCellulite, GeometryKind, GeometryKinds, OversizedLeafPolicy, QueryStats,
+    ShardedCellulite, ShardingStrategy,
+    keys::Key,
+    reader::{
+        DistanceModel, FilteringStep, ItemPredicate, MatchSource, OnLimitExceeded, QueryOptions,
+        WorkingSetLimit,
+    },
+};
 
 pub struct DatabaseHandle {
     pub env: Env<WithTls>,
@@ -26,50 +44,27 @@ impl Deref for DatabaseHandle {
 
 impl DatabaseHandle {
     fn snap(&self, rtxn: &RoTxn) -> String {
-        let mut s = String::new();
-
-        s.push_str(&format!(
-            "# Version: {}\n",
-            self.database.get_version(rtxn).unwrap()
-        ));
-        s.push_str("# Items\n");
-        let iter = self.database.item.iter(rtxn).unwrap();
-        for ret in iter {
-            let (key, value) = ret.unwrap();
-            s.push_str(&format!("{key}: {value:?}\n"));
-        }
-
-        let mut cells = Vec::new();
-        let mut belly = Vec::new();
-        for ret in self.database.cell.iter(rtxn).unwrap() {
-            let (key, value) = ret.unwrap();
-            match key {
-                Key::Cell(cell_index) => cells.push((cell_index, value)),
-                Key::Belly(cell_index) => belly.push((cell_index, value)),
-            }
-        }
+        self.database.debug_dump(rtxn).unwrap()
+    }
 
-        s.push_str("# Cells\n");
-        for (cell, bitmap) in cells {
-            let lat_lng = LatLng::from(cell);
-            let (lat, lng) = (lat_lng.lat(), lat_lng.lng());
-            let res = cell.resolution();
-            s.push_str(&format!(
-                "Cell {{ res: {res}, center: ({lat:.4}, {lng:.4}) }}: {bitmap:?}\n"
-            ));
-        }
+    /// Return all the cells of the database with their items, to compare two databases.
+    fn cells(&self, rtxn: &RoTxn) -> Vec<(CellIndex, RoaringBitmap)> {
+        self.inner_db_cells(rtxn)
+            .unwrap()
+            .map(|ret| ret.unwrap())
+            .collect()
+    }
 
-        s.push_str("# Belly Cells\n");
-        for (cell, bitmap) in belly {
-            let lat_lng = LatLng::from(cell);
-            let (lat, lng) = (lat_lng.lat(), lat_lng.lng());
-            let res = cell.resolution();
-            s.push_str(&format!(
-                "Cell {{ res: {res}, center: ({lat:.4}, {lng:.4}) }}: {bitmap:?}\n"
-            ));
+    /// Insert `count` points along the diagonal starting at (0, 0), they're 0.01 degree apart and
+    /// their ids start at zero.
+    fn add_diagonal_points(&self, wtxn: &mut RwTxn, count: u32) {
+        for i in 0..count {
+            let lng = i as f64 * 0.01;
+            let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+                lng, lng,
+            ])));
+            self.add(wtxn, i, &point).unwrap();
         }
-
-        s
     }
 }
 
@@ -96,14 +91,14 @@ fn create_database() -> DatabaseHandle {
 fn basic_write() {
     let mut db = create_database();
     let mut wtxn = db.env.write_txn().unwrap();
-    db.database.threshold = 3;
+    db.database.options.threshold = 3;
     let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
         0.0, 0.0,
     ])));
     db.add(&mut wtxn, 0, &point).unwrap();
 
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Point(Zoint { lng: 0.0, lat: 0.0 })
     # Cells
@@ -112,7 +107,7 @@ fn basic_write() {
 
     db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Point(Zoint { lng: 0.0, lat: 0.0 })
     # Cells
@@ -130,7 +125,7 @@ fn basic_write() {
     db.add(&mut wtxn, 2, &point).unwrap();
 
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Point(Zoint { lng: 0.0, lat: 0.0 })
     1: Point(Zoint { lng: 0.0, lat: 1.0 })
@@ -143,7 +138,7 @@ fn basic_write() {
     db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
 
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Point(Zoint { lng: 0.0, lat: 0.0 })
     1: Point(Zoint { lng: 0.0, lat: 1.0 })
@@ -164,7 +159,7 @@ fn basic_write() {
     db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
 
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Point(Zoint { lng: 0.0, lat: 0.0 })
     1: Point(Zoint { lng: 0.0, lat: 1.0 })
@@ -188,7 +183,7 @@ fn bug_write_points_create_cells_too_deep() {
     // This simple test was creating 5 cells instead of 3 with two cells too deep for on reason.
     let mut db = create_database();
     let mut wtxn = db.env.write_txn().unwrap();
-    db.database.threshold = 2;
+    db.database.options.threshold = 2;
     let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
         -11.460678226504395,
         48.213563161838714,
@@ -201,7 +196,7 @@ fn bug_write_points_create_cells_too_deep() {
     db.add(&mut wtxn, 1, &point).unwrap();
     db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Point(Zoint { lng: -11.460678226504395, lat: 48.213563161838714 })
     1: Point(Zoint { lng: -1.520397001416467, lat: 54.586501531522245 })
@@ -218,7 +213,7 @@ fn bug_write_points_create_unrelated_cells() {
     // This simple test was creating 4 cells instead of 3 with two completely unrelated cells.
     let mut db = create_database();
     let mut wtxn = db.env.write_txn().unwrap();
-    db.database.threshold = 2;
+    db.database.options.threshold = 2;
     let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
         6.0197316417968105,
         49.63676497357687,
@@ -232,7 +227,7 @@ fn bug_write_points_create_unrelated_cells() {
     db.add(&mut wtxn, 1, &point).unwrap();
     db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Point(Zoint { lng: 6.0197316417968105, lat: 49.63676497357687 })
     1: Point(Zoint { lng: 7.435508967561083, lat: 43.76438119061842 })
@@ -266,12 +261,12 @@ fn query_points_on_transmeridian_cell() {
 
     let mut db = create_database();
     let mut wtxn = db.env.write_txn().unwrap();
-    db.database.threshold = 2;
+    db.database.options.threshold = 2;
 
     db.add(&mut wtxn, 0, &lake).unwrap();
     db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Point(Zoint { lng: -172.36201, lat: 64.42921 })
     # Cells
@@ -289,7 +284,7 @@ fn query_points_on_transmeridian_cell() {
     db.add(&mut wtxn, 1, &airport).unwrap();
     db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Point(Zoint { lng: -172.36201, lat: 64.42921 })
     1: Point(Zoint { lng: -173.23841, lat: 64.37949 })
@@ -315,7 +310,7 @@ fn store_all_kind_of_collection() {
     // Purpose of the test is just to make sure w e can store all kinds of collection
     let mut db = create_database();
     let mut wtxn = db.env.write_txn().unwrap();
-    db.database.threshold = 2;
+    db.database.options.threshold = 2;
     let geometry_collection = geojson::Value::GeometryCollection(vec![geojson::Geometry::new(
         geojson::Value::Point(vec![6.0197316417968105, 49.63676497357687]),
     )]);
@@ -334,7 +329,7 @@ fn store_all_kind_of_collection() {
 
     db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
     insta::assert_snapshot!(db.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Collection(Zollection { bounding_box: BoundingBox { bottom_left: Coord { x: 6.0197316417968105, y: 49.63676497357687 }, top_right: Coord { x: 6.0197316417968105, y: 49.63676497357687 } }, points: ZultiPoints { bounding_box: BoundingBox { bottom_left: Coord { x: 6.0197316417968105, y: 49.63676497357687 }, top_right: Coord { x: 6.0197316417968105, y: 49.63676497357687 } }, points: [Zoint { lng: 6.0197316417968105, lat: 49.63676497357687 }] }, lines: ZultiLines { bounding_box: BoundingBox { bottom_left: Coord { x: 0.0, y: 0.0 }, top_right: Coord { x: 0.0, y: 0.0 } }, zines: [] }, polygons: ZultiPolygons { bounding_box: BoundingBox { bottom_left: Coord { x: 0.0, y: 0.0 }, top_right: Coord { x: 0.0, y: 0.0 } }, zolygons: [] } })
     1: Collection(Zollection { bounding_box: BoundingBox { bottom_left: Coord { x: 6.0197316417968105, y: 49.63676497357687 }, top_right: Coord { x: 6.0197316417968105, y: 49.63676497357687 } }, points: ZultiPoints { bounding_box: BoundingBox { bottom_left: Coord { x: 6.0197316417968105, y: 49.63676497357687 }, top_right: Coord { x: 6.0197316417968105, y: 49.63676497357687 } }, points: [Zoint { lng: 6.0197316417968105, lat: 49.63676497357687 }] }, lines: ZultiLines { bounding_box: BoundingBox { bottom_left: Coord { x: 0.0, y: 0.0 }, top_right: Coord { x: 0.0, y: 0.0 } }, zines: [] }, polygons: ZultiPolygons { bounding_box: BoundingBox { bottom_left: Coord { x: 0.0, y: 0.0 }, top_right: Coord { x: 0.0, y: 0.0 } }, zolygons: [] } })
@@ -388,7 +383,7 @@ fn write_polygon_with_belly_cells_at_res0() {
     // normal cells for its edges.
     let mut cellulite = create_database();
     let mut wtxn = cellulite.env.write_txn().unwrap();
-    cellulite.database.threshold = 2;
+    cellulite.database.options.threshold = 2;
     let point = GeometryCollection::from(point! { x:-10.38791, y: 51.68380 });
     cellulite
         .add(&mut wtxn, 0, &FeatureCollection::from(&point).into())
@@ -406,7 +401,7 @@ fn write_polygon_with_belly_cells_at_res0() {
 
     cellulite.build(&mut wtxn, &|| false, &NoProgress).unwrap();
     insta::assert_snapshot!(cellulite.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Collection(Zollection { bounding_box: BoundingBox { bottom_left: Coord { x: -10.38791, y: 51.6838 }, top_right: Coord { x: -10.38791, y: 51.6838 } }, points: ZultiPoints { bounding_box: BoundingBox { bottom_left: Coord { x: -10.38791, y: 51.6838 }, top_right: Coord { x: -10.38791, y: 51.6838 } }, points: [Zoint { lng: -10.38791, lat: 51.6838 }] }, lines: ZultiLines { bounding_box: BoundingBox { bottom_left: Coord { x: 0.0, y: 0.0 }, top_right: Coord { x: 0.0, y: 0.0 } }, zines: [] }, polygons: ZultiPolygons { bounding_box: BoundingBox { bottom_left: Coord { x: 0.0, y: 0.0 }, top_right: Coord { x: 0.0, y: 0.0 } }, zolygons: [] } })
     1: Polygon(Zolygon { bounding_box: BoundingBox { bottom_left: Coord { x: -36.80442428588867, y: 37.05668258666992 }, top_right: Coord { x: 12.589740753173828, y: 65.76936340332031 } }, coords: [Coord { x: -36.80442428588867, y: 59.85004425048828 }, Coord { x: -8.567954063415527, y: 65.76936340332031 }, Coord { x: 12.589740753173828, y: 56.09892654418945 }, Coord { x: 6.169264793395996, y: 41.49180603027344 }, Coord { x: -11.232604026794434, y: 37.05668258666992 }, Coord { x: -32.81175231933594, y: 44.35645294189453 }, Coord { x: -36.80442428588867, y: 59.85004425048828 }] })
@@ -430,6 +425,34 @@ fn write_polygon_with_belly_cells_at_res0() {
     ];
     let res = cellulite.in_shape(&wtxn, &filter).unwrap();
     insta::assert_debug_snapshot!(res, @"RoaringBitmap<[0, 1]>");
+
+    let res = cellulite.in_shape_with_provenance(&wtxn, &filter).unwrap();
+    let sources: Vec<_> = res
+        .iter()
+        .map(|(item, provenance)| (*item, provenance.source))
+        .collect();
+    assert_eq!(
+        sources,
+        vec![(0, MatchSource::DoubleCheck), (1, MatchSource::BellyCell)]
+    );
+
+    // The inspector tells which belly cell returned the item
+    let mut steps = Vec::new();
+    cellulite
+        .in_shape_with_inspector(&wtxn, &filter, |step| steps.push(step))
+        .unwrap();
+    let (belly_cell, _) = cellulite
+        .inner_belly_cells(&wtxn)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(
+        steps.iter().any(|(step, cell)| {
+            matches!(step, FilteringStep::ReturnedFromBelly) && *cell == belly_cell
+        }),
+        "{steps:?}"
+    );
 }
 
 #[test]
@@ -437,7 +460,7 @@ fn write_polygon_with_belly_cells_at_res1() {
     // same test as above except we're doing everything at the res1 to be sure both the code at resolution 0 and 1 works
     let mut cellulite = create_database();
     let mut wtxn = cellulite.env.write_txn().unwrap();
-    cellulite.database.threshold = 2;
+    cellulite.database.options.threshold = 2;
     let point = GeometryCollection::from(point! { x:-10.89288, y: 52.91525 });
     cellulite
         .add(&mut wtxn, 0, &FeatureCollection::from(&point).into())
@@ -455,7 +478,7 @@ fn write_polygon_with_belly_cells_at_res1() {
 
     cellulite.build(&mut wtxn, &|| false, &NoProgress).unwrap();
     insta::assert_snapshot!(cellulite.snap(&wtxn), @r"
-    # Version: 0.3.0
+    # Version: 0.4.0
     # Items
     0: Collection(Zollection { bounding_box: BoundingBox { bottom_left: Coord { x: -10.89288, y: 52.91525 }, top_right: Coord { x: -10.89288, y: 52.91525 } }, points: ZultiPoints { bounding_box: BoundingBox { bottom_left: Coord { x: -10.89288, y: 52.91525 }, top_right: Coord { x: -10.89288, y: 52.91525 } }, points: [Zoint { lng: -10.89288, lat: 52.91525 }] }, lines: ZultiLines { bounding_box: BoundingBox { bottom_left: Coord { x: 0.0, y: 0.0 }, top_right: Coord { x: 0.0, y: 0.0 } }, zines: [] }, polygons: ZultiPolygons { bounding_box: BoundingBox { bottom_left: Coord { x: 0.0, y: 0.0 }, top_right: Coord { x: 0.0, y: 0.0 } }, zolygons: [] } })
     1: Polygon(Zolygon { bounding_box: BoundingBox { bottom_left: Coord { x: -22.350751876831055, y: 46.764404296875 }, top_right: Coord { x: -1.9412200450897217, y: 57.86238098144531 } }, coords: [Coord { x: -22.350751876831055, y: 54.04570388793945 }, Coord { x: -14.230262756347656, y: 57.86238098144531 }, Coord { x: -3.6089367866516113, y: 56.31303405761719 }, Coord { x: -1.9412200450897217, y: 50.917137145996094 }, Coord { x: -7.79402494430542, y: 46.764404296875 }, Coord { x: -18.57700538635254, y: 48.349578857421875 }, Coord { x: -22.350751876831055, y: 54.04570388793945 }] })
@@ -482,6 +505,3671 @@ fn write_polygon_with_belly_cells_at_res1() {
     insta::assert_debug_snapshot!(res, @"RoaringBitmap<[0, 1]>");
 }
 
+#[test]
+fn query_in_stored_item() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let region = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&polygon![
+        (x: 0.0, y: 0.0),
+        (x: 10.0, y: 0.0),
+        (x: 10.0, y: 10.0),
+        (x: 0.0, y: 10.0),
+        (x: 0.0, y: 0.0)
+    ])));
+    db.add(&mut wtxn, 0, &region).unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        5.0, 5.0,
+    ])));
+    db.add(&mut wtxn, 1, &point).unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        1.0, 1.0,
+    ])));
+    db.add(&mut wtxn, 2, &point).unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        20.0, 20.0,
+    ])));
+    db.add(&mut wtxn, 3, &point).unwrap();
+    let overlapping = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&polygon![
+        (x: 8.0, y: 8.0),
+        (x: 12.0, y: 8.0),
+        (x: 12.0, y: 12.0),
+        (x: 8.0, y: 12.0),
+        (x: 8.0, y: 8.0)
+    ])));
+    db.add(&mut wtxn, 4, &overlapping).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let ret = db.in_item(&wtxn, 0, ItemPredicate::Intersects).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[1, 2, 4]>");
+    let ret = db.in_item(&wtxn, 0, ItemPredicate::Within).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[1, 2]>");
+    let ret = db.in_item(&wtxn, 0, ItemPredicate::Contains).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[]>");
+    let ret = db.in_item(&wtxn, 1, ItemPredicate::Within).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[]>");
+    let ret = db.in_item(&wtxn, 1, ItemPredicate::Contains).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+    assert!(matches!(
+        db.in_item(&wtxn, 42, ItemPredicate::Intersects),
+        Err(Error::ItemDoesntExists(42))
+    ));
+}
+
+#[test]
+fn query_tiny_shape() {
+    // Tiny shapes don't start their search at the resolution zero, we must make sure
+    // we still find the items stored higher in the tree.
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let points = [
+        (2.3522, 48.8566),
+        (2.3525, 48.8568),
+        (2.2945, 48.8584),
+        (4.8357, 45.764),
+    ];
+    for (id, (lng, lat)) in points.into_iter().enumerate() {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            lng, lat,
+        ])));
+        db.add(&mut wtxn, id as u32, &point).unwrap();
+    }
+    let building = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&polygon![
+        (x: 2.3520, y: 48.8565),
+        (x: 2.3524, y: 48.8565),
+        (x: 2.3524, y: 48.8567),
+        (x: 2.3520, y: 48.8567),
+        (x: 2.3520, y: 48.8565)
+    ])));
+    db.add(&mut wtxn, 4, &building).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let shape = polygon![
+        (x: 2.3521, y: 48.8565),
+        (x: 2.3523, y: 48.8565),
+        (x: 2.3523, y: 48.8567),
+        (x: 2.3521, y: 48.8567),
+        (x: 2.3521, y: 48.8565)
+    ];
+    let ret = db.in_shape(&wtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 4]>");
+
+    // When every cell is considered as a leaf we must find the same items
+    db.database.options.threshold = 200;
+    let ret = db.in_shape(&wtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 4]>");
+}
+
+#[test]
+fn query_strictly_within() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        5.0, 5.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        20.0, 20.0,
+    ])));
+    db.add(&mut wtxn, 1, &point).unwrap();
+    let inside = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&polygon![
+        (x: 2.0, y: 2.0),
+        (x: 4.0, y: 2.0),
+        (x: 4.0, y: 4.0),
+        (x: 2.0, y: 4.0),
+        (x: 2.0, y: 2.0)
+    ])));
+    db.add(&mut wtxn, 2, &inside).unwrap();
+    let overlapping = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&polygon![
+        (x: 8.0, y: 8.0),
+        (x: 12.0, y: 8.0),
+        (x: 12.0, y: 12.0),
+        (x: 8.0, y: 12.0),
+        (x: 8.0, y: 8.0)
+    ])));
+    db.add(&mut wtxn, 3, &overlapping).unwrap();
+    let covering = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&polygon![
+        (x: -30.0, y: -30.0),
+        (x: 30.0, y: -30.0),
+        (x: 30.0, y: 30.0),
+        (x: -30.0, y: 30.0),
+        (x: -30.0, y: -30.0)
+    ])));
+    db.add(&mut wtxn, 4, &covering).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let shape = polygon![
+        (x: 0.0, y: 0.0),
+        (x: 10.0, y: 0.0),
+        (x: 10.0, y: 10.0),
+        (x: 0.0, y: 10.0),
+        (x: 0.0, y: 0.0)
+    ];
+    let ret = db.in_shape(&wtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 2, 3, 4]>");
+    let ret = db.in_shape_strictly_within(&wtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 2]>");
+}
+
+#[test]
+fn build_in_multiple_transactions() {
+    let mut db = create_database();
+    db.database.options.threshold = 2;
+    let mut wtxn = db.env.write_txn().unwrap();
+    for i in 0..5 {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            i as f64, i as f64,
+        ])));
+        db.add(&mut wtxn, i, &point).unwrap();
+    }
+    wtxn.commit().unwrap();
+
+    db.database
+        .build_in_multiple_transactions(&db.env, 2, &|| false, &NoProgress)
+        .unwrap();
+
+    let rtxn = db.env.read_txn().unwrap();
+    assert_eq!(db.build_checkpoint(&rtxn).unwrap(), None);
+    let shape = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 5.0, y: -1.0),
+        (x: 5.0, y: 5.0),
+        (x: -1.0, y: 5.0),
+        (x: -1.0, y: -1.0)
+    ];
+    let ret = db.in_shape(&rtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1, 2, 3, 4]>");
+}
+
+#[test]
+fn build_up_to_resolution() {
+    let mut db = create_database();
+    db.database.options.threshold = 2;
+    let mut wtxn = db.env.write_txn().unwrap();
+    for i in 0..5 {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            i as f64 / 100.0,
+            i as f64 / 100.0,
+        ])));
+        db.add(&mut wtxn, i, &point).unwrap();
+    }
+    db.build_up_to_resolution(&mut wtxn, Resolution::Two, &|| false, &NoProgress)
+        .unwrap();
+
+    let stats = db.stats(&wtxn).unwrap();
+    assert_eq!(
+        stats.cells_by_resolution.keys().max(),
+        Some(&Resolution::Two)
+    );
+    let shape = polygon![
+        (x: -0.005, y: -0.005),
+        (x: 0.025, y: -0.005),
+        (x: 0.025, y: 0.025),
+        (x: -0.005, y: 0.025),
+        (x: -0.005, y: -0.005)
+    ];
+    let ret = db.in_shape(&wtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1, 2]>");
+
+    let ret = db.build_up_to_resolution(&mut wtxn, Resolution::Three, &|| false, &NoProgress);
+    insta::assert_snapshot!(ret.unwrap_err(), @"Cannot increase the maximum resolution of the database from 2 to 3. Clear the database and build it again instead.");
+}
+
+#[test]
+fn cancel_while_exploding_a_huge_item() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let mut polygons = Vec::new();
+    for lng in -100..100 {
+        for lat in -50..50 {
+            let (lng, lat) = (lng as f64 * 1.5, lat as f64 * 1.5);
+            polygons.push(polygon![
+                (x: lng, y: lat),
+                (x: lng + 0.1, y: lat),
+                (x: lng + 0.1, y: lat + 0.1),
+                (x: lng, y: lat + 0.1),
+                (x: lng, y: lat)
+            ]);
+        }
+    }
+    let multi_polygon = geo::MultiPolygon::new(polygons);
+    let huge = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&multi_polygon)));
+    db.add(&mut wtxn, 0, &huge).unwrap();
+
+    // Cancel the build once we started exploding the item at level zero
+    let calls = AtomicUsize::new(0);
+    let canceled_at = OnceLock::new();
+    let cancel = || {
+        if calls.fetch_add(1, Ordering::Relaxed) < 100 {
+            false
+        } else {
+            canceled_at.get_or_init(Instant::now);
+            true
+        }
+    };
+    let ret = db.build(&mut wtxn, &cancel, &NoProgress);
+    let latency = canceled_at.get().unwrap().elapsed();
+    let Err(Error::BuildCanceled(Some(canceled))) = ret else {
+        panic!("{ret:?}");
+    };
+    assert!(
+        matches!(canceled.step, BuildSteps::InsertItemsAtLevelZero),
+        "{canceled:?}"
+    );
+    assert_eq!((canceled.items, canceled.committed_updates), (1, 0));
+    assert!(latency < Duration::from_millis(200), "{latency:?}");
+}
+
+#[test]
+fn read_while_building() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.0, 0.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+
+    let shape = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 1.0, y: -1.0),
+        (x: 1.0, y: 1.0),
+        (x: -1.0, y: 1.0),
+        (x: -1.0, y: -1.0)
+    ];
+    let mut wtxn = db.env.write_txn().unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.5, 0.5,
+    ])));
+    db.add(&mut wtxn, 1, &point).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    // The readers of another thread still see the previous build
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            let rtxn = db.env.read_txn().unwrap();
+            assert_eq!(db.build_generation(&rtxn).unwrap(), 1);
+            assert!(!db.is_building(&rtxn).unwrap());
+            let ret = db.in_shape(&rtxn, &shape).unwrap();
+            insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+        });
+    });
+    wtxn.commit().unwrap();
+
+    let rtxn = db.env.read_txn().unwrap();
+    assert_eq!(db.build_generation(&rtxn).unwrap(), 2);
+    let ret = db.in_shape(&rtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1]>");
+}
+
+#[test]
+fn query_circle_with_distance_models() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    // At this latitude a degree of longitude is about 38km
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        3.0, 70.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let center = point! { x: 0.0, y: 70.0 };
+    let ret = db.in_circle(&wtxn, center, 200_000.0, 20).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+    db.database.distance_model = DistanceModel::Geodesic;
+    let ret = db.in_circle(&wtxn, center, 200_000.0, 20).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+    // On a plane the circle is much narrower
+    db.database.distance_model = DistanceModel::Planar;
+    let ret = db.in_circle(&wtxn, center, 200_000.0, 20).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[]>");
+}
+
+#[test]
+fn query_circle_with_grid_disk() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    for (i, lng) in [2.30, 2.34, 2.38, 2.50].into_iter().enumerate() {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            lng, 48.85,
+        ])));
+        db.add(&mut wtxn, i as u32, &point).unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let center = point! { x: 2.34, y: 48.85 };
+    let ret = db.in_circle(&wtxn, center, 5_000.0, 20).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1, 2]>");
+    let ret = db.in_circle_with_grid_disk(&wtxn, center, 5_000.0).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1, 2]>");
+}
+
+#[test]
+fn query_circle_with_grid_disk_around_a_pentagon() {
+    use geo::Distance;
+
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    // The cells are smaller around the pentagons, the disk must still cover the whole circle
+    let pentagon = CellIndex::base_cells()
+        .find(|cell| cell.is_pentagon())
+        .unwrap();
+    let center = LatLng::from(pentagon);
+    let center = point! { x: center.lng(), y: center.lat() };
+    let mut points = Vec::new();
+    for i in 0..25 {
+        for j in 0..25 {
+            let point = point! {
+                x: center.x() - 3.0 + i as f64 * 0.25,
+                y: center.y() - 3.0 + j as f64 * 0.25,
+            };
+            let geojson = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+                point.x(),
+                point.y(),
+            ])));
+            db.add(&mut wtxn, points.len() as u32, &geojson).unwrap();
+            points.push(point);
+        }
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let radius = 150_000.0;
+    let expected: RoaringBitmap = points
+        .iter()
+        .enumerate()
+        .filter(|(_, point)| db.distance_model.distance(center, **point) <= radius)
+        .map(|(i, _)| i as u32)
+        .collect();
+    assert!(!expected.is_empty() && expected.len() < points.len() as u64);
+    let ret = db.in_circle_with_grid_disk(&wtxn, center, radius).unwrap();
+    assert_eq!(ret, expected);
+}
+
+#[test]
+fn query_ellipse() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    // One point 8km to the north and one 8km to the east of the center
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.0, 0.072,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.072, 0.0,
+    ])));
+    db.add(&mut wtxn, 1, &point).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let center = point! { x: 0.0, y: 0.0 };
+    let ret = db
+        .in_ellipse(&wtxn, center, 10_000.0, 2_000.0, 0.0)
+        .unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+    let ret = db
+        .in_ellipse(&wtxn, center, 10_000.0, 2_000.0, 90.0)
+        .unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[1]>");
+}
+
+#[test]
+fn query_any_geometry() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        1.0, 1.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        5.0, 5.0,
+    ])));
+    db.add(&mut wtxn, 1, &point).unwrap();
+    let square = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&polygon![
+        (x: 2.0, y: 2.0),
+        (x: 4.0, y: 2.0),
+        (x: 4.0, y: 4.0),
+        (x: 2.0, y: 4.0),
+        (x: 2.0, y: 2.0)
+    ])));
+    db.add(&mut wtxn, 2, &square).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let rect = geo::Rect::new(coord! { x: 0.0, y: 0.0 }, coord! { x: 3.0, y: 3.0 });
+    let ret = db.in_geometry(&wtxn, rect).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 2]>");
+    let triangle = geo::Triangle::new(
+        coord! { x: 4.5, y: 4.5 },
+        coord! { x: 6.0, y: 4.5 },
+        coord! { x: 6.0, y: 6.0 },
+    );
+    let ret = db.in_geometry(&wtxn, triangle).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[1]>");
+    let line = geo::LineString::from(vec![(0.0, 3.0), (6.0, 3.0)]);
+    let ret = db.in_geometry(&wtxn, line).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[2]>");
+    let ret = db.in_geometry(&wtxn, point! { x: 5.0, y: 5.0 }).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[1]>");
+    let ret = db
+        .items_containing_point(&wtxn, point! { x: 3.0, y: 3.0 })
+        .unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[2]>");
+    let geometry = Geometry::from(point! { x: 3.0, y: 3.0 });
+    let ret = db.in_geometry(&wtxn, geometry).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[2]>");
+}
+
+#[test]
+fn expire_items() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.0, 0.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    db.add_with_expiration(&mut wtxn, 1, &point, 10).unwrap();
+    db.add_with_expiration(&mut wtxn, 2, &point, 20).unwrap();
+    // Re-adding an item without expiration removes its previous expiration
+    db.add_with_expiration(&mut wtxn, 3, &point, 10).unwrap();
+    db.add(&mut wtxn, 3, &point).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    assert_eq!(db.expiration(&wtxn, 2).unwrap(), Some(20));
+    assert_eq!(db.expiration(&wtxn, 3).unwrap(), None);
+
+    let expired = db.expire(&mut wtxn, 15).unwrap();
+    insta::assert_debug_snapshot!(expired, @"RoaringBitmap<[1]>");
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    let shape = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 1.0, y: -1.0),
+        (x: 1.0, y: 1.0),
+        (x: -1.0, y: 1.0),
+        (x: -1.0, y: -1.0)
+    ];
+    let ret = db.in_shape(&wtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 2, 3]>");
+    let expired = db.expire(&mut wtxn, 15).unwrap();
+    insta::assert_debug_snapshot!(expired, @"RoaringBitmap<[]>");
+}
+
+#[test]
+fn soft_delete_and_purge() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.0, 0.0,
+    ])));
+    for i in 0..4 {
+        db.add(&mut wtxn, i, &point).unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    let shape = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 1.0, y: -1.0),
+        (x: 1.0, y: 1.0),
+        (x: -1.0, y: 1.0),
+        (x: -1.0, y: -1.0)
+    ];
+
+    db.soft_delete(&mut wtxn, 1).unwrap();
+    db.soft_delete(&mut wtxn, 2).unwrap();
+    // Re-inserting an item revives it
+    db.soft_delete(&mut wtxn, 3).unwrap();
+    db.add(&mut wtxn, 3, &point).unwrap();
+    let ret = db.in_shape(&wtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 3]>");
+    insta::assert_debug_snapshot!(db.tombstones(&wtxn).unwrap(), @"RoaringBitmap<[1, 2]>");
+
+    db.purge(&mut wtxn, &|| false, &NoProgress).unwrap();
+    insta::assert_debug_snapshot!(db.tombstones(&wtxn).unwrap(), @"RoaringBitmap<[]>");
+    assert!(db.item(&wtxn, 1).unwrap().is_none());
+    let ret = db.in_shape(&wtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 3]>");
+
+    db.soft_delete(&mut wtxn, 0).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    insta::assert_debug_snapshot!(db.tombstones(&wtxn).unwrap(), @"RoaringBitmap<[]>");
+    let ret = db.in_shape(&wtxn, &shape).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[3]>");
+}
+
+#[test]
+fn last_build_changes() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.0, 0.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        100.0, 40.0,
+    ])));
+    db.add(&mut wtxn, 1, &point).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    let first = LatLng::new(0.0, 0.0).unwrap().to_cell(Resolution::Zero);
+    let second = LatLng::new(40.0, 100.0).unwrap().to_cell(Resolution::Zero);
+    let mut expected = vec![first, second];
+    expected.sort_unstable();
+    assert_eq!(db.last_build_changes(&wtxn).unwrap(), expected);
+
+    db.delete(&mut wtxn, 1).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    assert_eq!(db.last_build_changes(&wtxn).unwrap(), vec![second]);
+
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    assert_eq!(db.last_build_changes(&wtxn).unwrap(), vec![]);
+}
+
+#[test]
+fn diff_two_databases() {
+    let yesterday = create_database();
+    let today = create_database();
+    let origin = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.0, 0.0,
+    ])));
+    let far = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        100.0, 40.0,
+    ])));
+
+    let mut wtxn = yesterday.env.write_txn().unwrap();
+    yesterday.add(&mut wtxn, 0, &origin).unwrap();
+    yesterday.add(&mut wtxn, 1, &origin).unwrap();
+    yesterday.add(&mut wtxn, 2, &origin).unwrap();
+    yesterday.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+
+    let mut wtxn = today.env.write_txn().unwrap();
+    today.add(&mut wtxn, 0, &origin).unwrap();
+    today.add(&mut wtxn, 1, &far).unwrap();
+    today.add(&mut wtxn, 3, &origin).unwrap();
+    today.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+
+    let rtxn = yesterday.env.read_txn().unwrap();
+    let other_rtxn = today.env.read_txn().unwrap();
+    let diff = yesterday.diff(&rtxn, &today, &other_rtxn).unwrap();
+    insta::assert_debug_snapshot!(diff.added, @"RoaringBitmap<[3]>");
+    insta::assert_debug_snapshot!(diff.removed, @"RoaringBitmap<[2]>");
+    insta::assert_debug_snapshot!(diff.modified, @"RoaringBitmap<[1]>");
+    let first = LatLng::new(0.0, 0.0).unwrap().to_cell(Resolution::Zero);
+    let second = LatLng::new(40.0, 100.0).unwrap().to_cell(Resolution::Zero);
+    let mut expected = vec![first, second];
+    expected.sort_unstable();
+    assert_eq!(diff.changed_cells, expected);
+}
+
+#[test]
+fn retrieve_cells_in_shape() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        1.0, 1.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        1.5, 1.5,
+    ])));
+    db.add(&mut wtxn, 1, &point).unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        4.0, 4.0,
+    ])));
+    db.add(&mut wtxn, 2, &point).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let shape = polygon![
+        (x: 0.0, y: 0.0),
+        (x: 2.0, y: 0.0),
+        (x: 2.0, y: 2.0),
+        (x: 0.0, y: 2.0),
+        (x: 0.0, y: 0.0)
+    ];
+    for resolution in [Resolution::Three, Resolution::Six] {
+        let cells = db.cells_in_shape(&wtxn, &shape, resolution).unwrap();
+        let mut all = RoaringBitmap::new();
+        for (cell, bitmap) in cells {
+            assert_eq!(cell.resolution(), resolution);
+            all |= bitmap;
+        }
+        insta::assert_debug_snapshot!(all, @"RoaringBitmap<[0, 1]>");
+    }
+}
+
+#[test]
+fn distance_buckets() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    // Respectively at about 11km, 55km, 111km and 555km of the origin
+    for (i, lat) in [0.1, 0.5, 1.0, 5.0].into_iter().enumerate() {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            0.0, lat,
+        ])));
+        db.add(&mut wtxn, i as u32, &point).unwrap();
+    }
+    let square = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&polygon![
+        (x: -1.0, y: -1.0),
+        (x: 1.0, y: -1.0),
+        (x: 1.0, y: 1.0),
+        (x: -1.0, y: 1.0),
+        (x: -1.0, y: -1.0)
+    ])));
+    db.add(&mut wtxn, 4, &square).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let origin = point! { x: 0.0, y: 0.0 };
+    let edges = [20_000.0, 100_000.0, 200_000.0];
+    let buckets = db.distance_buckets(&wtxn, origin, &edges, None).unwrap();
+    insta::assert_debug_snapshot!(buckets, @r"
+    [
+        RoaringBitmap<[0, 4]>,
+        RoaringBitmap<[1]>,
+        RoaringBitmap<[2]>,
+        RoaringBitmap<[3]>,
+    ]
+    ");
+
+    let candidates = RoaringBitmap::from_iter([1, 3]);
+    let buckets = db
+        .distance_buckets(&wtxn, origin, &edges, Some(&candidates))
+        .unwrap();
+    insta::assert_debug_snapshot!(buckets, @r"
+    [
+        RoaringBitmap<[]>,
+        RoaringBitmap<[1]>,
+        RoaringBitmap<[]>,
+        RoaringBitmap<[3]>,
+    ]
+    ");
+}
+
+#[test]
+fn cells_are_stored_by_locality() {
+    use crate::keys::{cell_to_locality_key, locality_key_to_cell};
+
+    let cell = LatLng::new(43.99, 3.60)
+        .unwrap()
+        .to_cell(Resolution::Fifteen);
+    for res in Resolution::range(Resolution::Zero, Resolution::Fifteen) {
+        let parent = cell.parent(res).unwrap();
+        assert_eq!(
+            locality_key_to_cell(cell_to_locality_key(parent)).unwrap(),
+            parent
+        );
+
+        // The children of a cell are stored right before it
+        if let Some(next) = res.succ() {
+            for child in parent.children(next) {
+                assert!(cell_to_locality_key(child) < cell_to_locality_key(parent));
+            }
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "checksum")]
+fn detect_corrupted_values() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.0, 0.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    assert!(db.item(&wtxn, 0).unwrap().is_some());
+
+    // Flip a bit of the shape without updating its checksum
+    let item_db = db.database.item.remap_data_type::<heed::types::Bytes>();
+    let mut shape = item_db.get(&wtxn, &0).unwrap().unwrap().to_vec();
+    shape[0] ^= 1;
+    item_db.put(&mut wtxn, &0, &shape).unwrap();
+
+    let err = db.item(&wtxn, 0).unwrap_err();
+    assert!(
+        matches!(&err, Error::Corruption(key, _) if key == "item 0"),
+        "{err}"
+    );
+    let square = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 1.0, y: -1.0),
+        (x: 1.0, y: 1.0),
+        (x: -1.0, y: 1.0),
+        (x: -1.0, y: -1.0)
+    ];
+    let err = db.in_shape(&wtxn, &square).unwrap_err();
+    assert!(
+        matches!(&err, Error::Corruption(key, _) if key == "item 0"),
+        "{err}"
+    );
+}
+
+#[test]
+fn open_a_database_written_with_another_checksum_feature() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.add_diagonal_points(&mut wtxn, 3);
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    // Pretend the values were written by a build of cellulite with the other configuration
+    db.database
+        .set_values_checksummed(&mut wtxn, !cfg!(feature = "checksum"))
+        .unwrap();
+    wtxn.commit().unwrap();
+
+    let rtxn = db.env.read_txn().unwrap();
+    let err = Cellulite::open_from_env(&db.env, &rtxn, "cellulite").unwrap_err();
+    if cfg!(feature = "checksum") {
+        assert!(matches!(err, Error::UnexpectedChecksumFeature), "{err}");
+    } else {
+        assert!(matches!(err, Error::MissingChecksumFeature), "{err}");
+    }
+    drop(rtxn);
+
+    // Once cleared, the database is written with the configuration of this build
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.clear(&mut wtxn).unwrap();
+    wtxn.commit().unwrap();
+    let rtxn = db.env.read_txn().unwrap();
+    Cellulite::open_from_env(&db.env, &rtxn, "cellulite").unwrap();
+}
+
+#[test]
+fn group_items_by_cell() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    for (i, (lng, lat)) in [(0.0, 0.0), (0.001, 0.001), (100.0, 40.0)]
+        .into_iter()
+        .enumerate()
+    {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            lng, lat,
+        ])));
+        db.add(&mut wtxn, i as u32, &point).unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let first = LatLng::new(0.0, 0.0).unwrap().to_cell(Resolution::Two);
+    let second = LatLng::new(40.0, 100.0).unwrap().to_cell(Resolution::Two);
+    let groups = db
+        .group_by_cell(&wtxn, &RoaringBitmap::from_iter([0, 1, 2]), Resolution::Two)
+        .unwrap();
+    assert_eq!(groups.len(), 2);
+    insta::assert_debug_snapshot!(groups[&first], @"RoaringBitmap<[0, 1]>");
+    insta::assert_debug_snapshot!(groups[&second], @"RoaringBitmap<[2]>");
+
+    let err = db.group_by_cell(&wtxn, &RoaringBitmap::from_iter([3]), Resolution::Two);
+    assert!(matches!(err, Err(Error::ItemDoesntExists(3))));
+}
+
+#[test]
+fn query_with_limit() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 3;
+    db.add_diagonal_points(&mut wtxn, 20);
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let square = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 1.0, y: -1.0),
+        (x: 1.0, y: 1.0),
+        (x: -1.0, y: 1.0),
+        (x: -1.0, y: -1.0)
+    ];
+    let all = db.in_shape(&wtxn, &square).unwrap();
+    assert_eq!(all.len(), 20);
+
+    let options = QueryOptions::default();
+    assert_eq!(
+        db.in_shape_with_options(&wtxn, &square, options).unwrap(),
+        all
+    );
+    let options = QueryOptions {
+        limit: Some(100),
+        ..QueryOptions::default()
+    };
+    assert_eq!(
+        db.in_shape_with_options(&wtxn, &square, options).unwrap(),
+        all
+    );
+
+    let options = QueryOptions {
+        limit: Some(3),
+        ..QueryOptions::default()
+    };
+    let first = db.in_shape_with_options(&wtxn, &square, options).unwrap();
+    assert_eq!(first.len(), 3);
+    assert!(first.is_subset(&all));
+
+    let options = QueryOptions {
+        stop_after_candidates: Some(1),
+        ..QueryOptions::default()
+    };
+    let ret = db.in_shape_with_options(&wtxn, &square, options).unwrap();
+    assert!(!ret.is_empty());
+    assert!(ret.is_subset(&all));
+}
+
+#[test]
+fn health_report() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    db.add_diagonal_points(&mut wtxn, 10);
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let report = db.health_report(&wtxn).unwrap();
+    assert_eq!(report.item_db.entries, 10);
+    assert_eq!(report.pending_updates, 0);
+    assert_eq!(report.empty_bitmaps, 0);
+    assert_eq!(report.orphaned_belly_cells, 0);
+    assert_eq!(
+        report.shallowest_leaf.is_some(),
+        report.deepest_leaf.is_some()
+    );
+    insta::assert_debug_snapshot!(report.warnings, @"[]");
+
+    db.delete(&mut wtxn, 0).unwrap();
+    let report = db.health_report(&wtxn).unwrap();
+    insta::assert_debug_snapshot!(report.warnings, @r#"
+    [
+        "1 updates are waiting for a build, they're not visible to the queries.",
+    ]
+    "#);
+}
+
+#[test]
+fn reject_empty_geometries() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let empty = [
+        geojson::Value::LineString(Vec::new()),
+        geojson::Value::MultiPoint(Vec::new()),
+        geojson::Value::MultiPolygon(Vec::new()),
+        geojson::Value::MultiPolygon(vec![vec![Vec::new()]]),
+        geojson::Value::GeometryCollection(Vec::new()),
+    ];
+    for value in empty {
+        let geojson = GeoJson::from(geojson::Geometry::new(value));
+        let err = db.add(&mut wtxn, 0, &geojson).unwrap_err();
+        assert!(matches!(err, Error::EmptyGeometry(0)), "{err}");
+    }
+    assert!(db.item(&wtxn, 0).unwrap().is_none());
+}
+
+#[test]
+fn reject_or_repair_self_intersecting_polygons() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let bow_tie = polygon![
+        (x: 0.0, y: 0.0),
+        (x: 1.0, y: 1.0),
+        (x: 1.0, y: 0.0),
+        (x: 0.0, y: 1.0),
+        (x: 0.0, y: 0.0)
+    ];
+    let bow_tie = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&bow_tie)));
+    let err = db.add(&mut wtxn, 0, &bow_tie).unwrap_err();
+    insta::assert_snapshot!(err, @"The item `0` contains an invalid polygon: exterior ring has a self-intersection.");
+
+    db.database.repair_polygons = true;
+    db.add(&mut wtxn, 0, &bow_tie).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    let Geometry::MultiPolygon(shape) = db.item(&wtxn, 0).unwrap().unwrap().to_geo() else {
+        panic!("The bow-tie should have been split in a multi-polygon");
+    };
+    assert_eq!(shape.0.len(), 2);
+
+    // Both triangles of the bow-tie can be found
+    let left = polygon![
+        (x: 0.0, y: 0.4),
+        (x: 0.1, y: 0.4),
+        (x: 0.1, y: 0.6),
+        (x: 0.0, y: 0.6),
+        (x: 0.0, y: 0.4)
+    ];
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &left).unwrap(), @"RoaringBitmap<[0]>");
+}
+
+#[test]
+fn winding_order_does_not_matter() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let counter_clockwise = polygon![
+        (x: 0.0, y: 0.0),
+        (x: 1.0, y: 0.0),
+        (x: 1.0, y: 1.0),
+        (x: 0.0, y: 1.0),
+        (x: 0.0, y: 0.0)
+    ];
+    let mut clockwise = counter_clockwise.clone();
+    clockwise.exterior_mut(|exterior| exterior.0.reverse());
+
+    let geojson = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&clockwise)));
+    db.add(&mut wtxn, 0, &geojson).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    let Geometry::Polygon(stored) = db.item(&wtxn, 0).unwrap().unwrap().to_geo() else {
+        panic!("The item should be a polygon");
+    };
+    assert_eq!(stored.exterior().0[1], counter_clockwise.exterior().0[1]);
+
+    let ret = db.in_shape(&wtxn, &counter_clockwise).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+    let ret = db.in_shape(&wtxn, &clockwise).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+}
+
+#[test]
+fn clean_the_vertices_on_insertion() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.clean_vertices = true;
+    let dirty = polygon![
+        (x: 0.0, y: 0.0),
+        (x: 0.5, y: 0.0),
+        (x: 1.0, y: 0.0),
+        (x: 1.0, y: 0.0),
+        (x: 1.0, y: 1.0),
+        // A spike going outside of the square and back
+        (x: 1.0, y: 2.0),
+        (x: 1.0, y: 1.0),
+        (x: 0.0, y: 1.0),
+        (x: 0.0, y: 0.0)
+    ];
+    let geojson = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&dirty)));
+    db.add(&mut wtxn, 0, &geojson).unwrap();
+    let Geometry::Polygon(stored) = db.item(&wtxn, 0).unwrap().unwrap().to_geo() else {
+        panic!("The item should be a polygon");
+    };
+    insta::assert_debug_snapshot!(stored.exterior().0, @r"
+    [
+        Coord {
+            x: 0.0,
+            y: 0.0,
+        },
+        Coord {
+            x: 1.0,
+            y: 0.0,
+        },
+        Coord {
+            x: 1.0,
+            y: 1.0,
+        },
+        Coord {
+            x: 0.0,
+            y: 1.0,
+        },
+        Coord {
+            x: 0.0,
+            y: 0.0,
+        },
+    ]
+    ");
+}
+
+#[test]
+fn sharded_database() {
+    let dbs: Vec<_> = (0..3).map(|_| create_database()).collect();
+    let envs = dbs.iter().map(|db| db.env.clone()).collect();
+    let sharded = ShardedCellulite::create(envs, "cellulite", ShardingStrategy::ById).unwrap();
+    let items = (0..6).map(|i| {
+        let point = point!(x: i as f64, y: i as f64);
+        (
+            i,
+            GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point))),
+        )
+    });
+    sharded.add(items).unwrap();
+    sharded.delete(&RoaringBitmap::from_iter([4])).unwrap();
+    sharded.build(&|| false, &NoProgress).unwrap();
+
+    let shards: Vec<RoaringBitmap> = sharded
+        .shards()
+        .iter()
+        .map(|(env, cellulite)| {
+            let rtxn = env.read_txn().unwrap();
+            cellulite
+                .items(&rtxn)
+                .unwrap()
+                .map(|ret| ret.unwrap().0)
+                .collect()
+        })
+        .collect();
+    insta::assert_debug_snapshot!(shards, @r"
+    [
+        RoaringBitmap<[0, 3]>,
+        RoaringBitmap<[1]>,
+        RoaringBitmap<[2, 5]>,
+    ]
+    ");
+
+    let ret = ShardedCellulite::create(Vec::new(), "cellulite", ShardingStrategy::ById);
+    assert!(matches!(ret, Err(Error::NoShards)));
+}
+
+#[test]
+fn query_sharded_database() {
+    let dbs: Vec<_> = (0..3).map(|_| create_database()).collect();
+    let envs = dbs.iter().map(|db| db.env.clone()).collect();
+    let mut sharded =
+        ShardedCellulite::create(envs, "cellulite", ShardingStrategy::ByRegion).unwrap();
+    let items = (0..6).map(|i| {
+        let point = point!(x: i as f64 / 10.0, y: i as f64 / 10.0);
+        (
+            i,
+            GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point))),
+        )
+    });
+    sharded.add(items).unwrap();
+    sharded.build(&|| false, &NoProgress).unwrap();
+
+    let square = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 6.0, y: -1.0),
+        (x: 6.0, y: 6.0),
+        (x: -1.0, y: 6.0),
+        (x: -1.0, y: -1.0)
+    ];
+    let ret = sharded.in_shape(&square).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1, 2, 3, 4, 5]>");
+
+    // All the points are in the same base cell, thus in the same shard
+    let shard = sharded
+        .shard_of(0, &Geometry::Point(point!(x: 0.0, y: 0.0)))
+        .unwrap();
+    sharded.set_id_offset(shard, 100).unwrap();
+    let ret = sharded.in_shape(&square).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[100, 101, 102, 103, 104, 105]>");
+
+    sharded.set_id_offset(shard, u32::MAX - 3).unwrap();
+    let ret = sharded.in_shape(&square);
+    assert!(matches!(ret, Err(Error::ItemIdOverflow(4, _))), "{ret:?}");
+    let ret = sharded.set_id_offset(3, 100);
+    assert!(
+        matches!(ret, Err(Error::ShardDoesntExists(3, 3))),
+        "{ret:?}"
+    );
+}
+
+#[test]
+fn copy_to_replica() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let point = point!(x: 6.0, y: 45.0);
+    db.add(
+        &mut wtxn,
+        0,
+        &GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point))),
+    )
+    .unwrap();
+    wtxn.commit().unwrap();
+
+    // The item is not built yet, it wouldn't be visible in the replica
+    let dir = tempfile::tempdir().unwrap();
+    let err = Cellulite::copy_to(&db.env, "cellulite", dir.path(), true).unwrap_err();
+    insta::assert_snapshot!(err, @"The copy of the database cannot be used as a replica because 1 updates are waiting for a build, they would never be visible.");
+    assert!(!dir.path().join("data.mdb").exists());
+
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+    Cellulite::copy_to(&db.env, "cellulite", dir.path(), true).unwrap();
+
+    let env = unsafe {
+        EnvOpenOptions::new()
+            .max_dbs(Cellulite::nb_dbs())
+            .open(dir.path())
+    }
+    .unwrap();
+    let rtxn = env.read_txn().unwrap();
+    let replica = Cellulite::open_from_env(&env, &rtxn, "cellulite").unwrap();
+    let area = polygon![
+        (x: 5.0, y: 44.0),
+        (x: 7.0, y: 44.0),
+        (x: 7.0, y: 46.0),
+        (x: 5.0, y: 46.0)
+    ];
+    let ret = replica.in_shape(&rtxn, &area).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+}
+
+#[test]
+fn incremental_build_matches_full_build() {
+    let points: Vec<_> = (0..300)
+        .map(|i| {
+            let point = point!(x: (i % 20) as f64 / 10.0, y: (i / 20) as f64 / 10.0);
+            GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point)))
+        })
+        .collect();
+
+    let full = create_database();
+    let mut wtxn = full.env.write_txn().unwrap();
+    for (i, point) in points.iter().enumerate() {
+        full.add(&mut wtxn, i as u32, point).unwrap();
+    }
+    full.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let incremental = create_database();
+    let mut wtxn2 = incremental.env.write_txn().unwrap();
+    for (i, point) in points.iter().enumerate().take(290) {
+        incremental.add(&mut wtxn2, i as u32, point).unwrap();
+    }
+    incremental
+        .build(&mut wtxn2, &|| false, &NoProgress)
+        .unwrap();
+    for (i, point) in points.iter().enumerate().skip(290) {
+        incremental.add(&mut wtxn2, i as u32, point).unwrap();
+    }
+    incremental
+        .build(&mut wtxn2, &|| false, &NoProgress)
+        .unwrap();
+
+    assert_eq!(full.cells(&wtxn), incremental.cells(&wtxn2));
+}
+
+#[test]
+fn options_are_stored_in_the_database() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let options = CelluliteOptions {
+        threshold: 2,
+        max_resolution: Resolution::Five,
+        belly_cells: false,
+        ..CelluliteOptions::default()
+    };
+    db.database.set_options(&mut wtxn, options).unwrap();
+    let point = point!(x: 6.0, y: 45.0);
+    let geojson = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point)));
+    for i in 0..3 {
+        db.add(&mut wtxn, i, &geojson).unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let reopened = Cellulite::open_from_env(&db.env, &wtxn, "cellulite").unwrap();
+    assert_eq!(reopened.options(), &options);
+
+    let lower_threshold = CelluliteOptions {
+        threshold: 1,
+        ..options
+    };
+    let err = db
+        .database
+        .set_options(&mut wtxn, lower_threshold)
+        .unwrap_err();
+    insta::assert_snapshot!(err, @"Cannot decrease the threshold of the database from 2 to 1. Clear the database and build it again instead.");
+}
+
+#[test]
+fn list_the_prefixes_of_an_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let env = unsafe {
+        EnvOpenOptions::new()
+            .max_dbs(Cellulite::nb_dbs() * 2 + 1)
+            .open(dir.path())
+    }
+    .unwrap();
+    let mut wtxn = env.write_txn().unwrap();
+    Cellulite::create_from_env(&env, &mut wtxn, "parcels").unwrap();
+    Cellulite::create_from_env(&env, &mut wtxn, "cities").unwrap();
+    // An incomplete database must be ignored
+    env.create_database::<heed::types::Bytes, heed::types::Bytes>(&mut wtxn, Some("roads-item"))
+        .unwrap();
+    insta::assert_debug_snapshot!(Cellulite::list_prefixes(&env, &wtxn).unwrap(), @r#"
+    [
+        "cities",
+        "parcels",
+    ]
+    "#);
+}
+
+#[test]
+fn ignore_the_optional_metadata_of_newer_versions() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let metadata = db
+        .metadata
+        .remap_types::<heed::types::Bytes, heed::types::Bytes>();
+    metadata.put(&mut wtxn, &[100], b"optional").unwrap();
+    Cellulite::open_from_env(&db.env, &wtxn, "cellulite").unwrap();
+
+    metadata.put(&mut wtxn, &[200], b"required").unwrap();
+    let err = Cellulite::open_from_env(&db.env, &wtxn, "cellulite").unwrap_err();
+    insta::assert_snapshot!(err, @"The database was written by a newer version of cellulite, the metadata `200` is required to read it. Upgrade cellulite to open it.");
+}
+
+#[test]
+fn densify_the_items_on_insertion() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let line = geo::line_string![(x: 0.0, y: 0.0), (x: 10.0, y: 0.0)];
+    let geojson = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&line)));
+    db.add(&mut wtxn, 0, &geojson).unwrap();
+    let options = CelluliteOptions {
+        densify_items: Some(500_000.0),
+        ..*db.options()
+    };
+    db.database.set_options(&mut wtxn, options).unwrap();
+    db.add(&mut wtxn, 1, &geojson).unwrap();
+    db.database.distance_model = DistanceModel::Planar;
+    db.add(&mut wtxn, 2, &geojson).unwrap();
+
+    let nb_points = |item| {
+        let Geometry::LineString(line) = db.item(&wtxn, item).unwrap().unwrap().to_geo() else {
+            panic!("The item should be a line");
+        };
+        line.0.len()
+    };
+    // The line is 1112km long on the sphere
+    insta::assert_debug_snapshot!([nb_points(0), nb_points(1), nb_points(2)], @r"
+    [
+        2,
+        4,
+        2,
+    ]
+    ");
+
+    // The option is stored, the other handles densify the items too
+    let other = Cellulite::open_from_env(&db.env, &wtxn, "cellulite").unwrap();
+    assert_eq!(other.options().densify_items, Some(500_000.0));
+}
+
+#[test]
+fn split_the_polygons_crossing_the_antimeridian() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let crossing = polygon![
+        (x: 179.0, y: 64.0),
+        (x: -179.0, y: 64.0),
+        (x: -179.0, y: 65.0),
+        (x: 179.0, y: 65.0),
+        (x: 179.0, y: 64.0)
+    ];
+    let geojson = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&crossing)));
+    db.add(&mut wtxn, 0, &geojson).unwrap();
+    let Geometry::MultiPolygon(parts) = db.item(&wtxn, 0).unwrap().unwrap().to_geo() else {
+        panic!("The item should be split in a multi-polygon");
+    };
+    insta::assert_debug_snapshot!(parts.0.len(), @"2");
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let east = polygon![(x: 179.4, y: 64.4), (x: 179.6, y: 64.4), (x: 179.6, y: 64.6)];
+    let west = polygon![(x: -179.6, y: 64.4), (x: -179.4, y: 64.4), (x: -179.4, y: 64.6)];
+    // Without the split, the polygon would cover the whole latitude band
+    let greenwich = polygon![(x: -0.1, y: 64.4), (x: 0.1, y: 64.4), (x: 0.1, y: 64.6)];
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &east).unwrap(), @"RoaringBitmap<[0]>");
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &west).unwrap(), @"RoaringBitmap<[0]>");
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &greenwich).unwrap(), @"RoaringBitmap<[]>");
+}
+
+#[test]
+fn index_the_polygons_enclosing_a_pole() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    // A ring going around the south pole, like Antarctica
+    let around_the_pole = polygon![
+        (x: 0.0, y: -70.0),
+        (x: 90.0, y: -70.0),
+        (x: 180.0, y: -70.0),
+        (x: -90.0, y: -70.0),
+        (x: 0.0, y: -70.0)
+    ];
+    let geojson = GeoJson::from(geojson::Geometry::new(geojson::Value::from(
+        &around_the_pole,
+    )));
+    db.add(&mut wtxn, 0, &geojson).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let pole = polygon![(x: -10.0, y: -89.0), (x: 10.0, y: -89.0), (x: 0.0, y: -88.0)];
+    let east = polygon![(x: 135.0, y: -80.0), (x: 136.0, y: -80.0), (x: 136.0, y: -79.0)];
+    let west = polygon![(x: -136.0, y: -80.0), (x: -135.0, y: -80.0), (x: -135.0, y: -79.0)];
+    let outside = polygon![(x: 45.0, y: -60.0), (x: 46.0, y: -60.0), (x: 46.0, y: -59.0)];
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &pole).unwrap(), @"RoaringBitmap<[0]>");
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &east).unwrap(), @"RoaringBitmap<[0]>");
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &west).unwrap(), @"RoaringBitmap<[0]>");
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &outside).unwrap(), @"RoaringBitmap<[]>");
+}
+
+#[test]
+fn order_the_corridor_along_the_route() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let points = [(1.5, 0.01), (0.2, -0.01), (1.0, 0.02), (0.5, 1.0)];
+    for (i, (x, y)) in points.into_iter().enumerate() {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![x, y])));
+        db.add(&mut wtxn, i as u32, &point).unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let route = geo::line_string![(x: 0.0, y: 0.0), (x: 1.0, y: 0.0), (x: 2.0, y: 0.0)];
+    let corridor = db.in_corridor(&wtxn, &route, 5_000.0).unwrap();
+    insta::assert_debug_snapshot!(corridor, @"RoaringBitmap<[0, 1, 2]>");
+    let ordered = db.in_corridor_along_route(&wtxn, &route, 5_000.0).unwrap();
+    let items: Vec<_> = ordered.iter().map(|(item, _)| *item).collect();
+    insta::assert_debug_snapshot!(items, @r"
+    [
+        1,
+        2,
+        0,
+    ]
+    ");
+    // The positions are in meters from the start of the route
+    assert!((ordered[1].1 - 111_195.0).abs() < 100.0, "{ordered:?}");
+}
+
+#[test]
+fn centroids_of_the_items() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let square = polygon![
+        (x: 0.0, y: 0.0),
+        (x: 2.0, y: 0.0),
+        (x: 2.0, y: 2.0),
+        (x: 0.0, y: 2.0),
+        (x: 0.0, y: 0.0)
+    ];
+    let square = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&square)));
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        5.0, 6.0,
+    ])));
+    db.add(&mut wtxn, 0, &square).unwrap();
+    db.add(&mut wtxn, 1, &point).unwrap();
+
+    let items = RoaringBitmap::from_iter([0, 1, 2]);
+    let centroids: Vec<_> = db
+        .centroids(&wtxn, &items)
+        .map(|ret| ret.unwrap())
+        .collect();
+    insta::assert_debug_snapshot!(centroids, @r"
+    [
+        (
+            0,
+            POINT(1.0 1.0),
+        ),
+        (
+            1,
+            POINT(5.0 6.0),
+        ),
+    ]
+    ");
+}
+
+#[test]
+fn vacuum_the_stale_belly_cells() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    db.add_diagonal_points(&mut wtxn, 10);
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    // An empty belly cell under a split cell, and a belly cell whose parent doesn't exist
+    let empty = LatLng::new(0.0, 0.0).unwrap().to_cell(Resolution::One);
+    let orphan = LatLng::new(50.0, 50.0).unwrap().to_cell(Resolution::Three);
+    db.cell_db()
+        .put(&mut wtxn, &Key::Belly(empty), &RoaringBitmap::new())
+        .unwrap();
+    db.cell_db()
+        .put(
+            &mut wtxn,
+            &Key::Belly(orphan),
+            &RoaringBitmap::from_iter([0]),
+        )
+        .unwrap();
+    let report = db.health_report(&wtxn).unwrap();
+    assert_eq!((report.empty_bitmaps, report.orphaned_belly_cells), (1, 1));
+
+    assert_eq!(db.vacuum(&mut wtxn).unwrap(), 2);
+    let report = db.health_report(&wtxn).unwrap();
+    assert_eq!((report.empty_bitmaps, report.orphaned_belly_cells), (0, 0));
+    assert_eq!(db.vacuum(&mut wtxn).unwrap(), 0);
+
+    // A belly cell whose H3 parent is a leaf, but that is a sub-cell of a split neighbour
+    let split = LatLng::new(-30.0, -60.0).unwrap().to_cell(Resolution::Two);
+    let belly = split
+        .center_child(Resolution::Three)
+        .unwrap()
+        .grid_disk::<Vec<_>>(2)
+        .into_iter()
+        .find(|cell| cell.parent(Resolution::Two) != Some(split))
+        .unwrap();
+    db.cell_db()
+        .put(
+            &mut wtxn,
+            &Key::Cell(split),
+            &RoaringBitmap::from_iter([0, 1]),
+        )
+        .unwrap();
+    db.cell_db()
+        .put(
+            &mut wtxn,
+            &Key::Belly(belly),
+            &RoaringBitmap::from_iter([0]),
+        )
+        .unwrap();
+    let report = db.health_report(&wtxn).unwrap();
+    assert_eq!(report.orphaned_belly_cells, 0);
+    assert_eq!(db.vacuum(&mut wtxn).unwrap(), 0);
+}
+
+#[test]
+fn reindex_from_the_items() {
+    let points: Vec<_> = (0..100)
+        .map(|i| {
+            let point = point!(x: (i % 10) as f64 / 10.0, y: (i / 10) as f64 / 10.0);
+            GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point)))
+        })
+        .collect();
+    let options = CelluliteOptions {
+        threshold: 3,
+        ..CelluliteOptions::default()
+    };
+
+    let mut reindexed = create_database();
+    let mut wtxn = reindexed.env.write_txn().unwrap();
+    for (i, point) in points.iter().enumerate() {
+        reindexed.add(&mut wtxn, i as u32, point).unwrap();
+    }
+    reindexed.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    reindexed.delete(&mut wtxn, 0).unwrap();
+    // The threshold couldn't be decreased without reindexing
+    let database = &mut reindexed.database;
+    database
+        .reindex_with_options(&mut wtxn, options, &|| false, &NoProgress)
+        .unwrap();
+
+    let mut expected = create_database();
+    let mut wtxn2 = expected.env.write_txn().unwrap();
+    expected.database.set_options(&mut wtxn2, options).unwrap();
+    for (i, point) in points.iter().enumerate().skip(1) {
+        expected.add(&mut wtxn2, i as u32, point).unwrap();
+    }
+    expected.build(&mut wtxn2, &|| false, &NoProgress).unwrap();
+
+    assert_eq!(reindexed.cells(&wtxn), expected.cells(&wtxn2));
+    assert!(reindexed.item(&wtxn, 0).unwrap().is_none());
+
+    // Reindexing again without changing anything gives the same cells
+    reindexed
+        .reindex(&mut wtxn, &|| false, &NoProgress)
+        .unwrap();
+    assert_eq!(reindexed.cells(&wtxn), expected.cells(&wtxn2));
+}
+
+#[test]
+fn explain_the_full_map_errors() {
+    insta::assert_debug_snapshot!(Cellulite::estimated_size_for(1_000, 10), @"1408000");
+
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        1.0, 2.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    let usage = db.map_usage(&wtxn).unwrap();
+    let full = usage.explain(Error::Heed(heed::Error::Mdb(heed::MdbError::MapFull)));
+    assert!(
+        matches!(full, Error::MapFull(used, needed) if used > 0 && needed > 0),
+        "{full}"
+    );
+    let other = usage.explain(Error::BuildCanceled(None));
+    assert!(matches!(other, Error::BuildCanceled(None)), "{other}");
+}
+
+#[test]
+fn build_is_independent_of_the_number_of_threads() {
+    let items: Vec<_> = (0..200)
+        .map(|i| {
+            let (x, y) = ((i % 20) as f64 / 10.0, (i / 20) as f64 / 10.0);
+            let geometry = if i % 3 == 0 {
+                geojson::Value::from(&polygon![
+                    (x: x, y: y),
+                    (x: x + 0.5, y: y),
+                    (x: x + 0.5, y: y + 0.5),
+                    (x: x, y: y)
+                ])
+            } else {
+                geojson::Value::Point(vec![x, y])
+            };
+            GeoJson::from(geojson::Geometry::new(geometry))
+        })
+        .collect();
+
+    let build_with_threads = |threads: usize| {
+        let mut db = create_database();
+        db.database.options.threshold = 5;
+        let mut wtxn = db.env.write_txn().unwrap();
+        for (i, item) in items.iter().enumerate() {
+            db.add(&mut wtxn, i as u32, item).unwrap();
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| db.build(&mut wtxn, &|| false, &NoProgress))
+            .unwrap();
+        let cells = db.inner_db_cells(&wtxn).unwrap();
+        let bellies = db.inner_belly_cells(&wtxn).unwrap();
+        cells
+            .chain(bellies)
+            .map(|ret| ret.unwrap())
+            .collect::<Vec<_>>()
+    };
+    let single_thread = build_with_threads(1);
+    assert_eq!(single_thread, build_with_threads(2));
+    assert_eq!(single_thread, build_with_threads(8));
+}
+
+#[test]
+fn checksum_of_the_databases() {
+    let build = |items: u32| {
+        let mut db = create_database();
+        db.database.options.threshold = 3;
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.add_diagonal_points(&mut wtxn, items);
+        db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+        db.checksum(&wtxn).unwrap()
+    };
+    assert_eq!(build(20), build(20));
+    assert_ne!(build(20), build(21));
+}
+
+#[test]
+fn build_the_updates_of_a_region() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    for (i, lng) in [2.0, 50.0].into_iter().enumerate() {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            lng, 45.0,
+        ])));
+        db.add(&mut wtxn, i as u32, &point).unwrap();
+    }
+    let region = polygon![
+        (x: 0.0, y: 40.0),
+        (x: 10.0, y: 40.0),
+        (x: 10.0, y: 50.0),
+        (x: 0.0, y: 50.0)
+    ];
+    let both_points = polygon![
+        (x: -10.0, y: 30.0),
+        (x: 60.0, y: 30.0),
+        (x: 60.0, y: 60.0),
+        (x: -10.0, y: 60.0)
+    ];
+    db.build_region(&mut wtxn, &region, &|| false, &NoProgress)
+        .unwrap();
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &both_points).unwrap(), @"RoaringBitmap<[0]>");
+    assert_eq!(db.update_db_stats(&wtxn).unwrap().entries, 1);
+
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &both_points).unwrap(), @"RoaringBitmap<[0, 1]>");
+
+    // The item moving out of the region must leave its cells, even though its new shape is away
+    db.update_geometry(&mut wtxn, 0, point!(x: 50.0, y: 46.0).into())
+        .unwrap();
+    db.build_region(&mut wtxn, &region, &|| false, &NoProgress)
+        .unwrap();
+    assert_eq!(db.update_db_stats(&wtxn).unwrap().entries, 0);
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &region).unwrap(), @"RoaringBitmap<[]>");
+}
+
+#[test]
+fn fragment_the_oversized_polygons() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    for size in [0.0, -2.0, f64::NAN, f64::INFINITY] {
+        let options = CelluliteOptions {
+            fragment_size: Some(size),
+            ..*db.options()
+        };
+        let ret = db.database.set_options(&mut wtxn, options);
+        assert!(matches!(ret, Err(Error::InvalidFragmentSize(_))), "{ret:?}");
+    }
+    let options = CelluliteOptions {
+        fragment_size: Some(2.0),
+        ..*db.options()
+    };
+    db.database.set_options(&mut wtxn, options).unwrap();
+    let large = polygon![
+        (x: 0.0, y: 0.0),
+        (x: 10.0, y: 0.0),
+        (x: 10.0, y: 10.0),
+        (x: 0.0, y: 10.0),
+        (x: 0.0, y: 0.0)
+    ];
+    let small = polygon![
+        (x: 20.0, y: 0.0),
+        (x: 21.0, y: 0.0),
+        (x: 21.0, y: 1.0),
+        (x: 20.0, y: 0.0)
+    ];
+    for (i, polygon) in [large, small].iter().enumerate() {
+        let geojson = GeoJson::from(geojson::Geometry::new(geojson::Value::from(polygon)));
+        db.add(&mut wtxn, i as u32, &geojson).unwrap();
+    }
+    let Geometry::MultiPolygon(fragments) = db.item(&wtxn, 0).unwrap().unwrap().to_geo() else {
+        panic!("The item should be cut in fragments");
+    };
+    insta::assert_debug_snapshot!(fragments.0.len(), @"25");
+    assert!(matches!(
+        db.item(&wtxn, 1).unwrap().unwrap().to_geo(),
+        Geometry::Polygon(_)
+    ));
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    // Spans the border between multiple fragments
+    let center = polygon![(x: 3.9, y: 3.9), (x: 4.1, y: 3.9), (x: 4.1, y: 4.1), (x: 3.9, y: 4.1)];
+    let outside = polygon![(x: 12.0, y: 12.0), (x: 13.0, y: 12.0), (x: 13.0, y: 13.0)];
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &center).unwrap(), @"RoaringBitmap<[0]>");
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &outside).unwrap(), @"RoaringBitmap<[]>");
+}
+
+#[test]
+fn sum_the_weights_by_cell() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    for (i, lng) in [0.0, 0.0001, 10.0].into_iter().enumerate() {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            lng, 0.0,
+        ])));
+        db.add(&mut wtxn, i as u32, &point).unwrap();
+    }
+    db.set_weight(&mut wtxn, 0, 1_500.0).unwrap();
+    db.set_weight(&mut wtxn, 2, 20.0).unwrap();
+    let err = db.set_weight(&mut wtxn, 3, 1.0).unwrap_err();
+    insta::assert_snapshot!(err, @"The item `3` doesn't exists in the database.");
+
+    let items = RoaringBitmap::from_iter([0, 1, 2]);
+    let mut weights: Vec<_> = db
+        .weight_by_cell(&wtxn, &items, Resolution::Five)
+        .unwrap()
+        .into_values()
+        .collect();
+    // The first two points are in the same cell, the one without a weight counts for one
+    weights.sort_by(f64::total_cmp);
+    insta::assert_debug_snapshot!(weights, @r"
+    [
+        20.0,
+        1501.0,
+    ]
+    ");
+
+    // Adding the item again removes its weight
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.0, 0.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    assert_eq!(db.weight(&wtxn, 0).unwrap(), None);
+}
+
+#[test]
+fn scope_the_queries_to_a_namespace() {
+    let mut db = create_database();
+    db.database.enable_query_stats();
+    let mut wtxn = db.env.write_txn().unwrap();
+    for item in 0..4 {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            0.0, 0.0,
+        ])));
+        db.add(&mut wtxn, item, &point).unwrap();
+    }
+    db.add_to_namespace(&mut wtxn, "tenant-a", 0).unwrap();
+    db.add_to_namespace(&mut wtxn, "tenant-a", 1).unwrap();
+    db.add_to_namespace(&mut wtxn, "tenant-b", 1).unwrap();
+    db.add_to_namespace(&mut wtxn, "tenant-b", 2).unwrap();
+    let err = db.add_to_namespace(&mut wtxn, "tenant-b", 4).unwrap_err();
+    insta::assert_snapshot!(err, @"The item `4` doesn't exists in the database.");
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let shape = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 1.0, y: -1.0),
+        (x: 1.0, y: 1.0),
+        (x: -1.0, y: 1.0),
+        (x: -1.0, y: -1.0)
+    ];
+    let ret = db
+        .in_shape_for_namespace(&wtxn, "tenant-a", &shape)
+        .unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1]>");
+    // The items of the other namespaces are never double-checked
+    assert_eq!(db.query_stats().unwrap().double_checked, 2);
+    let ret = db
+        .in_shape_for_namespace(&wtxn, "tenant-c", &shape)
+        .unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[]>");
+
+    // The deleted items leave their namespaces, and the empty namespaces are removed
+    db.delete(&mut wtxn, 0).unwrap();
+    db.remove_from_namespace(&mut wtxn, "tenant-a", 1).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    insta::assert_debug_snapshot!(db.namespaces(&wtxn).unwrap(), @r#"
+    [
+        "tenant-b",
+    ]
+    "#);
+    let ret = db
+        .in_shape_for_namespace(&wtxn, "tenant-b", &shape)
+        .unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[1, 2]>");
+}
+
+#[test]
+#[cfg(feature = "proj")]
+fn reproject_the_items_on_insertion() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    // Montpellier in web mercator
+    let point = Geometry::Point(point! { x: 431_552.0, y: 5_405_409.0 });
+    db.add_with_crs(&mut wtxn, 0, point, "EPSG:3857").unwrap();
+    let Geometry::Point(stored) = db.item(&wtxn, 0).unwrap().unwrap().to_geo() else {
+        panic!("the item should be a point");
+    };
+    let round = |value: f64| (value * 100.0).round() / 100.0;
+    insta::assert_debug_snapshot!((round(stored.x()), round(stored.y())), @"(3.88, 43.61)");
+
+    let point = Geometry::Point(point! { x: 0.0, y: 0.0 });
+    let err = db.add_with_crs(&mut wtxn, 1, point, "EPSG:0").unwrap_err();
+    assert!(matches!(err, Error::CannotReproject(1, _, _)), "{err}");
+}
+
+#[test]
+fn retrieve_the_belly_items_of_a_cell() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    db.add_diagonal_points(&mut wtxn, 3);
+    let shape = polygon![
+        (x: -30.0, y: -30.0),
+        (x: 30.0, y: -30.0),
+        (x: 30.0, y: 30.0),
+        (x: -30.0, y: 30.0),
+        (x: -30.0, y: -30.0)
+    ];
+    db.add_geometry(&mut wtxn, 3, Geometry::Polygon(shape))
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let (cell, bitmap) = db
+        .inner_belly_cells(&wtxn)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(db.belly_items_of_cell(&wtxn, cell).unwrap(), bitmap);
+    assert!(db.is_item_belly_of(&wtxn, cell, 3).unwrap());
+    assert!(!db.is_item_belly_of(&wtxn, cell, 0).unwrap());
+
+    let far_away = LatLng::new(60.0, 120.0).unwrap().to_cell(Resolution::Zero);
+    assert!(db.belly_items_of_cell(&wtxn, far_away).unwrap().is_empty());
+    assert!(!db.is_item_belly_of(&wtxn, far_away, 3).unwrap());
+}
+
+#[test]
+fn export_the_cell_tree() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    // The points are at the same place, their cell is split down to the last resolution
+    for i in 0..3 {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            0.0, 0.0,
+        ])));
+        db.add(&mut wtxn, i, &point).unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let roots = db.cell_tree(&wtxn, None, 0).unwrap();
+    let base_cell = LatLng::new(0.0, 0.0).unwrap().to_cell(Resolution::Zero);
+    assert_eq!(
+        roots,
+        vec![CellTree {
+            cell: base_cell,
+            items: 3,
+            belly_items: 0,
+            children: Vec::new(),
+        }]
+    );
+
+    let mut tree = db.cell_tree(&wtxn, Some(base_cell), 15).unwrap();
+    let mut depth = 0;
+    while let Some(mut cell) = tree.pop() {
+        assert!(tree.is_empty());
+        assert_eq!(
+            (cell.cell.resolution(), cell.items),
+            (Resolution::try_from(depth).unwrap(), 3)
+        );
+        depth += 1;
+        tree = std::mem::take(&mut cell.children);
+    }
+    assert_eq!(depth, 16);
+
+    let root = LatLng::new(60.0, 120.0).unwrap().to_cell(Resolution::Zero);
+    assert!(db.cell_tree(&wtxn, Some(root), 15).unwrap().is_empty());
+
+    // The leaves next to the split cells don't get the sub-cells of their neighbours
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.001, 0.0,
+    ])));
+    db.add(&mut wtxn, 3, &point).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    let mut leaves = 0;
+    let mut tree = db.cell_tree(&wtxn, Some(base_cell), 15).unwrap();
+    while let Some(cell) = tree.pop() {
+        if cell.items < 2 {
+            assert!(cell.children.is_empty(), "{} is a leaf", cell.cell);
+            leaves += 1;
+        }
+        tree.extend(cell.children);
+    }
+    assert!(leaves > 0);
+}
+
+#[test]
+fn backup_with_the_pending_updates() {
+    let db = create_database();
+    let point = |lng: f64| {
+        let point = point!(x: lng, y: 45.0);
+        GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point)))
+    };
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.add(&mut wtxn, 0, &point(6.0)).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    db.add(&mut wtxn, 1, &point(7.0)).unwrap();
+    let checksum = db.checksum(&wtxn).unwrap();
+    wtxn.commit().unwrap();
+
+    // Another thread keeps writing while the backup is made, the backup is one of the states it
+    // committed
+    let dir = tempfile::tempdir().unwrap();
+    let writing = AtomicBool::new(true);
+    let checksums = std::thread::scope(|s| {
+        let writer = s.spawn(|| {
+            let mut checksums = vec![checksum];
+            let mut item = 2;
+            while writing.load(Ordering::Relaxed) {
+                let mut wtxn = db.env.write_txn().unwrap();
+                db.add(&mut wtxn, item, &point(item as f64 / 100.0))
+                    .unwrap();
+                checksums.push(db.checksum(&wtxn).unwrap());
+                wtxn.commit().unwrap();
+                item += 1;
+            }
+            checksums
+        });
+        Cellulite::backup_to(&db.env, "cellulite", dir.path()).unwrap();
+        writing.store(false, Ordering::Relaxed);
+        writer.join().unwrap()
+    });
+
+    let env = unsafe {
+        EnvOpenOptions::new()
+            .max_dbs(Cellulite::nb_dbs())
+            .open(dir.path())
+    }
+    .unwrap();
+    let rtxn = env.read_txn().unwrap();
+    let backup = Cellulite::open_from_env(&env, &rtxn, "cellulite").unwrap();
+    let backup_checksum = backup.checksum(&rtxn).unwrap();
+    let position = checksums.iter().position(|c| *c == backup_checksum);
+    let position = position.expect("the backup must be a state committed by the writer");
+    // Unlike a replica, the backup keeps the updates waiting for a build
+    assert_eq!(backup.update.len(&rtxn).unwrap(), position as u64 + 1);
+}
+
+#[test]
+fn ship_the_updates_to_a_replica() {
+    let primary = create_database();
+    let replica = create_database();
+    let point = |lng: f64| {
+        let point = point!(x: lng, y: 45.0);
+        GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point)))
+    };
+
+    let mut wtxn = primary.env.write_txn().unwrap();
+    primary.add(&mut wtxn, 0, &point(6.0)).unwrap();
+    primary.add(&mut wtxn, 1, &point(7.0)).unwrap();
+    let mut first = Vec::new();
+    assert_eq!(primary.export_updates(&wtxn, &mut first).unwrap(), 2);
+    primary.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    primary.delete(&mut wtxn, 0).unwrap();
+    primary.add(&mut wtxn, 2, &point(8.0)).unwrap();
+    let mut second = Vec::new();
+    assert_eq!(primary.export_updates(&wtxn, &mut second).unwrap(), 2);
+    primary.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+
+    let mut wtxn = replica.env.write_txn().unwrap();
+    for log in [&first, &second] {
+        replica.apply_updates(&mut wtxn, &log[..]).unwrap();
+        replica.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    }
+    wtxn.commit().unwrap();
+
+    let primary_rtxn = primary.env.read_txn().unwrap();
+    let replica_rtxn = replica.env.read_txn().unwrap();
+    assert_eq!(
+        replica.debug_dump(&replica_rtxn).unwrap(),
+        primary.debug_dump(&primary_rtxn).unwrap()
+    );
+
+    let mut wtxn = replica.env.write_txn().unwrap();
+    let err = replica
+        .apply_updates(&mut wtxn, &second[..second.len() - 1])
+        .unwrap_err();
+    insta::assert_snapshot!(err, @"The update log cannot be applied because it's truncated.");
+
+    // The logs written before the updates of the shapes are still understood
+    let mut v1 = first.clone();
+    v1[8] = 1;
+    replica.apply_updates(&mut wtxn, &v1[..]).unwrap();
+
+    // A corrupted length or shape is rejected before touching the database
+    let log = |len: u64, bytes: &[u8]| {
+        let mut log = b"CELLULOG".to_vec();
+        log.push(2);
+        log.extend(1u64.to_be_bytes());
+        log.extend(3u32.to_be_bytes());
+        log.push(0);
+        log.extend(len.to_be_bytes());
+        log.extend(bytes);
+        log
+    };
+    let err = replica
+        .apply_updates(&mut wtxn, &log(u64::MAX, &[0; 8])[..])
+        .unwrap_err();
+    insta::assert_snapshot!(err, @"The update log cannot be applied because it's truncated.");
+    let err = replica
+        .apply_updates(&mut wtxn, &log(3, &[1, 2, 3])[..])
+        .unwrap_err();
+    insta::assert_snapshot!(err, @"The item `3` is not a valid zerometry: its length of 3 bytes isn't a multiple of 8.");
+    assert!(replica.item(&wtxn, 3).unwrap().is_none());
+}
+
+#[test]
+fn nearest_items_to_an_item() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    for (i, lng) in [0.0, 0.01, 0.02, 0.05, 1.0].into_iter().enumerate() {
+        let point = point!(x: lng, y: 0.0);
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point)));
+        db.add(&mut wtxn, i as u32, &point).unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let round = |ret: Vec<(u32, f64)>| -> Vec<_> {
+        ret.into_iter()
+            .map(|(item, distance)| (item, distance.round()))
+            .collect()
+    };
+    let ret = db.nearest_to_item(&wtxn, 0, 2, true).unwrap();
+    assert_eq!(round(ret), vec![(1, 1112.0), (2, 2224.0)]);
+    let ret = db.nearest_to_item(&wtxn, 0, 1, false).unwrap();
+    assert_eq!(round(ret), vec![(0, 0.0)]);
+
+    // Asking for more items than there is returns all of them
+    let ret = db.nearest_to_item(&wtxn, 0, 10, true).unwrap();
+    let items: Vec<_> = ret.into_iter().map(|(item, _)| item).collect();
+    assert_eq!(items, vec![1, 2, 3, 4]);
+
+    // The isolated item is stored in a deep cell, its neighbour is found at a coarser resolution
+    let ret = db.nearest_to_item(&wtxn, 4, 1, true).unwrap();
+    assert_eq!(round(ret), vec![(3, 105635.0)]);
+}
+
+#[test]
+fn classify_points_in_polygons() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let square = |x: f64, y: f64, size: f64| {
+        Geometry::Polygon(polygon![
+            (x: x, y: y),
+            (x: x + size, y: y),
+            (x: x + size, y: y + size),
+            (x: x, y: y + size),
+            (x: x, y: y)
+        ])
+    };
+    db.add_geometry(&mut wtxn, 0, square(0.0, 0.0, 1.0))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 1, square(1.0, 0.0, 1.0))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 2, square(-1.0, -1.0, 4.0))
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let points = [
+        point!(x: 0.5, y: 0.5),
+        point!(x: 1.5, y: 0.5),
+        point!(x: 0.6, y: 0.5),
+        point!(x: 2.5, y: 2.5),
+        point!(x: 10.0, y: 10.0),
+    ];
+    let ret = db.classify_points(&wtxn, &points).unwrap();
+    let ret: Vec<Vec<u32>> = ret.iter().map(|bitmap| bitmap.iter().collect()).collect();
+    assert_eq!(
+        ret,
+        vec![vec![0, 2], vec![1, 2], vec![0, 2], vec![2], vec![]]
+    );
+
+    for (point, expected) in points.into_iter().zip(ret) {
+        let items = db.items_containing_point(&wtxn, point).unwrap();
+        assert_eq!(items.iter().collect::<Vec<_>>(), expected);
+    }
+}
+
+#[test]
+fn walk_the_tree_with_a_cursor() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    for i in 0..3 {
+        let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+            0.0, 0.0,
+        ])));
+        db.add(&mut wtxn, i, &point).unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let mut roots = db.root_cursors(&wtxn).unwrap();
+    assert_eq!(roots.len(), 1);
+    let mut cursor = roots.pop().unwrap();
+    let raw = RoaringBitmap::deserialize_from(cursor.raw_items().unwrap()).unwrap();
+    assert_eq!(raw, cursor.items().unwrap());
+
+    while !cursor.is_leaf().unwrap() {
+        let mut children = cursor.children().unwrap();
+        assert_eq!(children.len(), 1);
+        cursor = children.pop().unwrap();
+        insta::assert_debug_snapshot!(cursor.items().unwrap(), @"RoaringBitmap<[0, 1, 2]>");
+    }
+    assert_eq!(cursor.cell().resolution(), Resolution::Fifteen);
+    assert!(cursor.children().unwrap().is_empty());
+
+    let far_away = LatLng::new(60.0, 120.0).unwrap().to_cell(Resolution::Zero);
+    let cursor = db.cell_cursor(&wtxn, far_away).unwrap();
+    assert!(!cursor.exists());
+    assert!(cursor.belly_items().unwrap().is_empty());
+}
+
+#[test]
+fn oversized_leaves_policy() {
+    let build = |options: CelluliteOptions| -> Result<crate::Stats, Error> {
+        let mut db = create_database();
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.database.set_options(&mut wtxn, options).unwrap();
+        // The points cannot be split below the threshold, they're kept in the last cell
+        for i in 0..3 {
+            let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+                0.0, 0.0,
+            ])));
+            db.add(&mut wtxn, i, &point).unwrap();
+        }
+        db.build(&mut wtxn, &|| false, &NoProgress)?;
+        db.stats(&wtxn)
+    };
+
+    let options = CelluliteOptions {
+        threshold: 2,
+        ..CelluliteOptions::default()
+    };
+    assert_eq!(build(options).unwrap().oversized_leaves, 1);
+
+    let options = CelluliteOptions {
+        oversized_leaves: OversizedLeafPolicy::Reject,
+        ..options
+    };
+    let err = build(options).unwrap_err();
+    let Error::OversizedLeaf(cell, 3) = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(cell.resolution(), Resolution::Fifteen);
+}
+
+#[test]
+fn find_the_duplicate_locations() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let square = Geometry::Polygon(polygon![
+        (x: 10.0, y: 10.0),
+        (x: 11.0, y: 10.0),
+        (x: 11.0, y: 11.0),
+        (x: 10.0, y: 11.0),
+        (x: 10.0, y: 10.0)
+    ]);
+    db.add_geometry(&mut wtxn, 0, Geometry::Point(point!(x: 3.0, y: 45.0)))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 1, Geometry::Point(point!(x: 3.0000001, y: 45.0)))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 2, Geometry::Point(point!(x: 3.1, y: 45.0)))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 3, square.clone()).unwrap();
+    db.add_geometry(&mut wtxn, 4, square).unwrap();
+
+    let mut groups: Vec<_> = db
+        .duplicate_locations(&wtxn, Resolution::Twelve)
+        .unwrap()
+        .into_values()
+        .collect();
+    groups.sort_by_key(|items| items.min());
+    insta::assert_debug_snapshot!(groups, @r"
+    [
+        RoaringBitmap<[0, 1]>,
+        RoaringBitmap<[3, 4]>,
+    ]
+    ");
+}
+
+#[test]
+fn items_by_geometry_kind() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let square = polygon![
+        (x: 0.0, y: 0.0),
+        (x: 1.0, y: 0.0),
+        (x: 1.0, y: 1.0),
+        (x: 0.0, y: 1.0),
+        (x: 0.0, y: 0.0)
+    ];
+    db.add_geometry(&mut wtxn, 0, Geometry::Point(point!(x: 0.5, y: 0.5)))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 1, Geometry::Polygon(square.clone()))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 2, Geometry::Point(point!(x: 2.0, y: 2.0)))
+        .unwrap();
+    let collection = GeometryCollection::new_from(vec![
+        Geometry::Point(point!(x: 3.0, y: 3.0)),
+        Geometry::Polygon(square),
+    ]);
+    db.add_geometry(&mut wtxn, 3, Geometry::GeometryCollection(collection))
+        .unwrap();
+
+    let points: Vec<_> = db
+        .items_of_kind(&wtxn, GeometryKind::Point)
+        .unwrap()
+        .map(|ret| ret.unwrap().0)
+        .collect();
+    assert_eq!(points, vec![0, 2]);
+    assert_eq!(
+        db.items_of_kind(&wtxn, GeometryKind::Line).unwrap().count(),
+        0
+    );
+
+    let stats = db.stats(&wtxn).unwrap();
+    insta::assert_debug_snapshot!(stats.items_by_kind, @r"
+    {
+        Point: 2,
+        Polygon: 1,
+        Collection: 1,
+    }
+    ");
+}
+
+#[test]
+fn filter_the_query_by_geometry_kind() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let square = |x: f64, y: f64, size: f64| {
+        polygon![
+            (x: x, y: y),
+            (x: x + size, y: y),
+            (x: x + size, y: y + size),
+            (x: x, y: y + size),
+            (x: x, y: y)
+        ]
+    };
+    db.add_geometry(&mut wtxn, 0, Geometry::Point(point!(x: 0.5, y: 0.5)))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 1, Geometry::Polygon(square(0.2, 0.2, 0.2)))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 2, Geometry::Point(point!(x: 0.7, y: 0.7)))
+        .unwrap();
+    let line = geo::LineString::from(vec![(0.1, 0.1), (0.9, 0.2)]);
+    db.add_geometry(&mut wtxn, 3, Geometry::LineString(line))
+        .unwrap();
+    // Covers the whole query, it's returned from the belly cells
+    db.add_geometry(&mut wtxn, 4, Geometry::Polygon(square(-10.0, -10.0, 20.0)))
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let query = square(0.0, 0.0, 1.0);
+    let all = db.in_shape(&wtxn, &query).unwrap();
+    insta::assert_debug_snapshot!(all, @"RoaringBitmap<[0, 1, 2, 3, 4]>");
+
+    let options = QueryOptions {
+        kinds: Some(GeometryKind::Point.into()),
+        ..QueryOptions::default()
+    };
+    let ret = db.in_shape_with_options(&wtxn, &query, options).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 2]>");
+
+    let options = QueryOptions {
+        kinds: Some(GeometryKinds::from_iter([
+            GeometryKind::Line,
+            GeometryKind::Polygon,
+        ])),
+        ..QueryOptions::default()
+    };
+    let ret = db.in_shape_with_options(&wtxn, &query, options).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[1, 3, 4]>");
+
+    let options = QueryOptions {
+        kinds: Some(GeometryKind::Point.into()),
+        limit: Some(1),
+        ..QueryOptions::default()
+    };
+    let ret = db.in_shape_with_options(&wtxn, &query, options).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+}
+
+#[test]
+fn cancel_a_build_in_multiple_transactions() {
+    let create = |items: u32| {
+        let mut db = create_database();
+        db.database.options.threshold = 2;
+        let mut wtxn = db.env.write_txn().unwrap();
+        for i in 0..items {
+            let point = point!(x: i as f64, y: i as f64);
+            let point = GeoJson::from(geojson::Geometry::new(geojson::Value::from(&point)));
+            db.add(&mut wtxn, i, &point).unwrap();
+        }
+        wtxn.commit().unwrap();
+        db
+    };
+
+    // Count how many times a transaction building the first two items checks the cancellation
+    let calls = AtomicUsize::new(0);
+    let count = || {
+        calls.fetch_add(1, Ordering::Relaxed);
+        false
+    };
+    let db = create(2);
+    db.build_in_multiple_transactions(&db.env, 2, &count, &NoProgress)
+        .unwrap();
+    let first_transaction = calls.load(Ordering::Relaxed);
+
+    // Then cancel the build of the next transaction right away
+    let calls = AtomicUsize::new(0);
+    let cancel = || calls.fetch_add(1, Ordering::Relaxed) >= first_transaction;
+    let db = create(5);
+    let err = db
+        .build_in_multiple_transactions(&db.env, 2, &cancel, &NoProgress)
+        .unwrap_err();
+    let Error::BuildCanceled(Some(canceled)) = err else {
+        panic!("{err:?}");
+    };
+    assert!(
+        matches!(canceled.step, BuildSteps::RetrieveUpdatedItems),
+        "{canceled:?}"
+    );
+    assert_eq!((canceled.items, canceled.committed_updates), (0, 2));
+    insta::assert_snapshot!(err, @"The build was canceled at the step RetrieveUpdatedItems while building 0 items, 2 updates were committed before");
+
+    let rtxn = db.env.read_txn().unwrap();
+    assert_eq!(db.build_checkpoint(&rtxn).unwrap(), Some(2));
+}
+
+#[test]
+fn invalid_coordinates_fail_the_build_with_the_item() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.add_geometry(&mut wtxn, 0, Geometry::Point(point!(x: 6.0, y: 45.0)))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 1, Geometry::Point(point!(x: 6.0, y: f64::NAN)))
+        .unwrap();
+    let ret = db.build(&mut wtxn, &|| false, &NoProgress);
+    assert!(
+        matches!(ret, Err(Error::InvalidItemCoordinate(1, _))),
+        "{ret:?}"
+    );
+}
+
+#[test]
+fn update_the_items_in_place() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let point = |lng: f64| Geometry::Point(point!(x: lng, y: 45.0));
+    let around = |lng: f64| {
+        polygon![
+            (x: lng - 0.01, y: 44.99),
+            (x: lng + 0.01, y: 44.99),
+            (x: lng + 0.01, y: 45.01),
+            (x: lng - 0.01, y: 45.01),
+            (x: lng - 0.01, y: 44.99)
+        ]
+    };
+    for (item, lng) in [6.0, 7.0, 8.0].into_iter().enumerate() {
+        db.add_geometry(&mut wtxn, item as u32, point(lng)).unwrap();
+    }
+    db.set_weight(&mut wtxn, 0, 12.0).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    // A small move stays in the same cells, only the item database changes
+    db.update_geometry(&mut wtxn, 0, point(6.0000001)).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    assert!(db.last_build_changes(&wtxn).unwrap().is_empty());
+    assert_eq!(db.weight(&wtxn, 0).unwrap(), Some(12.0));
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &around(6.0)).unwrap(), @"RoaringBitmap<[0]>");
+
+    // A large move removes the item from its old cells before inserting it again
+    db.update_geometry(&mut wtxn, 0, point(7.001)).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    assert!(!db.last_build_changes(&wtxn).unwrap().is_empty());
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &around(6.0)).unwrap(), @"RoaringBitmap<[]>");
+    let ret = db.in_shape(&wtxn, &around(7.0)).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1]>");
+
+    // An item that was never built is simply inserted
+    db.update_geometry(&mut wtxn, 3, point(9.0)).unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &around(9.0)).unwrap(), @"RoaringBitmap<[3]>");
+}
+
+#[test]
+fn query_a_frozen_snapshot() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<FrozenCellulite>();
+
+    let dir = tempfile::tempdir().unwrap();
+    let env = unsafe {
+        EnvOpenOptions::new()
+            .read_txn_without_tls()
+            .map_size(200 * 1024 * 1024)
+            .max_dbs(Cellulite::nb_dbs())
+            .open(dir.path())
+    }
+    .unwrap();
+    let mut wtxn = env.write_txn().unwrap();
+    let db = Cellulite::create_from_env(&env, &mut wtxn, "cellulite").unwrap();
+    db.add_geometry(&mut wtxn, 0, Geometry::Point(point!(x: 6.0, y: 45.0)))
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+
+    let frozen = db.freeze(env.clone()).unwrap();
+    // The writes made after the freeze are not visible
+    let mut wtxn = env.write_txn().unwrap();
+    db.add_geometry(&mut wtxn, 1, Geometry::Point(point!(x: 6.5, y: 45.0)))
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+
+    let area = polygon![
+        (x: 5.0, y: 44.0),
+        (x: 7.0, y: 44.0),
+        (x: 7.0, y: 46.0),
+        (x: 5.0, y: 46.0),
+        (x: 5.0, y: 44.0)
+    ];
+    let ret = std::thread::scope(|s| s.spawn(|| frozen.in_shape(&area).unwrap()).join().unwrap());
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+    assert_eq!(
+        frozen.item(0).unwrap(),
+        Some(Geometry::Point(point!(x: 6.0, y: 45.0)))
+    );
+    assert_eq!(frozen.item(1).unwrap(), None);
+
+    let frozen = db.freeze(env).unwrap();
+    insta::assert_debug_snapshot!(frozen.in_shape(&area).unwrap(), @"RoaringBitmap<[0, 1]>");
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn write_and_query_asynchronously() {
+    let db = create_database();
+    let async_db = crate::AsyncCellulite::new(db.env.clone(), db.database.clone());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        async_db
+            .add_geometry(0, point!(x: 0.0, y: 0.0).into())
+            .await
+            .unwrap();
+        async_db
+            .add_geometry(1, point!(x: 10.0, y: 10.0).into())
+            .await
+            .unwrap();
+        async_db.build(|| false, NoProgress).await.unwrap();
+
+        let square = polygon![(x: -1., y: -1.), (x: 1., y: -1.), (x: 1., y: 1.), (x: -1., y: 1.)];
+        let ret = async_db.in_shape(square.clone()).await.unwrap();
+        insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+
+        async_db.delete(0).await.unwrap();
+        async_db.build(|| false, NoProgress).await.unwrap();
+        let ret = async_db.in_shape(square).await.unwrap();
+        insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[]>");
+        assert_eq!(
+            async_db.item(1).await.unwrap(),
+            Some(point!(x: 10.0, y: 10.0).into())
+        );
+
+        // The errors of the writes are returned to the caller
+        let ret = async_db
+            .write(|_, _| Err::<(), _>(Error::ItemDoesntExists(42)))
+            .await;
+        insta::assert_snapshot!(ret.unwrap_err(), @"The item `42` doesn't exists in the database.");
+    });
+}
+
+#[test]
+fn coalesce_the_writes_of_many_threads() {
+    let db = create_database();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let queue = {
+        let reports = reports.clone();
+        // The interval is long enough for the flush to be the only trigger
+        CelluliteWriterQueue::new(
+            db.env.clone(),
+            db.database.clone(),
+            Duration::from_secs(3600),
+            NoProgress,
+            move |report| reports.lock().unwrap().push(report.unwrap()),
+        )
+    };
+    std::thread::scope(|s| {
+        for thread in 0..4 {
+            let queue = &queue;
+            s.spawn(move || {
+                for i in 0..10 {
+                    let point = point!(x: i as f64, y: thread as f64);
+                    queue.add_geometry(thread * 10 + i, point.into()).unwrap();
+                }
+            });
+        }
+    });
+    queue.delete(0).unwrap();
+    queue
+        .add_geometry(100, GeometryCollection::default().into())
+        .unwrap();
+    queue.flush().unwrap();
+
+    let mut reports = std::mem::take(&mut *reports.lock().unwrap());
+    assert_eq!(reports.len(), 1);
+    let report = reports.pop().unwrap();
+    assert_eq!((report.added, report.deleted), (40, 1));
+    insta::assert_debug_snapshot!(report.failed, @r"
+    [
+        (
+            100,
+            EmptyGeometry(
+                100,
+            ),
+        ),
+    ]
+    ");
+
+    // The batch was built and committed
+    let rtxn = db.env.read_txn().unwrap();
+    assert_eq!(db.stats(&rtxn).unwrap().total_items, 39);
+    let square =
+        polygon![(x: -0.5, y: -0.5), (x: 1.5, y: -0.5), (x: 1.5, y: 0.5), (x: -0.5, y: 0.5)];
+    let ret = db.in_shape(&rtxn, &square).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[1]>");
+}
+
+#[test]
+fn resolution_report_for_threshold_tuning() {
+    let mut db = create_database();
+    db.database.options.threshold = 2;
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.add_geometry(&mut wtxn, 0, point!(x: 2.35, y: 48.85).into())
+        .unwrap();
+    db.add_geometry(&mut wtxn, 1, point!(x: 2.3501, y: 48.8501).into())
+        .unwrap();
+    db.add_geometry(&mut wtxn, 2, point!(x: 100.0, y: 40.0).into())
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let report = db.resolution_report(&wtxn).unwrap();
+    // The two close points share a cell at every resolution, the third one is alone in its cell
+    insta::assert_debug_snapshot!(report[&Resolution::Zero], @r"
+    ResolutionReport {
+        cells: 2,
+        min_items: 1,
+        median_items: 2,
+        max_items: 2,
+        at_threshold: 1,
+    }
+    ");
+    insta::assert_debug_snapshot!(report[&Resolution::One], @r"
+    ResolutionReport {
+        cells: 1,
+        min_items: 2,
+        median_items: 2,
+        max_items: 2,
+        at_threshold: 1,
+    }
+    ");
+
+    let stats = db.stats(&wtxn).unwrap();
+    let cells: BTreeMap<_, _> = report
+        .iter()
+        .map(|(resolution, report)| (*resolution, report.cells as usize))
+        .collect();
+    assert_eq!(cells, stats.cells_by_resolution);
+}
+
+#[test]
+fn coarse_extent_index() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let rome =
+        polygon![(x: 12.0, y: 41.0), (x: 13.0, y: 41.0), (x: 13.0, y: 42.0), (x: 12.0, y: 42.0)];
+    db.add_geometry(&mut wtxn, 0, point!(x: 2.35, y: 48.85).into())
+        .unwrap();
+    db.add_geometry(&mut wtxn, 1, rome.into()).unwrap();
+    db.add_geometry(&mut wtxn, 2, point!(x: -74.0, y: 40.7).into())
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let europe = geo::Rect::new(coord! { x: -10.0, y: 35.0 }, coord! { x: 30.0, y: 60.0 });
+    let paris = geo::Rect::new(coord! { x: 2.3, y: 48.8 }, coord! { x: 2.4, y: 48.9 });
+    let ret = db.items_in_extent(&wtxn, europe).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1]>");
+    insta::assert_debug_snapshot!(db.items_in_extent(&wtxn, paris).unwrap(), @"RoaringBitmap<[0]>");
+
+    // The deleted and moved items follow their new bounding box
+    db.delete(&mut wtxn, 0).unwrap();
+    db.update_geometry(&mut wtxn, 2, point!(x: 2.35, y: 48.85).into())
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    insta::assert_debug_snapshot!(db.items_in_extent(&wtxn, paris).unwrap(), @"RoaringBitmap<[2]>");
+    let square =
+        polygon![(x: 2.3, y: 48.8), (x: 2.4, y: 48.8), (x: 2.4, y: 48.9), (x: 2.3, y: 48.9)];
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &square).unwrap(), @"RoaringBitmap<[2]>");
+
+    // The databases created by older versions must be reindexed to use the index
+    db.metadata
+        .delete(&mut wtxn, &crate::keys::MetadataKey::ExtentIndex)
+        .unwrap();
+    let err = db.items_in_extent(&wtxn, paris).unwrap_err();
+    insta::assert_snapshot!(err, @"The extent index is missing, the database was created by an older version of cellulite. Reindex the database to build it.");
+    db.reindex(&mut wtxn, &|| false, &NoProgress).unwrap();
+    insta::assert_debug_snapshot!(db.items_in_extent(&wtxn, paris).unwrap(), @"RoaringBitmap<[2]>");
+}
+
+#[test]
+fn open_a_read_only_environment() {
+    let DatabaseHandle {
+        env,
+        database,
+        tempdir,
+    } = create_database();
+    let mut wtxn = env.write_txn().unwrap();
+    database
+        .add_geometry(&mut wtxn, 0, point!(x: 6.0, y: 45.0).into())
+        .unwrap();
+    database.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+    env.prepare_for_closing().wait();
+
+    let env = unsafe {
+        EnvOpenOptions::new()
+            .flags(heed::EnvFlags::READ_ONLY)
+            .map_size(200 * 1024 * 1024)
+            .max_dbs(Cellulite::nb_dbs())
+            .open(tempdir.path())
+    }
+    .unwrap();
+    let rtxn = env.read_txn().unwrap();
+    let reader = Cellulite::open_readonly(&env, &rtxn, "cellulite").unwrap();
+    let area = polygon![(x: 5.0, y: 44.0), (x: 7.0, y: 44.0), (x: 7.0, y: 46.0), (x: 5.0, y: 46.0)];
+    insta::assert_debug_snapshot!(reader.in_shape(&rtxn, &area).unwrap(), @"RoaringBitmap<[0]>");
+    assert_eq!(reader.stats(&rtxn).unwrap().total_items, 1);
+
+    // Nothing is created in a read-only environment
+    let err = Cellulite::open_readonly(&env, &rtxn, "roads")
+        .err()
+        .unwrap();
+    insta::assert_snapshot!(err, @"Tried to open a cellulite database, but it's inner database don't exists yet. Call `create_from_env` first.");
+}
+
+#[test]
+fn options_changed_by_another_handle() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let options = CelluliteOptions {
+        threshold: 2,
+        ..CelluliteOptions::default()
+    };
+    db.database.set_options(&mut wtxn, options).unwrap();
+    for i in 0..3 {
+        db.add_geometry(&mut wtxn, i, point!(x: 6.0 + i as f64, y: 45.0).into())
+            .unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    // Another process raises the threshold after this handle was opened
+    let mut other = Cellulite::open_from_env(&db.env, &wtxn, "cellulite").unwrap();
+    other
+        .set_options(
+            &mut wtxn,
+            CelluliteOptions {
+                threshold: 5,
+                ..options
+            },
+        )
+        .unwrap();
+    assert_eq!(db.options().threshold, 2);
+    assert_eq!(db.stored_options(&wtxn).unwrap().threshold, 5);
+
+    // The changes are validated against the stored options, not the stale ones of the handle
+    let err = db.database.set_options(
+        &mut wtxn,
+        CelluliteOptions {
+            threshold: 3,
+            ..options
+        },
+    );
+    insta::assert_snapshot!(err.unwrap_err(), @"Cannot decrease the threshold of the database from 5 to 3. Clear the database and build it again instead.");
+
+    // The stale handle must not build or clear the database with its own options
+    let err = db.build(&mut wtxn, &|| false, &NoProgress).unwrap_err();
+    insta::assert_snapshot!(err, @"The options of this handle are outdated, another handle changed the ones stored in the database. Call `Cellulite::reload_options` before building or clearing it.");
+    let err = db.clear(&mut wtxn).unwrap_err();
+    insta::assert_snapshot!(err, @"The options of this handle are outdated, another handle changed the ones stored in the database. Call `Cellulite::reload_options` before building or clearing it.");
+
+    db.database.reload_options(&wtxn).unwrap();
+    assert_eq!(db.options().threshold, 5);
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+}
+
+#[test]
+fn index_raw_h3_cells() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let grenoble = LatLng::new(45.19, 5.72).unwrap().to_cell(Resolution::Six);
+    let lyon = LatLng::new(45.76, 4.84).unwrap().to_cell(Resolution::Four);
+    db.add_cells(&mut wtxn, 0, &[grenoble, lyon, grenoble])
+        .unwrap();
+    // The cells are written directly in the tree, the item doesn't need to be built
+    assert_eq!(db.update_db_stats(&wtxn).unwrap().entries, 0);
+    let center = LatLng::from(lyon);
+    let center = point!(x: center.lng(), y: center.lat());
+    let ret = db.items_containing_point(&wtxn, center).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+
+    db.add_geometry(&mut wtxn, 1, point!(x: 5.72, y: 45.19).into())
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let ret = db.items_containing_point(&wtxn, center).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+
+    // The child is covered by its parent, only the polygon of the parent is stored
+    db.add_cells(
+        &mut wtxn,
+        3,
+        &[lyon.center_child(Resolution::Five).unwrap(), lyon],
+    )
+    .unwrap();
+    assert_eq!(db.update_db_stats(&wtxn).unwrap().entries, 0);
+    let Geometry::MultiPolygon(shape) = db.item(&wtxn, 3).unwrap().unwrap().to_geo() else {
+        panic!("the cells must be stored as a multi-polygon");
+    };
+    assert_eq!(shape.0.len(), 1);
+    let ret = db.items_containing_point(&wtxn, center).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 3]>");
+    let around_grenoble =
+        polygon![(x: 5.7, y: 45.17), (x: 5.74, y: 45.17), (x: 5.74, y: 45.21), (x: 5.7, y: 45.21)];
+    let ret = db.in_shape(&wtxn, &around_grenoble).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1]>");
+    let far_away = polygon![(x: 0.0, y: 0.0), (x: 1.0, y: 0.0), (x: 1.0, y: 1.0), (x: 0.0, y: 1.0)];
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &far_away).unwrap(), @"RoaringBitmap<[]>");
+
+    let err = db.add_cells(&mut wtxn, 2, &[]).unwrap_err();
+    insta::assert_snapshot!(err, @"The item `2` has an empty geometry, it must contain at least one coordinate.");
+
+    drop(wtxn);
+
+    // A cell reaching the threshold must be split by the build
+    let mut db = create_database();
+    db.database.options.threshold = 1;
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.add_cells(&mut wtxn, 0, &[lyon]).unwrap();
+    assert_eq!(db.update_db_stats(&wtxn).unwrap().entries, 1);
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    let ret = db.items_containing_point(&wtxn, center).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+}
+
+#[test]
+fn export_the_coverage_of_a_shape() {
+    let db = create_database();
+    let rtxn = db.env.read_txn().unwrap();
+    let square = polygon![
+        (x: 0.0, y: 40.0),
+        (x: 5.0, y: 40.0),
+        (x: 5.0, y: 45.0),
+        (x: 0.0, y: 45.0),
+    ];
+    let coverage = db
+        .coverage_of_shape(&rtxn, &square, Resolution::Three)
+        .unwrap();
+    assert!(coverage.is_sorted());
+    // The siblings fully inside the square are merged in their parent
+    assert!(
+        coverage
+            .iter()
+            .any(|cell| cell.resolution() == Resolution::Two)
+    );
+    assert!(
+        coverage
+            .iter()
+            .all(|cell| cell.resolution() <= Resolution::Three)
+    );
+
+    // The cells don't overlap and cover the whole square
+    let uncompacted: Vec<_> = coverage
+        .iter()
+        .flat_map(|cell| cell.children(Resolution::Three))
+        .collect();
+    let distinct: std::collections::BTreeSet<_> = uncompacted.iter().collect();
+    assert_eq!(uncompacted.len(), distinct.len());
+    for (lat, lng) in [(40.1, 0.1), (42.5, 2.5), (44.9, 4.9)] {
+        let cell = LatLng::new(lat, lng).unwrap().to_cell(Resolution::Three);
+        assert!(distinct.contains(&cell), "{cell} is missing");
+    }
+
+    // The resolution is capped to the maximum resolution of the database
+    let tiny = polygon![
+        (x: 2.0, y: 42.0),
+        (x: 2.0001, y: 42.0),
+        (x: 2.0001, y: 42.0001),
+        (x: 2.0, y: 42.0001),
+    ];
+    let coverage = db
+        .coverage_of_shape(&rtxn, &tiny, Resolution::Fifteen)
+        .unwrap();
+    assert!(!coverage.is_empty());
+    let max_resolution = db.max_resolution(&rtxn).unwrap();
+    assert!(
+        coverage
+            .iter()
+            .all(|cell| cell.resolution() <= max_resolution)
+    );
+}
+
+#[test]
+fn compact_and_uncompact_following_the_tree() {
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    // Around the center of a base cell so everything ends up in the same root
+    let base = LatLng::new(0.0, 0.0).unwrap().to_cell(Resolution::Zero);
+    let center = LatLng::from(base);
+    for (i, (x, y)) in [(0.0, 0.0), (1.0, 1.0), (-1.0, 1.0), (1.0, -1.0)]
+        .into_iter()
+        .enumerate()
+    {
+        let point = point! { x: center.lng() + x, y: center.lat() + y };
+        db.add_geometry(&mut wtxn, i as u32, Geometry::Point(point))
+            .unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let mut roots = db.root_cursors(&wtxn).unwrap();
+    assert_eq!(roots.len(), 1);
+    let root = roots.pop().unwrap();
+    let mut children: Vec<_> = root.children().unwrap().iter().map(|c| c.cell()).collect();
+    children.sort_unstable();
+    assert!(!children.is_empty());
+
+    // Every stored sub-cell is there, they're replaced by their parent
+    let compacted = db.compact_cells(&wtxn, children.iter().copied()).unwrap();
+    assert_eq!(compacted, vec![root.cell()]);
+    let uncompacted = db
+        .uncompact_cells(&wtxn, [root.cell()], Resolution::One)
+        .unwrap();
+    assert_eq!(uncompacted, children);
+
+    // A missing sub-cell prevents the merge
+    let compacted = db
+        .compact_cells(&wtxn, children[1..].iter().copied())
+        .unwrap();
+    assert_eq!(compacted, children[1..]);
+
+    // The leaves shallower than the resolution are kept as-is
+    let uncompacted = db
+        .uncompact_cells(&wtxn, [root.cell()], Resolution::Fifteen)
+        .unwrap();
+    for cell in uncompacted {
+        let cursor = db.cell_cursor(&wtxn, cell).unwrap();
+        assert!(cursor.exists());
+        assert!(cursor.is_leaf().unwrap() || cell.resolution() == Resolution::Fifteen);
+    }
+}
+
+#[test]
+fn build_in_the_background() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    for i in 0..10 {
+        let point = Geometry::Point(point! { x: i as f64, y: i as f64 });
+        db.add_geometry(&mut wtxn, i, point).unwrap();
+    }
+    wtxn.commit().unwrap();
+
+    // The build waits for our write transaction and is canceled before it starts
+    let wtxn = db.env.write_txn().unwrap();
+    let handle = db.database.clone().spawn_build(db.env.clone(), NoProgress);
+    handle.cancel();
+    wtxn.abort();
+    let ret = handle.join();
+    assert!(matches!(ret, Err(Error::BuildCanceled(_))), "{ret:?}");
+    let square = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 20.0, y: -1.0),
+        (x: 20.0, y: 20.0),
+        (x: -1.0, y: 20.0),
+    ];
+    let rtxn = db.env.read_txn().unwrap();
+    insta::assert_debug_snapshot!(db.in_shape(&rtxn, &square).unwrap(), @"RoaringBitmap<[]>");
+    drop(rtxn);
+
+    let handle = db.database.clone().spawn_build(db.env.clone(), NoProgress);
+    handle.join().unwrap();
+    let rtxn = db.env.read_txn().unwrap();
+    insta::assert_debug_snapshot!(db.in_shape(&rtxn, &square).unwrap(), @"RoaringBitmap<[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]>");
+}
+
+#[test]
+fn cap_the_working_set_of_a_query() {
+    let mut db = create_database();
+    db.database.options.threshold = 2;
+    let mut wtxn = db.env.write_txn().unwrap();
+    for i in 0..100 {
+        let point = point! { x: (i % 10) as f64 * 10.0, y: (i / 10) as f64 * 5.0 };
+        db.add_geometry(&mut wtxn, i, Geometry::Point(point))
+            .unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let query = polygon![
+        (x: -5.0, y: -5.0),
+        (x: 95.0, y: -5.0),
+        (x: 95.0, y: 50.0),
+        (x: -5.0, y: 50.0),
+    ];
+    let all = db.in_shape(&wtxn, &query).unwrap();
+    assert_eq!(all.len(), 100);
+
+    let limit = WorkingSetLimit {
+        max_queued_cells: 5,
+        max_double_check: 5,
+        on_exceeded: OnLimitExceeded::Fail,
+    };
+    let options = QueryOptions {
+        working_set_limit: Some(limit),
+        ..QueryOptions::default()
+    };
+    let ret = db.in_shape_with_options(&wtxn, &query, options);
+    let Err(Error::QueryTooLarge(working_set)) = ret else {
+        panic!("expected the query to be too large, got {ret:?}");
+    };
+    assert!(
+        working_set.queued_cells > 5 || working_set.double_check > 5,
+        "{working_set}"
+    );
+
+    // Only matching items are returned, but some are missing
+    let limit = WorkingSetLimit {
+        on_exceeded: OnLimitExceeded::Approximate,
+        ..limit
+    };
+    let options = QueryOptions {
+        working_set_limit: Some(limit),
+        ..QueryOptions::default()
+    };
+    let ret = db.in_shape_with_options(&wtxn, &query, options).unwrap();
+    assert!(ret.is_subset(&all));
+    assert!(ret.len() < all.len());
+
+    let limit = WorkingSetLimit {
+        max_queued_cells: usize::MAX,
+        max_double_check: u64::MAX,
+        ..limit
+    };
+    let options = QueryOptions {
+        working_set_limit: Some(limit),
+        ..QueryOptions::default()
+    };
+    assert_eq!(
+        db.in_shape_with_options(&wtxn, &query, options).unwrap(),
+        all
+    );
+}
+
+#[test]
+fn large_zones_are_never_double_checked() {
+    let mut db = create_database();
+    db.database.options.threshold = 2;
+    let mut wtxn = db.env.write_txn().unwrap();
+    let zone = polygon![
+        (x: -20.0, y: -10.0),
+        (x: 40.0, y: -10.0),
+        (x: 40.0, y: 50.0),
+        (x: -20.0, y: 50.0),
+    ];
+    db.add_geometry(&mut wtxn, 0, Geometry::Polygon(zone))
+        .unwrap();
+    for i in 1..20 {
+        let point = point! { x: i as f64, y: 20.0 - i as f64 / 2.0 };
+        db.add_geometry(&mut wtxn, i, Geometry::Point(point))
+            .unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let query = polygon![
+        (x: 0.0, y: 10.0),
+        (x: 20.0, y: 10.0),
+        (x: 20.0, y: 30.0),
+        (x: 0.0, y: 30.0),
+    ];
+    let provenance = db.in_shape_with_provenance(&wtxn, &query).unwrap();
+    assert_eq!(provenance.len(), 20);
+    // The zone covers whole cells of the query, it's returned from their belly cells
+    assert_eq!(provenance[&0].source, MatchSource::BellyCell);
+    // The cells entirely inside the query return their points without checking them
+    assert!(
+        provenance
+            .values()
+            .any(|provenance| provenance.source == MatchSource::ContainedCell)
+    );
+}
+
+#[test]
+fn recover_from_an_interrupted_build() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    for i in 0..5 {
+        let point = Geometry::Point(point! { x: i as f64, y: i as f64 });
+        db.add_geometry(&mut wtxn, i, point).unwrap();
+    }
+    assert!(!db.needs_recovery(&wtxn).unwrap());
+    // Like a build in multiple transactions that crashed after its first transaction
+    db.set_build_checkpoint(&mut wtxn, 2).unwrap();
+    wtxn.commit().unwrap();
+
+    let mut wtxn = db.env.write_txn().unwrap();
+    assert!(db.needs_recovery(&wtxn).unwrap());
+    let report = db.health_report(&wtxn).unwrap();
+    insta::assert_debug_snapshot!(report.warnings, @r#"
+    [
+        "A build in multiple transactions was interrupted. Call `Cellulite::recover` to finish it.",
+        "5 updates are waiting for a build, they're not visible to the queries.",
+    ]
+    "#);
+    let err = db.build(&mut wtxn, &|| false, &NoProgress).unwrap_err();
+    insta::assert_snapshot!(err, @"A build in multiple transactions was interrupted after building 2 updates. Call `Cellulite::recover` to finish it before building again.");
+
+    db.recover(&mut wtxn, &|| false, &NoProgress).unwrap();
+    assert!(!db.needs_recovery(&wtxn).unwrap());
+    assert_eq!(db.build_checkpoint(&wtxn).unwrap(), None);
+    let square = polygon![
+        (x: -1.0, y: -1.0),
+        (x: 5.0, y: -1.0),
+        (x: 5.0, y: 5.0),
+        (x: -1.0, y: 5.0),
+    ];
+    insta::assert_debug_snapshot!(db.in_shape(&wtxn, &square).unwrap(), @"RoaringBitmap<[0, 1, 2, 3, 4]>");
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+}
+
+#[test]
+fn a_single_writer_and_many_readers() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let writer = Cellulite::open_writer(&db.env, &mut wtxn, "cellulite").unwrap();
+    let err = Cellulite::open_writer(&db.env, &mut wtxn, "cellulite")
+        .err()
+        .unwrap();
+    insta::assert_snapshot!(err, @"Another writer already opened the cellulite database `cellulite`, in this process or another one. Only one writer can open it at a time.");
+
+    writer
+        .add_geometry(&mut wtxn, 0, point!(x: 6.0, y: 45.0).into())
+        .unwrap();
+    writer.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+
+    let rtxn = db.env.read_txn().unwrap();
+    let reader = Cellulite::open_readonly(&db.env, &rtxn, "cellulite").unwrap();
+    assert_eq!(reader.stats(&rtxn).unwrap().total_items, 1);
+    drop(rtxn);
+
+    // The lock is released with the writer
+    drop(writer);
+    let mut wtxn = db.env.write_txn().unwrap();
+    Cellulite::open_writer(&db.env, &mut wtxn, "cellulite").unwrap();
+}
+
+#[cfg(feature = "checked-zerometry")]
+#[test]
+fn reject_misaligned_zerometry() {
+    use heed::BytesDecode;
+
+    use crate::zerometry::{InvalidZerometryBytes, ZerometryCodec};
+
+    let buffer = [0_u64; 4];
+    let bytes: &[u8] = as_bytes(&buffer);
+    let error = ZerometryCodec::bytes_decode(&bytes[1..17]).unwrap_err();
+    insta::assert_snapshot!(error, @"The shape cannot be read, it's 16 bytes long and starts 1 bytes after a 64 bits boundary");
+    assert!(error.is::<InvalidZerometryBytes>());
+    let error = ZerometryCodec::bytes_decode(&bytes[..12]).unwrap_err();
+    insta::assert_snapshot!(error, @"The shape cannot be read, it's 12 bytes long and starts 0 bytes after a 64 bits boundary");
+
+    fn as_bytes(buffer: &[u64; 4]) -> &[u8] {
+        // Safe because a u64 is made of 8 initialized bytes
+        unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast(), size_of_val(buffer)) }
+    }
+}
+
+#[test]
+fn add_checked_raw_zerometry() {
+    let cellulite = create_database();
+    let mut wtxn = cellulite.env.write_txn().unwrap();
+
+    let mut bytes = Vec::new();
+    ::zerometry::Zerometry::write_from_geometry(
+        &mut bytes,
+        &Geometry::Point(point! { x: 2.35, y: 48.85 }),
+    )
+    .unwrap();
+    // The buffer of the caller doesn't have to be aligned
+    let mut unaligned = vec![0];
+    unaligned.extend_from_slice(&bytes);
+    cellulite
+        .add_raw_zerometry_checked(&mut wtxn, 0, &unaligned[1..])
+        .unwrap();
+
+    let error = cellulite
+        .add_raw_zerometry_checked(&mut wtxn, 1, &[0; 7])
+        .unwrap_err();
+    insta::assert_snapshot!(error, @"The item `1` is not a valid zerometry: its length of 7 bytes isn't a multiple of 8.");
+
+    let mut bytes = Vec::new();
+    ::zerometry::Zerometry::write_from_geometry(
+        &mut bytes,
+        &Geometry::Point(point! { x: 200.0, y: 48.85 }),
+    )
+    .unwrap();
+    let error = cellulite
+        .add_raw_zerometry_checked(&mut wtxn, 2, &bytes)
+        .unwrap_err();
+    insta::assert_snapshot!(error, @"The item `2` is not a valid zerometry: the coordinate (200, 48.85) is outside of the globe.");
+
+    // The bytes of the bounding box differ between the large and the small squares, but not when
+    // a single top corner moves. The lying square keeps the bounding box of the large one.
+    let encode = |top_left: f64, top_right: f64| {
+        crate::zerometry::encode(&Geometry::Polygon(polygon![
+            (x: 0.0, y: 0.0),
+            (x: 10.0, y: 0.0),
+            (x: 10.0, y: top_right),
+            (x: 0.0, y: top_left),
+        ]))
+        .unwrap()
+    };
+    let (large, small) = (encode(10.0, 10.0), encode(5.0, 5.0));
+    let (left, right) = (encode(5.0, 10.0), encode(10.0, 5.0));
+    let lying: Vec<u8> = (0..large.len())
+        .map(|i| {
+            if left[i] == large[i] && right[i] == large[i] {
+                large[i]
+            } else {
+                small[i]
+            }
+        })
+        .collect();
+    let error = cellulite
+        .add_raw_zerometry_checked(&mut wtxn, 3, &lying)
+        .unwrap_err();
+    insta::assert_snapshot!(error, @"The item `3` is not a valid zerometry: its bounding boxes don't match its coordinates.");
+
+    cellulite.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    let items = cellulite
+        .in_shape(
+            &wtxn,
+            &polygon![(x: 2.0, y: 48.0), (x: 3.0, y: 48.0), (x: 3.0, y: 49.0), (x: 2.0, y: 49.0)],
+        )
+        .unwrap();
+    insta::assert_debug_snapshot!(items, @"RoaringBitmap<[0]>");
+}
+
+#[test]
+fn precompute_the_zerometry_of_an_item() {
+    let cellulite = create_database();
+    let mut wtxn = cellulite.env.write_txn().unwrap();
+
+    // Crosses the antimeridian, the encoding of the database splits it like `add_geometry`
+    let shape = Geometry::Polygon(
+        polygon![(x: 179.0, y: 10.0), (x: -179.0, y: 10.0), (x: -179.0, y: 11.0), (x: 179.0, y: 11.0)],
+    );
+    let bytes = cellulite.encode_geometry(0, shape.clone()).unwrap();
+    cellulite.add_raw_zerometry(&mut wtxn, 0, &bytes).unwrap();
+    cellulite.add_geometry(&mut wtxn, 1, shape.clone()).unwrap();
+    assert_eq!(
+        cellulite.item(&wtxn, 0).unwrap().unwrap().to_geo(),
+        cellulite.item(&wtxn, 1).unwrap().unwrap().to_geo(),
+    );
+
+    let bytes = crate::zerometry::encode(&shape).unwrap();
+    let mut unaligned = vec![0];
+    unaligned.extend_from_slice(&bytes);
+    assert_eq!(crate::zerometry::decode(&unaligned[1..]).unwrap(), shape);
+    let error = crate::zerometry::decode(&bytes[..3]).unwrap_err();
+    insta::assert_snapshot!(error, @"error while decoding: The shape cannot be read, it's 3 bytes long and starts 0 bytes after a 64 bits boundary");
+}
+
+#[test]
+fn annotate_points_with_their_zones() {
+    let dir = tempfile::tempdir().unwrap();
+    let env = unsafe {
+        EnvOpenOptions::new()
+            .map_size(200 * 1024 * 1024)
+            .max_dbs(Cellulite::nb_dbs() * 2)
+            .open(dir.path())
+    }
+    .unwrap();
+    let mut wtxn = env.write_txn().unwrap();
+    let pois = Cellulite::create_from_env(&env, &mut wtxn, "pois").unwrap();
+    let zones = Cellulite::create_from_env(&env, &mut wtxn, "zones").unwrap();
+
+    let france =
+        polygon![(x: -5.0, y: 42.0), (x: 8.0, y: 42.0), (x: 8.0, y: 51.0), (x: -5.0, y: 51.0)];
+    let paris =
+        polygon![(x: 2.2, y: 48.8), (x: 2.5, y: 48.8), (x: 2.5, y: 48.9), (x: 2.2, y: 48.9)];
+    zones
+        .add_geometry(&mut wtxn, 0, Geometry::Polygon(france))
+        .unwrap();
+    zones
+        .add_geometry(&mut wtxn, 1, Geometry::Polygon(paris))
+        .unwrap();
+    zones.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    // The Eiffel tower, Marseille, New York and a line that is ignored
+    pois.add_geometry(
+        &mut wtxn,
+        0,
+        Geometry::Point(point! { x: 2.2945, y: 48.8584 }),
+    )
+    .unwrap();
+    pois.add_geometry(
+        &mut wtxn,
+        1,
+        Geometry::Point(point! { x: 5.3698, y: 43.2965 }),
+    )
+    .unwrap();
+    pois.add_geometry(
+        &mut wtxn,
+        2,
+        Geometry::Point(point! { x: -74.006, y: 40.7128 }),
+    )
+    .unwrap();
+    pois.add_geometry(
+        &mut wtxn,
+        3,
+        Geometry::LineString(geo::line_string![(x: 2.3, y: 48.85), (x: 2.4, y: 48.86)]),
+    )
+    .unwrap();
+    pois.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let annotated = pois
+        .annotate_with_zones(&wtxn, &RoaringBitmap::from_iter(0..4), &zones)
+        .unwrap();
+    insta::assert_debug_snapshot!(annotated, @r"
+    {
+        0: RoaringBitmap<[0, 1]>,
+        1: RoaringBitmap<[0]>,
+        2: RoaringBitmap<[]>,
+    }
+    ");
+}
+
+#[test]
+fn count_the_double_checked_items_across_queries() {
+    let mut db = create_database();
+    assert_eq!(db.query_stats(), None);
+    db.database.enable_query_stats();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.add_geometry(&mut wtxn, 0, Geometry::Point(point! { x: 2.35, y: 48.85 }))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 1, Geometry::Point(point! { x: 2.36, y: 48.86 }))
+        .unwrap();
+    db.add_geometry(&mut wtxn, 2, Geometry::Point(point! { x: 2.37, y: 48.87 }))
+        .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let around_the_first = polygon![(x: 2.345, y: 48.845), (x: 2.355, y: 48.845), (x: 2.355, y: 48.855), (x: 2.345, y: 48.855)];
+    let ret = db.in_shape(&wtxn, &around_the_first).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0]>");
+    // The clones share the counters
+    let clone = db.database.clone();
+    clone.in_shape(&wtxn, &around_the_first).unwrap();
+    let stats = db.query_stats().unwrap();
+    insta::assert_debug_snapshot!(stats, @r"
+    QueryStats {
+        queries: 2,
+        double_checked: 6,
+        confirmed: 2,
+    }
+    ");
+    insta::assert_snapshot!(stats.confirmed_ratio(), @"0.3333333333333333");
+
+    db.reset_query_stats();
+    assert_eq!(clone.query_stats(), Some(QueryStats::default()));
+}
+
+#[test]
+fn generate_reproducible_synthetic_datasets() {
+    use crate::test_utils::{SyntheticDataset, synthetic_dataset};
+
+    let datasets = [
+        SyntheticDataset::UniformPoints,
+        SyntheticDataset::ClusteredPoints { clusters: 3 },
+        SyntheticDataset::Polygons { vertices: 5 },
+        SyntheticDataset::Edges,
+    ];
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let mut item = 0;
+    for dataset in datasets {
+        let items: Vec<_> = synthetic_dataset(dataset, 20, 42).collect();
+        assert_eq!(
+            items,
+            synthetic_dataset(dataset, 20, 42).collect::<Vec<_>>()
+        );
+        assert_ne!(
+            items,
+            synthetic_dataset(dataset, 20, 43).collect::<Vec<_>>()
+        );
+        for geometry in items {
+            if let (SyntheticDataset::Polygons { vertices }, Geometry::Polygon(polygon)) =
+                (dataset, &geometry)
+            {
+                // The ring is closed by repeating the first vertex
+                assert_eq!(polygon.exterior().0.len(), vertices + 1);
+            }
+            db.add_geometry(&mut wtxn, item, geometry).unwrap();
+            item += 1;
+        }
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    assert_eq!(db.items(&wtxn).unwrap().count(), 80);
+}
+
+#[test]
+fn spread_the_build_over_a_cell_budget() {
+    use crate::{
+        BuildStatus,
+        test_utils::{SyntheticDataset, in_shape_brute_force, synthetic_dataset},
+    };
+
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let options = CelluliteOptions {
+        threshold: 3,
+        ..CelluliteOptions::default()
+    };
+    db.database.set_options(&mut wtxn, options).unwrap();
+    let points = synthetic_dataset(SyntheticDataset::ClusteredPoints { clusters: 4 }, 300, 7);
+    for (item, point) in points.enumerate() {
+        db.add_geometry(&mut wtxn, item as u32, point).unwrap();
+    }
+    wtxn.commit().unwrap();
+
+    let mut passes = 0;
+    loop {
+        let mut wtxn = db.env.write_txn().unwrap();
+        let status = db
+            .build_with_cell_budget(&mut wtxn, 50, &|| false, &NoProgress)
+            .unwrap();
+        wtxn.commit().unwrap();
+        passes += 1;
+        match status {
+            BuildStatus::Finished => break,
+            BuildStatus::Pending { pending_updates } => assert!(pending_updates > 0),
+        }
+    }
+    assert!(passes > 1, "the build was not split: {passes} pass");
+
+    let rtxn = db.env.read_txn().unwrap();
+    assert_eq!(db.update_db_stats(&rtxn).unwrap().entries, 0);
+    let query = polygon![(x: -170.0, y: -80.0), (x: 170.0, y: -80.0), (x: 170.0, y: 80.0), (x: -170.0, y: 80.0)];
+    assert_eq!(
+        db.in_shape(&rtxn, &query).unwrap(),
+        in_shape_brute_force(&db, &rtxn, &query).unwrap()
+    );
+}
+
+#[test]
+fn retrieve_a_cell_and_its_belly_at_once() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let cell = LatLng::new(52.6758, -11.6016)
+        .unwrap()
+        .to_cell(Resolution::Zero);
+    // Covers the whole cell and is stored in its belly
+    let shape =
+        polygon![(x: -40.0, y: 30.0), (x: 20.0, y: 30.0), (x: 20.0, y: 75.0), (x: -40.0, y: 75.0)];
+    db.add_geometry(&mut wtxn, 0, Geometry::Polygon(shape))
+        .unwrap();
+    db.add_geometry(
+        &mut wtxn,
+        1,
+        Geometry::Point(point! { x: -11.6016, y: 52.6758 }),
+    )
+    .unwrap();
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let (items, belly) = db.cell_entry(&wtxn, cell).unwrap();
+    assert_eq!(belly, Some(db.belly_items_of_cell(&wtxn, cell).unwrap()));
+    insta::assert_debug_snapshot!((items, belly), @r"
+    (
+        Some(
+            RoaringBitmap<[1]>,
+        ),
+        Some(
+            RoaringBitmap<[0]>,
+        ),
+    )
+    ");
+    let elsewhere = LatLng::new(-45.0, -120.0)
+        .unwrap()
+        .to_cell(Resolution::Zero);
+    assert_eq!(db.cell_entry(&wtxn, elsewhere).unwrap(), (None, None));
+}
+
+#[test]
+fn query_a_prepared_shape_in_many_transactions() {
+    let mut db = create_database();
+    let zone = polygon![(x: 2.2, y: 48.8), (x: 2.5, y: 48.8), (x: 2.5, y: 48.9), (x: 2.2, y: 48.9)];
+    let prepared = db.prepare_shape(&zone).unwrap();
+    assert_eq!(prepared.polygon(), &zone);
+
+    for (item, x) in [2.3, 2.4, 2.6].into_iter().enumerate() {
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.add_geometry(
+            &mut wtxn,
+            item as u32,
+            Geometry::Point(point! { x: x, y: 48.85 }),
+        )
+        .unwrap();
+        db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = db.env.read_txn().unwrap();
+        assert_eq!(
+            db.in_shape_prepared(&rtxn, &prepared).unwrap(),
+            db.in_shape(&rtxn, &zone).unwrap()
+        );
+    }
+
+    // The shape is prepared again when the distance model changes
+    db.database.distance_model = DistanceModel::Planar;
+    let rtxn = db.env.read_txn().unwrap();
+    let ret = db.in_shape_prepared(&rtxn, &prepared).unwrap();
+    assert_eq!(ret, db.in_shape(&rtxn, &zone).unwrap());
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[0, 1]>");
+}
+
+#[test]
+fn query_multiple_indexes_at_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let env = unsafe {
+        EnvOpenOptions::new()
+            .map_size(200 * 1024 * 1024)
+            .max_dbs(Cellulite::nb_dbs() * 2)
+            .open(dir.path())
+    }
+    .unwrap();
+    let mut wtxn = env.write_txn().unwrap();
+    let shops = Cellulite::create_from_env(&env, &mut wtxn, "shops").unwrap();
+    let mut roads = Cellulite::create_from_env(&env, &mut wtxn, "roads").unwrap();
+    // The shape must be prepared again for the roads
+    let options = CelluliteOptions {
+        densify_distance: 100.0,
+        ..*roads.options()
+    };
+    roads.set_options(&mut wtxn, options).unwrap();
+    shops
+        .add_geometry(&mut wtxn, 0, Geometry::Point(point! { x: 2.3, y: 48.85 }))
+        .unwrap();
+    shops
+        .add_geometry(&mut wtxn, 1, Geometry::Point(point! { x: 5.0, y: 45.0 }))
+        .unwrap();
+    roads
+        .add_geometry(
+            &mut wtxn,
+            0,
+            Geometry::LineString(geo::line_string![(x: 2.0, y: 48.5), (x: 2.4, y: 48.85)]),
+        )
+        .unwrap();
+    shops.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    roads.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+    wtxn.commit().unwrap();
+
+    let rtxn = env.read_txn().unwrap();
+    let reader = crate::MultiIndexReader::open_all(&env, &rtxn).unwrap();
+    insta::assert_debug_snapshot!(reader.prefixes().collect::<Vec<_>>(), @r#"
+    [
+        "roads",
+        "shops",
+    ]
+    "#);
+    let paris =
+        polygon![(x: 2.2, y: 48.8), (x: 2.5, y: 48.8), (x: 2.5, y: 48.9), (x: 2.2, y: 48.9)];
+    insta::assert_debug_snapshot!(reader.in_shape(&rtxn, &paris).unwrap(), @r#"
+    {
+        "roads": RoaringBitmap<[0]>,
+        "shops": RoaringBitmap<[0]>,
+    }
+    "#);
+    assert_eq!(
+        reader
+            .index("roads")
+            .unwrap()
+            .in_shape(&rtxn, &paris)
+            .unwrap(),
+        roads.in_shape(&rtxn, &paris).unwrap()
+    );
+
+    let error = crate::MultiIndexReader::open(&env, &rtxn, &["shops", "parcels"]).unwrap_err();
+    insta::assert_snapshot!(error, @"Tried to open a cellulite database, but it's inner database don't exists yet. Call `create_from_env` first.");
+}
+
+#[test]
+fn reject_the_geojson_without_geometry() {
+    let db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    let feature = GeoJson::Feature(geojson::Feature {
+        bbox: None,
+        geometry: None,
+        id: None,
+        properties: None,
+        foreign_members: None,
+    });
+    let err = db.add(&mut wtxn, 0, &feature).unwrap_err();
+    assert!(matches!(err, Error::InvalidGeoJson(_)), "{err}");
+
+    let point = GeoJson::from(geojson::Geometry::new(geojson::Value::Point(vec![
+        0.0, 0.0,
+    ])));
+    db.add(&mut wtxn, 0, &point).unwrap();
+    let err = db.update(&mut wtxn, 0, &feature).unwrap_err();
+    assert!(matches!(err, Error::InvalidGeoJson(_)), "{err}");
+}
+
+#[test]
+fn cache_the_children_of_the_split_cells() {
+    // A single build reuses the children of the split cells for all the chunks of items, while
+    // building the items one by one computes them again every time
+    let mut batched = create_database();
+    let mut incremental = create_database();
+    batched.database.options.threshold = 2;
+    incremental.database.options.threshold = 2;
+
+    let mut batched_wtxn = batched.env.write_txn().unwrap();
+    batched.add_diagonal_points(&mut batched_wtxn, 50);
+    batched
+        .build(&mut batched_wtxn, &|| false, &NoProgress)
+        .unwrap();
+
+    let mut incremental_wtxn = incremental.env.write_txn().unwrap();
+    for i in 0..50 {
+        let lng = i as f64 * 0.01;
+        incremental
+            .add_geometry(&mut incremental_wtxn, i, point!(x: lng, y: lng).into())
+            .unwrap();
+        incremental
+            .build(&mut incremental_wtxn, &|| false, &NoProgress)
+            .unwrap();
+    }
+
+    assert_eq!(
+        batched.cells(&batched_wtxn),
+        incremental.cells(&incremental_wtxn)
+    );
+}
+
+#[test]
+fn stop_the_writer_queue_after_a_panic_of_the_callback() {
+    let db = create_database();
+    let queue = CelluliteWriterQueue::new(
+        db.env.clone(),
+        db.database.clone(),
+        Duration::from_secs(3600),
+        NoProgress,
+        |_| panic!("the callback panicked"),
+    );
+    queue
+        .add_geometry(0, point!(x: 0.0, y: 0.0).into())
+        .unwrap();
+    let err = queue.flush().unwrap_err();
+    insta::assert_snapshot!(err, @"The writer thread of the queue stopped after a panic of the batch callback, the operations cannot be queued anymore.");
+    let err = queue.delete(0).unwrap_err();
+    insta::assert_snapshot!(err, @"The writer thread of the queue stopped after a panic of the batch callback, the operations cannot be queued anymore.");
+
+    // The panic of the callback is resumed once the queue is dropped
+    let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(queue))).unwrap_err();
+    assert_eq!(panic.downcast_ref::<&str>(), Some(&"the callback panicked"));
+}
+
+#[test]
+fn query_tiny_shapes_near_the_edges_of_the_cells() {
+    // The points cross the edges of many cells of the low resolutions. The sub-cells stick out of
+    // their H3 parent, a point close to an edge can be stored under the neighbour of the H3
+    // ancestor of the cell it's in.
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let point = |i: u32| (i as f64 * 0.05, 45.0 + (i % 7) as f64 * 0.01);
+    for i in 0..200 {
+        let (x, y) = point(i);
+        db.add_geometry(&mut wtxn, i, point!(x: x, y: y).into())
+            .unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    for i in 0..200 {
+        let (x, y) = point(i);
+        let tiny = polygon![
+            (x: x - 0.0001, y: y - 0.0001),
+            (x: x + 0.0001, y: y - 0.0001),
+            (x: x + 0.0001, y: y + 0.0001),
+            (x: x - 0.0001, y: y + 0.0001),
+        ];
+        let ret = db.in_shape(&wtxn, &tiny).unwrap();
+        assert_eq!(ret, RoaringBitmap::from_iter([i]), "{i} at {x} {y}");
+    }
+}
+
+#[test]
+fn retrieve_cells_in_shape_near_the_edges_of_the_cells() {
+    // The points cross the edges of many cells, the ones close to an edge can be stored under the
+    // neighbour of the H3 ancestor of their cell
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    let point = |i: u32| (i as f64 * 0.05, 45.0 + (i % 7) as f64 * 0.01);
+    for i in 0..200 {
+        let (x, y) = point(i);
+        db.add_geometry(&mut wtxn, i, point!(x: x, y: y).into())
+            .unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    let shape =
+        polygon![(x: -0.1, y: 44.9), (x: 10.1, y: 44.9), (x: 10.1, y: 45.2), (x: -0.1, y: 45.2)];
+    for resolution in [Resolution::Four, Resolution::Six] {
+        let cells: BTreeMap<_, _> = db
+            .cells_in_shape(&wtxn, &shape, resolution)
+            .unwrap()
+            .into_iter()
+            .collect();
+        for i in 0..200 {
+            let (x, y) = point(i);
+            let cell = LatLng::new(y, x).unwrap().to_cell(resolution);
+            assert!(
+                cells.get(&cell).is_some_and(|bitmap| bitmap.contains(i)),
+                "{i} is missing from {cell}"
+            );
+        }
+        let total: u64 = cells.values().map(|bitmap| bitmap.len()).sum();
+        assert_eq!(total, 200);
+    }
+}
+
+#[test]
+fn nearest_to_item_near_the_edges_of_the_cells() {
+    // The points cross the edges of many cells, the neighbours close to an edge can be stored
+    // under the neighbour of the H3 ancestor of the cells explored
+    let mut db = create_database();
+    let mut wtxn = db.env.write_txn().unwrap();
+    db.database.options.threshold = 2;
+    for i in 0..100 {
+        db.add_geometry(&mut wtxn, i, point!(x: i as f64 * 0.05, y: 45.0).into())
+            .unwrap();
+    }
+    db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+    for i in 1..99 {
+        let ret = db.nearest_to_item(&wtxn, i, 2, true).unwrap();
+        let mut neighbours: Vec<_> = ret.into_iter().map(|(item, _)| item).collect();
+        neighbours.sort_unstable();
+        assert_eq!(neighbours, [i - 1, i + 1], "{i}");
+    }
+}
+
+proptest::proptest! {
+    #![proptest_config(proptest::prelude::ProptestConfig::with_cases(16))]
+    #[test]
+    fn in_shape_matches_brute_force(
+        items in proptest::collection::vec(crate::test_utils::geojson(), 1..50),
+        query in crate::test_utils::polygon(),
+    ) {
+        let mut db = create_database();
+        // Force the items to be split in multiple resolutions
+        db.database.options.threshold = 3;
+        let mut wtxn = db.env.write_txn().unwrap();
+        for (i, item) in items.iter().enumerate() {
+            db.add(&mut wtxn, i as u32, item).unwrap();
+        }
+        db.build(&mut wtxn, &|| false, &NoProgress).unwrap();
+
+        let expected = crate::test_utils::in_shape_brute_force(&db, &wtxn, &query).unwrap();
+        let ret = db.in_shape(&wtxn, &query).unwrap();
+        proptest::prop_assert_eq!(ret, expected);
+    }
+}
+
 /*
 #[test]
 fn basic_nearest() {
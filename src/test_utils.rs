@@ -0,0 +1,212 @@
+//! Utilities to fuzz the consistency between the index and the queries with [`proptest`].
+//!
+//! The generators only produce valid geometries, including some close to the antimeridian and the poles.
+//! The [`in_shape_brute_force`] function is the reference implementation of [`Cellulite::in_shape`].
+//! The [`synthetic_dataset`] function generates reproducible datasets for the benchmarks.
+
+use std::f64::consts::TAU;
+
+use geo::{Geometry, LineString, MultiPoint, Point, Polygon};
+use geojson::GeoJson;
+use heed::RoTxn;
+use proptest::{
+    collection::vec,
+    prelude::*,
+    strategy::ValueTree,
+    test_runner::{Config, RngAlgorithm, TestRng, TestRunner},
+};
+use roaring::RoaringBitmap;
+use zerometry::RelationBetweenShapes;
+
+use crate::{Cellulite, Result};
+
+/// Generate a longitude, two times out of three it's close to the antimeridian, once on each side.
+pub fn longitude() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        -179.99..179.99,
+        // Close to the antimeridian on both sides
+        179.0..179.99,
+        -179.99..-179.0,
+    ]
+}
+
+/// Generate a latitude, two times out of three it's close to one of the poles, once for each pole.
+pub fn latitude() -> impl Strategy<Value = f64> {
+    prop_oneof![-89.99..89.99, 89.0..89.99, -89.99..-89.0]
+}
+
+/// Generate a point anywhere on earth.
+pub fn point() -> impl Strategy<Value = Point> {
+    (longitude(), latitude()).prop_map(|(lng, lat)| Point::new(lng, lat))
+}
+
+/// Generate between 1 and 20 points anywhere on earth.
+pub fn multi_point() -> impl Strategy<Value = MultiPoint> {
+    vec(point(), 1..20).prop_map(MultiPoint::new)
+}
+
+/// Generate a line of 2 to 10 points, the line stays close to its first point.
+pub fn line_string() -> impl Strategy<Value = LineString> {
+    (point(), vec((-1.0..1.0, -1.0..1.0), 1..10)).prop_map(|(start, offsets)| {
+        let mut coords = vec![start.0];
+        for (x, y) in offsets {
+            let last = coords[coords.len() - 1];
+            coords.push(geo::coord! {
+                x: (last.x + x).clamp(-180.0, 180.0),
+                y: (last.y + y).clamp(-90.0, 90.0),
+            });
+        }
+        LineString::new(coords)
+    })
+}
+
+/// Generate a star-shaped polygon of 3 to 12 vertices.
+/// Its size goes from a few meters to a few hundred kilometers, but it never crosses the antimeridian or the poles.
+pub fn polygon() -> impl Strategy<Value = Polygon> {
+    (3..12_usize).prop_flat_map(polygon_with_vertices)
+}
+
+/// Generate a star-shaped polygon of `vertices` vertices, like [`polygon`].
+pub fn polygon_with_vertices(vertices: usize) -> impl Strategy<Value = Polygon> {
+    (point(), 0.0001..5.0, vec(0.5..1.0, vertices)).prop_map(|(center, radius, factors)| {
+        // Shrink the polygon so it stays in the bounds of the coordinates
+        let radius: f64 = radius
+            .min(180.0 - center.x().abs())
+            .min(90.0 - center.y().abs());
+        star_polygon(center, radius, &factors)
+    })
+}
+
+/// Generate a polygon of a few hundred kilometers crossing the antimeridian, or close to one
+/// of the poles.
+pub fn edge_polygon() -> impl Strategy<Value = Polygon> {
+    let crossing =
+        (-89.0..89.0, 0.5..5.0, vec(0.5..1.0, 3..12)).prop_map(|(lat, radius, factors)| {
+            let radius: f64 = radius.min(90.0 - f64::abs(lat));
+            let mut polygon = star_polygon(Point::new(180.0, lat), radius, &factors);
+            // Bring back the vertices east of the antimeridian on the other side
+            polygon.exterior_mut(|ring| {
+                for coord in ring.coords_mut() {
+                    if coord.x > 180.0 {
+                        coord.x -= 360.0;
+                    }
+                }
+            });
+            polygon
+        });
+    let polar = (
+        longitude(),
+        prop_oneof![85.0..89.0, -89.0..-85.0],
+        vec(0.5..1.0, 3..12),
+    )
+        .prop_map(|(lng, lat, factors)| {
+            let radius = f64::min(1.0, 180.0 - f64::abs(lng));
+            star_polygon(Point::new(lng, lat), radius, &factors)
+        });
+    prop_oneof![crossing, polar]
+}
+
+fn star_polygon(center: Point, radius: f64, factors: &[f64]) -> Polygon {
+    let step = TAU / factors.len() as f64;
+    let coords = factors.iter().enumerate().map(|(i, factor)| {
+        let angle = step * i as f64;
+        let distance = radius * factor;
+        geo::coord! {
+            x: center.x() + distance * angle.cos(),
+            y: center.y() + distance * angle.sin(),
+        }
+    });
+    Polygon::new(LineString::from_iter(coords), Vec::new())
+}
+
+/// Generate any of the geometries supported by cellulite, the polygons can cross the antimeridian
+/// or be close to the poles.
+pub fn geometry() -> impl Strategy<Value = Geometry> {
+    prop_oneof![
+        point().prop_map(Geometry::Point),
+        multi_point().prop_map(Geometry::MultiPoint),
+        line_string().prop_map(Geometry::LineString),
+        polygon().prop_map(Geometry::Polygon),
+        edge_polygon().prop_map(Geometry::Polygon),
+    ]
+}
+
+/// Generate any of the geometries supported by cellulite as a geojson.
+pub fn geojson() -> impl Strategy<Value = GeoJson> {
+    geometry()
+        .prop_map(|geometry| GeoJson::from(geojson::Geometry::new(geojson::Value::from(&geometry))))
+}
+
+/// Return the items intersecting the polygon by comparing it to every single item of the database.
+/// This is the result [`Cellulite::in_shape`] is expected to return, the database must be built first.
+pub fn in_shape_brute_force(
+    cellulite: &Cellulite,
+    rtxn: &RoTxn,
+    polygon: &Polygon,
+) -> Result<RoaringBitmap> {
+    // Same as the query, the edges of the polygon follow the distance model
+    let polygon = cellulite
+        .distance_model
+        .densify(polygon, cellulite.options().densify_distance);
+    let tombstones = cellulite.tombstones(rtxn)?;
+    let mut ret = RoaringBitmap::new();
+    for entry in cellulite.items(rtxn)? {
+        let (item, shape) = entry?;
+        if !tombstones.contains(item) && shape.any_relation(&polygon).any_relation() {
+            ret.insert(item);
+        }
+    }
+    Ok(ret)
+}
+
+/// The items generated by [`synthetic_dataset`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntheticDataset {
+    /// Points spread uniformly over the latitudes and longitudes.
+    UniformPoints,
+    /// Points gathered in squares of one degree around a number of centers, like the shops of
+    /// the cities.
+    ClusteredPoints { clusters: usize },
+    /// Star-shaped polygons of a number of vertices, see [`polygon_with_vertices`].
+    Polygons { vertices: usize },
+    /// Polygons crossing the antimeridian or close to the poles, see [`edge_polygon`].
+    Edges,
+}
+
+/// Generate `count` items of a dataset, the same seed always generates the same items.
+/// Unlike the other generators, it doesn't need a proptest runner, which lets the benchmarks and
+/// the performance tests run on large datasets without downloading them.
+pub fn synthetic_dataset(
+    dataset: SyntheticDataset,
+    count: usize,
+    seed: u64,
+) -> impl Iterator<Item = Geometry> {
+    let mut bytes = [0; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    let rng = TestRng::from_seed(RngAlgorithm::ChaCha, &bytes);
+    let mut runner = TestRunner::new_with_rng(Config::default(), rng);
+
+    let uniform_point = (-180.0..=180.0, -90.0..=90.0).prop_map(|(lng, lat)| Point::new(lng, lat));
+    let strategy = match dataset {
+        SyntheticDataset::UniformPoints => uniform_point.prop_map(Geometry::Point).boxed(),
+        SyntheticDataset::ClusteredPoints { clusters } => {
+            let centers: Vec<Point> = (0..clusters.max(1))
+                .map(|_| uniform_point.new_tree(&mut runner).unwrap().current())
+                .collect();
+            (0..centers.len(), -0.5..0.5, -0.5..0.5)
+                .prop_map(move |(cluster, x, y)| {
+                    let center = centers[cluster];
+                    Geometry::Point(Point::new(
+                        (center.x() + x).clamp(-180.0, 180.0),
+                        (center.y() + y).clamp(-90.0, 90.0),
+                    ))
+                })
+                .boxed()
+        }
+        SyntheticDataset::Polygons { vertices } => polygon_with_vertices(vertices.max(3))
+            .prop_map(Geometry::Polygon)
+            .boxed(),
+        SyntheticDataset::Edges => edge_polygon().prop_map(Geometry::Polygon).boxed(),
+    };
+    (0..count).map(move |_| strategy.new_tree(&mut runner).unwrap().current())
+}
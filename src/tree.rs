@@ -0,0 +1,83 @@
+use h3o::{CellIndex, Resolution};
+use heed::RoTxn;
+
+use crate::{Cellulite, Result, keys::retrieve_cell_and_belly};
+
+/// A cell of the database and its sub-cells, returned by [`Cellulite::cell_tree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellTree {
+    pub cell: CellIndex,
+    /// The number of items in the cell.
+    pub items: u64,
+    /// The number of items in the belly cell of the cell, they cover the whole cell and are not
+    /// stored in its sub-cells.
+    pub belly_items: u64,
+    /// The sub-cells stored in the database, ordered by index. Like in the build, they're the cells
+    /// of the next resolution at most two rings away from the center child of the cell. Empty if
+    /// the cell is a leaf or if `max_depth` was reached.
+    pub children: Vec<CellTree>,
+}
+
+impl Cellulite {
+    /// Return the hierarchy of the cells stored in the database under `root`, or under all the
+    /// base cells if `root` is `None`. The cells deeper than `max_depth` resolutions below their
+    /// root are not returned, a depth of zero only returns the roots.
+    /// The roots that are not stored in the database are skipped.
+    pub fn cell_tree(
+        &self,
+        rtxn: &RoTxn,
+        root: Option<CellIndex>,
+        max_depth: u8,
+    ) -> Result<Vec<CellTree>> {
+        let roots: Vec<_> = match root {
+            Some(root) => vec![root],
+            None => CellIndex::base_cells().collect(),
+        };
+        let max_resolution = self.max_resolution(rtxn)?;
+        let mut ret = Vec::new();
+        for root in roots {
+            if let Some(tree) = self.cell_sub_tree(rtxn, root, max_resolution, max_depth)? {
+                ret.push(tree);
+            }
+        }
+        Ok(ret)
+    }
+
+    fn cell_sub_tree(
+        &self,
+        rtxn: &RoTxn,
+        cell: CellIndex,
+        max_resolution: Resolution,
+        depth: u8,
+    ) -> Result<Option<CellTree>> {
+        let (items, belly) = retrieve_cell_and_belly(rtxn, &self.cell, cell)?;
+        if items.is_none() && belly.is_none() {
+            return Ok(None);
+        }
+        let items = items.map_or(0, |bitmap| bitmap.len());
+        // Like in the reader, the sub-cells of a leaf belong to its neighbours
+        let leaf = items < self.options.threshold || cell.resolution() >= max_resolution;
+        let mut children = Vec::new();
+        if let Some(resolution) = cell.resolution().succ()
+            && depth > 0
+            && !leaf
+        {
+            // The build splits a cell in the cells around its center child, they overflow the
+            // cell and a sub-cell can be stored under multiple cells
+            let center_child = cell.center_child(resolution).unwrap();
+            let mut sub_cells: Vec<CellIndex> = center_child.grid_disk(2);
+            sub_cells.sort_unstable();
+            for child in sub_cells {
+                if let Some(tree) = self.cell_sub_tree(rtxn, child, max_resolution, depth - 1)? {
+                    children.push(tree);
+                }
+            }
+        }
+        Ok(Some(CellTree {
+            cell,
+            items,
+            belly_items: belly.map_or(0, |bitmap| bitmap.len()),
+            children,
+        }))
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::{Read, Write};
+
+use heed::{RoTxn, RwTxn, types::Bytes};
+
+use crate::{
+    Cellulite, Error, ItemId, Result, checksum::Checksummed, keys::UpdateType, pos, validation,
+};
+
+/// The first bytes of an update log, followed by the version of its format.
+const MAGIC: &[u8; 8] = b"CELLULOG";
+/// The v2 introduced the updates of the shapes, the v1 logs can still be applied.
+const FORMAT_VERSION: u8 = 2;
+
+impl Cellulite {
+    /// Write the updates waiting for a build to `writer`, so a replica can apply them with
+    /// [`Self::apply_updates`] and run its own build instead of receiving a full copy of the
+    /// database. The updates are cleared by the build, they must be exported before it.
+    /// Returns the number of updates written.
+    ///
+    /// The log starts with the `CELLULOG` magic, the version of the format on one byte and the
+    /// number of updates as a big-endian `u64`. Each update is the big-endian `u32` id of the item
+    /// followed by `0` and the length and bytes of its `Zerometry` for an insertion, by `1` for a
+    /// deletion, or by `2` and the length and bytes of its new `Zerometry` for an update.
+    pub fn export_updates(&self, rtxn: &RoTxn, mut writer: impl Write) -> Result<u64> {
+        let io = |e| Error::from(heed::Error::Io(e));
+        let count = self.update.len(rtxn)?;
+        writer.write_all(MAGIC).map_err(io)?;
+        writer.write_all(&[FORMAT_VERSION]).map_err(io)?;
+        writer.write_all(&count.to_be_bytes()).map_err(io)?;
+
+        let items = self.item_db().remap_data_type::<Checksummed<Bytes>>();
+        for ret in self.update.iter(rtxn)? {
+            let (item, update) = ret?;
+            writer.write_all(&item.to_be_bytes()).map_err(io)?;
+            writer.write_all(&[update as u8]).map_err(io)?;
+            if update != UpdateType::Delete {
+                let bytes = items
+                    .get(rtxn, &item)?
+                    .ok_or_else(|| Error::InternalDocIdMissing(item, pos!()))?;
+                writer
+                    .write_all(&(bytes.len() as u64).to_be_bytes())
+                    .map_err(io)?;
+                writer.write_all(bytes).map_err(io)?;
+            }
+        }
+        writer.flush().map_err(io)?;
+        Ok(count)
+    }
+
+    /// Apply the updates exported by [`Self::export_updates`] as if the items were added and
+    /// deleted on this database. For the items to be searchable you must [`Self::build`] the
+    /// database afterward. Returns the number of updates applied, an
+    /// [`Error::InvalidUpdateLog`] if the log was not written by a compatible version, or an
+    /// [`Error::InvalidZerometry`] if it contains a corrupted shape.
+    pub fn apply_updates(&self, wtxn: &mut RwTxn, mut reader: impl Read) -> Result<u64> {
+        let mut magic = [0; MAGIC.len()];
+        read_exact(&mut reader, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidUpdateLog(
+                "it doesn't start with the magic".to_string(),
+            ));
+        }
+        let [version] = read_array(&mut reader)?;
+        if !(1..=FORMAT_VERSION).contains(&version) {
+            return Err(Error::InvalidUpdateLog(format!(
+                "its format v{version} is unknown"
+            )));
+        }
+        let count = u64::from_be_bytes(read_array(&mut reader)?);
+
+        let mut bytes = Vec::new();
+        for _ in 0..count {
+            let item = ItemId::from_be_bytes(read_array(&mut reader)?);
+            match read_array(&mut reader)? {
+                [b] if b == UpdateType::Insert as u8 => {
+                    read_zerometry(&mut reader, item, &mut bytes)?;
+                    self.add_raw_zerometry(wtxn, item, &bytes)?;
+                }
+                [b] if b == UpdateType::Delete as u8 => self.delete(wtxn, item)?,
+                [b] if b == UpdateType::Update as u8 && version >= 2 => {
+                    read_zerometry(&mut reader, item, &mut bytes)?;
+                    self.update_raw_zerometry(wtxn, item, &bytes)?;
+                }
+                [b] => {
+                    return Err(Error::InvalidUpdateLog(format!(
+                        "the update of the item `{item}` has the unknown type {b}"
+                    )));
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Read the length and the bytes of a `Zerometry` and check them. The buffer only grows with the
+/// bytes actually read, a corrupted length cannot allocate more memory than the log contains.
+fn read_zerometry(reader: &mut impl Read, item: ItemId, bytes: &mut Vec<u8>) -> Result<()> {
+    let len = u64::from_be_bytes(read_array(reader)?);
+    bytes.clear();
+    reader
+        .take(len)
+        .read_to_end(bytes)
+        .map_err(|e| Error::from(heed::Error::Io(e)))?;
+    if bytes.len() as u64 != len {
+        return Err(Error::InvalidUpdateLog("it's truncated".to_string()));
+    }
+    validation::check_zerometry(item, bytes)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut array = [0; N];
+    read_exact(reader, &mut array)?;
+    Ok(array)
+}
+
+fn read_exact(reader: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => Error::InvalidUpdateLog("it's truncated".to_string()),
+        _ => Error::from(heed::Error::Io(e)),
+    })
+}
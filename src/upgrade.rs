@@ -0,0 +1,188 @@
+use std::{ops::Bound, sync::atomic::Ordering};
+
+use h3o::CellIndex;
+use heed::{
+    Env, RwTxn,
+    byteorder::{BigEndian, ByteOrder},
+    types::{Bytes, DecodeIgnore},
+};
+use roaring::RoaringBitmap;
+use steppe::Progress;
+
+use crate::{
+    AtomicCellStep, AtomicItemStep, Cellulite, Error, Result,
+    checksum::Checksummed,
+    keys::{Key, KeyVariant},
+    metadata::Version,
+};
+
+/// The smallest old key of a cell below the resolution zero. A cell index starts with its mode, `1`
+/// for the cells, followed by its resolution.
+const FIRST_OLD_KEY: u64 = (1 << 59) | (1 << 52);
+
+impl Cellulite {
+    /// Open all the databases like [`Self::open_from_env`], and upgrade them with [`Self::upgrade`]
+    /// if they were created by an older version of cellulite. The databases added by the newer
+    /// versions are created.
+    /// Returns [`Error::CannotUpgradeFromVersion`] right away if they were created by a newer
+    /// version.
+    pub fn open_and_upgrade<Tls>(
+        env: &Env<Tls>,
+        wtxn: &mut RwTxn,
+        prefix: &str,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<Self> {
+        // Every version had the item database
+        env.open_database::<Bytes, DecodeIgnore>(wtxn, Some(&format!("{prefix}-item")))?
+            .ok_or(Error::DatabaseDoesntExists)?;
+        let cellulite = Self::create_from_env(env, wtxn, prefix)?;
+        cellulite.upgrade(wtxn, cancel, progress)?;
+        Ok(cellulite)
+    }
+
+    /// Upgrade a database created by an older version of cellulite to the current version.
+    /// Does nothing if the database is already up to date.
+    ///
+    /// Must be called before building or querying a database that was created by an older version.
+    /// Returns [`Error::UpgradeCanceled`] if `cancel` returns `true`, the transaction must then be
+    /// aborted since the database is only partially upgraded.
+    pub fn upgrade(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        let version = self.get_version(wtxn)?;
+        let current = Version::default();
+        if version == current {
+            return Ok(());
+        }
+
+        match (version.major, version.minor) {
+            // Before v0.4 the cells were stored in the order of their index instead of their locality
+            // and the values had no checksum, they're only added with the `checksum` feature
+            (0, ..=3) => {
+                self.upgrade_cell_keys_to_locality(wtxn, cancel, progress)?;
+                if cfg!(feature = "checksum") {
+                    self.add_checksum_to_items(wtxn, cancel, progress)?;
+                }
+                self.set_values_checksummed(wtxn, cfg!(feature = "checksum"))?;
+            }
+            (major, minor) if major == current.major && minor == current.minor => (),
+            _ => return Err(Error::CannotUpgradeFromVersion(version)),
+        }
+
+        self.set_version(wtxn, &current)?;
+        Ok(())
+    }
+
+    fn upgrade_cell_keys_to_locality(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        /// The number of cells read before being written with their new key.
+        const BATCH_SIZE: usize = 10_000;
+
+        steppe::make_enum_progress! {
+            pub enum UpgradeCellKeysSteps {
+                RewriteTheBaseCells,
+                RewriteTheCells,
+            }
+        }
+
+        let db = self.cell.remap_types::<Bytes, Bytes>();
+        let (atomic, step) = AtomicCellStep::new(db.len(wtxn)?);
+        // The old keys were the cell index as a big endian u64 followed by the variant
+        let decode = |key: &[u8]| -> Result<Key> {
+            let cell = CellIndex::try_from(BigEndian::read_u64(key))?;
+            let variant = key[size_of::<u64>()];
+            Ok(match variant {
+                v if v == KeyVariant::Cell as u8 => Key::Cell(cell),
+                v if v == KeyVariant::Belly as u8 => Key::Belly(cell),
+                _ => unreachable!(),
+            })
+        };
+
+        // The old and new keys of the cells at resolution zero are the same, only their values are
+        // rewritten. There are at most two entries per base cell.
+        progress.update(UpgradeCellKeysSteps::RewriteTheBaseCells);
+        progress.update(step);
+        let first_old_key = FIRST_OLD_KEY.to_be_bytes();
+        let mut base_cells = Vec::new();
+        for ret in db.range(
+            wtxn,
+            &(Bound::Unbounded, Bound::Excluded(&first_old_key[..])),
+        )? {
+            let (key, bitmap) = ret?;
+            base_cells.push((decode(key)?, bitmap.to_vec()));
+        }
+        let checksummed = self.cell.remap_data_type::<Checksummed<Bytes>>();
+        for (key, bitmap) in base_cells {
+            checksummed.put(wtxn, &key, &bitmap)?;
+            atomic.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // The new keys don't have the bits of the resolution and are all lower than the old keys of
+        // the other cells. We rewrite them by batches, always starting from the first old key left.
+        progress.update(UpgradeCellKeysSteps::RewriteTheCells);
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        loop {
+            if cancel() {
+                return Err(Error::UpgradeCanceled);
+            }
+            batch.clear();
+            for ret in db
+                .range(
+                    wtxn,
+                    &(Bound::Included(&first_old_key[..]), Bound::Unbounded),
+                )?
+                .take(BATCH_SIZE)
+            {
+                let (key, bitmap) = ret?;
+                batch.push((key.to_vec(), bitmap.to_vec()));
+            }
+            if batch.is_empty() {
+                break;
+            }
+            for (old_key, bitmap) in &batch {
+                db.delete(wtxn, old_key)?;
+                checksummed.put(wtxn, &decode(old_key)?, bitmap)?;
+                atomic.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_checksum_to_items(
+        &self,
+        wtxn: &mut RwTxn,
+        cancel: &(impl Fn() -> bool + Send + Sync),
+        progress: &impl Progress,
+    ) -> Result<()> {
+        let (atomic, step) = AtomicItemStep::new(self.item.len(wtxn)?);
+        progress.update(step);
+        let db = self.item.remap_data_type::<Bytes>();
+        let mut items = RoaringBitmap::new();
+        for ret in db.iter(wtxn)? {
+            let (item, _) = ret?;
+            items.insert(item);
+        }
+        // The items are rewritten one by one to avoid loading all of them in memory
+        for item in items {
+            if cancel() {
+                return Err(Error::UpgradeCanceled);
+            }
+            let shape = db.get(wtxn, &item)?.unwrap_or_default().to_vec();
+            self.item
+                .remap_data_type::<Checksummed<Bytes>>()
+                .put(wtxn, &item, &shape)?;
+            atomic.fetch_add(1, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+}
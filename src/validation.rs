@@ -0,0 +1,362 @@
+use geo::{
+    BooleanOps, BoundingRect, Coord, CoordsIter, Geometry, HasDimensions, Kernel, LineString,
+    MapCoords, MultiPolygon, Orient, Orientation, Polygon, Rect, RemoveRepeatedPoints, Validation,
+    algorithm::validation::InvalidPolygon, coord, kernels::RobustKernel, orient::Direction,
+};
+use h3o::LatLng;
+
+use crate::{Error, ItemId, Result};
+
+/// Check that `bytes` can be stored as the shape of `item`: it must be readable as a `Zerometry`
+/// and contain at least one coordinate, every coordinate being finite and on the globe.
+/// The bounding boxes stored in the header must also be the ones of the coordinates.
+pub(crate) fn check_zerometry(item: ItemId, bytes: &[u8]) -> Result<()> {
+    let invalid = |reason: String| Error::InvalidZerometry(item, reason);
+    if bytes.len() % size_of::<u64>() != 0 {
+        return Err(invalid(format!(
+            "its length of {} bytes isn't a multiple of 8",
+            bytes.len()
+        )));
+    }
+    let geometry = crate::zerometry::read_unaligned(bytes).map_err(|e| invalid(e.to_string()))?;
+    if geometry.is_empty() {
+        return Err(Error::EmptyGeometry(item));
+    }
+    for coord in geometry.coords_iter() {
+        LatLng::new(coord.y, coord.x).map_err(|err| Error::InvalidItemCoordinate(item, err))?;
+        if !(-180.0..=180.0).contains(&coord.x) || !(-90.0..=90.0).contains(&coord.y) {
+            return Err(invalid(format!(
+                "the coordinate ({}, {}) is outside of the globe",
+                coord.x, coord.y
+            )));
+        }
+    }
+    // The relations trust the bounding boxes to skip the coordinates, they're computed again by
+    // encoding the coordinates and the rest of the header must not change
+    if crate::zerometry::encode(&geometry)? != bytes {
+        return Err(invalid(
+            "its bounding boxes don't match its coordinates".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that the polygons of the geometry don't intersect themselves.
+/// The tiler and the relations are undefined on these polygons.
+///
+/// If `repair` is set, the invalid polygons are replaced by their union with nothing instead,
+/// which splits them in valid polygons (a bow-tie becomes two triangles).
+pub(crate) fn check_polygons(item: ItemId, geometry: &mut Geometry, repair: bool) -> Result<()> {
+    match geometry {
+        Geometry::Polygon(polygon) => {
+            if let Some(reason) = self_intersection(polygon) {
+                if !repair {
+                    return Err(Error::InvalidPolygon(item, reason.to_string()));
+                }
+                *geometry = Geometry::MultiPolygon(repair_polygon(polygon));
+            }
+        }
+        Geometry::MultiPolygon(multi_polygon) => {
+            let mut repaired = Vec::with_capacity(multi_polygon.0.len());
+            for polygon in multi_polygon.0.drain(..) {
+                match self_intersection(&polygon) {
+                    None => repaired.push(polygon),
+                    Some(reason) if !repair => {
+                        return Err(Error::InvalidPolygon(item, reason.to_string()));
+                    }
+                    Some(_) => repaired.extend(repair_polygon(&polygon)),
+                }
+            }
+            multi_polygon.0 = repaired;
+        }
+        Geometry::GeometryCollection(collection) => {
+            for geometry in collection.0.iter_mut() {
+                check_polygons(item, geometry, repair)?;
+            }
+        }
+        // The other geometries cannot intersect themselves in a way that matters to us
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Orient the exterior rings of the polygons counter-clockwise and their interior rings clockwise,
+/// so the stored shapes don't depend on the conventions of their producer.
+pub(crate) fn orient_polygons(geometry: &mut Geometry) {
+    match geometry {
+        Geometry::Polygon(polygon) => *polygon = polygon.orient(Direction::Default),
+        Geometry::MultiPolygon(multi_polygon) => {
+            *multi_polygon = multi_polygon.orient(Direction::Default)
+        }
+        Geometry::GeometryCollection(collection) => {
+            for geometry in collection.0.iter_mut() {
+                orient_polygons(geometry);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Split the polygons crossing the antimeridian in two parts, one on each side.
+/// A ring crosses the antimeridian when one of its edges is more than 180° of longitude long.
+///
+/// The polygons whose exterior ring goes around a pole are closed through the pole instead, the
+/// pole is in the hemisphere of the ring. The other polygons are expected to span less than 180°
+/// of longitude.
+pub(crate) fn split_antimeridian(geometry: &mut Geometry) {
+    match geometry {
+        Geometry::Polygon(polygon) => {
+            if crosses_antimeridian(polygon.exterior()) {
+                *geometry = Geometry::MultiPolygon(split_polygon(polygon));
+            }
+        }
+        Geometry::MultiPolygon(multi_polygon) => {
+            let mut split = Vec::with_capacity(multi_polygon.0.len());
+            for polygon in multi_polygon.0.drain(..) {
+                if crosses_antimeridian(polygon.exterior()) {
+                    split.extend(split_polygon(&polygon));
+                } else {
+                    split.push(polygon);
+                }
+            }
+            multi_polygon.0 = split;
+        }
+        Geometry::GeometryCollection(collection) => {
+            collection.0.iter_mut().for_each(split_antimeridian)
+        }
+        _ => (),
+    }
+}
+
+fn crosses_antimeridian(ring: &LineString) -> bool {
+    ring.lines()
+        .any(|line| (line.end.x - line.start.x).abs() > 180.0)
+}
+
+fn split_polygon(polygon: &Polygon) -> MultiPolygon {
+    let exterior = unwrap_longitudes(polygon.exterior());
+    let winding = match (exterior.first(), exterior.last()) {
+        (Some(first), Some(last)) => last.x - first.x,
+        _ => 0.0,
+    };
+    let continuous = if winding.abs() > 180.0 {
+        close_through_pole(polygon, exterior)
+    } else {
+        // Move the western part to the east of the antimeridian so the polygon is continuous
+        polygon.map_coords(|c| {
+            if c.x < 0.0 {
+                coord! { x: c.x + 360.0, y: c.y }
+            } else {
+                c
+            }
+        })
+    };
+
+    // Cut the continuous polygon in the [-180, 180] windows it overlaps and move them back
+    let mut parts = MultiPolygon::new(Vec::new());
+    for offset in [-360.0, 0.0, 360.0] {
+        let window = Rect::new(
+            coord! { x: offset - 180.0, y: -90.0 },
+            coord! { x: offset + 180.0, y: 90.0 },
+        );
+        let part = continuous
+            .intersection(&window.to_polygon())
+            .map_coords(|c| coord! { x: c.x - offset, y: c.y });
+        parts.0.extend(part);
+    }
+    // The edges following a pole or the antimeridian can be 360° long, the tiler would take them
+    // for edges crossing the antimeridian
+    for part in parts.iter_mut() {
+        part.exterior_mut(split_long_edges);
+        part.interiors_mut(|interiors| interiors.iter_mut().for_each(split_long_edges));
+    }
+    parts
+}
+
+/// Return the coordinates of the ring without the jumps of 360° at the antimeridian.
+/// The last coordinate is one turn away from the first one if the ring goes around a pole.
+fn unwrap_longitudes(ring: &LineString) -> Vec<Coord> {
+    let mut offset = 0.0;
+    let mut previous: Option<Coord> = None;
+    let mut coords = Vec::with_capacity(ring.0.len());
+    for coord in ring.0.iter() {
+        if let Some(previous) = previous {
+            let delta = coord.x - previous.x;
+            if delta > 180.0 {
+                offset -= 360.0;
+            } else if delta < -180.0 {
+                offset += 360.0;
+            }
+        }
+        previous = Some(*coord);
+        coords.push(coord! { x: coord.x + offset, y: coord.y });
+    }
+    coords
+}
+
+/// Close the unwrapped exterior ring by following the pole of its hemisphere.
+fn close_through_pole(polygon: &Polygon, mut exterior: Vec<Coord>) -> Polygon {
+    let mean_latitude = exterior.iter().map(|c| c.y).sum::<f64>() / exterior.len() as f64;
+    let pole = if mean_latitude >= 0.0 { 90.0 } else { -90.0 };
+    // safe to unwrap because the ring goes around the pole
+    let first = exterior[0];
+    let last = *exterior.last().unwrap();
+    exterior.push(coord! { x: last.x, y: pole });
+    exterior.push(coord! { x: first.x, y: pole });
+    exterior.push(first);
+
+    // The holes are moved in the turn of the exterior ring
+    let interiors = polygon
+        .interiors()
+        .iter()
+        .map(|interior| {
+            let coords = unwrap_longitudes(interior);
+            let offset = match coords.first() {
+                Some(c) => ((first.x.min(last.x) - c.x) / 360.0).ceil() * 360.0,
+                None => 0.0,
+            };
+            LineString::new(coords).map_coords(|c| coord! { x: c.x + offset, y: c.y })
+        })
+        .collect();
+    Polygon::new(LineString::new(exterior), interiors)
+}
+
+/// Add vertices to the edges spanning more than 90° of longitude.
+fn split_long_edges(ring: &mut LineString) {
+    const MAX_SPAN: f64 = 90.0;
+    let mut coords = Vec::with_capacity(ring.0.len());
+    for line in ring.lines() {
+        coords.push(line.start);
+        let steps = ((line.end.x - line.start.x).abs() / MAX_SPAN).ceil() as usize;
+        for step in 1..steps {
+            let ratio = step as f64 / steps as f64;
+            coords.push(line.start + (line.end - line.start) * ratio);
+        }
+    }
+    if let Some(last) = ring.0.last() {
+        coords.push(*last);
+    }
+    *ring = LineString::new(coords);
+}
+
+/// Check the size of the fragments can be used by [`fragment_polygons`], a size that isn't a
+/// positive number would never stop cutting the polygons.
+pub(crate) fn check_fragment_size(size: f64) -> Result<()> {
+    if size.is_finite() && size > 0.0 {
+        Ok(())
+    } else {
+        Err(Error::InvalidFragmentSize(size))
+    }
+}
+
+/// Cut the polygons spanning more than `size` degrees of longitude or latitude in fragments along
+/// a grid of `size` degrees. The polygons must be valid and the size checked by
+/// [`check_fragment_size`].
+pub(crate) fn fragment_polygons(geometry: &mut Geometry, size: f64) {
+    match geometry {
+        Geometry::Polygon(polygon) => {
+            if is_oversized(polygon, size) {
+                *geometry = Geometry::MultiPolygon(fragment_polygon(polygon, size));
+            }
+        }
+        Geometry::MultiPolygon(multi_polygon) => {
+            let mut fragments = Vec::with_capacity(multi_polygon.0.len());
+            for polygon in multi_polygon.0.drain(..) {
+                if is_oversized(&polygon, size) {
+                    fragments.extend(fragment_polygon(&polygon, size));
+                } else {
+                    fragments.push(polygon);
+                }
+            }
+            multi_polygon.0 = fragments;
+        }
+        Geometry::GeometryCollection(collection) => collection
+            .0
+            .iter_mut()
+            .for_each(|geometry| fragment_polygons(geometry, size)),
+        _ => (),
+    }
+}
+
+fn is_oversized(polygon: &Polygon, size: f64) -> bool {
+    polygon
+        .bounding_rect()
+        .is_some_and(|rect| rect.width() > size || rect.height() > size)
+}
+
+fn fragment_polygon(polygon: &Polygon, size: f64) -> MultiPolygon {
+    let mut fragments = MultiPolygon::new(Vec::new());
+    // safe to unwrap because the polygon is oversized
+    let rect = polygon.bounding_rect().unwrap();
+    // The grid is aligned on the multiples of `size`
+    let mut x = (rect.min().x / size).floor() * size;
+    while x < rect.max().x {
+        let mut y = (rect.min().y / size).floor() * size;
+        while y < rect.max().y {
+            let tile = Rect::new(coord! { x: x, y: y }, coord! { x: x + size, y: y + size });
+            fragments.0.extend(polygon.intersection(&tile.to_polygon()));
+            y += size;
+        }
+        x += size;
+    }
+    fragments
+}
+
+/// Remove the repeated consecutive vertices of the geometry, and the collinear vertices of its polygons.
+/// The collinear vertices include the degenerate spikes where a ring goes back on itself.
+pub(crate) fn clean_vertices(geometry: &mut Geometry) {
+    geometry.remove_repeated_points_mut();
+    match geometry {
+        Geometry::Polygon(polygon) => clean_polygon(polygon),
+        Geometry::MultiPolygon(multi_polygon) => multi_polygon.0.iter_mut().for_each(clean_polygon),
+        Geometry::GeometryCollection(collection) => {
+            collection.0.iter_mut().for_each(clean_vertices)
+        }
+        _ => (),
+    }
+}
+
+fn clean_polygon(polygon: &mut Polygon) {
+    polygon.exterior_mut(remove_collinear_vertices);
+    polygon.interiors_mut(|interiors| interiors.iter_mut().for_each(remove_collinear_vertices));
+}
+
+fn remove_collinear_vertices(ring: &mut LineString) {
+    // The ring is closed, its last vertex is the same as the first one
+    let mut coords: Vec<Coord> = ring.0.iter().copied().skip(1).collect();
+    // Removing a vertex can make its neighbours collinear, we must loop until nothing changes
+    let mut changed = true;
+    while changed && coords.len() > 3 {
+        changed = false;
+        let mut i = 0;
+        while i < coords.len() && coords.len() > 3 {
+            let prev = coords[(i + coords.len() - 1) % coords.len()];
+            let next = coords[(i + 1) % coords.len()];
+            if RobustKernel::orient2d(prev, coords[i], next) == Orientation::Collinear {
+                coords.remove(i);
+                changed = true;
+            } else {
+                i += 1;
+            }
+        }
+    }
+    if let Some(first) = coords.last().copied() {
+        coords.insert(0, first);
+    }
+    *ring = LineString::new(coords);
+}
+
+fn self_intersection(polygon: &Polygon) -> Option<InvalidPolygon> {
+    polygon.validation_errors().into_iter().find(|error| {
+        matches!(
+            error,
+            InvalidPolygon::SelfIntersection(_)
+                | InvalidPolygon::IntersectingRingsOnALine(..)
+                | InvalidPolygon::IntersectingRingsOnAnArea(..)
+        )
+    })
+}
+
+fn repair_polygon(polygon: &Polygon) -> MultiPolygon {
+    polygon.union(&MultiPolygon::new(Vec::new()))
+}
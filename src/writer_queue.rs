@@ -0,0 +1,193 @@
+use std::{
+    panic::{AssertUnwindSafe, catch_unwind, resume_unwind},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use geo::Geometry;
+#[cfg(feature = "geojson")]
+use geojson::GeoJson;
+use heed::Env;
+use steppe::Progress;
+
+use crate::{Cellulite, Error, ItemId, Result, error::panic_message};
+
+enum Operation {
+    #[cfg(feature = "geojson")]
+    Add(ItemId, GeoJson),
+    AddGeometry(ItemId, Geometry),
+    Delete(ItemId),
+}
+
+enum Message {
+    Operation(Operation),
+    Flush(mpsc::Sender<()>),
+}
+
+/// What happened to a batch of operations, sent to the callback of the [`CelluliteWriterQueue`].
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// The number of items added or replaced by the batch.
+    pub added: usize,
+    /// The number of items deleted by the batch.
+    pub deleted: usize,
+    /// The operations that couldn't be applied, the rest of the batch was committed anyway.
+    pub failed: Vec<(ItemId, Error)>,
+    /// The time spent writing, building and committing the batch.
+    pub duration: Duration,
+}
+
+/// Accepts additions and deletions from any number of threads and applies them in batches.
+///
+/// A dedicated thread waits for the first operation, keeps accumulating the operations sent
+/// during the next `interval`, then writes them all in a single write transaction, builds the
+/// database and commits. The outcome of every batch is sent to the callback given on creation,
+/// a batch panicking is rolled back and reported as an [`Error::WriterQueuePanicked`].
+///
+/// If the callback panics, the thread stops and the queue returns an
+/// [`Error::WriterQueueStopped`] for every operation sent afterward. Dropping the queue applies
+/// the pending operations, waits for the thread to stop and resumes the panic of the callback.
+pub struct CelluliteWriterQueue {
+    sender: Option<mpsc::Sender<Message>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CelluliteWriterQueue {
+    /// Spawn the writer thread. `progress` follows the builds, `on_batch` is called once per
+    /// batch with its report, or the error or panic that prevented it from being committed.
+    pub fn new(
+        env: Env,
+        cellulite: Cellulite,
+        interval: Duration,
+        progress: impl Progress + Send + 'static,
+        mut on_batch: impl FnMut(Result<BatchReport>) + Send + 'static,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            // We stop once the queue is dropped and every operation has been applied
+            while let Ok(first) = receiver.recv() {
+                let deadline = Instant::now() + interval;
+                let mut batch = Vec::new();
+                let mut flush = None;
+                let mut next = Ok(first);
+                // A flush ends the batch early, otherwise we stop at the deadline
+                while let Ok(message) = next {
+                    match message {
+                        Message::Operation(operation) => batch.push(operation),
+                        Message::Flush(sender) => {
+                            flush = Some(sender);
+                            break;
+                        }
+                    }
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    next = receiver.recv_timeout(timeout);
+                }
+                if !batch.is_empty() {
+                    let report = catch_unwind(AssertUnwindSafe(|| {
+                        write_batch(&env, &cellulite, batch, &progress)
+                    }))
+                    .unwrap_or_else(|payload| {
+                        Err(Error::WriterQueuePanicked(panic_message(payload)))
+                    });
+                    if let Err(panic) = catch_unwind(AssertUnwindSafe(|| on_batch(report))) {
+                        // The queue must refuse the operations before the waiting flush returns
+                        drop(receiver);
+                        resume_unwind(panic);
+                    }
+                }
+                if let Some(flush) = flush {
+                    // The caller may not be waiting anymore
+                    let _ = flush.send(());
+                }
+            }
+        });
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    /// Queue the insertion of an item, see [`Cellulite::add`].
+    /// Returns [`Error::WriterQueueStopped`] if the writer thread stopped.
+    #[cfg(feature = "geojson")]
+    pub fn add(&self, item: ItemId, geo: GeoJson) -> Result<()> {
+        self.send(Message::Operation(Operation::Add(item, geo)))
+    }
+
+    /// Queue the insertion of an item, see [`Cellulite::add_geometry`].
+    /// Returns [`Error::WriterQueueStopped`] if the writer thread stopped.
+    pub fn add_geometry(&self, item: ItemId, geom: Geometry) -> Result<()> {
+        self.send(Message::Operation(Operation::AddGeometry(item, geom)))
+    }
+
+    /// Queue the deletion of an item, see [`Cellulite::delete`].
+    /// Returns [`Error::WriterQueueStopped`] if the writer thread stopped.
+    pub fn delete(&self, item: ItemId) -> Result<()> {
+        self.send(Message::Operation(Operation::Delete(item)))
+    }
+
+    /// Apply the operations queued so far without waiting for the end of the interval, and block
+    /// until they're committed. Returns [`Error::WriterQueueStopped`] if the writer thread stopped
+    /// before committing them.
+    pub fn flush(&self) -> Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.send(Message::Flush(sender))?;
+        receiver.recv().map_err(|_| Error::WriterQueueStopped)
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        // The sender is only taken on drop
+        let sender = self.sender.as_ref().unwrap();
+        // The panic of the writer thread is resumed on drop
+        sender.send(message).map_err(|_| Error::WriterQueueStopped)
+    }
+}
+
+impl Drop for CelluliteWriterQueue {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        // Don't panic again if we're already unwinding
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Err(panic)) if !std::thread::panicking() => std::panic::resume_unwind(panic),
+            _ => (),
+        }
+    }
+}
+
+fn write_batch(
+    env: &Env,
+    cellulite: &Cellulite,
+    batch: Vec<Operation>,
+    progress: &impl Progress,
+) -> Result<BatchReport> {
+    let started = Instant::now();
+    let mut report = BatchReport::default();
+    let mut wtxn = env.write_txn()?;
+    for operation in batch {
+        let (item, ret, counter) = match operation {
+            #[cfg(feature = "geojson")]
+            Operation::Add(item, geo) => (
+                item,
+                cellulite.add(&mut wtxn, item, &geo),
+                &mut report.added,
+            ),
+            Operation::AddGeometry(item, geom) => (
+                item,
+                cellulite.add_geometry(&mut wtxn, item, geom),
+                &mut report.added,
+            ),
+            Operation::Delete(item) => {
+                (item, cellulite.delete(&mut wtxn, item), &mut report.deleted)
+            }
+        };
+        match ret {
+            Ok(()) => *counter += 1,
+            Err(error) => report.failed.push((item, error)),
+        }
+    }
+    cellulite.build(&mut wtxn, &|| false, progress)?;
+    wtxn.commit()?;
+    report.duration = started.elapsed();
+    Ok(report)
+}
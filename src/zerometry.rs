@@ -4,17 +4,85 @@ use geo::Geometry;
 use heed::BoxedError;
 use zerometry::Zerometry;
 
+use crate::Result;
+
+/// Encode a geometry as it's stored in the database, the bytes can be given to
+/// [`crate::Cellulite::add_raw_zerometry`] later, by another process or on another machine.
+///
+/// The geometry is encoded as-is. [`crate::Cellulite::add_geometry`] also validates it and splits
+/// it on the antimeridian first, see [`crate::Cellulite::encode_geometry`] to do the same.
+pub fn encode(geometry: &Geometry) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    Zerometry::write_from_geometry(&mut bytes, geometry)
+        .map_err(|e| heed::Error::Encoding(e.into()))?;
+    Ok(bytes)
+}
+
+/// Decode the bytes returned by [`encode`], they don't need to be aligned.
+pub fn decode(bytes: &[u8]) -> Result<Geometry> {
+    Ok(read_unaligned(bytes).map_err(heed::Error::Decoding)?)
+}
+
+/// Read a shape from a buffer that may not be aligned on 64 bits by copying it first.
+pub(crate) fn read_unaligned(bytes: &[u8]) -> Result<Geometry, BoxedError> {
+    if bytes.len() % size_of::<u64>() != 0 {
+        return Err(InvalidZerometryBytes {
+            len: bytes.len(),
+            misalignment: 0,
+        }
+        .into());
+    }
+    let mut aligned = vec![0_u64; bytes.len() / size_of::<u64>()];
+    // Safe because the copy has the same length in bytes and any byte is a valid u64 part
+    let copy =
+        unsafe { std::slice::from_raw_parts_mut(aligned.as_mut_ptr().cast::<u8>(), bytes.len()) };
+    copy.copy_from_slice(bytes);
+    // Safe because the copy is aligned on 64 bits
+    let shape = unsafe { Zerometry::from_bytes(copy)? };
+    Ok(shape.to_geo())
+}
+
 pub struct ZerometryCodec;
 
 impl<'a> heed::BytesDecode<'a> for ZerometryCodec {
     type DItem = Zerometry<'a>;
 
     fn bytes_decode(bytes: &'a [u8]) -> Result<Self::DItem, BoxedError> {
+        #[cfg(feature = "checked-zerometry")]
+        check_bytes(bytes)?;
         // Safe because the keys and values are aligned on 64 bits
         unsafe { Zerometry::from_bytes(bytes).map_err(Into::into) }
     }
 }
 
+/// Returned instead of reading a shape that is not aligned on 64 bits or whose length isn't a
+/// multiple of 8 bytes. Only checked with the `checked-zerometry` feature, the values written by
+/// cellulite are always valid but not the ones given to [`crate::Cellulite::add_raw_zerometry`].
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "The shape cannot be read, it's {len} bytes long and starts {misalignment} bytes after a 64 bits boundary"
+)]
+pub struct InvalidZerometryBytes {
+    /// The length of the value.
+    pub len: usize,
+    /// The offset of the value from the previous 64 bits boundary.
+    pub misalignment: usize,
+}
+
+/// Check that `bytes` can be read as a `Zerometry` without undefined behavior.
+#[cfg(feature = "checked-zerometry")]
+fn check_bytes(bytes: &[u8]) -> Result<(), InvalidZerometryBytes> {
+    const ALIGNMENT: usize = align_of::<f64>();
+    let misalignment = bytes.as_ptr() as usize % ALIGNMENT;
+    if misalignment != 0 || bytes.len() % ALIGNMENT != 0 {
+        return Err(InvalidZerometryBytes {
+            len: bytes.len(),
+            misalignment,
+        });
+    }
+    Ok(())
+}
+
 impl heed::BytesEncode<'_> for ZerometryCodec {
     type EItem = Geometry;
 
@@ -1,4 +1,4 @@
-use cellulite::Cellulite;
+use cellulite::{Cellulite, Error};
 use geo::polygon;
 use steppe::NoProgress;
 
@@ -20,6 +20,10 @@ fn from_0_3_0() {
     let mut wtxn = env.write_txn().unwrap();
     let cellulite = Cellulite::create_from_env(&env, &mut wtxn, "cellulite").unwrap();
     insta::assert_snapshot!(cellulite.get_version(&wtxn).unwrap(), @"0.3.0");
+    cellulite
+        .upgrade(&mut wtxn, &|| false, &NoProgress)
+        .unwrap();
+    insta::assert_snapshot!(cellulite.get_version(&wtxn).unwrap(), @"0.4.0");
 
     // This matches only a subset of the multi-point containing all the trees
     let trees = polygon![
@@ -51,7 +55,6 @@ fn from_0_3_0() {
     let shape = cellulite.item(&wtxn, 2).unwrap().unwrap();
     assert!(shape.to_polygon().is_some());
 
-    // The next lines will break the day we do a DB-breaking, we should call the update function here
     cellulite.delete(&mut wtxn, 2).unwrap();
     cellulite
         .add(
@@ -83,3 +86,63 @@ fn from_0_3_0() {
     let shape = cellulite.item(&wtxn, 1000).unwrap().unwrap();
     assert!(shape.to_point().is_some());
 }
+
+#[test]
+fn open_and_upgrade_from_0_3_0() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::copy(
+        "tests/assets/v0_3_0.mdb/data.mdb",
+        dir.path().join("data.mdb"),
+    )
+    .unwrap();
+    let env = unsafe {
+        heed::EnvOpenOptions::new()
+            .map_size(1024 * 1024 * 1024)
+            .max_dbs(Cellulite::nb_dbs())
+            .open(dir.path())
+            .unwrap()
+    };
+    let mut wtxn = env.write_txn().unwrap();
+    let cellulite =
+        Cellulite::open_and_upgrade(&env, &mut wtxn, "cellulite", &|| false, &NoProgress).unwrap();
+    insta::assert_snapshot!(cellulite.get_version(&wtxn).unwrap(), @"0.4.0");
+
+    // The cells were migrated, we can query the database right away
+    let desk = polygon![
+        (x: 3.6071739196777344, y: 43.99156188964844),
+        (x: 3.607184648513794, y: 43.99156951904297),
+        (x: 3.6072075366973877, y: 43.991554260253906),
+        (x: 3.6071901321411133, y: 43.99154281616211),
+        (x: 3.6071739196777344, y: 43.99156188964844)
+    ];
+    let ret = cellulite.in_shape(&wtxn, &desk).unwrap();
+    insta::assert_debug_snapshot!(ret, @"RoaringBitmap<[1, 2]>");
+}
+
+#[test]
+fn cancel_the_upgrade_from_0_3_0() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::copy(
+        "tests/assets/v0_3_0.mdb/data.mdb",
+        dir.path().join("data.mdb"),
+    )
+    .unwrap();
+    let env = unsafe {
+        heed::EnvOpenOptions::new()
+            .map_size(1024 * 1024 * 1024)
+            .max_dbs(Cellulite::nb_dbs())
+            .open(dir.path())
+            .unwrap()
+    };
+    let mut wtxn = env.write_txn().unwrap();
+    let err = Cellulite::open_and_upgrade(&env, &mut wtxn, "cellulite", &|| true, &NoProgress)
+        .unwrap_err();
+    assert!(matches!(err, Error::UpgradeCanceled), "{err}");
+    wtxn.abort();
+
+    // Nothing was written, the upgrade can be done again
+    let mut wtxn = env.write_txn().unwrap();
+    let cellulite =
+        Cellulite::open_and_upgrade(&env, &mut wtxn, "cellulite", &|| false, &NoProgress).unwrap();
+    insta::assert_snapshot!(cellulite.get_version(&wtxn).unwrap(), @"0.4.0");
+}